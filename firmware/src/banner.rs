@@ -0,0 +1,42 @@
+use core::ops::Range;
+
+use defmt::info;
+
+use crate::config_store::StoreSummary;
+
+// Fixed details of the board the firmware was built for.
+pub struct BoardInfo {
+    pub name: &'static str,
+    pub servo_pins: &'static [u8],
+    pub feedback_pins: &'static [u8],
+}
+
+// Logs what the firmware found at boot so a new board can be brought up with
+// only a debug probe attached.
+pub fn log_startup(
+    board: &BoardInfo,
+    jedec_id: u32,
+    config_range: &Range<u32>,
+    configs: &StoreSummary,
+) {
+    info!(
+        "{=str} {=str} on {=str}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        board.name
+    );
+    info!(
+        "{} feeders: servo pins {=[u8]} feedback pins {=[u8]}",
+        board.servo_pins.len(),
+        board.servo_pins,
+        board.feedback_pins
+    );
+    info!(
+        "flash {=u32:#x}: configs at {=u32:#x}..{=u32:#x}",
+        jedec_id, config_range.start, config_range.end
+    );
+    info!(
+        "configs: {} stored, {} to migrate, {} unreadable",
+        configs.stored, configs.legacy, configs.unreadable
+    );
+}
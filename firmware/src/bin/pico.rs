@@ -7,59 +7,55 @@
 
 use embassy_executor::Spawner;
 use embassy_futures::join::{join3, join4};
-use embassy_rp::bind_interrupts;
-use embassy_rp::flash::Async;
-use embassy_rp::flash::Flash;
 use embassy_rp::gpio::{self, Pull};
-use embassy_rp::peripherals::USB;
-use embassy_rp::usb::InterruptHandler;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, pipe::Pipe};
-use pnpfeeder::{Feeder, FeederChannel, FeederClient, GCodeEventChannel, GCodeHandler};
-use rp2040_0816::config_store;
-use rp2040_0816::{gpio_input::GpioInput, pwm_servo::PwmServo, usb};
+use pnpfeeder::{
+    Feeder, FeederBank, FeederChannel, FeederClient, MotionController, MotionScheduler,
+};
+use rp2040_0816::banner::BoardInfo;
+use rp2040_0816::host::{self, HostPeripherals};
+use rp2040_0816::{gpio_input::GpioInput, pwm_servo::PwmServo};
 
 use {defmt_rtt as _, panic_probe as _};
 
-const FLASH_SIZE: usize = 2 * 1024 * 1024;
-
-bind_interrupts!(struct Irqs {
-    USBCTRL_IRQ => InterruptHandler<USB>;
-});
+const BOARD: BoardInfo = BoardInfo {
+    name: "pico",
+    servo_pins: &[16, 18, 20, 14],
+    feedback_pins: &[17, 19, 21, 15],
+};
 
 #[embassy_executor::main]
 async fn main(_spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
 
-    let mut flash = Flash::<_, Async, FLASH_SIZE>::new(p.FLASH, p.DMA_CH0);
-
-    let _jedec_id = flash.blocking_jedec_id().unwrap();
-    let mut unique_id = [0u8; 8];
-    flash.blocking_unique_id(&mut unique_id).unwrap();
-
-    let mut cdc_output_pipe = Pipe::<NoopRawMutex, 256>::new();
-    let (gcode_output_reader, gcode_output_writer) = cdc_output_pipe.split();
-
-    let gcode_event_channel = GCodeEventChannel::<2>::new();
-
-    let usb = usb::Usb::new(gcode_output_reader, gcode_event_channel.sender());
-    let usb_future = usb.run(p.USB, Irqs, &unique_id);
+    let mut servo_0 = PwmServo::new_a(p.PWM_CH0, p.PIN_16);
+    let mut servo_1 = PwmServo::new_a(p.PWM_CH1, p.PIN_18);
+    let mut servo_2 = PwmServo::new_a(p.PWM_CH2, p.PIN_20);
+    let mut servo_3 = PwmServo::new_a(p.PWM_CH7, p.PIN_14);
+    // All servo motion is driven by a single task.
+    let motion = MotionController::new([&mut servo_0, &mut servo_1, &mut servo_2, &mut servo_3]);
+    // Limited by M612 C once the board config is loaded.
+    let scheduler = MotionScheduler::new(0);
 
     let mut feeder_0 = Feeder::new(
-        PwmServo::new_a(p.PWM_CH0, p.PIN_16),
+        motion.servo(0),
         GpioInput::new(gpio::Input::new(p.PIN_17, Pull::Up)),
-    );
+    )
+    .with_motion_scheduler(&scheduler);
     let mut feeder_1 = Feeder::new(
-        PwmServo::new_a(p.PWM_CH1, p.PIN_18),
+        motion.servo(1),
         GpioInput::new(gpio::Input::new(p.PIN_19, Pull::Up)),
-    );
+    )
+    .with_motion_scheduler(&scheduler);
     let mut feeder_2 = Feeder::new(
-        PwmServo::new_a(p.PWM_CH2, p.PIN_20),
+        motion.servo(2),
         GpioInput::new(gpio::Input::new(p.PIN_21, Pull::Up)),
-    );
+    )
+    .with_motion_scheduler(&scheduler);
     let mut feeder_3 = Feeder::new(
-        PwmServo::new_a(p.PWM_CH7, p.PIN_14),
+        motion.servo(3),
         GpioInput::new(gpio::Input::new(p.PIN_15, Pull::Up)),
-    );
+    )
+    .with_motion_scheduler(&scheduler);
 
     let channels = [
         &FeederChannel::new(),
@@ -75,20 +71,24 @@ async fn main(_spawner: Spawner) {
         feeder_3.run(channels[3]),
     );
 
-    // Hard coding flash range here is terrible.
-    let store = config_store::FlashConfigStore::new(flash, (2048 - 32) * 1024..(2048) * 1024);
-
-    let mut gcode_handler = GCodeHandler::new(
-        [
-            FeederClient::new(channels[0]),
-            FeederClient::new(channels[1]),
-            FeederClient::new(channels[2]),
-            FeederClient::new(channels[3]),
-        ],
-        gcode_output_writer,
-        store,
-    );
-    let gcode_future = gcode_handler.run(gcode_event_channel.receiver());
-
-    join3(usb_future, gcode_future, feeder_future).await;
+    let mut feeders = FeederBank::new();
+    for channel in channels {
+        feeders.register(FeederClient::new(channel)).unwrap();
+    }
+
+    let host_peripherals = HostPeripherals {
+        usb: p.USB,
+        flash: p.FLASH,
+        dma: p.DMA_CH0,
+        watchdog: p.WATCHDOG,
+        led: p.PIN_25,
+        aux: p.PIN_22,
+    };
+
+    join3(
+        host::run(host_peripherals, &BOARD, feeders, &scheduler, None),
+        feeder_future,
+        motion.run(),
+    )
+    .await;
 }
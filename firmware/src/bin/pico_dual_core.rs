@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![feature(const_option)]
+#![feature(type_alias_impl_trait)]
+// This is used for `utf8_char_width`.
+#![feature(str_internals)]
+
+// The pico board with its feeders and servos run by core 1 so heavy USB
+// traffic on core 0 can never delay a lever settling.
+
+use embassy_executor::Spawner;
+use embassy_futures::join::{join3, join4};
+use embassy_rp::gpio::{self, Pull};
+use embassy_rp::peripherals::{
+    PIN_14, PIN_15, PIN_16, PIN_17, PIN_18, PIN_19, PIN_20, PIN_21, PWM_CH0, PWM_CH1, PWM_CH2,
+    PWM_CH7,
+};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use pnpfeeder::{
+    Feeder, FeederBank, FeederChannel, FeederClient, MotionController, MotionScheduler,
+};
+use rp2040_0816::banner::BoardInfo;
+use rp2040_0816::host::{self, HostPeripherals};
+use rp2040_0816::{core1, watchdog};
+use rp2040_0816::{gpio_input::GpioInput, pwm_servo::PwmServo};
+
+use {defmt_rtt as _, panic_probe as _};
+
+const BOARD: BoardInfo = BoardInfo {
+    name: "pico_dual_core",
+    servo_pins: &[16, 18, 20, 14],
+    feedback_pins: &[17, 19, 21, 15],
+};
+
+// Guards the feeder channels and motion scheduler, which are shared between
+// the cores.
+type CoreMutex = CriticalSectionRawMutex;
+
+// Beaten by core 1 so core 0 stops feeding the watchdog if core 1 wedges.
+static CORE1_HEARTBEAT: watchdog::Heartbeat = watchdog::Heartbeat::new();
+
+// The feeders' peripherals, handed over to core 1 which sets them up.
+struct FeederPeripherals {
+    pwm: (PWM_CH0, PWM_CH1, PWM_CH2, PWM_CH7),
+    servo_pins: (PIN_16, PIN_18, PIN_20, PIN_14),
+    feedback_pins: (PIN_17, PIN_19, PIN_21, PIN_15),
+}
+
+#[embassy_executor::task]
+async fn run_feeders(
+    p: FeederPeripherals,
+    channels: &'static [FeederChannel<CoreMutex>; 4],
+    scheduler: &'static MotionScheduler<CoreMutex>,
+) {
+    let mut servo_0 = PwmServo::new_a(p.pwm.0, p.servo_pins.0);
+    let mut servo_1 = PwmServo::new_a(p.pwm.1, p.servo_pins.1);
+    let mut servo_2 = PwmServo::new_a(p.pwm.2, p.servo_pins.2);
+    let mut servo_3 = PwmServo::new_a(p.pwm.3, p.servo_pins.3);
+    // All servo motion is driven by a single task.
+    let motion = MotionController::new([&mut servo_0, &mut servo_1, &mut servo_2, &mut servo_3]);
+
+    let mut feeder_0 = Feeder::new(
+        motion.servo(0),
+        GpioInput::new(gpio::Input::new(p.feedback_pins.0, Pull::Up)),
+    )
+    .with_motion_scheduler(scheduler);
+    let mut feeder_1 = Feeder::new(
+        motion.servo(1),
+        GpioInput::new(gpio::Input::new(p.feedback_pins.1, Pull::Up)),
+    )
+    .with_motion_scheduler(scheduler);
+    let mut feeder_2 = Feeder::new(
+        motion.servo(2),
+        GpioInput::new(gpio::Input::new(p.feedback_pins.2, Pull::Up)),
+    )
+    .with_motion_scheduler(scheduler);
+    let mut feeder_3 = Feeder::new(
+        motion.servo(3),
+        GpioInput::new(gpio::Input::new(p.feedback_pins.3, Pull::Up)),
+    )
+    .with_motion_scheduler(scheduler);
+
+    join3(
+        join4(
+            feeder_0.run(&channels[0]),
+            feeder_1.run(&channels[1]),
+            feeder_2.run(&channels[2]),
+            feeder_3.run(&channels[3]),
+        ),
+        motion.run(),
+        CORE1_HEARTBEAT.run(),
+    )
+    .await;
+}
+
+#[embassy_executor::main]
+async fn main(_spawner: Spawner) {
+    let p = embassy_rp::init(Default::default());
+
+    let channels: &'static [FeederChannel<CoreMutex>; 4] = cortex_m::singleton!(
+        : [FeederChannel<CoreMutex>; 4] = core::array::from_fn(|_| FeederChannel::with_raw_mutex())
+    )
+    .unwrap();
+    // Limited by M612 C once the board config is loaded by the handler on
+    // core 0.
+    let scheduler: &'static MotionScheduler<CoreMutex> =
+        cortex_m::singleton!(: MotionScheduler<CoreMutex> = MotionScheduler::with_raw_mutex(0))
+            .unwrap();
+
+    let feeder_peripherals = FeederPeripherals {
+        pwm: (p.PWM_CH0, p.PWM_CH1, p.PWM_CH2, p.PWM_CH7),
+        servo_pins: (p.PIN_16, p.PIN_18, p.PIN_20, p.PIN_14),
+        feedback_pins: (p.PIN_17, p.PIN_19, p.PIN_21, p.PIN_15),
+    };
+    core1::start(p.CORE1, move |spawner| {
+        spawner
+            .spawn(run_feeders(feeder_peripherals, channels, scheduler))
+            .unwrap()
+    });
+
+    let mut feeders = FeederBank::new();
+    for channel in channels.iter() {
+        feeders.register(FeederClient::new(channel)).unwrap();
+    }
+
+    let host_peripherals = HostPeripherals {
+        usb: p.USB,
+        flash: p.FLASH,
+        dma: p.DMA_CH0,
+        watchdog: p.WATCHDOG,
+        led: p.PIN_25,
+        aux: p.PIN_22,
+    };
+
+    // Only core 0 feeds the watchdog, and only while core 1's heartbeat shows
+    // it is still running the feeders.
+    host::run(
+        host_peripherals,
+        &BOARD,
+        feeders,
+        scheduler,
+        Some(&CORE1_HEARTBEAT),
+    )
+    .await;
+}
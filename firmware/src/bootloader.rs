@@ -0,0 +1,10 @@
+use embassy_rp::rom_data;
+
+// Reboots into the RP2040 ROM's UF2 bootloader so new firmware can be copied
+// to the mass storage drive without pressing BOOTSEL.
+pub fn reboot_to_bootloader() -> ! {
+    // No activity LED and all USB interfaces enabled.
+    rom_data::reset_to_usb_boot(0, 0);
+
+    unreachable!()
+}
@@ -1,18 +1,145 @@
 use core::ops::Range;
 
-use defmt::{debug, error};
+use defmt::{debug, error, info};
 use embedded_storage::nor_flash::NorFlash;
-use pnpfeeder::{ConfigStore, Error, FeederConfig, Value};
+use pnpfeeder::{
+    BoardConfig, CartridgeId, ConfigStore, Error, FeedCounters, FeederConfig, RetractPolicy, Value,
+    Value64,
+};
 use sequential_storage::map::{fetch_item, store_item, StorageItem};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 enum ConfigKey {
+    // Only read to migrate configs stored by older firmware.
     FeederConfigV0(usize),
+    SlotMapV0(usize),
+    // Stored as a list of (field tag, value) pairs so fields can be added
+    // without a migration.  Only read to migrate pulse widths in PWM counts.
+    FeederConfigV1(usize),
+    CartridgeV0(usize),
+    BoardConfigV0,
+    FeedCountersV0(usize),
+    AdvanceMarkerV0(usize),
+    CrashCountV0,
+    // The `FeederConfigV1` layout with pulse widths in microseconds.
+    FeederConfigV2(usize),
 }
 
 enum ConfigValue {
-    FeederConfigV0(FeederConfig),
+    FeederConfig(FeederConfig),
+    SlotMapV0(usize),
+    CartridgeV0(CartridgeId),
+    BoardConfigV0(BoardConfig),
+    FeedCountersV0(FeedCounters),
+    AdvanceMarkerV0(bool),
+    CrashCountV0(u32),
+}
+
+// Layout of `ConfigKey::FeederConfigV0` records.
+#[derive(Deserialize)]
+struct FeederConfigV0 {
+    advanced_angle: Value,
+    half_advanced_angle: Value,
+    retract_angle: Value,
+    feed_length: Value,
+    settle_time: u32,
+    pwm_0: Value,
+    pwm_180: Value,
+    ignore_feeback_pin: bool,
+    always_retract: bool,
+}
+
+impl From<FeederConfigV0> for FeederConfig {
+    fn from(config: FeederConfigV0) -> Self {
+        Self {
+            advanced_angle: config.advanced_angle,
+            half_advanced_angle: config.half_advanced_angle,
+            retract_angle: config.retract_angle,
+            feed_length: config.feed_length,
+            advance_settle_time: config.settle_time,
+            retract_settle_time: config.settle_time,
+            pwm_0: config.pwm_0,
+            pwm_180: config.pwm_180,
+            ignore_feeback_pin: config.ignore_feeback_pin,
+            retract_policy: if config.always_retract {
+                RetractPolicy::Always
+            } else {
+                RetractPolicy::FullAdvance
+            },
+            ..default_config()
+        }
+    }
+}
+
+// Reads the next value of a record, or `default` if the record has ended.
+fn take_or<'a, T: Deserialize<'a>>(buf: &mut &'a [u8], default: T) -> Result<T, Error> {
+    if buf.is_empty() {
+        return Ok(default);
+    }
+    let (value, rest) = postcard::take_from_bytes(buf).map_err(|_| Error::ConfigSetError)?;
+    *buf = rest;
+    Ok(value)
+}
+
+// Upper bound on the number of fields in a `FeederConfigV2` record.  Leaves
+// room for fields to be added without changing the buffer size.
+const MAX_STORED_FIELDS: usize = 48;
+const _: () = assert!(FeederConfig::FIELDS.len() <= MAX_STORED_FIELDS);
+
+fn serialize_feeder_config(config: &FeederConfig, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut len = postcard::to_slice(&FeederConfig::FIELDS.len(), buffer)
+        .map_err(|_| Error::ConfigSetError)?
+        .len();
+    for letter in FeederConfig::FIELDS {
+        let field = (letter, config.get_field(letter)?);
+        len += postcard::to_slice(&field, &mut buffer[len..])
+            .map_err(|_| Error::ConfigSetError)?
+            .len();
+    }
+    Ok(len)
+}
+
+fn deserialize_feeder_config(buffer: &[u8], pwm_counts: bool) -> Result<FeederConfig, Error> {
+    let (count, mut buffer): (usize, _) =
+        postcard::take_from_bytes(buffer).map_err(|_| Error::ConfigGetError)?;
+    let mut config = default_config();
+    let mut has_retract_settle_time = false;
+    for _ in 0..count {
+        let (field, rest): ((char, Value), _) =
+            postcard::take_from_bytes(buffer).map_err(|_| Error::ConfigGetError)?;
+        // Fields unknown to this firmware are ignored.
+        let _ = config.set_field(field.0, field.1);
+        has_retract_settle_time |= field.0 == 'Q';
+        buffer = rest;
+    }
+    // Records from before settle times were split only have `U`, which
+    // applied to both directions.
+    if !has_retract_settle_time {
+        config.retract_settle_time = config.advance_settle_time;
+    }
+    if pwm_counts {
+        config
+            .convert_legacy_pwm_counts()
+            .map_err(|_| Error::ConfigGetError)?;
+    }
+    Ok(config)
+}
+
+fn default_config() -> FeederConfig {
+    FeederConfig {
+        advanced_angle: Value::from_num(135.0),
+        half_advanced_angle: Value::from_num(107.5),
+        retract_angle: Value::from_num(80),
+        feed_length: Value::from_num(2.0),
+        advance_settle_time: 300,
+        retract_settle_time: 300,
+        pwm_0: Value::from_num(1000),
+        pwm_180: Value::from_num(2000),
+        ignore_feeback_pin: false,
+        retract_policy: RetractPolicy::Always,
+        ..Default::default()
+    }
 }
 
 struct ConfigStorageItem {
@@ -21,22 +148,88 @@ struct ConfigStorageItem {
 }
 
 impl ConfigStorageItem {
-    // Key = 2 u32s, FeederConfig =
-    const KEY_WORDS: usize = 2;
-    const FEEDER_WORDS: usize = 8;
-    const PADDING_WORDS: usize = 0;
-    const BYTES_PER_WORD: usize = 5;
-    const BUFFER_SIZE: usize =
-        (Self::KEY_WORDS + Self::FEEDER_WORDS + Self::PADDING_WORDS) * Self::BYTES_PER_WORD;
+    // Key = 2 varints, FeederConfigV2 = length varint + (char, Value) per field.
+    // A `CartridgeV0` string is much smaller than a `FeederConfigV2`.
+    const KEY_BYTES: usize = 2 * 5;
+    const FIELD_BYTES: usize = 2 + 5;
+    const BUFFER_SIZE: usize = Self::KEY_BYTES + 5 + MAX_STORED_FIELDS * Self::FIELD_BYTES;
 
     fn new_config(index: usize, config: FeederConfig) -> Self {
         Self {
-            key: ConfigKey::FeederConfigV0(index),
-            value: ConfigValue::FeederConfigV0(config),
+            key: ConfigKey::FeederConfigV2(index),
+            value: ConfigValue::FeederConfig(config),
+        }
+    }
+
+    fn new_slot(index: usize, slot: usize) -> Self {
+        Self {
+            key: ConfigKey::SlotMapV0(index),
+            value: ConfigValue::SlotMapV0(slot),
+        }
+    }
+
+    fn new_cartridge(index: usize, cartridge: CartridgeId) -> Self {
+        Self {
+            key: ConfigKey::CartridgeV0(index),
+            value: ConfigValue::CartridgeV0(cartridge),
+        }
+    }
+
+    fn new_counters(slot: usize, counters: FeedCounters) -> Self {
+        Self {
+            key: ConfigKey::FeedCountersV0(slot),
+            value: ConfigValue::FeedCountersV0(counters),
+        }
+    }
+
+    fn new_advance_marker(slot: usize, in_progress: bool) -> Self {
+        Self {
+            key: ConfigKey::AdvanceMarkerV0(slot),
+            value: ConfigValue::AdvanceMarkerV0(in_progress),
+        }
+    }
+
+    fn new_crash_count(count: u32) -> Self {
+        Self {
+            key: ConfigKey::CrashCountV0,
+            value: ConfigValue::CrashCountV0(count),
+        }
+    }
+
+    fn new_board_config(config: BoardConfig) -> Self {
+        Self {
+            key: ConfigKey::BoardConfigV0,
+            value: ConfigValue::BoardConfigV0(config),
         }
     }
 }
 
+macro_rules! log_map_error {
+    ($e:expr, $op:expr, $index:expr) => {
+        match $e {
+            sequential_storage::map::MapError::Item(_) => {
+                error!("config {=str} {} item error", $op, $index)
+            }
+            sequential_storage::map::MapError::Storage(_) => {
+                error!("config {=str} {} storage error", $op, $index)
+            }
+            sequential_storage::map::MapError::FullStorage => {
+                error!("config {=str} {} full storage error", $op, $index)
+            }
+            sequential_storage::map::MapError::Corrupted => {
+                error!("config {=str} {} corrupted error", $op, $index)
+            }
+            sequential_storage::map::MapError::BufferTooBig => {
+                error!("config {=str} {} buffer too big error", $op, $index)
+            }
+            sequential_storage::map::MapError::BufferTooSmall(_) => {
+                error!("config {=str} {} buffer too small error", $op, $index)
+            }
+            _ => error!("config {=str} {} unknown error", $op, $index),
+        }
+    };
+}
+
 impl StorageItem for ConfigStorageItem {
     type Key = ConfigKey;
     type Error = Error;
@@ -45,13 +238,58 @@ impl StorageItem for ConfigStorageItem {
         let key_buf = postcard::to_slice(&self.key, buffer).map_err(|_| Error::ConfigSetError)?;
         let key_len = key_buf.len();
         let value_buf = &mut buffer[key_len..];
-        let value_buf = match &self.value {
-            ConfigValue::FeederConfigV0(config) => {
-                postcard::to_slice(&config, value_buf).map_err(|_| Error::ConfigSetError)?
+        let value_len = match (&self.key, &self.value) {
+            (ConfigKey::FeederConfigV2(_), ConfigValue::FeederConfig(config)) => {
+                serialize_feeder_config(config, value_buf)?
+            }
+            (ConfigKey::SlotMapV0(_), ConfigValue::SlotMapV0(slot)) => {
+                postcard::to_slice(&slot, value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
+            }
+            (ConfigKey::CartridgeV0(_), ConfigValue::CartridgeV0(cartridge)) => {
+                postcard::to_slice(cartridge.as_str(), value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
+            }
+            (ConfigKey::FeedCountersV0(_), ConfigValue::FeedCountersV0(counters)) => {
+                let value = (
+                    counters.parts,
+                    counters.length.to_bits(),
+                    counters.remaining,
+                );
+                postcard::to_slice(&value, value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
             }
+            (ConfigKey::AdvanceMarkerV0(_), ConfigValue::AdvanceMarkerV0(in_progress)) => {
+                postcard::to_slice(in_progress, value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
+            }
+            (ConfigKey::CrashCountV0, ConfigValue::CrashCountV0(count)) => {
+                postcard::to_slice(count, value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
+            }
+            (ConfigKey::BoardConfigV0, ConfigValue::BoardConfigV0(config)) => {
+                let value = (
+                    config.startup_enable,
+                    config.startup_delay,
+                    config.idle_timeout,
+                    config.idle_reenable,
+                    config.defer_while_disabled,
+                    config.max_moving_feeders,
+                );
+                postcard::to_slice(&value, value_buf)
+                    .map_err(|_| Error::ConfigSetError)?
+                    .len()
+            }
+            // Older record versions are never written.
+            _ => return Err(Error::ConfigSetError),
         };
 
-        Ok(key_len + value_buf.len())
+        Ok(key_len + value_len)
     }
 
     fn deserialize_from(buffer: &[u8]) -> Result<Self, Self::Error>
@@ -62,8 +300,59 @@ impl StorageItem for ConfigStorageItem {
             postcard::take_from_bytes(buffer).map_err(|_| Error::ConfigSetError)?;
         let value = match key {
             ConfigKey::FeederConfigV0(_) => {
-                let config = postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
-                ConfigValue::FeederConfigV0(config)
+                let config: FeederConfigV0 =
+                    postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
+                let mut config = FeederConfig::from(config);
+                config
+                    .convert_legacy_pwm_counts()
+                    .map_err(|_| Error::ConfigGetError)?;
+                ConfigValue::FeederConfig(config)
+            }
+            ConfigKey::FeederConfigV1(_) => {
+                ConfigValue::FeederConfig(deserialize_feeder_config(value_buf, true)?)
+            }
+            ConfigKey::FeederConfigV2(_) => {
+                ConfigValue::FeederConfig(deserialize_feeder_config(value_buf, false)?)
+            }
+            ConfigKey::SlotMapV0(_) => {
+                let slot = postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
+                ConfigValue::SlotMapV0(slot)
+            }
+            ConfigKey::CartridgeV0(_) => {
+                let cartridge: &str =
+                    postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
+                ConfigValue::CartridgeV0(cartridge.try_into().map_err(|_| Error::ConfigSetError)?)
+            }
+            ConfigKey::FeedCountersV0(_) => {
+                let mut buf = value_buf;
+                ConfigValue::FeedCountersV0(FeedCounters {
+                    parts: take_or(&mut buf, 0)?,
+                    length: Value64::from_bits(take_or(&mut buf, 0)?),
+                    remaining: take_or(&mut buf, None)?,
+                })
+            }
+            ConfigKey::AdvanceMarkerV0(_) => {
+                let in_progress =
+                    postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
+                ConfigValue::AdvanceMarkerV0(in_progress)
+            }
+            ConfigKey::CrashCountV0 => {
+                let count = postcard::from_bytes(value_buf).map_err(|_| Error::ConfigSetError)?;
+                ConfigValue::CrashCountV0(count)
+            }
+            ConfigKey::BoardConfigV0 => {
+                // Fields are appended as they are added so older records end
+                // early and leave the rest at their defaults.
+                let default = BoardConfig::default();
+                let mut buf = value_buf;
+                ConfigValue::BoardConfigV0(BoardConfig {
+                    startup_enable: take_or(&mut buf, default.startup_enable)?,
+                    startup_delay: take_or(&mut buf, default.startup_delay)?,
+                    idle_timeout: take_or(&mut buf, default.idle_timeout)?,
+                    idle_reenable: take_or(&mut buf, default.idle_reenable)?,
+                    defer_while_disabled: take_or(&mut buf, default.defer_while_disabled)?,
+                    max_moving_feeders: take_or(&mut buf, default.max_moving_feeders)?,
+                })
             }
         };
 
@@ -75,6 +364,15 @@ impl StorageItem for ConfigStorageItem {
     }
 }
 
+// Feeder configs found in flash by `FlashConfigStore::summarize()`.
+#[derive(Default)]
+pub struct StoreSummary {
+    pub stored: usize,
+    // Stored by older firmware and migrated when loaded.
+    pub legacy: usize,
+    pub unreadable: usize,
+}
+
 pub struct FlashConfigStore<Flash: NorFlash> {
     flash: Flash,
     range: Range<u32>,
@@ -85,95 +383,183 @@ impl<Flash: NorFlash> FlashConfigStore<Flash> {
         Self { flash, range }
     }
 
-    fn default_config() -> FeederConfig {
-        FeederConfig {
-            advanced_angle: Value::from_num(135.0),
-            half_advanced_angle: Value::from_num(107.5),
-            retract_angle: Value::from_num(80),
-            feed_length: Value::from_num(2.0),
-            settle_time: 300,
-            pwm_0: Value::from_num(490.2),
-            pwm_180: Value::from_num(980.4),
-            ignore_feeback_pin: false,
-            always_retract: true,
+    // Returns `Err` if the item exists but can not be read.  The error is
+    // logged.
+    fn try_fetch(&mut self, key: ConfigKey, index: usize) -> Result<Option<ConfigValue>, ()> {
+        let mut buf = [0u8; ConfigStorageItem::BUFFER_SIZE];
+        let range = self.range.clone();
+        let item: Result<Option<ConfigStorageItem>, _> =
+            fetch_item(&mut self.flash, range, &mut buf, key);
+        match item {
+            Ok(item) => Ok(item.map(|item| item.value)),
+            Err(e) => {
+                log_map_error!(e, "get", index);
+                Err(())
+            }
+        }
+    }
+
+    // Returns `None` if the item does not exist or can not be read.
+    fn fetch(&mut self, key: ConfigKey, index: usize) -> Option<ConfigValue> {
+        // On any error fall back to the default.
+        self.try_fetch(key, index).ok().flatten()
+    }
+
+    // Counts the stored configs of `feeders` feeders for the startup banner.
+    pub fn summarize(&mut self, feeders: usize) -> StoreSummary {
+        let mut summary = StoreSummary::default();
+        for index in 0..feeders {
+            match self.try_fetch(ConfigKey::FeederConfigV2(index), index) {
+                Ok(Some(_)) => summary.stored += 1,
+                Ok(None) => {
+                    let legacy = [
+                        ConfigKey::FeederConfigV1(index),
+                        ConfigKey::FeederConfigV0(index),
+                    ]
+                    .into_iter()
+                    .any(|key| matches!(self.try_fetch(key, index), Ok(Some(_))));
+                    if legacy {
+                        summary.legacy += 1;
+                    }
+                }
+                Err(()) => summary.unreadable += 1,
+            }
         }
+        summary
+    }
+
+    fn store(&mut self, item: ConfigStorageItem, index: usize) -> pnpfeeder::Result<()> {
+        let mut buf = [0u8; ConfigStorageItem::BUFFER_SIZE];
+        let range = self.range.clone();
+        store_item(&mut self.flash, range, &mut buf, item).map_err(|e| {
+            log_map_error!(e, "set", index);
+            Error::ConfigSetError
+        })
     }
 }
 
 impl<Flash: NorFlash> ConfigStore for FlashConfigStore<Flash> {
     fn get(&mut self, index: usize) -> pnpfeeder::Result<FeederConfig> {
         debug!("config get {}", index);
-        let mut buf = [0u8; ConfigStorageItem::BUFFER_SIZE];
-        let range = self.range.clone();
-        // Too much extraneous error handling here.  We should be able to clean this up.
-        let item: Option<ConfigStorageItem> = fetch_item(
-            &mut self.flash,
-            range,
-            &mut buf,
-            ConfigKey::FeederConfigV0(index),
-        )
-        .unwrap_or_else(|e| {
-            // On any error, log it and return the default config.
-            match e {
-                sequential_storage::map::MapError::Item(_) => {
-                    error!("config get {} item error", index)
-                }
-                sequential_storage::map::MapError::Storage(_) => {
-                    error!("config get {} storage error", index)
-                }
-                sequential_storage::map::MapError::FullStorage => {
-                    error!("config get {} full storage error", index)
+        let value = self
+            .fetch(ConfigKey::FeederConfigV2(index), index)
+            .or_else(|| {
+                let value = self.fetch(ConfigKey::FeederConfigV1(index), index);
+                if value.is_some() {
+                    info!("config {} migrated from v1", index);
                 }
-                sequential_storage::map::MapError::Corrupted => {
-                    error!("config get {} corrupted error", index)
+                value
+            })
+            .or_else(|| {
+                let value = self.fetch(ConfigKey::FeederConfigV0(index), index);
+                if value.is_some() {
+                    info!("config {} migrated from v0", index);
                 }
-                sequential_storage::map::MapError::BufferTooBig => {
-                    error!("config get {} buffer too big error", index)
-                }
-                sequential_storage::map::MapError::BufferTooSmall(_) => {
-                    error!("config get {} buffer too small error", index)
-                }
-                _ => error!("config get {} unknown error", index),
-            };
-            None
-        });
-
-        match item
-            .map(|item| item.value)
-            .unwrap_or(ConfigValue::FeederConfigV0(Self::default_config()))
-        {
-            ConfigValue::FeederConfigV0(feeder) => Ok(feeder),
+                value
+            });
+        match value {
+            Some(ConfigValue::FeederConfig(feeder)) => Ok(feeder),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(default_config()),
         }
     }
 
     fn set(&mut self, index: usize, config: &FeederConfig) -> pnpfeeder::Result<()> {
         debug!("config set {}", index);
-        let mut buf = [0u8; ConfigStorageItem::BUFFER_SIZE];
-        let range = self.range.clone();
-        let item = ConfigStorageItem::new_config(index, config.clone());
-        store_item(&mut self.flash, range, &mut buf, item).map_err(|e| {
-            match e {
-                sequential_storage::map::MapError::Item(_) => {
-                    error!("config get {} item error", index)
-                }
-                sequential_storage::map::MapError::Storage(_) => {
-                    error!("config get {} storage error", index)
-                }
-                sequential_storage::map::MapError::FullStorage => {
-                    error!("config get {} full storage error", index)
-                }
-                sequential_storage::map::MapError::Corrupted => {
-                    error!("config get {} corrupted error", index)
-                }
-                sequential_storage::map::MapError::BufferTooBig => {
-                    error!("config get {} buffer too big error", index)
-                }
-                sequential_storage::map::MapError::BufferTooSmall(_) => {
-                    error!("config get {} buffer too small error", index)
-                }
-                _ => error!("config get {} unknown error", index),
-            };
-            Error::ConfigSetError
-        })
+        self.store(ConfigStorageItem::new_config(index, config.clone()), index)
+    }
+
+    fn get_default(&self, _index: usize) -> FeederConfig {
+        default_config()
+    }
+
+    fn get_slot(&mut self, index: usize) -> pnpfeeder::Result<usize> {
+        debug!("config get slot {}", index);
+        match self.fetch(ConfigKey::SlotMapV0(index), index) {
+            Some(ConfigValue::SlotMapV0(slot)) => Ok(slot),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(index),
+        }
+    }
+
+    fn set_slot(&mut self, index: usize, slot: usize) -> pnpfeeder::Result<()> {
+        debug!("config set slot {}", index);
+        self.store(ConfigStorageItem::new_slot(index, slot), index)
+    }
+
+    fn get_cartridge(&mut self, index: usize) -> pnpfeeder::Result<CartridgeId> {
+        debug!("config get cartridge {}", index);
+        match self.fetch(ConfigKey::CartridgeV0(index), index) {
+            Some(ConfigValue::CartridgeV0(cartridge)) => Ok(cartridge),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(CartridgeId::new()),
+        }
+    }
+
+    fn set_cartridge(&mut self, index: usize, cartridge: &str) -> pnpfeeder::Result<()> {
+        debug!("config set cartridge {}", index);
+        let cartridge = cartridge
+            .try_into()
+            .map_err(|_| Error::InvalidCartridgeId)?;
+        self.store(ConfigStorageItem::new_cartridge(index, cartridge), index)
+    }
+
+    fn get_counters(&mut self, slot: usize) -> pnpfeeder::Result<FeedCounters> {
+        debug!("config get counters {}", slot);
+        match self.fetch(ConfigKey::FeedCountersV0(slot), slot) {
+            Some(ConfigValue::FeedCountersV0(counters)) => Ok(counters),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(FeedCounters::default()),
+        }
+    }
+
+    fn set_counters(&mut self, slot: usize, counters: &FeedCounters) -> pnpfeeder::Result<()> {
+        debug!("config set counters {}", slot);
+        self.store(ConfigStorageItem::new_counters(slot, *counters), slot)
+    }
+
+    fn get_board_config(&mut self) -> pnpfeeder::Result<BoardConfig> {
+        debug!("config get board");
+        match self.fetch(ConfigKey::BoardConfigV0, 0) {
+            Some(ConfigValue::BoardConfigV0(config)) => Ok(config),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(BoardConfig::default()),
+        }
+    }
+
+    fn set_board_config(&mut self, config: &BoardConfig) -> pnpfeeder::Result<()> {
+        debug!("config set board");
+        self.store(ConfigStorageItem::new_board_config(config.clone()), 0)
+    }
+
+    fn get_advance_marker(&mut self, slot: usize) -> pnpfeeder::Result<bool> {
+        debug!("config get advance marker {}", slot);
+        match self.fetch(ConfigKey::AdvanceMarkerV0(slot), slot) {
+            Some(ConfigValue::AdvanceMarkerV0(in_progress)) => Ok(in_progress),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(false),
+        }
+    }
+
+    fn set_advance_marker(&mut self, slot: usize, in_progress: bool) -> pnpfeeder::Result<()> {
+        debug!("config set advance marker {}", slot);
+        self.store(
+            ConfigStorageItem::new_advance_marker(slot, in_progress),
+            slot,
+        )
+    }
+
+    fn get_crash_count(&mut self) -> pnpfeeder::Result<u32> {
+        debug!("config get crash count");
+        match self.fetch(ConfigKey::CrashCountV0, 0) {
+            Some(ConfigValue::CrashCountV0(count)) => Ok(count),
+            Some(_) => Err(Error::ConfigGetError),
+            None => Ok(0),
+        }
+    }
+
+    fn set_crash_count(&mut self, count: u32) -> pnpfeeder::Result<()> {
+        debug!("config set crash count {}", count);
+        self.store(ConfigStorageItem::new_crash_count(count), 0)
     }
 }
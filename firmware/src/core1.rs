@@ -0,0 +1,22 @@
+use embassy_executor::{Executor, Spawner};
+use embassy_rp::multicore::{spawn_core1, Stack};
+use embassy_rp::peripherals::CORE1;
+
+// Core 1's tasks live in the executor's statics so the stack only has to hold
+// what they call.
+const STACK_SIZE: usize = 8 * 1024;
+
+// Starts core 1 with its own executor and has `init` spawn its tasks.  They
+// are then never held up by core 0's, such as USB handling.  Anything they
+// share with core 0 must use a mutex which is safe across cores, e.g.
+// `CriticalSectionRawMutex`.
+//
+// Flash writes pause core 1 while they run, which embassy does for cores
+// started here.
+pub fn start(core1: CORE1, init: impl FnOnce(Spawner) + Send + 'static) {
+    let stack = cortex_m::singleton!(: Stack<STACK_SIZE> = Stack::new()).unwrap();
+    spawn_core1(core1, stack, move || {
+        let executor = cortex_m::singleton!(: Executor = Executor::new()).unwrap();
+        executor.run(init)
+    });
+}
@@ -0,0 +1,43 @@
+use embassy_rp::pwm::{self, Config, InputMode, Pwm};
+use embassy_rp::Peripheral;
+use pnpfeeder::CountingInput;
+
+// Counts the pulses of a sprocket hole sensor or a single channel encoder in
+// hardware with a PWM slice's counter, clocked by the rising edges of its B
+// pin.
+pub struct PwmEdgeCounter<'d, CH: pwm::Channel> {
+    pwm: Pwm<'d, CH>,
+    last: u16,
+    count: i32,
+}
+
+impl<'d, CH: pwm::Channel> PwmEdgeCounter<'d, CH> {
+    pub fn new_b(
+        peripheral: impl Peripheral<P = CH> + 'd,
+        pin: impl Peripheral<P = impl pwm::PwmPinB<CH>> + 'd,
+    ) -> Self {
+        let mut config: Config = Default::default();
+        config.top = u16::MAX;
+
+        let pwm = Pwm::new_input(peripheral, pin, InputMode::RisingEdge, config);
+
+        Self {
+            pwm,
+            last: 0,
+            count: 0,
+        }
+    }
+}
+
+impl<'d, CH: pwm::Channel> CountingInput for PwmEdgeCounter<'d, CH> {
+    fn count(&mut self) -> i32 {
+        // The hardware counter is only 16 bits so it is extended on each read.
+        // Far fewer pulses than that pass between reads.
+        let counter = self.pwm.counter();
+        self.count = self
+            .count
+            .wrapping_add(counter.wrapping_sub(self.last) as i32);
+        self.last = counter;
+        self.count
+    }
+}
@@ -0,0 +1,23 @@
+use embassy_rp::gpio::{self, Level, Pin};
+use pnpfeeder::HookOutputs;
+
+pub struct GpioHookOutputs<'d, L: Pin, A: Pin> {
+    led: gpio::Output<'d, L>,
+    aux: gpio::Output<'d, A>,
+}
+
+impl<'d, L: Pin, A: Pin> GpioHookOutputs<'d, L, A> {
+    pub fn new(led: gpio::Output<'d, L>, aux: gpio::Output<'d, A>) -> Self {
+        Self { led, aux }
+    }
+}
+
+impl<'d, L: Pin, A: Pin> HookOutputs for GpioHookOutputs<'d, L, A> {
+    fn set_led(&mut self, on: bool) {
+        self.led.set_level(Level::from(on));
+    }
+
+    fn set_aux(&mut self, on: bool) {
+        self.aux.set_level(Level::from(on));
+    }
+}
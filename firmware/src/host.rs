@@ -0,0 +1,112 @@
+use embassy_futures::join::join3;
+use embassy_rp::bind_interrupts;
+use embassy_rp::flash::{Async, Flash};
+use embassy_rp::peripherals::{DMA_CH0, FLASH, PIN_22, PIN_25, USB, WATCHDOG};
+use embassy_rp::usb::InterruptHandler;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::{
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    pipe::Pipe,
+};
+use pnpfeeder::{FeederBank, GCodeEventChannel, GCodeHandler, MotionScheduler, TransportStats};
+
+use crate::banner::{self, BoardInfo};
+use crate::watchdog::{self, Heartbeat};
+use crate::{config_store, reset, usb};
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+// Lines a host can stream ahead of the one being handled before it is told
+// to wait.
+const GCODE_QUEUE_LEN: usize = 8;
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<USB>;
+});
+
+// What the USB G-code interface and handler need, whichever core runs the
+// feeders.
+pub struct HostPeripherals {
+    pub usb: USB,
+    pub flash: FLASH,
+    pub dma: DMA_CH0,
+    pub watchdog: WATCHDOG,
+    // The Pico's on board LED and the first spare GPIO after the feeders,
+    // driven by feeder hooks.
+    pub led: PIN_25,
+    pub aux: PIN_22,
+}
+
+// Runs USB and the G-code handler for `feeders`, which share `scheduler`, on
+// the calling core.  The watchdog is fed while this core runs and
+// `heartbeat`, if given, shows the core running the feeders does too.
+pub async fn run<R: RawMutex>(
+    p: HostPeripherals,
+    board: &BoardInfo,
+    feeders: FeederBank<'_, R>,
+    scheduler: &MotionScheduler<R>,
+    heartbeat: Option<&Heartbeat>,
+) {
+    let mut flash = Flash::<_, Async, FLASH_SIZE>::new(p.flash, p.dma);
+
+    let jedec_id = flash.blocking_jedec_id().unwrap();
+    let mut unique_id = [0u8; 8];
+    flash.blocking_unique_id(&mut unique_id).unwrap();
+
+    let mut cdc_output_pipe = Pipe::<NoopRawMutex, 256>::new();
+    let (gcode_output_reader, gcode_output_writer) = cdc_output_pipe.split();
+
+    let gcode_event_channel = GCodeEventChannel::<GCODE_QUEUE_LEN>::new();
+    let transport_stats = TransportStats::new();
+
+    let usb = usb::Usb::new(
+        gcode_output_reader,
+        gcode_event_channel.sender(),
+        &transport_stats,
+    );
+    let usb_future = usb.run(p.usb, Irqs, &unique_id);
+
+    #[cfg(feature = "hook-outputs")]
+    let mut hook_outputs = crate::gpio_hook_outputs::GpioHookOutputs::new(
+        embassy_rp::gpio::Output::new(p.led, embassy_rp::gpio::Level::Low),
+        embassy_rp::gpio::Output::new(p.aux, embassy_rp::gpio::Level::Low),
+    );
+
+    // Hard coding flash range here is terrible.
+    let config_range = (2048 - 32) * 1024..(2048) * 1024;
+    let mut store = config_store::FlashConfigStore::new(flash, config_range.clone());
+    let watchdog = Watchdog::new(p.watchdog);
+    let crash_count = watchdog::count_crash_resets(&watchdog, &mut store);
+    banner::log_startup(
+        board,
+        jedec_id,
+        &config_range,
+        &store.summarize(feeders.len()),
+    );
+
+    // Holds the session captured by M670 for M671 to dump.
+    #[cfg(feature = "session-capture")]
+    let mut capture_buffer = [0u8; 4096];
+
+    let gcode_handler = GCodeHandler::new(feeders, gcode_output_writer, store)
+        .with_transport_stats(&transport_stats)
+        .with_motion_scheduler(scheduler)
+        .with_reset(reset::reset)
+        .with_crash_count(crash_count)
+        .with_firmware_info(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    #[cfg(feature = "hook-outputs")]
+    let gcode_handler = gcode_handler.with_hook_outputs(&mut hook_outputs);
+    #[cfg(feature = "bootloader")]
+    let gcode_handler = gcode_handler.with_bootloader(crate::bootloader::reboot_to_bootloader);
+    #[cfg(feature = "session-capture")]
+    let gcode_handler = gcode_handler.with_session_capture(&mut capture_buffer);
+    let mut gcode_handler = gcode_handler;
+    let gcode_future = gcode_handler.run(gcode_event_channel.receiver());
+
+    join3(
+        usb_future,
+        gcode_future,
+        watchdog::feed(watchdog, heartbeat),
+    )
+    .await;
+}
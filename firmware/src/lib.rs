@@ -4,7 +4,20 @@
 // This is used for `utf8_char_width`.
 #![feature(str_internals)]
 
+pub mod banner;
+#[cfg(feature = "bootloader")]
+pub mod bootloader;
 pub mod config_store;
+pub mod core1;
+pub mod encoders;
+#[cfg(feature = "hook-outputs")]
+pub mod gpio_hook_outputs;
 pub mod gpio_input;
+pub mod host;
+pub mod pca9685;
+pub mod peelers;
+pub mod pio_servo;
 pub mod pwm_servo;
+pub mod reset;
 pub mod usb;
+pub mod watchdog;
@@ -0,0 +1,126 @@
+use core::cell::RefCell;
+
+use az::Cast;
+use embassy_rp::i2c::{self, I2c};
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
+use pnpfeeder::{AngleScaler, Error, PwmLimits, Result, Servo, Value, Value64};
+use {defmt_rtt as _, panic_probe as _};
+
+// An I2C bus shared by the expanders on it and any other devices.  Each
+// transfer locks the bus so the servos of different expanders, driven from
+// different feeder tasks, don't interleave their writes.
+pub type SharedI2c<'d, T, M> = Mutex<NoopRawMutex, RefCell<I2c<'d, T, M>>>;
+
+const MODE1: u8 = 0x00;
+const PRESCALE: u8 = 0xfe;
+const LED0_ON_L: u8 = 0x06;
+
+const MODE1_AUTO_INCREMENT: u8 = 0x20;
+const MODE1_SLEEP: u8 = 0x10;
+// Set in the high byte of a channel's off count to hold the output low.
+const FULL_OFF: u16 = 0x1000;
+
+// 25MHz / (4096 * (121 + 1)) = 50Hz from the internal oscillator.
+const PRESCALE_50HZ: u8 = 121;
+const EXPANDER_COUNTS: i64 = 4096;
+// 50Hz, as hobby servos expect.
+const PERIOD_MICROS: i64 = 20_000;
+
+// A PCA9685 16 channel PWM expander, so a board can drive many more servos
+// than it has PWM outputs over two wires.
+pub struct Pca9685<'a, 'd, T: i2c::Instance, M: i2c::Mode> {
+    bus: &'a SharedI2c<'d, T, M>,
+    address: u8,
+}
+
+impl<'a, 'd, T: i2c::Instance, M: i2c::Mode> Pca9685<'a, 'd, T, M> {
+    pub const CHANNELS: usize = 16;
+
+    // Sets the expander at `address` up for 50Hz servo pulses with every
+    // output off.
+    pub fn new(bus: &'a SharedI2c<'d, T, M>, address: u8) -> Result<Self> {
+        let expander = Self { bus, address };
+        // The prescaler can only be changed while the oscillator sleeps.
+        expander.write(&[MODE1, MODE1_SLEEP])?;
+        expander.write(&[PRESCALE, PRESCALE_50HZ])?;
+        expander.write(&[MODE1, MODE1_AUTO_INCREMENT])?;
+        for channel in 0..Self::CHANNELS {
+            expander.set_off_count(channel, FULL_OFF)?;
+        }
+        Ok(expander)
+    }
+
+    // Returns a `Servo` for output `channel`.  Each channel has its own
+    // limits.
+    pub fn servo(&self, channel: usize) -> Pca9685Servo<'_, 'a, 'd, T, M> {
+        assert!(channel < Self::CHANNELS);
+        Pca9685Servo {
+            expander: self,
+            channel,
+            scaler: AngleScaler::new(PwmLimits::standard()),
+        }
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<()> {
+        self.bus.lock(|bus| {
+            bus.borrow_mut()
+                .blocking_write(self.address, bytes)
+                .map_err(|_| Error::Io)
+        })
+    }
+
+    // Pulses start at the beginning of the period and end at `off`.
+    fn set_off_count(&self, channel: usize, off: u16) -> Result<()> {
+        let register = LED0_ON_L + 4 * channel as u8;
+        let [off_low, off_high] = off.to_le_bytes();
+        self.write(&[register, 0, 0, off_low, off_high])
+    }
+}
+
+pub struct Pca9685Servo<'e, 'a, 'd, T: i2c::Instance, M: i2c::Mode> {
+    expander: &'e Pca9685<'a, 'd, T, M>,
+    channel: usize,
+    scaler: AngleScaler,
+}
+
+impl<'e, 'a, 'd, T: i2c::Instance, M: i2c::Mode> Servo for Pca9685Servo<'e, 'a, 'd, T, M> {
+    fn set_angle(&mut self, angle: Value) -> Result<()> {
+        // Skip the bus transfer if the output wouldn't change.
+        if let Some(micros) = self.scaler.update(angle)? {
+            let off = Value64::from(micros) * EXPANDER_COUNTS / PERIOD_MICROS;
+            if let Err(e) = self.expander.set_off_count(self.channel, off.cast()) {
+                // Retry the write with the next move.
+                self.scaler.invalidate();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+        // A pulse the whole period long would encode as `FULL_OFF` and hold
+        // the output low.
+        limits.check_range(PERIOD_MICROS as u32 - 1)?;
+        self.scaler.set_limits(limits);
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.scaler.limits().clone()
+    }
+
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+        let off = Value64::from(micros) * EXPANDER_COUNTS / PERIOD_MICROS;
+        if off < 0 || off >= EXPANDER_COUNTS {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        // The next angle has to be written even if it matches the last one.
+        self.scaler.invalidate();
+        self.expander.set_off_count(self.channel, off.cast())
+    }
+
+    fn disable_output(&mut self) -> Result<()> {
+        self.scaler.invalidate();
+        self.expander.set_off_count(self.channel, FULL_OFF)
+    }
+}
@@ -0,0 +1,56 @@
+use az::Cast;
+use embassy_rp::gpio::{self, Level, Pin};
+use embassy_rp::pwm::{self, Config, Pwm};
+use embassy_rp::Peripheral;
+use pnpfeeder::{Peeler, Result, Value};
+
+// A peel motor switched by a GPIO, usually through a transistor or relay.
+pub struct GpioPeeler<'d, P: Pin> {
+    output: gpio::Output<'d, P>,
+}
+
+impl<'d, P: Pin> GpioPeeler<'d, P> {
+    pub fn new(output: gpio::Output<'d, P>) -> Self {
+        Self { output }
+    }
+}
+
+impl<'d, P: Pin> Peeler for GpioPeeler<'d, P> {
+    fn set_strength(&mut self, strength: Value) -> Result<()> {
+        self.output.set_level(Level::from(strength > 0));
+        Ok(())
+    }
+}
+
+// A peel motor driven by a PWM output through a motor driver.
+pub struct PwmPeeler<'d, CH: pwm::Channel> {
+    pwm: Pwm<'d, CH>,
+    config: Config,
+}
+
+impl<'d, CH: pwm::Channel> PwmPeeler<'d, CH> {
+    // 125MHz / 6250 = 20kHz, above what N20 motors whine at.
+    const COUNTS_PER_PERIOD: u16 = 6250;
+
+    pub fn new_a(
+        peripheral: impl Peripheral<P = CH> + 'd,
+        pin: impl Peripheral<P = impl pwm::PwmPinA<CH>> + 'd,
+    ) -> Self {
+        let mut config: pwm::Config = Default::default();
+        config.top = Self::COUNTS_PER_PERIOD;
+
+        let pwm = Pwm::new_output_a(peripheral, pin, config.clone());
+
+        Self { pwm, config }
+    }
+}
+
+impl<'d, CH: pwm::Channel> Peeler for PwmPeeler<'d, CH> {
+    fn set_strength(&mut self, strength: Value) -> Result<()> {
+        let counts = strength.clamp(Value::from_num(0), Value::from_num(1))
+            * Value::from_num(Self::COUNTS_PER_PERIOD);
+        self.config.compare_a = counts.cast();
+        self.pwm.set_config(&self.config);
+        Ok(())
+    }
+}
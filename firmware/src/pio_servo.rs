@@ -0,0 +1,166 @@
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::gpio::Level;
+use embassy_rp::pio::{Common, Config, Direction, Instance, LoadedProgram, PioPin, StateMachine};
+use pio::{InstructionOperands, OutDestination};
+use pnpfeeder::{
+    AngleScaler, Error, PwmLimits, Result, Servo, Value, Value64, STANDARD_FRAME_RATE,
+};
+use {defmt_rtt as _, panic_probe as _};
+
+// The pulse program, loaded once into a PIO block and shared by the servos on
+// its state machines.
+pub struct PioServoProgram<'d, PIO: Instance> {
+    program: LoadedProgram<'d, PIO>,
+}
+
+impl<'d, PIO: Instance> PioServoProgram<'d, PIO> {
+    pub fn new(common: &mut Common<'d, PIO>) -> Self {
+        // Each period the pin is driven low, the next pulse width is pulled
+        // into X if one was written, and Y counts down from the period kept in
+        // ISR.  The pin goes high once Y reaches X so the pulse ends the
+        // period and is X loops of three cycles long.
+        let program = pio_proc::pio_asm!(
+            ".side_set 1 opt",
+            "    pull noblock    side 0",
+            "    mov x, osr",
+            "    mov y, isr",
+            "countloop:",
+            "    jmp x!=y noset",
+            "    jmp skip        side 1",
+            "noset:",
+            "    nop",
+            "skip:",
+            "    jmp y-- countloop",
+        );
+        Self {
+            program: common.load_program(&program.program),
+        }
+    }
+}
+
+// A servo driven by a PIO state machine, for pins without a free PWM output.
+// The two PIO blocks add eight servos on any GPIOs.
+pub struct PioServo<'d, PIO: Instance, const SM: usize> {
+    sm: StateMachine<'d, PIO, SM>,
+    scaler: AngleScaler,
+    frame_rate: u32,
+}
+
+impl<'d, PIO: Instance, const SM: usize> PioServo<'d, PIO, SM> {
+    // Never matched by the count so the pin stays low.
+    const OFF: u32 = u32::MAX;
+
+    pub fn new(
+        common: &mut Common<'d, PIO>,
+        mut sm: StateMachine<'d, PIO, SM>,
+        program: &PioServoProgram<'d, PIO>,
+        pin: impl PioPin,
+    ) -> Self {
+        let pin = common.make_pio_pin(pin);
+        sm.set_pins(Level::Low, &[&pin]);
+        sm.set_pin_dirs(Direction::Out, &[&pin]);
+
+        let mut config = Config::default();
+        config.use_program(&program.program, &[&pin]);
+        sm.set_config(&config);
+
+        let mut servo = Self {
+            sm,
+            scaler: AngleScaler::new(PwmLimits::standard()),
+            frame_rate: STANDARD_FRAME_RATE,
+        };
+        servo.load_period();
+        servo.sm.tx().push(Self::OFF);
+        servo.sm.set_enable(true);
+        servo
+    }
+
+    // Loads the period for `frame_rate` into ISR, where the program expects
+    // it.  The state machine has to be stopped.
+    fn load_period(&mut self) {
+        let micros = Value64::from_num(1_000_000) / i64::from(self.frame_rate);
+        self.sm.tx().push(Self::micros_to_loops(micros));
+        unsafe {
+            self.sm.exec_instr(
+                InstructionOperands::PULL {
+                    if_empty: false,
+                    block: false,
+                }
+                .encode(),
+            );
+            self.sm.exec_instr(
+                InstructionOperands::OUT {
+                    destination: OutDestination::ISR,
+                    bit_count: 32,
+                }
+                .encode(),
+            );
+        }
+    }
+
+    fn micros_to_loops(micros: Value64) -> u32 {
+        // Three cycles per loop of the program.
+        (micros * i64::from(clk_sys_freq()) / 3_000_000).to_num()
+    }
+
+    fn set_loops(&mut self, loops: u32) {
+        // The program only pulls once a period.  Only the latest pulse width
+        // matters so anything still queued is dropped rather than waited on.
+        self.sm.clear_fifos();
+        self.sm.tx().push(loops);
+    }
+}
+
+impl<'d, PIO: Instance, const SM: usize> Servo for PioServo<'d, PIO, SM> {
+    fn set_angle(&mut self, angle: Value) -> Result<()> {
+        // Skip rewriting the pulse width if it wouldn't change.
+        if let Some(micros) = self.scaler.update(angle)? {
+            self.set_loops(Self::micros_to_loops(Value64::from(micros)));
+        }
+        Ok(())
+    }
+
+    fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+        // A pulse the whole period long would leave the program no low time
+        // and hold the output high.
+        limits.check_range((1_000_000 / self.frame_rate).saturating_sub(1))?;
+        self.scaler.set_limits(limits);
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.scaler.limits().clone()
+    }
+
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+        if micros < 0 || Value64::from(micros) >= i64::from(1_000_000 / self.frame_rate) {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        self.set_loops(Self::micros_to_loops(Value64::from(micros)));
+        // The next angle has to be written even if it matches the last one.
+        self.scaler.invalidate();
+        Ok(())
+    }
+
+    fn disable_output(&mut self) -> Result<()> {
+        self.set_loops(Self::OFF);
+        self.scaler.invalidate();
+        Ok(())
+    }
+
+    fn set_frame_rate(&mut self, hz: u32) -> Result<()> {
+        if hz == 0 {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        self.scaler
+            .limits()
+            .check_range((1_000_000 / hz).saturating_sub(1))?;
+        self.frame_rate = hz;
+        // X keeps the current pulse width while the period is swapped.
+        self.sm.set_enable(false);
+        self.sm.clear_fifos();
+        self.load_period();
+        self.sm.set_enable(true);
+        Ok(())
+    }
+}
@@ -1,56 +1,179 @@
 use az::Cast;
-use embassy_rp::pwm::{self, Config, Pwm};
-use embassy_rp::Peripheral;
+use embassy_rp::pac;
+use embassy_rp::pwm::{self, Channel as _, Config, Pwm};
+use embassy_rp::{into_ref, Peripheral};
 use fixed::traits::ToFixed;
-use pnpfeeder::{Error, PwmLimits, Result, Servo, Value};
+use pnpfeeder::{
+    AngleScaler, Error, PwmLimits, Result, Servo, Value, Value64, STANDARD_FRAME_RATE,
+};
 use {defmt_rtt as _, panic_probe as _};
 
+// Which of a PWM slice's two outputs a servo is on.
+#[derive(Clone, Copy)]
+enum Output {
+    A,
+    B,
+}
+
 pub struct PwmServo<'d, CH: pwm::Channel> {
-    pwm: Pwm<'d, CH>,
-    config: Config,
-    limits: PwmLimits,
+    // Keeps the slice running and its pins assigned to it.  Servos borrowed
+    // from a `PwmServoPair` rely on the pair's.
+    _pwm: Option<Pwm<'d, CH>>,
+    regs: pac::pwm::Channel,
+    output: Output,
+    scaler: AngleScaler,
+    frame_rate: u32,
+    // Set for both servos of a pair, which share the slice's period.
+    paired: bool,
 }
 
 impl<'d, CH: pwm::Channel> PwmServo<'d, CH> {
+    // At the standard 50Hz.  The divider is fixed so a count is always
+    // 2.04us and faster frame rates shorten the period instead.
     const COUNTS_PER_PERIOD: u16 = 9804;
+    const PERIOD_MICROS: i64 = 20_000;
 
     pub fn new_a(
         peripheral: impl Peripheral<P = CH> + 'd,
         pin: impl Peripheral<P = impl pwm::PwmPinA<CH>> + 'd,
     ) -> Self {
-        let mut config: pwm::Config = Default::default();
+        into_ref!(peripheral);
+        let regs = peripheral.regs();
+        let pwm = Pwm::new_output_a(peripheral, pin, Self::config());
+        Self::from_parts(Some(pwm), regs, Output::A, false)
+    }
+
+    pub fn new_b(
+        peripheral: impl Peripheral<P = CH> + 'd,
+        pin: impl Peripheral<P = impl pwm::PwmPinB<CH>> + 'd,
+    ) -> Self {
+        into_ref!(peripheral);
+        let regs = peripheral.regs();
+        let pwm = Pwm::new_output_b(peripheral, pin, Self::config());
+        Self::from_parts(Some(pwm), regs, Output::B, false)
+    }
+
+    fn config() -> Config {
+        let mut config: Config = Default::default();
         config.divider = 255.to_fixed();
         config.top = Self::COUNTS_PER_PERIOD;
+        config
+    }
 
-        let pwm = Pwm::new_output_a(peripheral, pin, config.clone());
-
-        let counts_per_ms = Value::from_num(Self::COUNTS_PER_PERIOD) / Value::from_num(20.0);
-        let zero = Value::from_num(1.0) * counts_per_ms;
-        let one_eighty = Value::from_num(2.0) * counts_per_ms;
+    fn from_parts(
+        pwm: Option<Pwm<'d, CH>>,
+        regs: pac::pwm::Channel,
+        output: Output,
+        paired: bool,
+    ) -> Self {
         Self {
-            pwm,
-            config,
-            limits: PwmLimits { zero, one_eighty },
+            _pwm: pwm,
+            regs,
+            output,
+            scaler: AngleScaler::new(PwmLimits::standard()),
+            frame_rate: STANDARD_FRAME_RATE,
+            paired,
         }
     }
+
+    fn micros_to_counts(micros: Value) -> Value64 {
+        Value64::from(micros) * i64::from(Self::COUNTS_PER_PERIOD) / Self::PERIOD_MICROS
+    }
+
+    fn set_compare(&mut self, counts: u16) {
+        // Only this servo's half of the compare register is written so the
+        // servo on the slice's other output isn't disturbed.
+        let output = self.output;
+        self.regs.cc().modify(|w| match output {
+            Output::A => w.set_a(counts),
+            Output::B => w.set_b(counts),
+        });
+    }
+}
+
+// Both outputs of a slice, so every slice can drive two feeders.  The pair
+// owns the slice and its servos borrow it, so they can't outlive it.  They
+// share the slice's period so stay at the standard frame rate.
+pub struct PwmServoPair<'d, CH: pwm::Channel> {
+    _pwm: Pwm<'d, CH>,
+    regs: pac::pwm::Channel,
+}
+
+impl<'d, CH: pwm::Channel> PwmServoPair<'d, CH> {
+    pub fn new(
+        peripheral: impl Peripheral<P = CH> + 'd,
+        pin_a: impl Peripheral<P = impl pwm::PwmPinA<CH>> + 'd,
+        pin_b: impl Peripheral<P = impl pwm::PwmPinB<CH>> + 'd,
+    ) -> Self {
+        into_ref!(peripheral);
+        let regs = peripheral.regs();
+        let pwm = Pwm::new_output_ab(peripheral, pin_a, pin_b, PwmServo::<CH>::config());
+        Self { _pwm: pwm, regs }
+    }
+
+    // The servos on outputs A and B.
+    pub fn servos(&mut self) -> (PwmServo<'_, CH>, PwmServo<'_, CH>) {
+        (
+            PwmServo::from_parts(None, self.regs, Output::A, true),
+            PwmServo::from_parts(None, self.regs, Output::B, true),
+        )
+    }
 }
 
 impl<'d, CH: pwm::Channel> Servo for PwmServo<'d, CH> {
     fn set_angle(&mut self, angle: Value) -> Result<()> {
-        self.config.compare_a = self.limits.scale_angle(angle)?.cast();
-        self.pwm.set_config(&self.config);
+        // Skip reconfiguring the PWM if the output wouldn't change.
+        if let Some(micros) = self.scaler.update(angle)? {
+            self.set_compare(Self::micros_to_counts(micros).cast());
+        }
         Ok(())
     }
 
     fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
-        if limits.zero > Self::COUNTS_PER_PERIOD || limits.one_eighty > Self::COUNTS_PER_PERIOD {
+        // A pulse the whole period long would reach the counter's top and
+        // hold the output high.
+        limits.check_range((1_000_000 / self.frame_rate).saturating_sub(1))?;
+        self.scaler.set_limits(limits);
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.scaler.limits().clone()
+    }
+
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+        if micros < 0 || Value64::from(micros) >= i64::from(1_000_000 / self.frame_rate) {
             return Err(Error::PwmValueOutOfRange);
         }
-        self.limits = limits;
+        self.set_compare(Self::micros_to_counts(micros).to_num());
+        // The next angle has to be written even if it matches the last one.
+        self.scaler.invalidate();
         Ok(())
     }
 
-    fn get_pwm_limits(&self) -> PwmLimits {
-        self.limits.clone()
+    fn disable_output(&mut self) -> Result<()> {
+        self.set_compare(0);
+        self.scaler.invalidate();
+        Ok(())
+    }
+
+    // Both servos of a pair share the slice's period so changing one's frame
+    // rate would change the other's too.
+    fn set_frame_rate(&mut self, hz: u32) -> Result<()> {
+        if self.paired && hz != self.frame_rate {
+            return Err(Error::SharedFrameRate);
+        }
+        let counts = u32::from(Self::COUNTS_PER_PERIOD) * STANDARD_FRAME_RATE;
+        // Slower rates have more counts per period than the counter can hold.
+        let counts_per_period = counts
+            .checked_div(hz)
+            .and_then(|counts| u16::try_from(counts).ok())
+            .ok_or(Error::PwmValueOutOfRange)?;
+        self.scaler
+            .limits()
+            .check_range((1_000_000 / hz).saturating_sub(1))?;
+        self.regs.top().write(|w| w.set_top(counts_per_period));
+        self.frame_rate = hz;
+        Ok(())
     }
 }
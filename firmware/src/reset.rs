@@ -0,0 +1,7 @@
+use cortex_m::peripheral::SCB;
+
+// Restarts the RP2040 through the Cortex-M system reset request, which resets
+// the core and peripherals the same way the watchdog does.
+pub fn reset() -> ! {
+    SCB::sys_reset()
+}
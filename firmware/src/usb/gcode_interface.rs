@@ -1,13 +1,19 @@
+use core::fmt::Write as _;
+
 use defmt::info;
-use embassy_futures::select::{select3, Either3};
+use embassy_futures::select::{select, select3, Either, Either3};
 use embassy_rp::usb::{Driver, Instance};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{with_timeout, Duration};
 use embassy_usb::{
     class::cdc_acm::{self, CdcAcmClass},
     driver::EndpointError,
 };
 use embedded_io_async::Read;
-use heapless::Vec;
-use pnpfeeder::{Error, GCodeEvent, GCodeEventSender, Line, Result};
+use heapless::{String, Vec};
+use pnpfeeder::{
+    Error, GCodeEvent, GCodeEventSender, LineChecker, ResponseChecksum, Result, TransportStats,
+};
 
 struct CharAssembler {
     buf: [u8; 4],
@@ -27,12 +33,22 @@ impl CharAssembler {
         self.buf[self.len] = b;
         self.len += 1;
 
+        // Stray continuation bytes and other invalid lead bytes are dropped
+        // rather than being allowed to overflow `self.buf`.
+        let width = core::str::utf8_char_width(self.buf[0]);
+        if width == 0 {
+            self.len = 0;
+            return None;
+        }
+
         // Only proceed if we have the correct number of bytes for a character.
-        if self.len != core::str::utf8_char_width(self.buf[0]) {
+        if self.len != width {
             return None;
         }
 
-        let ret = char::from_u32(u32::from_le_bytes(self.buf));
+        let ret = core::str::from_utf8(&self.buf[..self.len])
+            .ok()
+            .and_then(|s| s.chars().next());
 
         // Reset the internal buffer regardless of the character's validity.
         self.len = 0;
@@ -42,11 +58,21 @@ impl CharAssembler {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum Comment {
+    None,
+    // Inside a `(...)` comment.
+    Paren,
+    // Inside a `;` comment which runs to the end of the line.
+    Line,
+}
+
 struct LineReader<const N: usize> {
     char_assembler: CharAssembler,
     input_buffer: Vec<u8, N>,
     in_overflow: bool,
     new_line: bool,
+    comment: Comment,
 }
 
 impl<const N: usize> LineReader<N> {
@@ -56,6 +82,7 @@ impl<const N: usize> LineReader<N> {
             input_buffer: Vec::new(),
             in_overflow: false,
             new_line: false,
+            comment: Comment::None,
         }
     }
 
@@ -64,6 +91,7 @@ impl<const N: usize> LineReader<N> {
         if self.new_line {
             self.input_buffer.clear();
             self.new_line = false;
+            self.comment = Comment::None;
         }
 
         // wait for a valid unicode char.
@@ -80,6 +108,7 @@ impl<const N: usize> LineReader<N> {
             // Otherwise record the overflow and reset the buffer length and overflow state
             self.in_overflow = false;
             self.input_buffer.clear();
+            self.comment = Comment::None;
             Err(Error::InputBufferOverflow)
         } else if Self::is_newline(c) {
             // If we're not in overflow and have a newline, return the line.
@@ -87,7 +116,11 @@ impl<const N: usize> LineReader<N> {
             // Safety: We only write valid utf8 to self.buf.
             let s = unsafe { core::str::from_utf8_unchecked(self.input_buffer.as_slice()) };
             self.new_line = true;
-            Ok(Some(s))
+            Ok(Some(s.trim()))
+        } else if self.skip_comment(c) {
+            // Comments are dropped as they arrive so they don't count against the
+            // buffer size.
+            Ok(None)
         } else {
             let mut encode_buf = [0u8; 4];
             let encoded = c.encode_utf8(&mut encode_buf).as_bytes();
@@ -101,11 +134,27 @@ impl<const N: usize> LineReader<N> {
         }
     }
 
+    // Tracks `;` and `(...)` comments, returning true if `c` is part of one.
+    fn skip_comment(&mut self, c: char) -> bool {
+        match (self.comment, c) {
+            (Comment::None, ';') => self.comment = Comment::Line,
+            (Comment::None, '(') => self.comment = Comment::Paren,
+            (Comment::None, _) => return false,
+            (Comment::Paren, ')') => self.comment = Comment::None,
+            (Comment::Paren, _) | (Comment::Line, _) => {}
+        }
+        true
+    }
+
     fn is_newline(c: char) -> bool {
         c == '\n' || c == '\r'
     }
 }
 
+// Longest a packet write may wait for the host before it is dropped.  Keeps a
+// host which has stopped reading from wedging input handling.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+
 fn to_error(val: EndpointError) -> Error {
     match val {
         EndpointError::BufferOverflow => panic!("Buffer overflow"),
@@ -119,22 +168,30 @@ pub struct GCodeInterface<
     const GCODE_CHANNEL_LEN: usize,
     OutputReader: Read,
     T: Instance + 'd,
+    R: RawMutex,
 > {
     cdc_sender: cdc_acm::Sender<'d, Driver<'d, T>>,
     cdc_receiver: cdc_acm::Receiver<'d, Driver<'d, T>>,
     cdc_control_changed: cdc_acm::ControlChanged<'d>,
     output_reader: OutputReader,
-    event_sender: GCodeEventSender<'g, GCODE_CHANNEL_LEN>,
+    event_sender: GCodeEventSender<'g, GCODE_CHANNEL_LEN, R>,
+    stats: &'g TransportStats,
     connected: bool,
+    line_checker: LineChecker,
+    response_checksums: bool,
+    response_checksum: ResponseChecksum,
+    // Set when the last handler output forwarded to the host ended a line.
+    output_line_start: bool,
 }
 
-impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + 'd>
-    GCodeInterface<'d, 'g, GCODE_CHANNEL_LEN, OutputReader, T>
+impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + 'd, R: RawMutex>
+    GCodeInterface<'d, 'g, GCODE_CHANNEL_LEN, OutputReader, T, R>
 {
     pub fn new(
         class: CdcAcmClass<'d, Driver<'d, T>>,
         output_reader: OutputReader,
-        event_sender: GCodeEventSender<'g, GCODE_CHANNEL_LEN>,
+        event_sender: GCodeEventSender<'g, GCODE_CHANNEL_LEN, R>,
+        stats: &'g TransportStats,
     ) -> Self {
         let (cdc_sender, cdc_receiver, cdc_control_changed) = class.split_with_control();
         Self {
@@ -143,10 +200,22 @@ impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + '
             cdc_control_changed,
             output_reader,
             event_sender,
+            stats,
             connected: false,
+            line_checker: LineChecker::new(),
+            response_checksums: false,
+            response_checksum: ResponseChecksum::new(),
+            output_line_start: true,
         }
     }
 
+    // Appends a checksum to every response line for links where output may
+    // be corrupted.
+    pub fn with_response_checksums(mut self, enabled: bool) -> Self {
+        self.response_checksums = enabled;
+        self
+    }
+
     pub async fn run(&mut self) {
         loop {
             info!("Waiting for connection");
@@ -160,7 +229,10 @@ impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + '
     async fn handle_connection(&mut self) -> Result<()> {
         let mut usb_buf = [0; 64];
         let mut output_buf = [0; 64];
-        let mut line_reader = LineReader::<64>::new();
+        let mut line_reader = LineReader::<128>::new();
+        self.line_checker = LineChecker::new();
+        self.response_checksum = ResponseChecksum::new();
+        self.output_line_start = true;
         loop {
             match select3(
                 self.output_reader.read(&mut output_buf),
@@ -171,7 +243,7 @@ impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + '
             {
                 Either3::First(read_len) => {
                     let read_len = read_len.map_err(|_| Error::Io)?;
-                    self.write(&output_buf[..read_len]).await?;
+                    self.forward_output(&output_buf[..read_len]).await?;
                 }
                 Either3::Second(()) => {
                     let new_connected = self.cdc_receiver.dtr();
@@ -184,11 +256,15 @@ impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + '
                 }
                 Either3::Third(read_len) => {
                     let read_len = read_len.map_err(to_error)?;
+                    self.stats.rx_bytes.add(read_len as u32);
                     // Echo input back to the connection.
                     self.write(&usb_buf[..read_len]).await?;
 
                     for b in &usb_buf[..read_len] {
-                        if let Some(line) = line_reader.handle_byte(*b)? {
+                        let line = line_reader
+                            .handle_byte(*b)
+                            .inspect_err(|_| self.stats.overflows.increment())?;
+                        if let Some(line) = line {
                             // Echo a new line incase were just send a '\r'.  Having a
                             // real line editor would make things nicer here.
                             self.write(b"\n").await?;
@@ -201,14 +277,143 @@ impl<'d, 'g, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, T: Instance + '
     }
 
     async fn handle_line(&mut self, line: &str) -> Result<()> {
-        match line.parse::<Line>() {
-            Ok(command) => self.event_sender.send(GCodeEvent::Line(command)).await,
-            Err(_e) => self.write(b"error parsing gcode").await?,
+        // Blank and comment only lines are ignored.
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        self.stats.lines.increment();
+        let line = match self.line_checker.check(line) {
+            Ok(line) => line,
+            Err(e) => {
+                // Ask the host to resend from the first line which wasn't
+                // accepted.
+                self.stats.resends.increment();
+                let mut s = String::<64>::new();
+                writeln!(s, "error:{} {}", e.code(), e).ok();
+                self.write_line(s.as_bytes()).await?;
+                s.clear();
+                writeln!(s, "Resend: {}", self.line_checker.resend_line()).ok();
+                return self.write_line(s.as_bytes()).await;
+            }
+        };
+
+        let event = match GCodeEvent::parse(line) {
+            Ok(event) => event,
+            Err(e) => {
+                self.stats.parse_errors.increment();
+                let mut s = String::<64>::new();
+                writeln!(s, "error:{} {}", e.code(), e).ok();
+                return self.write_line(s.as_bytes()).await;
+            }
+        };
+        self.send_event(event).await
+    }
+
+    // Queues an event for the handler.  When the queue is full the host is
+    // sent `wait`, as GRBL does, so a streaming host can hold off instead of
+    // stalling on a read loop which has silently stopped.  Responses are
+    // still forwarded while waiting so the handler can work through the
+    // queue.
+    async fn send_event(&mut self, event: GCodeEvent) -> Result<()> {
+        let Err(mut event) = self.event_sender.try_send(event) else {
+            return Ok(());
+        };
+        let mut output_buf = [0; 64];
+        loop {
+            self.stats.queue_waits.increment();
+            self.write_line(b"wait\n").await?;
+            loop {
+                match select(
+                    self.event_sender.ready_to_send(&event),
+                    self.output_reader.read(&mut output_buf),
+                )
+                .await
+                {
+                    Either::First(()) => break,
+                    Either::Second(read_len) => {
+                        let read_len = read_len.map_err(|_| Error::Io)?;
+                        self.forward_output(&output_buf[..read_len]).await?;
+                    }
+                }
+            }
+            // The handler is the only receiver so there is room now, but try
+            // again rather than block if not.
+            match self.event_sender.try_send(event) {
+                Ok(()) => return Ok(()),
+                Err(unsent) => event = unsent,
+            }
+        }
+    }
+
+    // Forwards output from the handler to the host.
+    async fn forward_output(&mut self, output: &[u8]) -> Result<()> {
+        let Some(&last) = output.last() else {
+            return Ok(());
+        };
+        self.output_line_start = last == b'\n';
+        self.write_response(output).await
+    }
+
+    // Writes a response line of the interface's own.  The handler writes
+    // its responses in pieces which may be read at any point, so its output
+    // is forwarded up to the end of the line in progress first rather than
+    // having `line` land in the middle of it.
+    async fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        let mut output_buf = [0; 64];
+        while !self.output_line_start {
+            let read_len = self
+                .output_reader
+                .read(&mut output_buf)
+                .await
+                .map_err(|_| Error::Io)?;
+            let output = &output_buf[..read_len];
+            if let Some(end) = output.iter().position(|&b| b == b'\n') {
+                self.forward_output(&output[..=end]).await?;
+                self.write_response(line).await?;
+                return self.forward_output(&output[end + 1..]).await;
+            }
+            self.forward_output(output).await?;
+        }
+        self.write_response(line).await
+    }
+
+    // Writes response output, adding checksums to each line if enabled.
+    // Input echo is written with `write()` and is never checksummed.
+    async fn write_response(&mut self, buffer: &[u8]) -> Result<()> {
+        if !self.response_checksums {
+            return self.write(buffer).await;
+        }
+
+        let mut packet = Vec::<u8, 64>::new();
+        for &b in buffer {
+            let suffix = self.response_checksum.update(b);
+            let bytes = suffix.as_ref().map_or(&[][..], |s| s.as_bytes());
+            for &b in bytes.iter().chain(core::iter::once(&b)) {
+                if packet.is_full() {
+                    self.write(&packet).await?;
+                    packet.clear();
+                }
+                // There is room since the packet was just flushed if full.
+                let _ = packet.push(b);
+            }
+        }
+        if !packet.is_empty() {
+            self.write(&packet).await?;
         }
         Ok(())
     }
 
     async fn write(&mut self, buffer: &[u8]) -> Result<()> {
-        self.cdc_sender.write_packet(buffer).await.map_err(to_error)
+        match with_timeout(WRITE_TIMEOUT, self.cdc_sender.write_packet(buffer)).await {
+            Ok(result) => {
+                self.stats.tx_bytes.add(buffer.len() as u32);
+                result.map_err(to_error)
+            }
+            Err(_) => {
+                self.stats.write_timeouts.increment();
+                Ok(())
+            }
+        }
     }
 }
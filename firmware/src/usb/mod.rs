@@ -3,33 +3,46 @@ use embassy_futures::join::join;
 use embassy_rp::interrupt::typelevel::Binding;
 use embassy_rp::usb::{Driver, Instance, InterruptHandler};
 use embassy_rp::Peripheral;
+use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_usb::class::cdc_acm::{self, CdcAcmClass};
 use embassy_usb::{Builder, Config};
 use embedded_io_async::Read;
 use heapless::{String, Vec};
-use pnpfeeder::GCodeEventSender;
+use pnpfeeder::{GCodeEventSender, TransportStats};
 
 mod gcode_interface;
+#[cfg(feature = "picotool")]
 mod picotool;
 
-pub struct Usb<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read> {
+pub struct Usb<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, R: RawMutex> {
     gcode_output_reader: OutputReader,
-    gcode_event_sender: GCodeEventSender<'a, GCODE_CHANNEL_LEN>,
+    gcode_event_sender: GCodeEventSender<'a, GCODE_CHANNEL_LEN, R>,
+    stats: &'a TransportStats,
+    response_checksums: bool,
 }
 
-impl<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read>
-    Usb<'a, GCODE_CHANNEL_LEN, OutputReader>
+impl<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read, R: RawMutex>
+    Usb<'a, GCODE_CHANNEL_LEN, OutputReader, R>
 {
     pub fn new(
         cdc_output_reader: OutputReader,
-        gcode_event_sender: GCodeEventSender<'a, GCODE_CHANNEL_LEN>,
+        gcode_event_sender: GCodeEventSender<'a, GCODE_CHANNEL_LEN, R>,
+        stats: &'a TransportStats,
     ) -> Self {
         Self {
             gcode_output_reader: cdc_output_reader,
             gcode_event_sender,
+            stats,
+            response_checksums: false,
         }
     }
 
+    // Appends a checksum to every G-code response line.
+    pub fn with_response_checksums(mut self, enabled: bool) -> Self {
+        self.response_checksums = enabled;
+        self
+    }
+
     pub async fn run<'d, T: Instance>(
         self,
         usb_peripheral: impl Peripheral<P = T> + 'd,
@@ -63,6 +76,7 @@ impl<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read>
         let mut control_buf = [0; 64];
 
         let mut cdc_acm_state = cdc_acm::State::new();
+        #[cfg(feature = "picotool")]
         let mut picotool_state = picotool::State::new();
 
         let mut builder = Builder::new(
@@ -77,6 +91,7 @@ impl<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read>
 
         // Start building the USB device
         let cdc_acm_class = CdcAcmClass::new(&mut builder, &mut cdc_acm_state, 64);
+        #[cfg(feature = "picotool")]
         let mut _picotool_class = picotool::PicotoolClass::new(&mut builder, &mut picotool_state);
 
         // Finish building USB device.
@@ -86,7 +101,9 @@ impl<'a, const GCODE_CHANNEL_LEN: usize, OutputReader: Read>
             cdc_acm_class,
             self.gcode_output_reader,
             self.gcode_event_sender,
-        );
+            self.stats,
+        )
+        .with_response_checksums(self.response_checksums);
 
         let usb_future = usb.run();
         let gcode_future = gcode.run();
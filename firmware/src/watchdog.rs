@@ -0,0 +1,82 @@
+use core::cell::Cell;
+
+use defmt::warn;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Timer};
+use pnpfeeder::ConfigStore;
+
+// Long enough to ride out a flash erase.  Panics halt the core so they end in
+// a watchdog reset too.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(2);
+
+const FEED_INTERVAL: Duration = Duration::from_millis(500);
+
+// Twice per feed so a late beat doesn't cost a feed.
+const BEAT_INTERVAL: Duration = Duration::from_millis(250);
+
+// Updates the stored count of crash resets in a row and returns it.  A
+// watchdog reset adds one and any other reset, such as a power cycle or M999,
+// clears it.
+pub fn count_crash_resets(watchdog: &Watchdog, store: &mut impl ConfigStore) -> u32 {
+    let stored = store.get_crash_count().unwrap_or(0);
+    let count = match watchdog.reset_reason() {
+        Some(_) => stored.saturating_add(1),
+        None => 0,
+    };
+    if count > 0 {
+        warn!("watchdog reset, {} in a row", count);
+    }
+    // Clean boots are the common case so skip the flash write.
+    if count != stored {
+        store.set_crash_count(count).ok();
+    }
+    count
+}
+
+// Starts the watchdog and feeds it for as long as the executor keeps running
+// tasks, and `heartbeat`, if given, keeps beating.  A wedged core then ends in
+// a reset rather than leaving the board unresponsive.
+pub async fn feed(mut watchdog: Watchdog, heartbeat: Option<&Heartbeat>) -> ! {
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT);
+    loop {
+        if heartbeat.map_or(true, Heartbeat::take) {
+            watchdog.feed();
+        }
+        Timer::after(FEED_INTERVAL).await;
+    }
+}
+
+// Shows that another core, which doesn't feed the watchdog itself, is still
+// running its tasks.
+pub struct Heartbeat {
+    beat: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+}
+
+impl Heartbeat {
+    pub const fn new() -> Self {
+        Self {
+            beat: Mutex::new(Cell::new(false)),
+        }
+    }
+
+    // Beats for as long as the calling core's executor keeps running tasks.
+    pub async fn run(&self) -> ! {
+        loop {
+            self.beat.lock(|beat| beat.set(true));
+            Timer::after(BEAT_INTERVAL).await;
+        }
+    }
+
+    // Whether there was a beat since the last call.
+    fn take(&self) -> bool {
+        self.beat.lock(|beat| beat.replace(false))
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,401 @@
+#![no_main]
+
+// Drives a `GCodeHandler` and two fake feeders with an arbitrary stream of
+// G-code lines, connect/disconnect events, and feedback edges.  Checks that
+// nothing panics, that the handler keeps making progress, and that every line
+// is answered with exactly one `ok` or `error:` response.
+
+use std::{cell::RefCell, collections::HashMap, convert::Infallible};
+
+use embassy_futures::{
+    join::{join, join_array},
+    select::{select3, Either3},
+    yield_now,
+};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, MockDriver, Timer};
+use futures_executor::block_on;
+use libfuzzer_sys::fuzz_target;
+use pnpfeeder::{
+    BoardConfig, CartridgeId, ConfigStore, Error, FeedCounters, Feeder, FeederChannel, FeederClient, FeederConfig, GCodeEvent,
+    GCodeEventChannel, GCodeEventSender, GCodeHandler, Input, Line, PwmLimits, Result, Servo,
+    Value,
+};
+
+// Commands the handler implements.  Other codes are generated too but less
+// often.
+const COMMANDS: [(char, u32); 47] = [
+    ('G', 28),
+    ('G', 4),
+    ('M', 110),
+    ('M', 112),
+    ('M', 115),
+    ('M', 154),
+    ('M', 280),
+    ('M', 400),
+    ('M', 410),
+    ('M', 500),
+    ('M', 501),
+    ('M', 502),
+    ('M', 600),
+    ('M', 601),
+    ('M', 602),
+    ('M', 603),
+    ('M', 604),
+    ('M', 608),
+    ('M', 610),
+    ('M', 611),
+    ('M', 612),
+    ('M', 619),
+    ('M', 620),
+    ('M', 621),
+    ('M', 622),
+    ('M', 623),
+    ('M', 624),
+    ('M', 625),
+    ('M', 626),
+    ('M', 627),
+    ('M', 628),
+    ('M', 629),
+    ('M', 630),
+    ('M', 631),
+    ('M', 632),
+    ('M', 633),
+    ('M', 640),
+    ('M', 641),
+    ('M', 650),
+    ('M', 660),
+    ('M', 661),
+    ('M', 670),
+    ('M', 671),
+    ('M', 680),
+    ('M', 681),
+    ('M', 997),
+    ('M', 999),
+];
+
+// Longest the handler may go without answering an outstanding line.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(600);
+
+enum Op {
+    Connect,
+    Disconnect,
+    Line(Line),
+    Feedback(usize, bool),
+    Wait(u64),
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn next(&mut self) -> Option<u8> {
+        let (first, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(*first)
+    }
+
+    fn next_i32(&mut self) -> Option<i32> {
+        let mut bytes = [0u8; 4];
+        for byte in bytes.iter_mut() {
+            *byte = self.next()?;
+        }
+        Some(i32::from_le_bytes(bytes))
+    }
+
+    fn next_value(&mut self) -> Option<Value> {
+        // Mostly small integers so arguments land in the interesting ranges.
+        let kind = self.next()?;
+        if kind & 1 == 0 {
+            Some(Value::from_num(self.next()? as i8))
+        } else {
+            Some(Value::from_bits(self.next_i32()?))
+        }
+    }
+
+    fn next_line(&mut self) -> Option<String> {
+        let selector = self.next()? as usize;
+        let (letter, code) = match COMMANDS.get(selector % (COMMANDS.len() + 1)) {
+            Some(command) => *command,
+            None => ('M', self.next()? as u32 * 4),
+        };
+        let mut line = format!("{letter}{code}");
+        for _ in 0..self.next()? % 5 {
+            let arg = (b'A' + self.next()? % 26) as char;
+            line += &format!(" {arg}{}", self.next_value()?);
+        }
+        Some(line)
+    }
+
+    fn next_op(&mut self) -> Option<Op> {
+        let op = match self.next()? % 8 {
+            0 => Op::Connect,
+            1 => Op::Disconnect,
+            6 => {
+                let edge = self.next()?;
+                Op::Feedback(edge as usize & 1, edge & 2 != 0)
+            }
+            7 => Op::Wait(self.next()? as u64 * 4),
+            _ => match self.next_line()?.parse() {
+                Ok(line) => Op::Line(line),
+                Err(_) => Op::Wait(0),
+            },
+        };
+        Some(op)
+    }
+}
+
+struct NullServo {
+    limits: PwmLimits,
+}
+
+impl Servo for NullServo {
+    fn set_angle(&mut self, angle: Value) -> Result<()> {
+        self.limits.scale_angle(angle).map(|_| ())
+    }
+
+    fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+        self.limits = limits;
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.limits.clone()
+    }
+
+    fn set_pulse_width(&mut self, _micros: Value) -> Result<()> {
+        Ok(())
+    }
+}
+
+type InputChannel = Channel<NoopRawMutex, bool, 4>;
+
+struct ChannelInput<'a> {
+    channel: &'a InputChannel,
+    state: bool,
+}
+
+impl<'a> ChannelInput<'a> {
+    fn poll_state(&mut self) -> bool {
+        while let Ok(state) = self.channel.try_receive() {
+            self.state = state;
+        }
+        self.state
+    }
+
+    async fn wait_for_state(&mut self, state: bool) {
+        while self.poll_state() != state {
+            self.wait_for_state_change().await;
+        }
+    }
+}
+
+impl<'a> Input for ChannelInput<'a> {
+    async fn wait_for_high(&mut self) {
+        self.wait_for_state(true).await
+    }
+
+    async fn wait_for_low(&mut self) {
+        self.wait_for_state(false).await
+    }
+
+    async fn wait_for_state_change(&mut self) {
+        self.state = self.channel.receive().await;
+    }
+
+    async fn get_state(&mut self) -> bool {
+        self.poll_state()
+    }
+}
+
+#[derive(Default)]
+struct MemoryConfigStore {
+    configs: HashMap<usize, FeederConfig>,
+    slots: HashMap<usize, usize>,
+    cartridges: HashMap<usize, CartridgeId>,
+    counters: HashMap<usize, FeedCounters>,
+    board: BoardConfig,
+    advance_markers: HashMap<usize, bool>,
+    crash_count: u32,
+}
+
+impl ConfigStore for MemoryConfigStore {
+    fn get(&mut self, index: usize) -> Result<FeederConfig> {
+        Ok(self.configs.get(&index).cloned().unwrap_or_default())
+    }
+
+    fn get_default(&self, _index: usize) -> FeederConfig {
+        FeederConfig::default()
+    }
+
+    fn set(&mut self, index: usize, config: &FeederConfig) -> Result<()> {
+        self.configs.insert(index, config.clone());
+        Ok(())
+    }
+
+    fn get_slot(&mut self, index: usize) -> Result<usize> {
+        Ok(self.slots.get(&index).copied().unwrap_or(index))
+    }
+
+    fn set_slot(&mut self, index: usize, slot: usize) -> Result<()> {
+        self.slots.insert(index, slot);
+        Ok(())
+    }
+
+    fn get_cartridge(&mut self, index: usize) -> Result<CartridgeId> {
+        Ok(self.cartridges.get(&index).cloned().unwrap_or_default())
+    }
+
+    fn set_cartridge(&mut self, index: usize, cartridge: &str) -> Result<()> {
+        let cartridge = cartridge.try_into().map_err(|_| Error::InvalidCartridgeId)?;
+        self.cartridges.insert(index, cartridge);
+        Ok(())
+    }
+
+    fn get_counters(&mut self, slot: usize) -> Result<FeedCounters> {
+        Ok(self.counters.get(&slot).copied().unwrap_or_default())
+    }
+
+    fn set_counters(&mut self, slot: usize, counters: &FeedCounters) -> Result<()> {
+        self.counters.insert(slot, *counters);
+        Ok(())
+    }
+
+    fn get_board_config(&mut self) -> Result<BoardConfig> {
+        Ok(self.board.clone())
+    }
+
+    fn set_board_config(&mut self, config: &BoardConfig) -> Result<()> {
+        self.board = config.clone();
+        Ok(())
+    }
+
+    fn get_advance_marker(&mut self, slot: usize) -> Result<bool> {
+        Ok(self.advance_markers.get(&slot).copied().unwrap_or(false))
+    }
+
+    fn set_advance_marker(&mut self, slot: usize, in_progress: bool) -> Result<()> {
+        self.advance_markers.insert(slot, in_progress);
+        Ok(())
+    }
+
+    fn get_crash_count(&mut self) -> Result<u32> {
+        Ok(self.crash_count)
+    }
+
+    fn set_crash_count(&mut self, count: u32) -> Result<()> {
+        self.crash_count = count;
+        Ok(())
+    }
+}
+
+struct SharedOutput<'a>(&'a RefCell<Vec<u8>>);
+
+impl<'a> embedded_io_async::ErrorType for SharedOutput<'a> {
+    type Error = Infallible;
+}
+
+impl<'a> embedded_io_async::Write for SharedOutput<'a> {
+    async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Infallible> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+fn count_responses(output: &RefCell<Vec<u8>>) -> usize {
+    String::from_utf8_lossy(&output.borrow())
+        .lines()
+        .filter(|line| *line == "ok" || line.starts_with("error:"))
+        .count()
+}
+
+// Advances mock time whenever the tasks have had a chance to run.
+async fn clock() -> ! {
+    loop {
+        for _ in 0..4 {
+            yield_now().await;
+        }
+        MockDriver::get().advance(Duration::from_millis(1));
+    }
+}
+
+async fn drive(
+    ops: Vec<Op>,
+    sender: GCodeEventSender<'_, 2>,
+    inputs: &[InputChannel; 2],
+    output: &RefCell<Vec<u8>>,
+) {
+    let mut lines_sent = 0;
+    for op in ops {
+        match op {
+            Op::Connect => sender.send(GCodeEvent::Connect).await,
+            Op::Disconnect => sender.send(GCodeEvent::Disconnect).await,
+            Op::Line(line) => {
+                sender.send(GCodeEvent::Line(line)).await;
+                lines_sent += 1;
+            }
+            Op::Feedback(index, state) => inputs[index].send(state).await,
+            Op::Wait(ms) => Timer::after(Duration::from_millis(ms)).await,
+        }
+        assert!(count_responses(output) <= lines_sent, "unsolicited response");
+    }
+
+    let mut responses = count_responses(output);
+    let mut last_progress = Instant::now();
+    while responses < lines_sent {
+        Timer::after(Duration::from_millis(10)).await;
+        let new_responses = count_responses(output);
+        if new_responses != responses {
+            responses = new_responses;
+            last_progress = Instant::now();
+        }
+        assert!(
+            last_progress.elapsed() < RESPONSE_TIMEOUT,
+            "handler stopped responding"
+        );
+    }
+    assert_eq!(responses, lines_sent, "unsolicited response");
+}
+
+async fn run(ops: Vec<Op>) {
+    let inputs = [InputChannel::new(), InputChannel::new()];
+    let channels = [&FeederChannel::new(), &FeederChannel::new()];
+    let gcode_channel = GCodeEventChannel::<2>::new();
+    let output = RefCell::new(Vec::new());
+
+    let new_feeder = |input| {
+        let servo = NullServo {
+            limits: PwmLimits::standard(),
+        };
+        Feeder::new(servo, ChannelInput { channel: input, state: false })
+    };
+    let mut feeder_0 = new_feeder(&inputs[0]);
+    let mut feeder_1 = new_feeder(&inputs[1]);
+    // Small enough that captures wrap.
+    let mut capture_buffer = [0; 64];
+    let mut handler = GCodeHandler::new(
+        [
+            FeederClient::new(channels[0]),
+            FeederClient::new(channels[1]),
+        ],
+        SharedOutput(&output),
+        MemoryConfigStore::default(),
+    )
+    .with_session_capture(&mut capture_buffer);
+
+    let system = join(
+        join_array([feeder_0.run(channels[0]), feeder_1.run(channels[1])]),
+        handler.run(gcode_channel.receiver()),
+    );
+    let driver = drive(ops, gcode_channel.sender(), &inputs, &output);
+    match select3(system, driver, clock()).await {
+        Either3::First(_) => panic!("handler exited"),
+        Either3::Second(()) => {}
+        Either3::Third(never) => never,
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut bytes = Bytes(data);
+    let ops = core::iter::from_fn(|| bytes.next_op()).collect();
+    block_on(run(ops));
+});
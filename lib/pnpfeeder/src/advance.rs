@@ -0,0 +1,519 @@
+// Commands which move feeders: advances, homing, dwells and direct servo
+// moves, along with enabling feeders and stopping them.
+
+use az::Cast;
+use core::fmt::Write as _;
+use core::future::pending;
+use embassy_futures::select::{select3, select_array, Either3};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+
+use crate::{
+    index_arg, ConfigStore, DeferredAdvance, Error, FeedLength, GCodeHandler, Line, PendingReply,
+    Result, ServoPosition, Value, Value64, BUSY_INTERVAL, MAX_DEFERRED_ADVANCES, MAX_FEEDERS,
+};
+
+impl<'a, W: Write, C: ConfigStore, R: RawMutex> GCodeHandler<'a, W, C, R> {
+    pub(crate) async fn handle_m600(&mut self, command: Line) -> Result<()> {
+        let mut indices: Vec<usize, MAX_FEEDERS> = Vec::new();
+        let mut feed_length = FeedLength::Default;
+        let mut override_error = false;
+
+        for arg in command.arguments() {
+            match arg.letter {
+                // Repeated to advance a group of feeders together.
+                'N' => {
+                    let index = index_arg(arg)?;
+                    if indices.contains(&index) {
+                        return Err(Error::InvalidArgument(arg.letter));
+                    }
+                    // More feeders than there are means one doesn't exist.
+                    indices
+                        .push(index)
+                        .map_err(|_| Error::InvalidIndex(index))?;
+                }
+                'F' => feed_length = FeedLength::Millimeters(arg.value.cast()),
+                // Number of parts, converted by the feeder using its pitch.
+                'C' => {
+                    let parts = arg
+                        .value
+                        .checked_to_num()
+                        .ok_or(Error::InvalidArgument(arg.letter))?;
+                    feed_length = FeedLength::Parts(parts);
+                }
+                'X' => override_error = arg.value != 0,
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index = match indices[..] {
+            [] => return Err(Error::NoIndex),
+            [index] => index,
+            _ => {
+                return self
+                    .run_group_advance(&indices, feed_length, override_error)
+                    .await
+            }
+        };
+        let (slot, feeder) = self.resolve_feeder(Some(index))?;
+        let mut feeder = *feeder;
+
+        if self.board.defer_while_disabled && !feeder.get_status().await?.enabled {
+            let advance = DeferredAdvance {
+                index,
+                feed_length,
+                override_error,
+            };
+            let mut s: String<64> = String::new();
+            writeln!(s, "deferred: {advance}").ok();
+            self.deferred_advances
+                .push(advance)
+                .map_err(|_| Error::TooManyDeferredAdvances)?;
+            self.write_output(s.as_bytes()).await;
+            return Ok(());
+        }
+
+        self.run_advance(slot, feed_length, override_error)
+            .await
+            .map_err(|e| e.for_feeder(index))
+    }
+
+    // Advances the feeders at `indices` at the same time.  Each feeder's
+    // result is waited for, so the response only follows the last of them,
+    // and any failures are output before an error naming the failed feeders.
+    async fn run_group_advance(
+        &mut self,
+        indices: &[usize],
+        feed_length: FeedLength,
+        override_error: bool,
+    ) -> Result<()> {
+        let mut slots: Vec<usize, MAX_FEEDERS> = Vec::new();
+        for &index in indices {
+            // Failures are reported as a bit mask.
+            if index >= u32::BITS as usize {
+                return Err(Error::InvalidIndex(index));
+            }
+            let (slot, _) = self.resolve_feeder(Some(index))?;
+            slots.push(slot).ok();
+        }
+
+        if self.board.defer_while_disabled {
+            let mut disabled = false;
+            for &slot in &slots {
+                disabled |= !self.feeders[slot].get_status().await?.enabled;
+            }
+            // The group is deferred as a whole, one advance per feeder.
+            if disabled {
+                if self.deferred_advances.len() + indices.len() > MAX_DEFERRED_ADVANCES {
+                    return Err(Error::TooManyDeferredAdvances);
+                }
+                for &index in indices {
+                    let advance = DeferredAdvance {
+                        index,
+                        feed_length,
+                        override_error,
+                    };
+                    let mut s: String<64> = String::new();
+                    writeln!(s, "deferred: {advance}").ok();
+                    self.deferred_advances.push(advance).ok();
+                    self.write_output(s.as_bytes()).await;
+                }
+                return Ok(());
+            }
+        }
+
+        let mut pending = [None; MAX_FEEDERS];
+        for &slot in &slots {
+            self.set_advance_marker(slot, true);
+            let mut feeder = self.feeders[slot];
+            pending[slot] = Some(feeder.start_advance(feed_length, override_error).await);
+        }
+        let results = self.wait_while_group_busy(pending).await;
+
+        let mut failed = 0;
+        for (&index, &slot) in indices.iter().zip(&slots) {
+            let result = results[slot].clone();
+            if result != Err(Error::Aborted) {
+                self.set_advance_marker(slot, false);
+            }
+            if let Err(e) = result {
+                let e = e.for_feeder(index);
+                let mut s: String<96> = String::new();
+                writeln!(s, "group: N{index} error:{} {e}", e.code()).ok();
+                self.write_output(s.as_bytes()).await;
+                failed |= 1 << index;
+            }
+        }
+        match failed {
+            0 => Ok(()),
+            failed => Err(Error::GroupAdvanceFailed(failed)),
+        }
+    }
+
+    // Runs the advances deferred while feeders were disabled in the order
+    // they arrived, reporting the result of each.
+    async fn replay_deferred_advances(&mut self) {
+        let advances = core::mem::take(&mut self.deferred_advances);
+        for advance in advances {
+            let result = match self.resolve_feeder(Some(advance.index)) {
+                Ok((slot, _)) => self
+                    .run_advance(slot, advance.feed_length, advance.override_error)
+                    .await
+                    .map_err(|e| e.for_feeder(advance.index)),
+                Err(e) => Err(e),
+            };
+            let mut s: String<128> = String::new();
+            match result {
+                Ok(()) => writeln!(s, "replayed: {advance} ok"),
+                Err(e) => writeln!(s, "replayed: {advance} error:{} {e}", e.code()),
+            }
+            .ok();
+            self.write_output(s.as_bytes()).await;
+        }
+    }
+
+    // Advances the feeder in `slot` with its advance marker set.  An aborted
+    // advance may leave the lever anywhere so the marker is kept until the
+    // feeder is homed.
+    async fn run_advance(
+        &mut self,
+        slot: usize,
+        feed_length: FeedLength,
+        override_error: bool,
+    ) -> Result<()> {
+        self.set_advance_marker(slot, true);
+        let mut feeder = self.feeders[slot];
+        let pending = feeder.start_advance(feed_length, override_error).await;
+        let result = self.wait_while_busy(slot, pending).await;
+        if result != Err(Error::Aborted) {
+            self.set_advance_marker(slot, false);
+        }
+        result
+    }
+
+    fn set_advance_marker(&mut self, slot: usize, in_progress: bool) {
+        if self.advance_marked[slot] != in_progress
+            && self
+                .config_store
+                .set_advance_marker(slot, in_progress)
+                .is_ok()
+        {
+            self.advance_marked[slot] = in_progress;
+        }
+    }
+
+    // Like `wait_while_busy` but for a command started on each slot with a
+    // reply pending.  Returns the result of each, by slot.
+    async fn wait_while_group_busy(
+        &mut self,
+        mut waiting: [Option<PendingReply>; MAX_FEEDERS],
+    ) -> [Result<()>; MAX_FEEDERS] {
+        let feeders = self.feeders.clone();
+        let mut results = core::array::from_fn(|_| Ok(()));
+        while waiting.iter().any(Option::is_some) {
+            // Feeders outside the group are never polled.
+            let finished = select_array(core::array::from_fn::<_, MAX_FEEDERS, _>(|slot| {
+                let (feeder, waiting) = (feeders.get(slot), waiting[slot]);
+                async move {
+                    match (feeder, waiting) {
+                        (Some(feeder), Some(reply)) => feeder.finish(reply).await,
+                        _ => pending().await,
+                    }
+                }
+            }));
+            let notified = select_array(core::array::from_fn::<_, MAX_FEEDERS, _>(|slot| {
+                let (feeder, waiting) = (feeders.get(slot), waiting[slot].is_some());
+                async move {
+                    match feeder {
+                        Some(feeder) if waiting => feeder.wait_for_notification().await,
+                        _ => pending().await,
+                    }
+                }
+            }));
+            match select3(finished, notified, Timer::after(BUSY_INTERVAL)).await {
+                Either3::First((result, slot)) => {
+                    results[slot] = result;
+                    waiting[slot] = None;
+                }
+                Either3::Second((notification, slot)) => {
+                    self.output_notification(slot, notification).await
+                }
+                Either3::Third(()) => self.write_output(b"busy: processing\n").await,
+            }
+        }
+        results
+    }
+
+    // Waits for a command started on the feeder in `slot`, reporting that the
+    // handler is busy every `BUSY_INTERVAL`.  The feeder's notifications, such
+    // as advance progress, are output as they arrive.
+    async fn wait_while_busy(&mut self, slot: usize, pending: PendingReply) -> Result<()> {
+        let feeder = self.feeders[slot];
+        loop {
+            // Progress is sent while the advance is still moving so it is
+            // output ahead of the response.  Notifications sent along with
+            // the response, such as auto-disabling, follow it.
+            match select3(
+                feeder.finish(pending),
+                feeder.wait_for_notification(),
+                Timer::after(BUSY_INTERVAL),
+            )
+            .await
+            {
+                Either3::First(result) => return result,
+                Either3::Second(notification) => self.output_notification(slot, notification).await,
+                Either3::Third(()) => self.write_output(b"busy: processing\n").await,
+            }
+        }
+    }
+
+    // Wiggles feeder N's lever to find it on the machine.
+    pub(crate) async fn handle_m601(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index = index.ok_or(Error::NoIndex)?;
+        let (slot, feeder) = self.resolve_feeder(Some(index))?;
+        let mut feeder = *feeder;
+        let pending = feeder.start_identify().await;
+        self.wait_while_busy(slot, pending)
+            .await
+            .map_err(|e| e.for_feeder(index))
+    }
+
+    pub(crate) async fn handle_m603(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        let mut angle = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                'A' => angle = Some(arg.value.cast()),
+                _ => return Err(Error::InvalidArgument(arg.letter)),
+            }
+        }
+
+        let index = index.ok_or(Error::NoIndex)?;
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        if let Some(angle) = angle {
+            feeder
+                .set_servo_angle(angle)
+                .await
+                .map_err(|e| e.for_feeder(index))?;
+        }
+
+        Ok(())
+    }
+
+    // Moves feeder P's servo to S, ignoring the feed offset, for calibration.
+    // S values below `MIN_PULSE_WIDTH` are angles, the rest are pulse widths
+    // in microseconds.
+    pub(crate) async fn handle_m280(&mut self, command: Line) -> Result<()> {
+        const MIN_PULSE_WIDTH: Value = Value::lit("200");
+
+        let mut index = None;
+        let mut position = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'P' => index = Some(index_arg(arg)?),
+                'S' => {
+                    let value: Value = arg.value.cast();
+                    position = Some(if value < MIN_PULSE_WIDTH {
+                        ServoPosition::Angle(value)
+                    } else {
+                        ServoPosition::PulseWidth(value)
+                    });
+                }
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index = index.ok_or(Error::NoIndex)?;
+        let position = position.ok_or(Error::InvalidArgument('S'))?;
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        feeder
+            .set_servo_raw(position)
+            .await
+            .map_err(|e| e.for_feeder(index))
+    }
+
+    // Clears the error, such as a jam, latched by feeder N, or by every feeder
+    // without an index, so it advances again.
+    pub(crate) async fn handle_m608(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        for index in index.map_or(0..self.feeders.len(), |index| index..index + 1) {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            feeder.clear_latched_error().await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m610(&mut self, command: Line) -> Result<()> {
+        let mut status = None;
+
+        for arg in command.arguments() {
+            match arg.letter {
+                'S' => status = Some(arg.value != 0.0),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        if let Some(status) = status {
+            if status && self.in_safe_mode() {
+                return Err(Error::SafeMode);
+            }
+            for feeder in self.feeders.iter_mut() {
+                feeder.enable(status).await?;
+            }
+            if status {
+                self.replay_deferred_advances().await;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Locks out (`S1`) or re-enables (`S0`) feedback button feeds while a host
+    // job is running.  Applies to the listed feeders (`N0 N3`), or all feeders
+    // if none are listed.
+    pub(crate) async fn handle_m611(&mut self, command: Line) -> Result<()> {
+        let mut selected = [false; MAX_FEEDERS];
+        let mut lockout = None;
+
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => {
+                    let index = index_arg(arg)?;
+                    if index >= self.feeders.len() {
+                        return Err(Error::InvalidIndex(index));
+                    }
+                    selected[index] = true;
+                }
+                'S' => lockout = Some(arg.value != 0),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let lockout = lockout.ok_or(Error::InvalidArgument('S'))?;
+        if !selected.contains(&true) {
+            selected[..self.feeders.len()].fill(true);
+        }
+
+        for index in (0..self.feeders.len()).filter(|index| selected[*index]) {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            feeder.set_button_lockout(lockout).await?;
+        }
+
+        Ok(())
+    }
+
+    // Homes feeder N, or all feeders if no index is given.
+    pub(crate) async fn handle_g28(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        match index {
+            Some(index) => self.home(index).await,
+            None => {
+                // Home every feeder and report the first error.
+                let mut result = Ok(());
+                for index in 0..self.feeders.len() {
+                    let feeder_result = self.home(index).await;
+                    if result.is_ok() {
+                        result = feeder_result;
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    // Dwells for P milliseconds or S seconds before replying.
+    pub(crate) async fn handle_g4(&mut self, command: Line) -> Result<()> {
+        let mut millis = 0u64;
+        for arg in command.arguments() {
+            let scale = match arg.letter {
+                'P' => 1,
+                'S' => 1000,
+                letter => return Err(Error::InvalidArgument(letter)),
+            };
+            millis = (Value64::from(arg.value) * scale)
+                .checked_to_num()
+                .ok_or(Error::InvalidArgument(arg.letter))?;
+        }
+
+        Timer::after(Duration::from_millis(millis)).await;
+        Ok(())
+    }
+
+    async fn home(&mut self, index: usize) -> Result<()> {
+        let (slot, feeder) = self.resolve_feeder(Some(index))?;
+        let mut feeder = *feeder;
+        let pending = feeder.start_home().await;
+        self.wait_while_busy(slot, pending)
+            .await
+            .map_err(|e| e.for_feeder(index))?;
+        self.set_advance_marker(slot, false);
+        Ok(())
+    }
+
+    // Emergency stop.  Any feed in progress was aborted when the stop was
+    // received so all that's left is making sure every feeder is disabled.
+    pub(crate) async fn handle_m112(&mut self, _command: Line) -> Result<()> {
+        let mut result = Ok(());
+        for feeder in self.feeders.iter_mut() {
+            let disabled = feeder.enable(false).await;
+            if result.is_ok() {
+                result = disabled;
+            }
+        }
+        result
+    }
+
+    // Waits for every feeder to finish its queued commands and motion so
+    // hosts can synchronize with the feeders before moving on.
+    pub(crate) async fn handle_m400(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        for slot in 0..self.feeders.len() {
+            let mut feeder = self.feeders[slot];
+            let pending = feeder.start_wait_for_motion().await;
+            self.wait_while_busy(slot, pending)
+                .await
+                .map_err(|e| e.for_feeder(self.logical_index(slot)))?;
+        }
+        Ok(())
+    }
+
+    // Quick stop.  The feed in progress was aborted when the stop was
+    // received.  Advances queued behind it are failed but feeders stay
+    // enabled and an interrupted feeder only needs homing before it can
+    // continue.
+    pub(crate) async fn handle_m410(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        self.discard_queued_advances = true;
+        self.deferred_advances.clear();
+        Ok(())
+    }
+}
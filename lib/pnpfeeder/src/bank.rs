@@ -0,0 +1,74 @@
+use core::ops::{Deref, DerefMut};
+
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, RawMutex};
+use heapless::Vec;
+
+use crate::{Error, FeederClient, Result};
+
+// Most feeders a board can drive, counting those behind expanders.  Group
+// advance failures are reported as a `u32` mask so this can't exceed 32.
+pub const MAX_FEEDERS: usize = 16;
+
+// The feeders driven by a `GCodeHandler`, registered at startup so board
+// variants and expanders can add lanes without changing the handler's type.
+// Feeders are numbered by slot in the order they were registered.
+pub struct FeederBank<'a, R: RawMutex = NoopRawMutex> {
+    clients: Vec<FeederClient<'a, R>, MAX_FEEDERS>,
+}
+
+impl<'a, R: RawMutex> FeederBank<'a, R> {
+    pub const fn new() -> Self {
+        Self {
+            clients: Vec::new(),
+        }
+    }
+
+    // Adds a feeder in the next slot, which is returned.
+    pub fn register(&mut self, client: FeederClient<'a, R>) -> Result<usize> {
+        let slot = self.clients.len();
+        self.clients
+            .push(client)
+            .map_err(|_| Error::TooManyFeeders)?;
+        Ok(slot)
+    }
+}
+
+impl<R: RawMutex> Default for FeederBank<'_, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Not derived, which would needlessly require `R: Clone`.
+impl<R: RawMutex> Clone for FeederBank<'_, R> {
+    fn clone(&self) -> Self {
+        Self {
+            clients: self.clients.clone(),
+        }
+    }
+}
+
+// For boards whose feeders are all known up front.
+impl<'a, R: RawMutex, const N: usize> From<[FeederClient<'a, R>; N]> for FeederBank<'a, R> {
+    fn from(clients: [FeederClient<'a, R>; N]) -> Self {
+        let mut bank = Self::new();
+        for client in clients {
+            bank.register(client).expect("too many feeders");
+        }
+        bank
+    }
+}
+
+impl<'a, R: RawMutex> Deref for FeederBank<'a, R> {
+    type Target = [FeederClient<'a, R>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.clients
+    }
+}
+
+impl<R: RawMutex> DerefMut for FeederBank<'_, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.clients
+    }
+}
@@ -0,0 +1,193 @@
+use core::fmt::{self, Display};
+
+use crate::{Error, FeederConfig, Result, ServoPosition, Value};
+
+// Settings the M619 wizard steps through, in order.  Pulse widths come first
+// so the lever positions are found with the final limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Pwm0,
+    Pwm180,
+    Retract,
+    HalfAdvanced,
+    Advanced,
+}
+
+impl Step {
+    // M620 letter of the setting.
+    fn letter(self) -> char {
+        match self {
+            Self::Pwm0 => 'V',
+            Self::Pwm180 => 'W',
+            Self::Retract => 'C',
+            Self::HalfAdvanced => 'B',
+            Self::Advanced => 'A',
+        }
+    }
+
+    fn is_pulse_width(self) -> bool {
+        matches!(self, Self::Pwm0 | Self::Pwm180)
+    }
+
+    fn next(self, config: &FeederConfig) -> Option<Self> {
+        match self {
+            Self::Pwm0 => Some(Self::Pwm180),
+            Self::Pwm180 => Some(Self::Retract),
+            Self::Retract if config.half_advance => Some(Self::HalfAdvanced),
+            Self::Retract | Self::HalfAdvanced => Some(Self::Advanced),
+            Self::Advanced => None,
+        }
+    }
+}
+
+// A line typed while the wizard runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CalibrationInput {
+    // A bare `+` or `-`: one step up or down.
+    Nudge(Value),
+    // `+` or `-` followed by how far to move, in microseconds or degrees.
+    Adjust(Value),
+    // `y`, like pressing the feedback button.
+    Accept,
+    // `q`, which leaves the config as it was.
+    Quit,
+}
+
+impl CalibrationInput {
+    pub fn parse(line: &str) -> Result<Self> {
+        match line.trim() {
+            "y" => Ok(Self::Accept),
+            "q" => Ok(Self::Quit),
+            "+" => Ok(Self::Nudge(Value::from_num(1))),
+            "-" => Ok(Self::Nudge(Value::from_num(-1))),
+            line => {
+                let (sign, amount) = match (line.strip_prefix('+'), line.strip_prefix('-')) {
+                    (Some(amount), _) => (Value::from_num(1), amount),
+                    (_, Some(amount)) => (Value::from_num(-1), amount),
+                    _ => return Err(Error::ParseError),
+                };
+                let amount: Value = amount.parse().map_err(|_| Error::ParseError)?;
+                Ok(Self::Adjust(sign * amount))
+            }
+        }
+    }
+}
+
+pub enum CalibrationProgress {
+    // The next setting is being calibrated.  The feeder's config has to be
+    // updated first if `limits_changed`.
+    Next { limits_changed: bool },
+    Done,
+}
+
+// The M619 wizard.  A candidate value for each setting is output to the servo
+// and adjusted until accepted.  Pulse widths are in microseconds and lever
+// positions in degrees, whatever the feeder's `position_units`.
+pub struct Calibration {
+    pub index: usize,
+    pub slot: usize,
+    step: Step,
+    value: Value,
+    config: FeederConfig,
+    original: FeederConfig,
+}
+
+impl Calibration {
+    // How far a bare `+` or `-` moves pulse widths, in microseconds, and
+    // lever positions, in degrees.
+    const PULSE_WIDTH_STEP: Value = Value::lit("10");
+    const ANGLE_STEP: Value = Value::lit("1");
+
+    pub fn new(index: usize, slot: usize, config: FeederConfig) -> Self {
+        Self {
+            index,
+            slot,
+            step: Step::Pwm0,
+            value: config.pwm_0,
+            original: config.clone(),
+            config,
+        }
+    }
+
+    // Where the servo is held for the current setting.
+    pub fn position(&self) -> ServoPosition {
+        if self.step.is_pulse_width() {
+            ServoPosition::PulseWidth(self.value)
+        } else {
+            ServoPosition::Angle(self.value)
+        }
+    }
+
+    // Changes the current setting by `input`.  Returns the previous value to
+    // restore if the servo can't be moved.
+    pub fn adjust(&mut self, input: CalibrationInput) -> Value {
+        let previous = self.value;
+        let amount = match input {
+            CalibrationInput::Nudge(steps) if self.step.is_pulse_width() => {
+                steps * Self::PULSE_WIDTH_STEP
+            }
+            CalibrationInput::Nudge(steps) => steps * Self::ANGLE_STEP,
+            CalibrationInput::Adjust(amount) => amount,
+            CalibrationInput::Accept | CalibrationInput::Quit => return previous,
+        };
+        let max = if self.step.is_pulse_width() {
+            Value::MAX
+        } else {
+            Value::from_num(180)
+        };
+        self.value = self.value.saturating_add(amount).clamp(Value::ZERO, max);
+        previous
+    }
+
+    pub fn restore(&mut self, value: Value) {
+        self.value = value;
+    }
+
+    // Keeps the current value and moves on to the next setting.
+    pub fn accept(&mut self) -> Result<CalibrationProgress> {
+        let letter = self.step.letter();
+        let value = if self.step.is_pulse_width() {
+            self.value
+        } else {
+            self.config.angle_position(self.value)?
+        };
+        self.config.set_field(letter, value)?;
+
+        let Some(next) = self.step.next(&self.config) else {
+            return Ok(CalibrationProgress::Done);
+        };
+        self.value = match next {
+            Step::Pwm180 => self.config.pwm_180,
+            step => self
+                .config
+                .position_angle(self.config.get_field(step.letter())?)?,
+        };
+        let limits_changed = self.step == Step::Pwm180;
+        self.step = next;
+        Ok(CalibrationProgress::Next { limits_changed })
+    }
+
+    pub fn config(&self) -> &FeederConfig {
+        &self.config
+    }
+
+    // The config the feeder had before the wizard started.
+    pub fn original(&self) -> &FeederConfig {
+        &self.original
+    }
+}
+
+impl Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self
+            .config
+            .field_info(self.step.letter())
+            .map_or("?", |info| info.name);
+        let unit = if self.step.is_pulse_width() {
+            "us"
+        } else {
+            "deg"
+        };
+        write!(f, "feeder {} {} {}{}", self.index, name, self.value, unit)
+    }
+}
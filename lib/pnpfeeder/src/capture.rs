@@ -0,0 +1,126 @@
+use core::fmt::Display;
+
+// Records the most recent lines of a serial session in RAM so users can dump
+// an exact transcript with M671 when they can't capture on the host.  Lines
+// are kept in the format of the golden transcripts: `> ` for commands, `< `
+// for responses and `@connect`/`@disconnect` for connection changes.  Once the
+// buffer is full the oldest whole lines are dropped.
+pub struct SessionCapture<'a> {
+    buffer: &'a mut [u8],
+    start: usize,
+    len: usize,
+    enabled: bool,
+    // Set when the next output byte starts a new response line.
+    response_line_start: bool,
+}
+
+impl<'a> SessionCapture<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            start: 0,
+            len: 0,
+            enabled: false,
+            response_line_start: true,
+        }
+    }
+
+    // Starting a capture discards the previous one.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled && !self.enabled {
+            self.start = 0;
+            self.len = 0;
+            self.response_line_start = true;
+        }
+        self.enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Records a line sent to the controller.
+    pub fn record_command(&mut self, command: impl Display) {
+        self.record_line(format_args!("> {command}"));
+    }
+
+    // Records a connection change or other out of band event, e.g. `connect`.
+    pub fn record_event(&mut self, event: &str) {
+        self.record_line(format_args!("@{event}"));
+    }
+
+    // Records output from the controller, which may arrive in pieces.
+    pub fn record_output(&mut self, output: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        for &b in output {
+            if self.response_line_start {
+                self.push(b"< ");
+            }
+            self.push(&[b]);
+            self.response_line_start = b == b'\n';
+        }
+    }
+
+    // The captured transcript in order, split where the buffer wraps.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let end = self.start + self.len;
+        if end <= self.buffer.len() {
+            (&self.buffer[self.start..end], &[])
+        } else {
+            (
+                &self.buffer[self.start..],
+                &self.buffer[..end - self.buffer.len()],
+            )
+        }
+    }
+
+    fn record_line(&mut self, line: core::fmt::Arguments) {
+        if !self.enabled {
+            return;
+        }
+        // Finish a response which didn't end with a newline so the line
+        // starts on its own.
+        if !self.response_line_start {
+            self.push(b"\n");
+            self.response_line_start = true;
+        }
+        let _ = core::fmt::write(self, line);
+        self.push(b"\n");
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        for &b in bytes {
+            if self.len == self.buffer.len() {
+                self.drop_oldest_line();
+            }
+            let index = (self.start + self.len) % self.buffer.len();
+            self.buffer[index] = b;
+            self.len += 1;
+        }
+    }
+
+    // Drops bytes up to and including the oldest newline.  Only the end of a
+    // line longer than the buffer is kept.
+    fn drop_oldest_line(&mut self) {
+        while self.len > 0 {
+            let b = self.buffer[self.start];
+            self.start = (self.start + 1) % self.buffer.len();
+            self.len -= 1;
+            if b == b'\n' {
+                break;
+            }
+        }
+    }
+}
+
+impl core::fmt::Write for SessionCapture<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
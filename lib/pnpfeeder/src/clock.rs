@@ -0,0 +1,66 @@
+use core::fmt::{self, Display};
+use embassy_time::Instant;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+// Uptime based time source with an optional wall clock time of day supplied
+// by the host.
+pub struct Clock {
+    // Wall clock time of day, in milliseconds, at uptime zero.
+    wall_clock_offset: Option<u64>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            wall_clock_offset: None,
+        }
+    }
+
+    pub fn set_time_of_day(&mut self, hours: u64, minutes: u64, seconds: u64) {
+        let time_of_day = ((hours * 60 + minutes) * 60 + seconds) * 1000;
+        let uptime = Instant::now().as_millis() % MS_PER_DAY;
+        self.wall_clock_offset = Some((time_of_day + MS_PER_DAY - uptime) % MS_PER_DAY);
+    }
+
+    pub fn now(&self) -> Timestamp {
+        let uptime_ms = Instant::now().as_millis();
+        Timestamp {
+            uptime_ms,
+            time_of_day_ms: self
+                .wall_clock_offset
+                .map(|offset| (offset + uptime_ms) % MS_PER_DAY),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Timestamp {
+    pub uptime_ms: u64,
+    pub time_of_day_ms: Option<u64>,
+}
+
+// Formatted as `<uptime seconds>.<ms>` followed by ` HH:MM:SS` if the wall
+// clock has been set.
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:03}", self.uptime_ms / 1000, self.uptime_ms % 1000)?;
+        if let Some(time_of_day) = self.time_of_day_ms {
+            let seconds = time_of_day / 1000;
+            write!(
+                f,
+                " {:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,705 @@
+// Commands which read, change, calibrate and save feeder and board configs.
+
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+
+use crate::{
+    calibration::{Calibration, CalibrationInput, CalibrationProgress},
+    command_code, config_code, format_feeder_config, index_arg, parse_setup_row, sanitize,
+    CartridgeId, ConfigGroup, ConfigStore, Error, FeederConfig, FeederConfigUpdate, GCodeHandler,
+    Line, Result, MAX_FEEDERS,
+};
+
+impl<'a, W: Write, C: ConfigStore, R: RawMutex> GCodeHandler<'a, W, C, R> {
+    // Sets whether feeders are enabled at power on (`S1`) and the delay
+    // beforehand in ms (`P`), the seconds idle before feeders are disabled
+    // (`I`, zero for never), whether they are re-enabled by the next command
+    // (`E1`), whether advances for disabled feeders wait for M610 S1 (`Q1`)
+    // and how many feeders may move at once (`C`, zero for any number).
+    // Unlike feeder configs these are saved immediately.  Without arguments
+    // the board config is reported as
+    // `M612 S<enable> P<delay> I<timeout> E<reenable> Q<defer> C<moving>`.
+    pub(crate) async fn handle_m612(&mut self, command: Line) -> Result<()> {
+        let mut config = self.board.clone();
+        let mut changed = false;
+        for arg in command.arguments() {
+            let to_u32 = || {
+                arg.value
+                    .checked_to_num()
+                    .ok_or(Error::InvalidArgument(arg.letter))
+            };
+            match arg.letter {
+                'S' => config.startup_enable = arg.value != 0,
+                'P' => config.startup_delay = to_u32()?,
+                'I' => config.idle_timeout = to_u32()?,
+                'E' => config.idle_reenable = arg.value != 0,
+                'Q' => config.defer_while_disabled = arg.value != 0,
+                'C' => config.max_moving_feeders = to_u32()?,
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+            changed = true;
+        }
+
+        if changed {
+            self.config_store.set_board_config(&config)?;
+            self.apply_motion_limit(&config);
+            self.board = config;
+            return Ok(());
+        }
+        let mut s: String<64> = String::new();
+        writeln!(
+            s,
+            "M612 S{} P{} I{} E{} Q{} C{}",
+            u8::from(config.startup_enable),
+            config.startup_delay,
+            config.idle_timeout,
+            u8::from(config.idle_reenable),
+            u8::from(config.defer_while_disabled),
+            config.max_moving_feeders
+        )
+        .ok();
+        self.write_output(s.as_bytes()).await;
+        Ok(())
+    }
+
+    // M620 accepts either a list of feeders (`N0 N3 N5`) or a range of feeders
+    // (`N0 L9`) and applies the parameters to each of them.  Without N the
+    // parameters are applied to every feeder.  The line is applied as a
+    // whole: if any field is invalid each one is reported as
+    // `invalid: <letter><value>` and no feeder is changed.
+    pub(crate) async fn handle_m620(&mut self, command: Line) -> Result<()> {
+        self.update_feeder_configs(command, ConfigGroup::Feed).await
+    }
+
+    // M623, M634 and M635 set the fields of the other `ConfigGroup`s and
+    // select feeders as M620 does.
+    pub(crate) async fn handle_m623(&mut self, command: Line) -> Result<()> {
+        self.update_feeder_configs(command, ConfigGroup::Recovery)
+            .await
+    }
+
+    pub(crate) async fn handle_m634(&mut self, command: Line) -> Result<()> {
+        self.update_feeder_configs(command, ConfigGroup::Servo)
+            .await
+    }
+
+    pub(crate) async fn handle_m635(&mut self, command: Line) -> Result<()> {
+        self.update_feeder_configs(command, ConfigGroup::Peel).await
+    }
+
+    // Describes every config field of feeder `N`, or of every feeder, one per
+    // line as the command which sets it followed by its name, unit, range and
+    // current value.
+    pub(crate) async fn handle_m624(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        match index {
+            Some(index) => self.output_field_info(index).await,
+            None => {
+                for index in 0..self.feeders.len() {
+                    self.output_field_info(index).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn output_field_info(&mut self, index: usize) -> Result<()> {
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        let config = feeder.get_config().await?;
+
+        for group in ConfigGroup::ALL {
+            for (tag, letter) in group.fields() {
+                let info = config.field_info(*tag)?;
+                let mut s: String<128> = String::new();
+                writeln!(
+                    s,
+                    "field: M{} N{} {} name={} unit={} min={} max={} value={}",
+                    group.code(),
+                    index,
+                    letter,
+                    info.name,
+                    info.unit,
+                    info.min,
+                    info.max,
+                    config.get_field(*tag)?
+                )
+                .ok();
+                self.write_output(s.as_bytes()).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_feeder_configs(&mut self, command: Line, group: ConfigGroup) -> Result<()> {
+        let mut selected = [false; MAX_FEEDERS];
+        let mut last_index = None;
+        let mut update = FeederConfigUpdate::default();
+        let mut invalid = None;
+
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => {
+                    let index = index_arg(arg)?;
+                    if index >= self.feeders.len() {
+                        return Err(Error::InvalidIndex(index));
+                    }
+                    selected[index] = true;
+                    last_index = Some(index);
+                }
+                'L' => {
+                    let first = last_index.ok_or(Error::NoIndex)?;
+                    let last = index_arg(arg)?;
+                    if last >= self.feeders.len() {
+                        return Err(Error::InvalidIndex(last));
+                    }
+                    if last < first {
+                        return Err(Error::InvalidArgument('L'));
+                    }
+                    selected[first..=last].fill(true);
+                }
+                letter => {
+                    let tag = group.tag(letter).ok_or(Error::InvalidArgument(letter));
+                    if tag.and_then(|tag| update.add(tag, arg.value)).is_err() {
+                        let mut s: String<32> = String::new();
+                        writeln!(s, "invalid: {}", arg).ok();
+                        self.write_output(s.as_bytes()).await;
+                        invalid.get_or_insert(letter);
+                    }
+                }
+            }
+        }
+        if let Some(letter) = invalid {
+            return Err(Error::InvalidArgument(letter));
+        }
+
+        if !selected.contains(&true) {
+            if update.fields.is_empty() {
+                return Err(Error::NoIndex);
+            }
+            selected[..self.feeders.len()].fill(true);
+        }
+        let count = selected.iter().filter(|selected| **selected).count();
+
+        let mut configs: Vec<(usize, FeederConfig), MAX_FEEDERS> = Vec::new();
+        for index in (0..self.feeders.len()).filter(|index| selected[*index]) {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            let mut config = feeder.get_config().await?;
+            update.apply(&mut config)?;
+            configs.push((index, config)).ok();
+        }
+        let result = self.set_feeder_configs(configs).await;
+
+        if count > 1 {
+            let updated = if result.is_ok() { count } else { 0 };
+            let mut s: String<64> = String::new();
+            writeln!(s, "updated {} of {} feeders", updated, count).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+
+        result
+    }
+
+    // Sets the config of each feeder in `configs`, by logical index.  If a
+    // feeder rejects its config, such as for PWM limits its servo can't
+    // produce, the feeders already set are rolled back so either all or none
+    // change.  Changes are only persisted by M500.
+    async fn set_feeder_configs(
+        &mut self,
+        configs: Vec<(usize, FeederConfig), MAX_FEEDERS>,
+    ) -> Result<()> {
+        let mut previous: Vec<(usize, FeederConfig), MAX_FEEDERS> = Vec::new();
+        for (index, config) in configs {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            let old_config = feeder.get_config().await?;
+            if let Err(e) = feeder.set_config(config).await {
+                while let Some((index, config)) = previous.pop() {
+                    let (_, feeder) = self.resolve_feeder(Some(index))?;
+                    feeder.set_config(config).await.ok();
+                }
+                return Err(e);
+            }
+            previous.push((index, old_config)).ok();
+        }
+        Ok(())
+    }
+
+    // Starts a setup block.  Until the block is applied with M626, each line
+    // which isn't G-code is a CSV row as described by `parse_setup_row`, such
+    // as a changeover sheet exported from a spreadsheet.  A bad row discards
+    // the whole block.
+    pub(crate) async fn handle_m625(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        self.setup_block = Some(Vec::new());
+        Ok(())
+    }
+
+    pub(crate) fn handle_setup_row(&mut self, row: &str) -> Result<()> {
+        let Some(rows) = &mut self.setup_block else {
+            if let Some(stats) = self.transport_stats {
+                stats.parse_errors.increment();
+            }
+            return Err(Error::ParseError);
+        };
+
+        let result = parse_setup_row(row).and_then(|(index, update)| {
+            if index >= self.feeders.len() {
+                return Err(Error::InvalidIndex(index));
+            }
+            if rows.iter().any(|(row_index, _)| *row_index == index) {
+                return Err(Error::InvalidArgument('N'));
+            }
+            // There can't be more rows than feeders since duplicates are
+            // rejected.
+            rows.push((index, update)).ok();
+            Ok(())
+        });
+        if result.is_err() {
+            self.setup_block = None;
+        }
+        result
+    }
+
+    // Applies the setup block started by M625.  Either every row is applied
+    // or, on error, no feeders are changed.  Like M620, changes are only
+    // persisted by M500.
+    pub(crate) async fn handle_m626(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        let rows = self.setup_block.take().ok_or(Error::NoSetupBlock)?;
+
+        // Build every new config before changing any feeder.
+        let mut configs: Vec<(usize, FeederConfig), MAX_FEEDERS> = Vec::new();
+        for (index, update) in rows.iter() {
+            let (_, feeder) = self.resolve_feeder(Some(*index))?;
+            let mut config = feeder.get_config().await?;
+            update.apply(&mut config)?;
+            configs.push((*index, config)).ok();
+        }
+        self.set_feeder_configs(configs).await?;
+
+        let mut s: String<32> = String::new();
+        writeln!(s, "updated {} feeders", rows.len()).ok();
+        self.write_output(s.as_bytes()).await;
+        Ok(())
+    }
+
+    // Prints feeder N's config as a code which M628 can apply, such as on a
+    // different machine.  See `config_code` for the format.
+    pub(crate) async fn handle_m627(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let (_, feeder) = self.resolve_feeder(index)?;
+        let config = feeder.get_config().await?;
+        let code = config_code::encode(&config)?;
+        self.write_output(code.as_bytes()).await;
+        self.write_output(b"\n").await;
+        Ok(())
+    }
+
+    // Starts the calibration wizard on feeder N.  The lever's pulse widths and
+    // then its positions are output in turn, each adjusted from the console
+    // and accepted with `y` or the feedback button.  `q` or any G-code leaves
+    // the config as it was.  Like M620, changes are only persisted by M500.
+    pub(crate) async fn handle_m619(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let (slot, feeder) = self.resolve_feeder(index)?;
+        let index = index.ok_or(Error::NoIndex)?;
+        let calibration = Calibration::new(index, slot, feeder.get_config().await?);
+        feeder
+            .set_servo_raw(calibration.position())
+            .await
+            .map_err(|e| e.for_feeder(index))?;
+        feeder.set_button_capture(true).await?;
+        self.calibration = Some(calibration);
+
+        self.write_output(
+            b"calibrate: +/-[amount] adjusts, y or the feedback button accepts, q quits\n",
+        )
+        .await;
+        self.output_calibration_step().await;
+        Ok(())
+    }
+
+    async fn output_calibration_step(&mut self) {
+        if let Some(calibration) = &self.calibration {
+            let mut s: String<64> = String::new();
+            writeln!(s, "calibrate: {}", calibration).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+    }
+
+    pub(crate) async fn handle_calibration_input(&mut self, line: &str) -> Result<()> {
+        let input = CalibrationInput::parse(line)?;
+        let Some(calibration) = &mut self.calibration else {
+            return Ok(());
+        };
+        match input {
+            CalibrationInput::Accept => self.accept_calibration_step().await,
+            CalibrationInput::Quit => {
+                let index = calibration.index;
+                self.cancel_calibration().await;
+                let mut s: String<32> = String::new();
+                writeln!(s, "calibrate: feeder {} cancelled", index).ok();
+                self.write_output(s.as_bytes()).await;
+                Ok(())
+            }
+            input => {
+                let previous = calibration.adjust(input);
+                let feeder = &mut self.feeders[calibration.slot];
+                if let Err(e) = feeder.set_servo_raw(calibration.position()).await {
+                    calibration.restore(previous);
+                    return Err(e.for_feeder(calibration.index));
+                }
+                self.output_calibration_step().await;
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) async fn accept_calibration_step(&mut self) -> Result<()> {
+        let Some(mut calibration) = self.calibration.take() else {
+            return Ok(());
+        };
+        let index = calibration.index;
+        let feeder = &mut self.feeders[calibration.slot];
+        let result: Result<bool> = async {
+            match calibration.accept()? {
+                CalibrationProgress::Next { limits_changed } => {
+                    if limits_changed {
+                        feeder.set_config(calibration.config().clone()).await?;
+                    }
+                    feeder.set_servo_raw(calibration.position()).await?;
+                    Ok(false)
+                }
+                CalibrationProgress::Done => {
+                    feeder.set_button_capture(false).await?;
+                    feeder.set_config(calibration.config().clone()).await?;
+                    Ok(true)
+                }
+            }
+        }
+        .await;
+        match result {
+            Ok(false) => {
+                self.calibration = Some(calibration);
+                self.output_calibration_step().await;
+            }
+            Ok(true) => {
+                let mut s: String<48> = String::new();
+                writeln!(s, "calibrate: feeder {} done, M500 saves", index).ok();
+                self.write_output(s.as_bytes()).await;
+            }
+            Err(e) => {
+                // The wizard can't carry on from a step which failed.
+                self.calibration = Some(calibration);
+                self.cancel_calibration().await;
+                return Err(e.for_feeder(index));
+            }
+        }
+        Ok(())
+    }
+
+    // Ends the calibration wizard, if running, and puts the feeder's config
+    // back as it was.
+    pub(crate) async fn cancel_calibration(&mut self) {
+        if let Some(calibration) = self.calibration.take() {
+            let feeder = &mut self.feeders[calibration.slot];
+            // The feeder is left as it is if it can't be restored.
+            feeder.set_button_capture(false).await.ok();
+            feeder.set_config(calibration.original().clone()).await.ok();
+        }
+    }
+
+    // Applies the config code on the next line to feeder N.  Like M620,
+    // changes are only persisted by M500.
+    pub(crate) async fn handle_m628(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let (_, _) = self.resolve_feeder(index)?;
+        self.config_code_target = index;
+        Ok(())
+    }
+
+    pub(crate) async fn apply_config_code(&mut self, index: usize, code: &str) -> Result<()> {
+        let config = config_code::decode(code)?;
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        feeder.set_config(config).await
+    }
+
+    // Write a feeder config to the store and read it back to catch writes
+    // which silently failed.
+    fn store_config(&mut self, slot: usize, config: &FeederConfig) -> Result<()> {
+        self.config_store.set(slot, config)?;
+        if self.config_store.get(slot)? != *config {
+            return Err(Error::ConfigVerifyError);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m621(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        // Without an index, dump every feeder like `handle_connect` does.
+        match index {
+            Some(_) => self.output_feeder_config(index).await?,
+            None => {
+                for index in 0..self.feeders.len() {
+                    self.output_feeder_config(Some(index)).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn output_feeder_config(&mut self, index: Option<usize>) -> Result<()> {
+        let (_, feeder) = self.resolve_feeder(index)?;
+        let config = feeder.get_config().await?;
+        let index = index.ok_or(Error::NoIndex)?;
+
+        let s = format_feeder_config("", index, &config)?;
+        self.write_output(s.as_bytes()).await;
+        Ok(())
+    }
+
+    // Copies every setting of feeder `S` to feeder `N`, e.g. to clone a
+    // calibrated feeder to its neighbors.  Like M620 the copy is saved by M500.
+    pub(crate) async fn handle_m622(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        let mut source = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                'S' => source = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index = index.ok_or(Error::NoIndex)?;
+        let source = source.ok_or(Error::InvalidArgument('S'))?;
+        let (_, feeder) = self.resolve_feeder(Some(source))?;
+        let config = feeder.get_config().await?;
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        feeder.set_config(config).await
+    }
+
+    // M629 S1 reports every config change as the config lines which
+    // would make it, prefixed with `config:`, so tools sharing the connection
+    // stay in sync without polling M621.  S0 stops reporting.
+    pub(crate) async fn handle_m629(&mut self, command: Line) -> Result<()> {
+        let mut watch = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'S' => watch = Some(arg.value != 0),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+        self.watch_config = watch.ok_or(Error::InvalidArgument('S'))?;
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m630(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        let mut slot = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                'S' => slot = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index: usize = index.ok_or(Error::NoIndex)?;
+        if index >= self.feeders.len() {
+            return Err(Error::InvalidIndex(index));
+        }
+        let slot: usize = slot.ok_or(Error::InvalidArgument('S'))?;
+        if slot >= self.feeders.len() {
+            return Err(Error::InvalidIndex(slot));
+        }
+
+        // The map stays a permutation: the feeder which was mapped to `slot`
+        // takes over this feeder's old slot.
+        let old_slot = self.slot_map[index];
+        let displaced = self
+            .slot_map
+            .iter()
+            .take(self.feeders.len())
+            .position(|mapped| *mapped == slot)
+            .filter(|other| *other != index);
+        if let Some(other) = displaced {
+            self.store_slot(other, old_slot)?;
+        }
+        self.store_slot(index, slot)
+    }
+
+    // Saves one entry of the slot map and applies it once read back.
+    fn store_slot(&mut self, index: usize, slot: usize) -> Result<()> {
+        self.config_store.set_slot(index, slot)?;
+        if self.config_store.get_slot(index)? != slot {
+            return Err(Error::ConfigVerifyError);
+        }
+        self.slot_map[index] = slot;
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m631(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        let slot_map = self.slot_map;
+        for (index, slot) in slot_map.iter().take(self.feeders.len()).enumerate() {
+            let mut s: String<32> = String::new();
+            writeln!(s, "M630 N{} S{}", index, slot).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+
+        Ok(())
+    }
+
+    // Handles a line with a string argument.  Only M632 takes one.
+    pub(crate) async fn handle_string_line(&mut self, line: Line, text: &str) -> Result<()> {
+        let Some(command) = line.command() else {
+            return Err(Error::ParseError);
+        };
+        if command_code(command) == Some(('M', 632)) {
+            self.handle_m632(line, text).await
+        } else {
+            Err(Error::InvalidArgument('"'))
+        }
+    }
+
+    // Associates a cartridge serial or part number with feeder N so the host
+    // can verify which part is loaded: `M632 N<n> "<id>"`.  Without an id the
+    // association is cleared.  Saved immediately, like M630.
+    pub(crate) async fn handle_m632(&mut self, command: Line, cartridge: &str) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let index: usize = index.ok_or(Error::NoIndex)?;
+        if index >= self.feeders.len() {
+            return Err(Error::InvalidIndex(index));
+        }
+        // Truncating an id could make two cartridges look the same so long ids
+        // are rejected instead.
+        if cartridge.chars().count() > CartridgeId::new().capacity() {
+            return Err(Error::InvalidCartridgeId);
+        }
+        let cartridge: CartridgeId = sanitize(cartridge);
+
+        self.config_store.set_cartridge(index, &cartridge)?;
+        if self.config_store.get_cartridge(index)? != cartridge {
+            return Err(Error::ConfigVerifyError);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m633(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        for index in 0..self.feeders.len() {
+            let cartridge = self.config_store.get_cartridge(index)?;
+            let mut s: String<64> = String::new();
+            writeln!(s, "M632 N{} \"{}\"", index, cartridge).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+
+        Ok(())
+    }
+
+    // Saves every feeder's config to the store, along with any feed counters
+    // which changed since they were last saved.
+    pub(crate) async fn handle_m500(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        self.save_counters().await;
+
+        let mut result = Ok(());
+        for slot in 0..self.feeders.len() {
+            let stored = match self.feeders[slot].get_config().await {
+                Ok(config) => self.store_config(slot, &config),
+                Err(e) => Err(e),
+            };
+            if result.is_ok() {
+                result = stored;
+            }
+        }
+        result
+    }
+
+    // Discards unsaved changes by reloading every feeder's config from the
+    // store.
+    pub(crate) async fn handle_m501(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        self.load_feeder_configs().await
+    }
+
+    // Restores every feeder's default config.  Like M620, the defaults are
+    // only persisted by M500.
+    pub(crate) async fn handle_m502(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        let mut result = Ok(());
+        for slot in 0..self.feeders.len() {
+            let config = self.config_store.get_default(slot);
+            let restored = self.feeders[slot].set_config(config).await;
+            if result.is_ok() {
+                result = restored;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,174 @@
+use heapless::String;
+
+use crate::{Error, FeederConfig, Result, Value, Value64};
+
+// A feeder config encoded compactly enough to be printed as a QR code on a
+// label attached to the feeder.  Codes only use characters from QR's
+// alphanumeric mode and look like `$3/F1JK/+D-5K/.../4X`: a version, each
+// field which differs from its default and a two digit checksum, separated by
+// `/`.  Fields are their `FeederConfig::FIELDS` tag, uppercased and preceded
+// by `+` for the lowercase ones, and their value in hundredths as signed base
+// 36.  Fields left out of a code keep their defaults.
+pub type ConfigCode = String<MAX_CODE_LEN>;
+
+const PREFIX: &str = "$3/";
+// Codes from before only changed fields were included, with every
+// `FeederConfig::FIELDS` value in order.  Still accepted so printed labels
+// keep working.
+const POSITIONAL_PREFIX: &str = "$2/";
+// Positional codes from before pulse widths were in microseconds.
+const LEGACY_PREFIX: &str = "$1/";
+// A sign and five base 36 digits cover hundredths of any `Value`.
+const MAX_VALUE_LEN: usize = 6;
+// Every field changed, each with a `+`, letter and separator.
+const MAX_CODE_LEN: usize = PREFIX.len() + FeederConfig::FIELDS.len() * (2 + MAX_VALUE_LEN + 1) + 2;
+const DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+// Two base 36 digits.
+const CHECKSUM_MODULUS: u32 = 36 * 36;
+
+// Fields are rounded to hundredths.
+pub fn encode(config: &FeederConfig) -> Result<ConfigCode> {
+    let mut code = ConfigCode::new();
+    code.push_str(PREFIX)
+        .map_err(|_| Error::InvalidConfigCode)?;
+    let defaults = FeederConfig::default();
+    for letter in FeederConfig::FIELDS {
+        let value = config.get_field(letter)?;
+        if value == defaults.get_field(letter)? {
+            continue;
+        }
+        if letter.is_ascii_lowercase() {
+            code.push('+').map_err(|_| Error::InvalidConfigCode)?;
+        }
+        code.push(letter.to_ascii_uppercase())
+            .map_err(|_| Error::InvalidConfigCode)?;
+        let hundredths = (Value64::from(value) * 100).round().to_num::<i64>();
+        push_base36(&mut code, hundredths, 1)?;
+        code.push('/').map_err(|_| Error::InvalidConfigCode)?;
+    }
+    let checksum = checksum(&code);
+    push_base36(&mut code, checksum.into(), 2)?;
+    Ok(code)
+}
+
+pub fn decode(code: &str) -> Result<FeederConfig> {
+    // Checking for ASCII makes splitting off the checksum safe.
+    if !code.is_ascii() || code.len() < PREFIX.len() + 2 {
+        return Err(Error::InvalidConfigCode);
+    }
+    let (body, checksum_digits) = code.split_at(code.len() - 2);
+    let expected =
+        u32::from_str_radix(checksum_digits, 36).map_err(|_| Error::InvalidConfigCode)?;
+    if expected != checksum(body) {
+        return Err(Error::InvalidConfigCode);
+    }
+
+    if let Some(fields) = body.strip_prefix(PREFIX) {
+        return decode_changed_fields(fields);
+    }
+    let (fields, legacy) = match body.strip_prefix(POSITIONAL_PREFIX) {
+        Some(fields) => (fields, false),
+        None => (
+            body.strip_prefix(LEGACY_PREFIX)
+                .ok_or(Error::InvalidConfigCode)?,
+            true,
+        ),
+    };
+    let fields = fields.strip_suffix('/').ok_or(Error::InvalidConfigCode)?;
+    let mut cells = fields.split('/');
+    let mut config = FeederConfig::default();
+    for (letter, cell) in FeederConfig::FIELDS.into_iter().zip(cells.by_ref()) {
+        let hundredths = i64::from_str_radix(cell, 36).map_err(|_| Error::InvalidConfigCode)?;
+        config
+            .set_field(letter, from_hundredths(hundredths)?)
+            .map_err(|_| Error::InvalidConfigCode)?;
+    }
+    if cells.next().is_some() {
+        return Err(Error::InvalidConfigCode);
+    }
+    if legacy {
+        config
+            .convert_legacy_pwm_counts()
+            .map_err(|_| Error::InvalidConfigCode)?;
+    }
+
+    Ok(config)
+}
+
+fn decode_changed_fields(fields: &str) -> Result<FeederConfig> {
+    let mut config = FeederConfig::default();
+    // Bit `i` is set once `FeederConfig::FIELDS[i]` has been seen.
+    let mut seen = 0u64;
+    let mut fields = fields;
+    while !fields.is_empty() {
+        let (field, rest) = fields.split_once('/').ok_or(Error::InvalidConfigCode)?;
+        fields = rest;
+        let (lowercase, field) = match field.strip_prefix('+') {
+            Some(field) => (true, field),
+            None => (false, field),
+        };
+        let mut chars = field.chars();
+        let letter = chars
+            .next()
+            .filter(char::is_ascii_uppercase)
+            .map(|letter| match lowercase {
+                true => letter.to_ascii_lowercase(),
+                false => letter,
+            })
+            .ok_or(Error::InvalidConfigCode)?;
+        let index = FeederConfig::FIELDS
+            .iter()
+            .position(|&field| field == letter)
+            .ok_or(Error::InvalidConfigCode)?;
+        if seen & (1 << index) != 0 {
+            return Err(Error::InvalidConfigCode);
+        }
+        seen |= 1 << index;
+        let hundredths =
+            i64::from_str_radix(chars.as_str(), 36).map_err(|_| Error::InvalidConfigCode)?;
+        config
+            .set_field(letter, from_hundredths(hundredths)?)
+            .map_err(|_| Error::InvalidConfigCode)?;
+    }
+    Ok(config)
+}
+
+// Rounds to the nearest `Value` so values with two or fewer decimal places
+// survive a round trip exactly.
+fn from_hundredths(hundredths: i64) -> Result<Value> {
+    let one = 1i64 << Value::FRAC_NBITS;
+    let bits = hundredths
+        .checked_mul(one)
+        .and_then(|scaled| scaled.checked_add(50))
+        .map(|scaled| scaled.div_euclid(100))
+        .and_then(|bits| i32::try_from(bits).ok())
+        .ok_or(Error::InvalidConfigCode)?;
+    Ok(Value::from_bits(bits))
+}
+
+// Weighting each character by its position catches transposed characters.
+fn checksum(s: &str) -> u32 {
+    s.bytes().enumerate().fold(0, |sum, (i, b)| {
+        (sum + (i as u32 + 1) * u32::from(b)) % CHECKSUM_MODULUS
+    })
+}
+
+// Appends `value` in base 36, zero padded to at least `width` digits.
+fn push_base36(code: &mut ConfigCode, value: i64, width: usize) -> Result<()> {
+    if value < 0 {
+        code.push('-').map_err(|_| Error::InvalidConfigCode)?;
+    }
+    let mut digits = [0u8; 13];
+    let mut remaining = value.unsigned_abs();
+    let mut len = 0;
+    while remaining > 0 || len < width {
+        digits[len] = DIGITS[(remaining % 36) as usize];
+        remaining /= 36;
+        len += 1;
+    }
+    for digit in digits[..len].iter().rev() {
+        code.push(*digit as char)
+            .map_err(|_| Error::InvalidConfigCode)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,392 @@
+// Commands which report on the feeders and the board: their status,
+// counters and metrics, the clock and session captures.
+
+use az::Cast;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Write;
+use heapless::String;
+
+use crate::{
+    index_arg, ConfigStore, DisplayLine, Error, FeedCounters, GCodeHandler, Line, Result, Word,
+    SUPPORTED_COMMANDS,
+};
+
+impl<'a, W: Write, C: ConfigStore, R: RawMutex> GCodeHandler<'a, W, C, R> {
+    // Reports feeder N's runtime state, or every feeder's without an index.
+    pub(crate) async fn handle_m602(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        match index {
+            Some(index) => self.output_feeder_status(index).await,
+            None => {
+                for index in 0..self.feeders.len() {
+                    self.output_feeder_status(index).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn output_feeder_status(&mut self, index: usize) -> Result<()> {
+        let (_, feeder) = self.resolve_feeder(Some(index))?;
+        let status = feeder.get_status().await?;
+
+        let flag = |flag: bool| if flag { 1 } else { 0 };
+        let mut s: String<192> = String::new();
+        write!(
+            s,
+            "feeder {}: enabled={} attention={} feedback={} offset={}",
+            index,
+            flag(status.enabled),
+            flag(status.attention),
+            flag(status.feedback),
+            status.advance_offset
+        )
+        .ok();
+        match status.angle {
+            Some(angle) => write!(s, " angle={angle}").ok(),
+            None => write!(s, " angle=none").ok(),
+        };
+        match status.interrupted_offset {
+            Some(offset) => write!(s, " interrupted={offset}").ok(),
+            None => write!(s, " interrupted=none").ok(),
+        };
+        write!(
+            s,
+            " parts={} cycles={} remaining={}",
+            status.parts, status.progress.cycles, status.progress.remaining
+        )
+        .ok();
+        match status.latched_error {
+            Some(e) => write!(s, " latched={}", e.code()).ok(),
+            None => write!(s, " latched=none").ok(),
+        };
+        // The message goes last as it contains spaces.
+        match status.last_error {
+            Some(e) => writeln!(s, " last_error={} {}", e.code(), e).ok(),
+            None => writeln!(s, " last_error=none").ok(),
+        };
+        self.write_output(s.as_bytes()).await;
+        Ok(())
+    }
+
+    // Reports the parts and length fed by feeder N, or by every feeder without
+    // an index, as `feeder <index>: parts=<count> length=<mm>`, followed by
+    // `remaining=<count>` once the parts loaded are known.  `R1` resets the
+    // counters instead, e.g. after loading a new reel, and `P` sets the number
+    // of parts loaded.  Either saves the counters.
+    pub(crate) async fn handle_m604(&mut self, command: Line) -> Result<()> {
+        let mut index = None;
+        let mut reset = false;
+        let mut loaded = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'N' => index = Some(index_arg(arg)?),
+                'R' => reset = arg.value != 0,
+                'P' => {
+                    loaded = Some(
+                        arg.value
+                            .checked_to_num()
+                            .ok_or(Error::InvalidArgument(arg.letter))?,
+                    )
+                }
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        for index in index.map_or(0..self.feeders.len(), |index| index..index + 1) {
+            let (slot, feeder) = self.resolve_feeder(Some(index))?;
+            let status = feeder.get_status().await?;
+            if reset || loaded.is_some() {
+                let mut counters = if reset {
+                    FeedCounters::default()
+                } else {
+                    FeedCounters {
+                        parts: status.parts,
+                        length: status.length_fed,
+                        remaining: status.parts_remaining,
+                    }
+                };
+                if loaded.is_some() {
+                    counters.remaining = loaded;
+                }
+                feeder.set_counters(counters).await?;
+                self.config_store.set_counters(slot, &counters)?;
+                self.saved_counters[slot] = counters;
+                continue;
+            }
+            let mut s: String<80> = String::new();
+            write!(
+                s,
+                "feeder {}: parts={} length={}",
+                index, status.parts, status.length_fed
+            )
+            .ok();
+            if let Some(remaining) = status.parts_remaining {
+                write!(s, " remaining={remaining}").ok();
+            }
+            writeln!(s).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+        Ok(())
+    }
+
+    // Sets the wall clock time of day used in timestamps: `M640 H<hours>
+    // I<minutes> S<seconds>`.
+    pub(crate) async fn handle_m640(&mut self, command: Line) -> Result<()> {
+        let mut hours = 0u64;
+        let mut minutes = 0u64;
+        let mut seconds = 0u64;
+        for arg in command.arguments() {
+            match arg.letter {
+                'H' if (0..24).contains(&arg.value) => hours = arg.value.cast(),
+                'I' if (0..60).contains(&arg.value) => minutes = arg.value.cast(),
+                'S' if (0..60).contains(&arg.value) => seconds = arg.value.cast(),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        self.clock.set_time_of_day(hours, minutes, seconds);
+
+        Ok(())
+    }
+
+    pub(crate) async fn handle_m641(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        let mut s: String<32> = String::new();
+        writeln!(s, "time: {}", self.clock.now()).ok();
+        self.write_output(s.as_bytes()).await;
+
+        Ok(())
+    }
+
+    // Reports the firmware and its capabilities in the style of Marlin's M115.
+    pub(crate) async fn handle_m115(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        let mut s: String<96> = String::new();
+        write!(
+            s,
+            "FIRMWARE_NAME:{} FIRMWARE_VERSION:{} FEEDER_COUNT:{} COMMANDS:",
+            self.firmware_name,
+            self.firmware_version,
+            self.feeders.len()
+        )
+        .ok();
+        self.write_output(s.as_bytes()).await;
+        let capture = self.capture.is_some();
+        let bootloader = self.reboot_to_bootloader.is_some();
+        let reset = self.reset.is_some();
+        let commands = SUPPORTED_COMMANDS.iter().filter(|command| match **command {
+            "M670" | "M671" => capture,
+            "M997" => bootloader,
+            "M999" => reset,
+            _ => true,
+        });
+        for (i, command) in commands.enumerate() {
+            if i > 0 {
+                self.write_output(b",").await;
+            }
+            self.write_output(command.as_bytes()).await;
+        }
+        self.write_output(b"\n").await;
+
+        Ok(())
+    }
+
+    // Enables periodic status reports every S seconds.  `M154 S0` disables them.
+    pub(crate) async fn handle_m154(&mut self, command: Line) -> Result<()> {
+        let mut interval = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'S' if arg.value >= 0 => interval = Some(arg.value.cast()),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let interval: u64 = interval.ok_or(Error::InvalidArgument('S'))?;
+        self.status_interval = match interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        };
+        if let Some(interval) = self.status_interval {
+            self.next_status_report = Instant::now() + interval;
+        }
+
+        Ok(())
+    }
+
+    // Outputs a single line status report:
+    // `status: <timestamp> errors=<count> enabled=<flags> attention=<flags>
+    // feedback=<flags> queued=<count> deferred=<count>` with one 0/1 flag per
+    // feeder.  Feedback flags are the raw pin levels.  `queued` is the number
+    // of G-code events waiting behind the handler and `deferred` the number
+    // of advances held until their feeders are enabled.
+    pub(crate) async fn output_status(&mut self, queued: usize) {
+        if let Some(interval) = self.status_interval {
+            self.next_status_report += interval;
+            // Don't try to catch up on missed reports.
+            let now = Instant::now();
+            if self.next_status_report < now {
+                self.next_status_report = now + interval;
+            }
+        }
+
+        let mut enabled: String<32> = String::new();
+        let mut attention: String<32> = String::new();
+        let mut feedback: String<32> = String::new();
+        let flag = |flag: bool| if flag { '1' } else { '0' };
+        for index in 0..self.feeders.len() {
+            let (enabled_flag, attention_flag, feedback_flag) =
+                match self.resolve_feeder(Some(index)) {
+                    Ok((_, feeder)) => match feeder.get_status().await {
+                        Ok(status) => (
+                            flag(status.enabled),
+                            flag(status.attention),
+                            flag(status.feedback),
+                        ),
+                        Err(_) => ('?', '?', '?'),
+                    },
+                    Err(_) => ('?', '?', '?'),
+                };
+            enabled.push(enabled_flag).ok();
+            attention.push(attention_flag).ok();
+            feedback.push(feedback_flag).ok();
+        }
+
+        let mut s: String<160> = String::new();
+        writeln!(
+            s,
+            "status: {} errors={} enabled={} attention={} feedback={} queued={} deferred={}",
+            self.clock.now(),
+            self.error_count,
+            enabled,
+            attention,
+            feedback,
+            queued,
+            self.deferred_advances.len()
+        )
+        .ok();
+        self.write_output(s.as_bytes()).await;
+    }
+
+    // Dumps counters as `name value` lines for host side scrapers.
+    pub(crate) async fn handle_m650(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        self.output_metric("commands_total", None, self.command_count)
+            .await;
+        self.output_metric("command_errors_total", None, self.error_count)
+            .await;
+        self.output_metric("write_timeouts_total", None, self.write_timeouts)
+            .await;
+
+        for index in 0..self.feeders.len() {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            let status = feeder.get_status().await?;
+            self.output_metric("feeds_total", Some(index), status.feeds)
+                .await;
+            self.output_metric("feed_errors_total", Some(index), status.feed_errors)
+                .await;
+            self.output_metric("hook_events_total", Some(index), status.hook_count)
+                .await;
+        }
+
+        if let Some(stats) = self.transport_stats {
+            self.output_metric("transport_rx_bytes_total", None, stats.rx_bytes.get())
+                .await;
+            self.output_metric("transport_tx_bytes_total", None, stats.tx_bytes.get())
+                .await;
+            self.output_metric("transport_lines_total", None, stats.lines.get())
+                .await;
+            self.output_metric(
+                "transport_parse_errors_total",
+                None,
+                stats.parse_errors.get(),
+            )
+            .await;
+            self.output_metric("transport_overflows_total", None, stats.overflows.get())
+                .await;
+            self.output_metric(
+                "transport_write_timeouts_total",
+                None,
+                stats.write_timeouts.get(),
+            )
+            .await;
+            self.output_metric("transport_resends_total", None, stats.resends.get())
+                .await;
+            self.output_metric("transport_queue_waits_total", None, stats.queue_waits.get())
+                .await;
+        }
+
+        Ok(())
+    }
+
+    // Starts (`S1`) or stops (`S0`) capturing the session.  Starting discards
+    // the previous capture.
+    pub(crate) async fn handle_m670(&mut self, command: Line) -> Result<()> {
+        let Some(capture) = &mut self.capture else {
+            return Err(Error::UnsupportedCommand(Word::new('M', 670)));
+        };
+        let mut enabled = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'S' => enabled = Some(arg.value != 0),
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+        let enabled = enabled.ok_or(Error::InvalidArgument('S'))?;
+        if enabled && !capture.enabled() {
+            capture.set_enabled(true);
+            // The command arrived before capturing started.
+            capture.record_command(DisplayLine(&command));
+        } else {
+            capture.set_enabled(enabled);
+        }
+        Ok(())
+    }
+
+    // Dumps the captured session as a transcript which can be replayed by the
+    // `golden_transcripts` test.  The dump itself isn't captured.
+    pub(crate) async fn handle_m671(&mut self, command: Line) -> Result<()> {
+        if self.capture.is_none() {
+            return Err(Error::UnsupportedCommand(Word::new('M', 671)));
+        }
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        let capture = self.capture.take();
+        if let Some(capture) = &capture {
+            let (first, second) = capture.as_slices();
+            self.write_output(first).await;
+            self.write_output(second).await;
+        }
+        self.capture = capture;
+        Ok(())
+    }
+
+    async fn output_metric(&mut self, name: &str, feeder: Option<usize>, value: u32) {
+        let mut s: String<64> = String::new();
+        match feeder {
+            Some(index) => writeln!(s, "{}{{feeder=\"{}\"}} {}", name, index, value),
+            None => writeln!(s, "{} {}", name, value),
+        }
+        .ok();
+        self.write_output(s.as_bytes()).await;
+    }
+}
@@ -0,0 +1,87 @@
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::{AngleScaler, Error, PwmLimits, Result, Servo, Value, Value64};
+
+// A servo on any PWM output implementing embedded-hal's `SetDutyCycle`, so
+// boards other than the RP2040 don't need their own driver.  The output's
+// frequency is set up by the caller; `frame_rate` tells the adapter what it
+// is so pulse widths can be converted to duty cycles.
+pub struct DutyCycleServo<P: SetDutyCycle> {
+    pwm: P,
+    scaler: AngleScaler,
+    frame_rate: u32,
+}
+
+impl<P: SetDutyCycle> DutyCycleServo<P> {
+    pub fn new(pwm: P, frame_rate: u32) -> Self {
+        assert!(frame_rate > 0);
+        Self {
+            pwm,
+            scaler: AngleScaler::new(PwmLimits::standard()),
+            frame_rate,
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.pwm
+    }
+
+    fn period_micros(&self) -> u32 {
+        1_000_000 / self.frame_rate
+    }
+
+    fn set_pulse(&mut self, micros: Value) -> Result<()> {
+        let max_duty = self.pwm.max_duty_cycle();
+        let duty = (Value64::from(micros) * i64::from(max_duty) / i64::from(self.period_micros()))
+            .round()
+            .to_num::<i64>();
+        let duty = u16::try_from(duty)
+            .ok()
+            .filter(|duty| *duty <= max_duty)
+            .ok_or(Error::PwmValueOutOfRange)?;
+        self.pwm.set_duty_cycle(duty).map_err(|_| Error::Io)
+    }
+}
+
+impl<P: SetDutyCycle> Servo for DutyCycleServo<P> {
+    fn set_angle(&mut self, angle: Value) -> Result<()> {
+        // Skip rewriting the duty cycle if it wouldn't change.
+        if let Some(micros) = self.scaler.update(angle)? {
+            if let Err(e) = self.set_pulse(micros) {
+                // Retry the write with the next move.
+                self.scaler.invalidate();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+        limits.check_range(self.period_micros())?;
+        self.scaler.set_limits(limits);
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.scaler.limits().clone()
+    }
+
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+        // The next angle has to be written even if it matches the last one.
+        self.scaler.invalidate();
+        self.set_pulse(micros)
+    }
+
+    fn disable_output(&mut self) -> Result<()> {
+        self.scaler.invalidate();
+        self.pwm.set_duty_cycle_fully_off().map_err(|_| Error::Io)
+    }
+
+    // The output's frequency is fixed by whoever set it up.
+    fn set_frame_rate(&mut self, hz: u32) -> Result<()> {
+        if hz != self.frame_rate {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        Ok(())
+    }
+}
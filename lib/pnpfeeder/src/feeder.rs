@@ -1,14 +1,21 @@
-use embassy_futures::select::{select, Either};
+use embassy_futures::select::{select, select3, select_array, Either, Either3};
 use embassy_sync::{
-    blocking_mutex::raw::NoopRawMutex,
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
     channel::{self, Channel},
+    signal::Signal,
 };
 use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    servo::{PwmLimits, Servo},
-    Error, Input, Result, Value,
+    hooks::{HookAction, HookEvent},
+    input::{CountingInput, NoCounter},
+    peel::{NoPeeler, Peeler},
+    replies::{Replies, Tag},
+    scheduler::{MotionPermit, MotionScheduler, Permits},
+    servo::{NoServo, PwmLimits, Servo, STANDARD_FRAME_RATE},
+    Error, Input, Result, Value, Value64,
 };
 
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
@@ -17,11 +24,283 @@ pub struct FeederConfig {
     pub half_advanced_angle: Value,
     pub retract_angle: Value,
     pub feed_length: Value,
-    pub settle_time: u32,
+    // Time in ms for the lever to settle after moving towards advanced and
+    // towards retracted.  Retracting is often unloaded and quicker.
+    pub advance_settle_time: u32,
+    pub retract_settle_time: u32,
+    // Pulse widths, in microseconds, at 0° and 180°.
     pub pwm_0: Value,
     pub pwm_180: Value,
     pub ignore_feeback_pin: bool,
-    pub always_retract: bool,
+    pub retract_policy: RetractPolicy,
+    // Minimum time in ms between feedback button triggered feeds.
+    pub button_feed_interval: u32,
+    pub motion_feedback: MotionFeedback,
+    // Feed offset, in mm, at which `RetractPolicy::AfterDistance` retracts.
+    // Rounded up to the half or full advance.
+    pub retract_distance: Value,
+    // Cleared for feeders whose lever only has advanced and retracted
+    // positions.  They can only feed in multiples of a full advance.
+    pub half_advance: bool,
+    // Pitch, in mm, of the parts on the tape.  Zero if unknown, in which case
+    // feeds can't be requested in parts and every feed counts as one part.
+    pub mm_per_part: Value,
+    pub feed_complete_hook: HookAction,
+    pub jam_hook: HookAction,
+    pub enable_hook: HookAction,
+    // Distance, in mm, between the tape's sprocket holes.
+    pub sprocket_pitch: Value,
+    // Sprocket holes moved by a full advance of the lever.
+    pub holes_per_retract: u32,
+    // Moves the lever proportionally between the retract and advanced angles
+    // for the distance fed instead of using the half advanced angle, so feeds
+    // can be any length.
+    pub interpolate_angle: bool,
+    // Average lever speed in degrees per second so parts aren't jerked out of
+    // their pockets.  Zero moves the lever as fast as the servo can.  Only
+    // servos driven by a `MotionController` can slew.
+    pub slew_rate: Value,
+    // Times a feed rechecks a feedback switch which reports not ready, waiting
+    // `retry_delay_ms` ms first, before failing.  Gives a tape which is still
+    // being loaded or a slow switch a chance to recover.
+    pub retry_count: u32,
+    pub retry_delay_ms: u32,
+    // Warns once the parts left of those loaded drop below this many.  Zero
+    // never warns.
+    pub runout_warning: u32,
+    // Time in ms after the lever is commanded to advance within which the
+    // feedback switch must change.  A feeder whose switch stays put is taken
+    // to be jammed and stops advancing until the jam is cleared by M608.  Zero skips
+    // the check.
+    pub jam_timeout: u32,
+    // Units of `advanced_angle`, `half_advanced_angle` and `retract_angle`.
+    pub position_units: PositionUnits,
+    // Time in ms the cover tape peeler runs for each feed, at `peel_strength`
+    // percent of full power.  It starts as the lever starts advancing, or
+    // once the feed is done if `peel_after` is set.  Zero never runs it.
+    pub peel_time: u32,
+    pub peel_strength: Value,
+    pub peel_after: bool,
+    // Angles of the secondary servo of two servo feeders, such as one
+    // tensioning the cover tape, with the lever advanced and retracted.  It
+    // moves in proportion to the lever between them.
+    pub secondary_advanced_angle: Value,
+    pub secondary_retract_angle: Value,
+    // Counts per mm of tape of the feeder's travel sensor.  When set, an
+    // advance which measures short by more than `feed_tolerance` mm is topped
+    // up with up to `max_corrections` further advances.  Zero feeds open
+    // loop.
+    pub encoder_counts_per_mm: Value,
+    pub feed_tolerance: Value,
+    pub max_corrections: u32,
+    // Soft limits, in degrees, on every lever move so a miscalibrated
+    // position can't drive the lever past its mechanical stops.
+    pub min_angle: Value,
+    pub max_angle: Value,
+    // Time in ms the servos hold position before they stop being driven, so
+    // they don't buzz and draw current between feeds.  Zero holds forever.
+    pub servo_idle_timeout: u32,
+    // Average speed, in degrees per second, of a move to the retract angle
+    // made when the feeder is enabled, so the lever is in a known position
+    // before the first command rather than snapping there.  As with
+    // `slew_rate`, only servos driven by a `MotionController` whose position
+    // is known can slew.  Zero leaves the lever alone until the first move.
+    pub enable_home_rate: Value,
+    // Servo pulses a second.  Digital servos often hold position better at
+    // 200-333Hz; analog servos need 50Hz.  The lever's pulse widths are kept
+    // so `pwm_0` and `pwm_180` don't need recalibrating.
+    pub servo_frame_rate: u32,
+    // Degrees added to every angle the lever servo is commanded to, so small
+    // differences in how feeders were assembled can be nulled out without
+    // recalibrating each lever position.  Angle limits apply to the trimmed
+    // angle; reported angles are untrimmed.
+    pub trim_angle: Value,
+    // Angle, in degrees, at which the lever rests on its ratchet with the
+    // pawl lifted clear of the tape.  Feeders which have one can back the
+    // tape up with negative feed lengths: the lever rests there, sweeps out
+    // with the pawl clear, re-engages and is brought back towards retracted,
+    // pulling the tape with it.  Zero rejects negative feeds.
+    pub release_angle: Value,
+}
+
+// The commands which set a feeder's config, one per part of the feeder.
+// Each takes its own letters so a part can gain fields without running out
+// of them.  New fields join the group of the part they configure.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigGroup {
+    // M620: the lever and feed, as set by OpenPnP's 0816 driver.
+    Feed,
+    // M623: retries, jams, runout and tape travel sensing.
+    Recovery,
+    // M634: servo limits, units, timing and trim.
+    Servo,
+    // M635: the cover tape peeler and secondary servo.
+    Peel,
+}
+
+impl ConfigGroup {
+    pub const ALL: [Self; 4] = [Self::Feed, Self::Recovery, Self::Servo, Self::Peel];
+
+    // M code of the command which sets and reports the group.
+    pub fn code(self) -> u32 {
+        match self {
+            Self::Feed => 620,
+            Self::Recovery => 623,
+            Self::Servo => 634,
+            Self::Peel => 635,
+        }
+    }
+
+    // `(tag, letter)` of each field in the group, in the order they are
+    // reported.  N and L select feeders so are never field letters.
+    pub fn fields(self) -> &'static [(char, char)] {
+        match self {
+            Self::Feed => &[
+                ('A', 'A'),
+                ('B', 'B'),
+                ('C', 'C'),
+                ('F', 'F'),
+                ('U', 'U'),
+                ('V', 'V'),
+                ('W', 'W'),
+                ('X', 'X'),
+                ('Y', 'Y'),
+                ('R', 'R'),
+                ('E', 'E'),
+                ('Z', 'Z'),
+                ('H', 'H'),
+                ('P', 'P'),
+                ('D', 'D'),
+                ('J', 'J'),
+                ('K', 'K'),
+                ('S', 'S'),
+                ('T', 'T'),
+                ('I', 'I'),
+                ('Q', 'Q'),
+                ('O', 'O'),
+            ],
+            Self::Recovery => &[
+                ('r', 'R'),
+                ('d', 'D'),
+                ('w', 'W'),
+                ('j', 'J'),
+                ('e', 'E'),
+                ('t', 'T'),
+                ('c', 'C'),
+            ],
+            Self::Servo => &[
+                ('u', 'U'),
+                ('f', 'F'),
+                ('h', 'H'),
+                ('i', 'I'),
+                ('o', 'O'),
+                ('q', 'Q'),
+                ('b', 'B'),
+                ('k', 'K'),
+            ],
+            Self::Peel => &[('p', 'P'), ('s', 'S'), ('a', 'A'), ('x', 'X'), ('y', 'Y')],
+        }
+    }
+
+    // Tag of the field set by `letter`.
+    pub fn tag(self, letter: char) -> Option<char> {
+        self.fields()
+            .iter()
+            .find(|(_, field_letter)| *field_letter == letter)
+            .map(|(tag, _)| *tag)
+    }
+}
+
+// Name, unit and valid range of a `FeederConfig` field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: Value,
+    pub max: Value,
+}
+
+// Units the lever positions are configured in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum PositionUnits {
+    #[default]
+    Degrees,
+    // Pulse widths, in microseconds, between `FeederConfig::pwm_0` and
+    // `pwm_180`, for exact pulses when calibrating with an oscilloscope.
+    PulseWidth,
+}
+
+impl PositionUnits {
+    fn from_value(value: Value) -> Option<Self> {
+        match value.checked_to_num::<u8>()? {
+            0 => Some(Self::Degrees),
+            1 => Some(Self::PulseWidth),
+            _ => None,
+        }
+    }
+
+    fn to_value(self) -> Value {
+        Value::from_num(self as u8)
+    }
+}
+
+// When the lever is retracted while advancing.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RetractPolicy {
+    // Once the lever reaches the full advance.  A half advance feed leaves
+    // the lever half advanced for the next feed.
+    #[default]
+    FullAdvance,
+    // After every advance.
+    Always,
+    // Once the lever reaches the full advance and at the end of every feed so
+    // the lever is never left part way advanced between feeds.
+    EndOfFeed,
+    // Once the lever reaches `FeederConfig::retract_distance`.
+    AfterDistance,
+}
+
+impl RetractPolicy {
+    fn from_value(value: Value) -> Option<Self> {
+        match value.checked_to_num::<u8>()? {
+            0 => Some(Self::FullAdvance),
+            1 => Some(Self::Always),
+            2 => Some(Self::EndOfFeed),
+            3 => Some(Self::AfterDistance),
+            _ => None,
+        }
+    }
+
+    fn to_value(self) -> Value {
+        Value::from_num(self as u8)
+    }
+}
+
+// What to do with feedback edges which arrive while the lever is moving.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MotionFeedback {
+    // Edges are assumed to come from the lever itself actuating the switch
+    // and are dropped.
+    #[default]
+    Ignore,
+    // The last edge is handed to the button recognizer once the lever has
+    // settled, so a press which starts during an advance is still recognized
+    // when it is released afterwards.
+    Replay,
+}
+
+impl MotionFeedback {
+    fn from_value(value: Value) -> Option<Self> {
+        match value.checked_to_num::<u8>()? {
+            0 => Some(Self::Ignore),
+            1 => Some(Self::Replay),
+            _ => None,
+        }
+    }
+
+    fn to_value(self) -> Value {
+        Value::from_num(self as u8)
+    }
 }
 
 impl Default for FeederConfig {
@@ -31,38 +310,501 @@ impl Default for FeederConfig {
             half_advanced_angle: Value::from_num(107.5),
             retract_angle: Value::from_num(80),
             feed_length: Value::from_num(2.0),
-            settle_time: 300,
+            advance_settle_time: 300,
+            retract_settle_time: 300,
             pwm_0: Value::from_num(0),
             pwm_180: Value::from_num(0),
             ignore_feeback_pin: false,
-            always_retract: false,
+            retract_policy: RetractPolicy::FullAdvance,
+            button_feed_interval: 0,
+            motion_feedback: MotionFeedback::Ignore,
+            retract_distance: Value::from_num(4),
+            half_advance: true,
+            mm_per_part: Value::from_num(0),
+            feed_complete_hook: HookAction::None,
+            jam_hook: HookAction::None,
+            enable_hook: HookAction::None,
+            sprocket_pitch: Value::from_num(4),
+            holes_per_retract: 1,
+            interpolate_angle: false,
+            slew_rate: Value::from_num(0),
+            retry_count: 0,
+            retry_delay_ms: 500,
+            runout_warning: 0,
+            jam_timeout: 0,
+            position_units: PositionUnits::Degrees,
+            peel_time: 0,
+            peel_strength: Value::from_num(100),
+            peel_after: false,
+            secondary_advanced_angle: Value::from_num(90),
+            secondary_retract_angle: Value::from_num(90),
+            encoder_counts_per_mm: Value::from_num(0),
+            feed_tolerance: Value::from_num(0.5),
+            max_corrections: 2,
+            min_angle: Value::from_num(0),
+            max_angle: Value::from_num(180),
+            servo_idle_timeout: 0,
+            enable_home_rate: Value::from_num(0),
+            servo_frame_rate: STANDARD_FRAME_RATE,
+            trim_angle: Value::from_num(0),
+            release_angle: Value::from_num(0),
         }
     }
 }
 
+impl FeederConfig {
+    // Tape moved by a full advance of the lever, in mm.
+    pub fn full_advance(&self) -> Value {
+        self.sprocket_pitch
+            .saturating_mul(Value::saturating_from_num(self.holes_per_retract))
+    }
+
+    pub fn half_advance_length(&self) -> Value {
+        self.full_advance() / 2
+    }
+
+    // Tags of every field, in the order they are stored.  Tags only name
+    // fields in stored configs, config codes and setup rows; commands set
+    // them by the letters in `ConfigGroup::fields`.  The feed fields are
+    // tagged with their M620 letter and the rest with a lowercase letter.
+    pub const FIELDS: [char; 42] = [
+        'A', 'B', 'C', 'F', 'U', 'V', 'W', 'X', 'Y', 'R', 'E', 'Z', 'H', 'P', 'D', 'J', 'K', 'S',
+        'T', 'I', 'Q', 'O', 'r', 'd', 'w', 'j', 'u', 'p', 's', 'a', 'x', 'y', 'e', 't', 'c', 'f',
+        'h', 'i', 'o', 'q', 'b', 'k',
+    ];
+
+    // Servo frame rates, in Hz, a feeder can be set to.
+    const FRAME_RATES: core::ops::RangeInclusive<u32> = 40..=400;
+
+    // Largest trim, in degrees, either way.
+    const MAX_TRIM: Value = Value::lit("20");
+
+    // Tags of the lever positions, which are in `position_units`.
+    pub const POSITION_FIELDS: [char; 3] = ['A', 'B', 'C'];
+
+    pub fn pwm_limits(&self) -> PwmLimits {
+        PwmLimits {
+            zero: self.pwm_0,
+            one_eighty: self.pwm_180,
+        }
+    }
+
+    // Angle of a lever position given in `position_units`.
+    pub fn position_angle(&self, position: Value) -> Result<Value> {
+        match self.position_units {
+            PositionUnits::Degrees => Ok(position),
+            PositionUnits::PulseWidth => self.pwm_limits().pulse_width_to_angle(position),
+        }
+    }
+
+    // Lever position, in `position_units`, of `angle`.
+    pub fn angle_position(&self, angle: Value) -> Result<Value> {
+        match self.position_units {
+            PositionUnits::Degrees => Ok(angle),
+            PositionUnits::PulseWidth => self.pwm_limits().scale_angle(angle),
+        }
+    }
+
+    // Describes the field with `letter` so hosts can build settings forms
+    // without knowing the fields in advance.  Switches range from 0 to 1 and
+    // choices from 0 to their last value.
+    pub fn field_info(&self, letter: char) -> Result<FieldInfo> {
+        const MAX: Value = Value::lit("32767");
+        let zero = Value::from_num(0);
+        let (position_unit, position_min, position_max) = match self.position_units {
+            PositionUnits::Degrees => ("deg", zero, Value::from_num(180)),
+            PositionUnits::PulseWidth => (
+                "us",
+                self.pwm_0.min(self.pwm_180),
+                self.pwm_0.max(self.pwm_180),
+            ),
+        };
+        let (name, unit, min, max) = match letter {
+            'A' => ("advanced_angle", position_unit, position_min, position_max),
+            'B' => (
+                "half_advanced_angle",
+                position_unit,
+                position_min,
+                position_max,
+            ),
+            'C' => ("retract_angle", position_unit, position_min, position_max),
+            'F' => ("feed_length", "mm", Value::MIN, MAX),
+            'U' => ("advance_settle_time", "ms", zero, MAX),
+            'V' => ("pwm_0", "us", zero, MAX),
+            'W' => ("pwm_180", "us", zero, MAX),
+            'X' => ("ignore_feedback_pin", "bool", zero, Value::from_num(1)),
+            'Y' => ("retract_policy", "choice", zero, Value::from_num(3)),
+            'R' => ("button_feed_interval", "ms", zero, MAX),
+            'E' => ("motion_feedback", "choice", zero, Value::from_num(1)),
+            'Z' => ("retract_distance", "mm", Value::DELTA, MAX),
+            'H' => ("half_advance", "bool", zero, Value::from_num(1)),
+            'P' => ("mm_per_part", "mm", zero, MAX),
+            'D' => ("feed_complete_hook", "choice", zero, Value::from_num(5)),
+            'J' => ("jam_hook", "choice", zero, Value::from_num(5)),
+            'K' => ("enable_hook", "choice", zero, Value::from_num(5)),
+            'S' => ("sprocket_pitch", "mm", Value::DELTA, MAX),
+            'T' => ("holes_per_retract", "count", Value::from_num(1), MAX),
+            'I' => ("interpolate_angle", "bool", zero, Value::from_num(1)),
+            'Q' => ("retract_settle_time", "ms", zero, MAX),
+            'O' => ("slew_rate", "deg/s", zero, MAX),
+            'r' => ("retry_count", "count", zero, MAX),
+            'd' => ("retry_delay", "ms", zero, MAX),
+            'w' => ("runout_warning", "part", zero, MAX),
+            'j' => ("jam_timeout", "ms", zero, MAX),
+            'u' => ("position_units", "choice", zero, Value::from_num(1)),
+            'p' => ("peel_time", "ms", zero, MAX),
+            's' => ("peel_strength", "%", zero, Value::from_num(100)),
+            'a' => ("peel_after", "bool", zero, Value::from_num(1)),
+            'x' => (
+                "secondary_advanced_angle",
+                "deg",
+                zero,
+                Value::from_num(180),
+            ),
+            'y' => ("secondary_retract_angle", "deg", zero, Value::from_num(180)),
+            'e' => ("encoder_counts_per_mm", "count/mm", zero, MAX),
+            't' => ("feed_tolerance", "mm", zero, MAX),
+            'c' => ("max_corrections", "count", zero, MAX),
+            'f' => ("min_angle", "deg", zero, Value::from_num(180)),
+            'h' => ("max_angle", "deg", zero, Value::from_num(180)),
+            'i' => ("servo_idle_timeout", "ms", zero, MAX),
+            'o' => ("enable_home_rate", "deg/s", zero, MAX),
+            'q' => (
+                "servo_frame_rate",
+                "Hz",
+                Value::from_num(*Self::FRAME_RATES.start()),
+                Value::from_num(*Self::FRAME_RATES.end()),
+            ),
+            'b' => ("trim_angle", "deg", -Self::MAX_TRIM, Self::MAX_TRIM),
+            'k' => ("release_angle", "deg", zero, Value::from_num(180)),
+            letter => return Err(Error::InvalidArgument(letter)),
+        };
+        Ok(FieldInfo {
+            name,
+            unit,
+            min,
+            max,
+        })
+    }
+
+    // Converts a config from before pulse widths were in microseconds, when
+    // they were counts of a 20ms period divided into 9804.  Lever positions
+    // in `PositionUnits::PulseWidth` are converted along with the limits.
+    pub fn convert_legacy_pwm_counts(&mut self) -> Result<()> {
+        let to_micros = |counts: Value| {
+            let micros = (Value64::from(counts) * 20_000 / 9804 * 100).round() / 100;
+            Value::checked_from_num(micros).ok_or(Error::FixedPointError)
+        };
+        self.pwm_0 = to_micros(self.pwm_0)?;
+        self.pwm_180 = to_micros(self.pwm_180)?;
+        if self.position_units == PositionUnits::PulseWidth {
+            self.advanced_angle = to_micros(self.advanced_angle)?;
+            self.half_advanced_angle = to_micros(self.half_advanced_angle)?;
+            self.retract_angle = to_micros(self.retract_angle)?;
+        }
+        Ok(())
+    }
+
+    fn lever_angles(&self) -> Result<LeverAngles> {
+        Ok(LeverAngles {
+            advanced: self.position_angle(self.advanced_angle)?,
+            half_advanced: self.position_angle(self.half_advanced_angle)?,
+            retract: self.position_angle(self.retract_angle)?,
+        })
+    }
+
+    pub fn get_field(&self, letter: char) -> Result<Value> {
+        let value = match letter {
+            'A' => self.advanced_angle,
+            'B' => self.half_advanced_angle,
+            'C' => self.retract_angle,
+            'F' => self.feed_length,
+            'U' => Value::saturating_from_num(self.advance_settle_time),
+            'V' => self.pwm_0,
+            'W' => self.pwm_180,
+            'X' => Value::from_num(u8::from(self.ignore_feeback_pin)),
+            'Y' => self.retract_policy.to_value(),
+            'R' => Value::saturating_from_num(self.button_feed_interval),
+            'E' => self.motion_feedback.to_value(),
+            'Z' => self.retract_distance,
+            'H' => Value::from_num(u8::from(self.half_advance)),
+            'P' => self.mm_per_part,
+            'D' => self.feed_complete_hook.to_value(),
+            'J' => self.jam_hook.to_value(),
+            'K' => self.enable_hook.to_value(),
+            'S' => self.sprocket_pitch,
+            'T' => Value::saturating_from_num(self.holes_per_retract),
+            'I' => Value::from_num(u8::from(self.interpolate_angle)),
+            'Q' => Value::saturating_from_num(self.retract_settle_time),
+            'O' => self.slew_rate,
+            'r' => Value::saturating_from_num(self.retry_count),
+            'd' => Value::saturating_from_num(self.retry_delay_ms),
+            'w' => Value::saturating_from_num(self.runout_warning),
+            'j' => Value::saturating_from_num(self.jam_timeout),
+            'u' => self.position_units.to_value(),
+            'p' => Value::saturating_from_num(self.peel_time),
+            's' => self.peel_strength,
+            'a' => Value::from_num(u8::from(self.peel_after)),
+            'x' => self.secondary_advanced_angle,
+            'y' => self.secondary_retract_angle,
+            'e' => self.encoder_counts_per_mm,
+            't' => self.feed_tolerance,
+            'c' => Value::saturating_from_num(self.max_corrections),
+            'f' => self.min_angle,
+            'h' => self.max_angle,
+            'i' => Value::saturating_from_num(self.servo_idle_timeout),
+            'o' => self.enable_home_rate,
+            'q' => Value::saturating_from_num(self.servo_frame_rate),
+            'b' => self.trim_angle,
+            'k' => self.release_angle,
+            letter => return Err(Error::InvalidArgument(letter)),
+        };
+        Ok(value)
+    }
+
+    pub fn set_field(&mut self, letter: char, value: Value) -> Result<()> {
+        let to_u32 = |value: Value| {
+            value
+                .checked_to_num::<u32>()
+                .ok_or(Error::InvalidArgument(letter))
+        };
+        let to_hook =
+            |value: Value| HookAction::from_value(value).ok_or(Error::InvalidArgument(letter));
+        match letter {
+            'A' => self.advanced_angle = value,
+            'B' => self.half_advanced_angle = value,
+            'C' => self.retract_angle = value,
+            'F' => self.feed_length = value,
+            'U' => self.advance_settle_time = to_u32(value)?,
+            'V' => self.pwm_0 = value,
+            'W' => self.pwm_180 = value,
+            'X' => self.ignore_feeback_pin = value != 0,
+            'Y' => {
+                self.retract_policy =
+                    RetractPolicy::from_value(value).ok_or(Error::InvalidArgument(letter))?
+            }
+            'R' => self.button_feed_interval = to_u32(value)?,
+            'E' => {
+                self.motion_feedback =
+                    MotionFeedback::from_value(value).ok_or(Error::InvalidArgument(letter))?
+            }
+            'Z' if value > 0 => self.retract_distance = value,
+            'Z' => return Err(Error::InvalidArgument(letter)),
+            'H' => self.half_advance = value != 0,
+            'P' if value >= 0 => self.mm_per_part = value,
+            'P' => return Err(Error::InvalidArgument(letter)),
+            'D' => self.feed_complete_hook = to_hook(value)?,
+            'J' => self.jam_hook = to_hook(value)?,
+            'K' => self.enable_hook = to_hook(value)?,
+            'S' if value > 0 => self.sprocket_pitch = value,
+            'S' => return Err(Error::InvalidArgument(letter)),
+            'T' if value > 0 => self.holes_per_retract = to_u32(value)?,
+            'T' => return Err(Error::InvalidArgument(letter)),
+            'I' => self.interpolate_angle = value != 0,
+            'Q' => self.retract_settle_time = to_u32(value)?,
+            'O' if value >= 0 => self.slew_rate = value,
+            'O' => return Err(Error::InvalidArgument(letter)),
+            'r' => self.retry_count = to_u32(value)?,
+            'd' => self.retry_delay_ms = to_u32(value)?,
+            'w' => self.runout_warning = to_u32(value)?,
+            'j' => self.jam_timeout = to_u32(value)?,
+            'u' => {
+                self.position_units =
+                    PositionUnits::from_value(value).ok_or(Error::InvalidArgument(letter))?
+            }
+            'p' => self.peel_time = to_u32(value)?,
+            's' if (0..=100).contains(&value) => self.peel_strength = value,
+            's' => return Err(Error::InvalidArgument(letter)),
+            'a' => self.peel_after = value != 0,
+            'x' | 'y' if !(0..=180).contains(&value) => return Err(Error::InvalidArgument(letter)),
+            'x' => self.secondary_advanced_angle = value,
+            'y' => self.secondary_retract_angle = value,
+            'e' | 't' if value < 0 => return Err(Error::InvalidArgument(letter)),
+            'e' => self.encoder_counts_per_mm = value,
+            't' => self.feed_tolerance = value,
+            'c' => self.max_corrections = to_u32(value)?,
+            'f' | 'h' if !(0..=180).contains(&value) => return Err(Error::InvalidArgument(letter)),
+            'f' => self.min_angle = value,
+            'h' => self.max_angle = value,
+            'i' => self.servo_idle_timeout = to_u32(value)?,
+            'o' if value >= 0 => self.enable_home_rate = value,
+            'o' => return Err(Error::InvalidArgument(letter)),
+            'q' => {
+                let rate = to_u32(value)?;
+                if !Self::FRAME_RATES.contains(&rate) {
+                    return Err(Error::InvalidArgument(letter));
+                }
+                self.servo_frame_rate = rate;
+            }
+            'b' if value.abs() <= Self::MAX_TRIM => self.trim_angle = value,
+            'b' => return Err(Error::InvalidArgument(letter)),
+            'k' if (0..=180).contains(&value) => self.release_angle = value,
+            'k' => return Err(Error::InvalidArgument(letter)),
+            letter => return Err(Error::InvalidArgument(letter)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeederStatus {
+    pub enabled: bool,
+    // Set when the feeder took itself out of service.  Cleared when re-enabled.
+    pub attention: bool,
+    pub button_lockout: bool,
+    pub feedback: bool,
+    // Number of successful and failed feeds, including button triggered feeds.
+    pub feeds: u32,
+    pub feed_errors: u32,
+    // Number of parts fed, counted in `FeederConfig::mm_per_part` if set.
+    pub parts: u32,
+    // Total tape fed, in mm.  Reversing the tape takes away from it.
+    pub length_fed: Value64,
+    // Parts left of those loaded, if the number loaded was set.
+    pub parts_remaining: Option<u32>,
+    // Number of hooks run with `HookAction::Count`.
+    pub hook_count: u32,
+    // Feed offset, in mm, the lever was moving to when an advance or home was
+    // aborted.  The feeder must be homed before it feeds again.
+    pub interrupted_offset: Option<Value>,
+    // Last angle the servo was commanded to, if any.
+    pub angle: Option<Value>,
+    // Feed offset, in mm, of the lever.  Non-zero while half advanced.
+    pub advance_offset: Value,
+    // Most recent error from a command or button feed.
+    pub last_error: Option<Error>,
+    // Error which fails every advance until cleared by M608.
+    pub latched_error: Option<Error>,
+    // Progress of the most recent advance.
+    pub progress: AdvanceProgress,
+}
+
+// Running totals of what a feeder has fed.  Restored from the config store at
+// startup so they cover the life of the tape or the feeder.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FeedCounters {
+    pub parts: u32,
+    // In mm.
+    pub length: Value64,
+    // Parts left on the tape, counted down from the number loaded.
+    pub remaining: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AdvanceProgress {
+    // Advance and retract cycles completed.
+    pub cycles: u32,
+    // Feed length, in mm, left to advance.
+    pub remaining: Value,
+}
+
+// How far an advance feeds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeedLength {
+    // `FeederConfig::feed_length`.
+    Default,
+    Millimeters(Value),
+    // A number of parts `FeederConfig::mm_per_part` apart.
+    Parts(u32),
+}
+
+// Servo position set by M280.  Either an angle or a raw pulse width in
+// microseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServoPosition {
+    Angle(Value),
+    PulseWidth(Value),
+}
+
 enum FeederCommand {
     SetConfig(FeederConfig),
     GetConfig(),
+    GetStatus,
     SetServoAngle(Value),
+    SetServoRaw(ServoPosition),
+    SetButtonLockout(bool),
+    SetButtonCapture(bool),
+    ClearLatchedError,
+    MarkInterrupted,
+    SetCounters(FeedCounters),
+    Detach,
+    Home,
+    Identify,
     Advance {
-        length: Option<Value>,
+        length: FeedLength,
         override_error: bool,
     },
     Enable(bool),
+    WaitForMotion,
     #[cfg(test)]
     Shutdown,
 }
 
-pub struct FeederChannel {
-    command_channel: channel::Channel<NoopRawMutex, FeederCommand, 2>,
-    response_channel: channel::Channel<NoopRawMutex, Result<Option<FeederConfig>>, 2>,
+// Sent by the feeder task without a corresponding command.
+#[derive(Debug)]
+pub enum FeederNotification {
+    AutoDisabled(Error),
+    // The parts remaining dropped below `FeederConfig::runout_warning`.
+    RunoutWarning(u32),
+    // Sent after each cycle of an advance which needs more than one.
+    Progress(AdvanceProgress),
+    // A hook whose action is carried out by the handler.
+    Hook(HookEvent, HookAction),
+    // A command changed the config.  Configs swapped in by `run_shared`
+    // don't count.
+    ConfigChanged(FeederConfig),
+    // The feedback button was pressed while captured.
+    ButtonPressed,
+}
+
+enum FeederResponse {
+    Done,
+    Config(FeederConfig),
+    Status(FeederStatus),
+}
+
+type AbortSignal<R> = Signal<R, ()>;
+
+// A command tagged so its response goes back to the client which sent it.
+struct Request {
+    tag: Tag,
+    command: FeederCommand,
+}
+
+// Returned when a long running command is started, to collect its result
+// with `FeederClient::finish()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[must_use]
+pub struct PendingReply(Tag);
+
+// Several clients, e.g. the G-code handler and another interface, can share
+// a channel.  Commands are handled one at a time in the order they arrive and
+// each response is matched to its command.
+pub struct FeederChannel<R: RawMutex = NoopRawMutex> {
+    command_channel: channel::Channel<R, Request, 2>,
+    responses: Replies<R, Result<FeederResponse>, 2>,
+    notification_channel: channel::Channel<R, FeederNotification, 4>,
+    // Interrupts the command in progress.  Cleared when the next command
+    // starts.
+    abort: AbortSignal<R>,
 }
 
 impl FeederChannel {
+    // A channel used by tasks on a single executor.
     pub fn new() -> Self {
+        Self::with_raw_mutex()
+    }
+}
+
+impl<R: RawMutex> FeederChannel<R> {
+    // A channel guarded by `R`, e.g. `CriticalSectionRawMutex` so the feeder
+    // can run on the other core or an interrupt executor.
+    pub fn with_raw_mutex() -> Self {
         Self {
             command_channel: Channel::new(),
-            response_channel: Channel::new(),
+            responses: Replies::new(),
+            notification_channel: Channel::new(),
+            abort: Signal::new(),
         }
     }
 }
@@ -73,84 +815,178 @@ impl Default for FeederChannel {
     }
 }
 
-pub struct FeederClient<'a> {
-    channel: &'a FeederChannel,
+pub struct FeederClient<'a, R: RawMutex = NoopRawMutex> {
+    channel: &'a FeederChannel<R>,
+}
+
+// Not derived, which would needlessly require `R: Copy`.
+impl<R: RawMutex> Clone for FeederClient<'_, R> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
-impl<'a> FeederClient<'a> {
-    pub fn new(channel: &'a FeederChannel) -> Self {
+impl<R: RawMutex> Copy for FeederClient<'_, R> {}
+
+impl<'a, R: RawMutex> FeederClient<'a, R> {
+    pub fn new(channel: &'a FeederChannel<R>) -> Self {
         Self { channel }
     }
 
-    pub async fn set_config(&mut self, config: FeederConfig) -> Result<()> {
+    async fn command(&mut self, command: FeederCommand) -> Result<FeederResponse> {
+        let pending = self.start(command).await;
+        self.channel.responses.take(pending.0).await
+    }
+
+    // Starts a long running command without waiting for it.  Its response is
+    // held until collected with `finish()`, so it must be.
+    async fn start(&mut self, command: FeederCommand) -> PendingReply {
+        let tag = self.channel.responses.next_tag();
         self.channel
             .command_channel
-            .send(FeederCommand::SetConfig(config))
+            .send(Request { tag, command })
             .await;
-        let response = self.channel.response_channel.receive().await?;
-        match response {
-            Some(_) => Err(Error::InvalidFeederCommandResponse),
-            None => Ok(()),
+        PendingReply(tag)
+    }
+
+    // Waits for the command started with `start_advance()` or `start_home()`
+    // to complete.  Safe to cancel and call again.
+    pub async fn finish(&self, pending: PendingReply) -> Result<()> {
+        match self.channel.responses.take(pending.0).await? {
+            FeederResponse::Done => Ok(()),
+            _ => Err(Error::InvalidFeederCommandResponse),
         }
     }
 
-    pub async fn get_config(&mut self) -> Result<FeederConfig> {
-        self.channel
-            .command_channel
-            .send(FeederCommand::GetConfig())
-            .await;
-        let response = self.channel.response_channel.receive().await?;
-        match response {
-            Some(config) => Ok(config),
-            None => Err(Error::InvalidFeederCommandResponse),
+    // Send a command which is expected to respond with `FeederResponse::Done`.
+    async fn command_done(&mut self, command: FeederCommand) -> Result<()> {
+        match self.command(command).await? {
+            FeederResponse::Done => Ok(()),
+            _ => Err(Error::InvalidFeederCommandResponse),
         }
     }
 
-    pub async fn set_servo_angle(&mut self, angle: Value) -> Result<()> {
-        self.channel
-            .command_channel
-            .send(FeederCommand::SetServoAngle(angle))
-            .await;
-        let response = self.channel.response_channel.receive().await?;
-        match response {
-            Some(_) => Err(Error::InvalidFeederCommandResponse),
-            None => Ok(()),
+    pub async fn set_config(&mut self, config: FeederConfig) -> Result<()> {
+        self.command_done(FeederCommand::SetConfig(config)).await
+    }
+
+    pub async fn get_config(&mut self) -> Result<FeederConfig> {
+        match self.command(FeederCommand::GetConfig()).await? {
+            FeederResponse::Config(config) => Ok(config),
+            _ => Err(Error::InvalidFeederCommandResponse),
         }
     }
 
-    pub async fn advance(&mut self, length: Option<Value>, override_error: bool) -> Result<()> {
-        self.channel
-            .command_channel
-            .send(FeederCommand::Advance {
-                length,
-                override_error,
-            })
-            .await;
-        let response = self.channel.response_channel.receive().await?;
-        match response {
-            Some(_) => Err(Error::InvalidFeederCommandResponse),
-            None => Ok(()),
+    pub async fn get_status(&mut self) -> Result<FeederStatus> {
+        match self.command(FeederCommand::GetStatus).await? {
+            FeederResponse::Status(status) => Ok(status),
+            _ => Err(Error::InvalidFeederCommandResponse),
         }
     }
 
+    pub async fn set_servo_angle(&mut self, angle: Value) -> Result<()> {
+        self.command_done(FeederCommand::SetServoAngle(angle)).await
+    }
+
+    // Moves the servo without updating the feed offset.  For calibration.
+    pub async fn set_servo_raw(&mut self, position: ServoPosition) -> Result<()> {
+        self.command_done(FeederCommand::SetServoRaw(position))
+            .await
+    }
+
+    pub async fn set_button_lockout(&mut self, lockout: bool) -> Result<()> {
+        self.command_done(FeederCommand::SetButtonLockout(lockout))
+            .await
+    }
+
+    // While captured, feedback button presses are sent as
+    // `FeederNotification::ButtonPressed` instead of feeding.
+    pub async fn set_button_capture(&mut self, capture: bool) -> Result<()> {
+        self.command_done(FeederCommand::SetButtonCapture(capture))
+            .await
+    }
+
+    // Replaces the parts and length fed counters, e.g. to restore or reset
+    // them.
+    pub async fn set_counters(&mut self, counters: FeedCounters) -> Result<()> {
+        self.command_done(FeederCommand::SetCounters(counters))
+            .await
+    }
+
+    // Re-arms a feeder which latched an error, such as a jam, so it advances
+    // again.
+    pub async fn clear_latched_error(&mut self) -> Result<()> {
+        self.command_done(FeederCommand::ClearLatchedError).await
+    }
+
+    // Flags the lever position as unknown, e.g. after power was lost part way
+    // through an advance, so the feeder doesn't advance again until homed.
+    pub async fn mark_interrupted(&mut self) -> Result<()> {
+        self.command_done(FeederCommand::MarkInterrupted).await
+    }
+
+    // Stops driving the servo so it goes limp until the next move.
+    pub async fn detach(&mut self) -> Result<()> {
+        self.command_done(FeederCommand::Detach).await
+    }
+
+    pub async fn home(&mut self) -> Result<()> {
+        self.command_done(FeederCommand::Home).await
+    }
+
+    pub async fn start_home(&mut self) -> PendingReply {
+        self.start(FeederCommand::Home).await
+    }
+
+    // Starts wiggling the lever so the feeder can be found on the machine.
+    pub async fn start_identify(&mut self) -> PendingReply {
+        self.start(FeederCommand::Identify).await
+    }
+
+    pub async fn advance(&mut self, length: FeedLength, override_error: bool) -> Result<()> {
+        self.command_done(FeederCommand::Advance {
+            length,
+            override_error,
+        })
+        .await
+    }
+
+    pub async fn start_advance(
+        &mut self,
+        length: FeedLength,
+        override_error: bool,
+    ) -> PendingReply {
+        self.start(FeederCommand::Advance {
+            length,
+            override_error,
+        })
+        .await
+    }
+
+    // Starts waiting for the feeder's servo to finish moving.  Completes once
+    // any command or button feed ahead of it has finished.
+    pub async fn start_wait_for_motion(&mut self) -> PendingReply {
+        self.start(FeederCommand::WaitForMotion).await
+    }
+
     pub async fn enable(&mut self, state: bool) -> Result<()> {
-        self.channel
-            .command_channel
-            .send(FeederCommand::Enable(state))
-            .await;
-        let response = self.channel.response_channel.receive().await?;
-        match response {
-            Some(_) => Err(Error::InvalidFeederCommandResponse),
-            None => Ok(()),
-        }
+        self.command_done(FeederCommand::Enable(state)).await
+    }
+
+    // Stops an in progress advance or home, which then fails with
+    // `Error::Aborted`.  Doesn't wait for the feeder so it can be used while
+    // another command is outstanding.
+    pub fn abort(&self) {
+        self.channel.abort.signal(());
+    }
+
+    pub async fn wait_for_notification(&self) -> FeederNotification {
+        self.channel.notification_channel.receive().await
     }
 
     #[cfg(test)]
     pub async fn shutdown(&mut self) {
-        self.channel
-            .command_channel
-            .send(FeederCommand::Shutdown)
-            .await;
+        let _ = self.start(FeederCommand::Shutdown).await;
     }
 }
 
@@ -166,7 +1002,10 @@ impl FeedbackInputRecognizer {
     }
 
     fn update(&mut self, state: bool) -> bool {
-        let now = Instant::now();
+        self.update_at(state, Instant::now())
+    }
+
+    fn update_at(&mut self, state: bool, now: Instant) -> bool {
         let mut should_feed = false;
         if let Some((last_state, last_time)) = self.last_event {
             let pulse_duration = now.saturating_duration_since(last_time);
@@ -183,17 +1022,110 @@ impl FeedbackInputRecognizer {
     }
 }
 
-pub struct Feeder<S: Servo, I: Input> {
+// Lever angles of the configured positions, converted from
+// `FeederConfig::position_units` when the config is set.
+#[derive(Clone, Copy)]
+struct LeverAngles {
+    advanced: Value,
+    half_advanced: Value,
+    retract: Value,
+}
+
+pub struct Feeder<
+    's,
+    S: Servo,
+    I: Input,
+    P: Peeler = NoPeeler,
+    T: Servo = NoServo,
+    E: CountingInput = NoCounter,
+> {
     servo: S,
     feedback: I,
+    peeler: P,
+    secondary: T,
+    // Measures tape travel for `FeederConfig::encoder_counts_per_mm`.
+    encoder: E,
     config: FeederConfig,
+    angles: LeverAngles,
     enabled: bool,
     feedback_recognizer: FeedbackInputRecognizer,
     advance_offset: Value,
+    feeds: u32,
+    feed_errors: u32,
+    parts: u32,
+    length_fed: Value64,
+    parts_remaining: Option<u32>,
+    hook_count: u32,
+    // Length, in mm, fed towards the next whole part.
+    part_offset: Value,
+    consecutive_feed_errors: u32,
+    interrupted_offset: Option<Value>,
+    angle: Option<Value>,
+    last_error: Option<Error>,
+    progress: AdvanceProgress,
+    attention: bool,
+    // An error, such as a jam, which fails every advance until cleared.
+    latched_error: Option<Error>,
+    // A failed feed can raise a jam hook, an auto-disable and an enable change
+    // hook.
+    pending_notifications: Vec<FeederNotification, 3>,
+    last_button_feed: Option<Instant>,
+    button_lockout: bool,
+    button_capture: bool,
+    // Most recent feedback edge seen while the lever was settling.
+    motion_edge: Option<(bool, Instant)>,
+    // When the running peeler is due to stop.
+    peel_until: Option<Instant>,
+    // Set while the servos aren't being driven, after a detach or once idle
+    // for `FeederConfig::servo_idle_timeout`.
+    output_disabled: bool,
+    // When the lever servo last finished moving.
+    last_motion: Instant,
+    // Staggers this feeder's moves with other feeders'.
+    scheduler: Option<&'s dyn Permits>,
 }
 
-impl<S: Servo, I: Input> Feeder<S, I> {
+impl<'s, S: Servo, I: Input> Feeder<'s, S, I> {
     pub fn new(servo: S, feedback: I) -> Self {
+        Self::from_parts(servo, feedback, NoPeeler, NoServo, NoCounter)
+    }
+}
+
+impl<'s, S: Servo, I: Input, P: Peeler> Feeder<'s, S, I, P> {
+    // A feeder which also drives a cover tape peeler.
+    pub fn new_with_peeler(servo: S, feedback: I, peeler: P) -> Self {
+        Self::from_parts(servo, feedback, peeler, NoServo, NoCounter)
+    }
+}
+
+impl<'s, S: Servo, I: Input, T: Servo> Feeder<'s, S, I, NoPeeler, T> {
+    // A feeder with a second servo which moves along with the lever.
+    pub fn new_dual(servo: S, secondary: T, feedback: I) -> Self {
+        Self::from_parts(servo, feedback, NoPeeler, secondary, NoCounter)
+    }
+}
+
+impl<'s, S: Servo, I: Input, E: CountingInput> Feeder<'s, S, I, NoPeeler, NoServo, E> {
+    // A feeder with a sensor measuring how far the tape moves, for closed
+    // loop feeding.
+    pub fn new_with_encoder(servo: S, feedback: I, encoder: E) -> Self {
+        Self::from_parts(servo, feedback, NoPeeler, NoServo, encoder)
+    }
+}
+
+impl<'s, S: Servo, I: Input, P: Peeler, T: Servo, E: CountingInput> Feeder<'s, S, I, P, T, E> {
+    // Number of consecutive hardware feed errors after which the feeder
+    // disables itself.
+    const MAX_CONSECUTIVE_FEED_ERRORS: u32 = 3;
+
+    // How far the lever is nudged towards advanced while homing or
+    // identifying the feeder.  Too little to advance the tape.
+    const NUDGE_ANGLE: Value = Value::lit("5");
+
+    // Number of times identifying a feeder nudges the lever and moves it back.
+    const IDENTIFY_WIGGLES: u32 = 3;
+
+    fn from_parts(servo: S, feedback: I, peeler: P, secondary: T, encoder: E) -> Self {
         let limits = servo.get_pwm_limits();
         let config = FeederConfig {
             pwm_0: limits.zero,
@@ -204,65 +1136,275 @@ impl<S: Servo, I: Input> Feeder<S, I> {
         Self {
             servo,
             feedback,
+            peeler,
+            secondary,
+            encoder,
+            angles: LeverAngles {
+                advanced: config.advanced_angle,
+                half_advanced: config.half_advanced_angle,
+                retract: config.retract_angle,
+            },
             config,
             enabled: false,
             feedback_recognizer: FeedbackInputRecognizer::new(),
             advance_offset: Value::from_num(0),
+            feeds: 0,
+            feed_errors: 0,
+            parts: 0,
+            length_fed: Value64::from_num(0),
+            parts_remaining: None,
+            hook_count: 0,
+            part_offset: Value::from_num(0),
+            consecutive_feed_errors: 0,
+            interrupted_offset: None,
+            angle: None,
+            last_error: None,
+            progress: AdvanceProgress::default(),
+            attention: false,
+            latched_error: None,
+            pending_notifications: Vec::new(),
+            last_button_feed: None,
+            button_lockout: false,
+            button_capture: false,
+            motion_edge: None,
+            peel_until: None,
+            output_disabled: true,
+            last_motion: Instant::now(),
+            scheduler: None,
         }
     }
 
-    pub async fn run(&mut self, channel: &FeederChannel) {
+    // Waits for a permit from `scheduler` before each lever move so it
+    // doesn't start moving along with too many other feeders.
+    pub fn with_motion_scheduler<R: RawMutex>(mut self, scheduler: &'s MotionScheduler<R>) -> Self {
+        self.scheduler = Some(scheduler);
+        self
+    }
+
+    pub async fn run<R: RawMutex>(&mut self, channel: &FeederChannel<R>) {
+        self.run_shared([channel]).await
+    }
+
+    // Serve `M` logical feeders which share this feeder's servo and feedback
+    // input.  Each channel carries its own config which is swapped in before its
+    // commands are handled.  Since a single task handles all channels, access to
+    // the hardware is naturally serialized.  Feedback button presses advance the
+    // most recently commanded logical feeder.
+    pub async fn run_shared<R: RawMutex, const M: usize>(
+        &mut self,
+        channels: [&FeederChannel<R>; M],
+    ) {
+        let mut configs: [FeederConfig; M] = core::array::from_fn(|_| self.config.clone());
+        let mut active = 0;
+        let mut handled_feedback = false;
         loop {
-            match select(
-                self.feedback.wait_for_state_change(),
-                channel.command_channel.receive(),
-            )
-            .await
-            {
-                Either::First(()) => self.handle_feedback_state_change().await,
-                Either::Second(command) => {
-                    if self.handle_command(channel, command).await {
+            // `select` polls feedback first so an input which is always ready
+            // would starve commands.  After handling feedback, a command which
+            // is already waiting is handled before feedback is waited on again.
+            let pending = if handled_feedback {
+                channels.iter().enumerate().find_map(|(index, channel)| {
+                    channel
+                        .command_channel
+                        .try_receive()
+                        .ok()
+                        .map(|request| (request, index))
+                })
+            } else {
+                None
+            };
+            let event = match pending {
+                Some(pending) => Either3::Second(pending),
+                None => {
+                    let idle_at = self.servo_idle_at();
+                    select3(
+                        self.feedback.wait_for_state_change(),
+                        select_array(channels.map(|channel| channel.command_channel.receive())),
+                        async move {
+                            match idle_at {
+                                Some(at) => Timer::at(at).await,
+                                None => core::future::pending().await,
+                            }
+                        },
+                    )
+                    .await
+                }
+            };
+            handled_feedback = matches!(event, Either3::First(()));
+
+            match event {
+                Either3::First(()) => self.handle_feedback_state_change(channels[active]).await,
+                Either3::Third(()) => self.disable_output(),
+                Either3::Second((request, index)) => {
+                    if index != active {
+                        configs[active] = self.config.clone();
+                        // Configs were validated when they were set, but a servo
+                        // which won't take one back fails the command rather than
+                        // running it with another feeder's config.
+                        if let Err(e) = self.set_config(configs[index].clone()) {
+                            self.last_error = Some(e.clone());
+                            channels[index].responses.post(request.tag, Err(e)).await;
+                            continue;
+                        }
+                        active = index;
+                    }
+                    if self.handle_command(channels[index], request).await {
                         return;
                     }
                 }
             }
+
+            for notification in core::mem::take(&mut self.pending_notifications) {
+                // Don't block the feeder if notifications aren't being
+                // consumed.  The attention flag is still set in the status.
+                let _ = channels[active].notification_channel.try_send(notification);
+            }
         }
     }
-    async fn handle_feedback_state_change(&mut self) {
-        if self
+    async fn handle_feedback_state_change<R: RawMutex>(&mut self, channel: &FeederChannel<R>) {
+        if !self
             .feedback_recognizer
             .update(self.feedback.get_state().await)
         {
-            let _ = self.advance(None, true).await;
+            return;
+        }
+
+        if self.button_capture {
+            // Dropped if the handler is behind; the press can be repeated.
+            let _ = self
+                .pending_notifications
+                .push(FeederNotification::ButtonPressed);
+            return;
+        }
+
+        if self.button_lockout {
+            return;
+        }
+
+        // Rate limit button feeds so a bouncing or abused switch can't dump
+        // a reel of parts.
+        let now = Instant::now();
+        let interval = Duration::from_millis(self.config.button_feed_interval as u64);
+        if let Some(last_feed) = self.last_button_feed {
+            if now.saturating_duration_since(last_feed) < interval {
+                return;
+            }
+        }
+        self.last_button_feed = Some(now);
+
+        if let Err(e) = self.feed(FeedLength::Default, true, channel).await {
+            self.last_error = Some(e);
         }
     }
 
-    async fn handle_command(&mut self, channel: &FeederChannel, command: FeederCommand) -> bool {
+    async fn handle_command<R: RawMutex>(
+        &mut self,
+        channel: &FeederChannel<R>,
+        request: Request,
+    ) -> bool {
+        let Request { tag, command } = request;
+        // Only aborts issued while this command runs apply to it.
+        channel.abort.reset();
+        let abort = &channel.abort;
         let response = match command {
-            FeederCommand::SetConfig(config) => self.set_config(config).map(|()| None),
-            FeederCommand::GetConfig() => Ok(Some(self.get_config())),
-            FeederCommand::SetServoAngle(angle) => self.set_servo_angle(angle).map(|()| None),
+            FeederCommand::SetConfig(config) => {
+                let changed = config != self.config;
+                self.set_config(config).map(|()| {
+                    if changed {
+                        self.pending_notifications
+                            .push(FeederNotification::ConfigChanged(self.config.clone()))
+                            .ok();
+                    }
+                    FeederResponse::Done
+                })
+            }
+            FeederCommand::GetConfig() => Ok(FeederResponse::Config(self.get_config())),
+            FeederCommand::GetStatus => Ok(FeederResponse::Status(self.get_status().await)),
+            FeederCommand::SetServoAngle(angle) => {
+                self.set_servo_angle(angle).map(|()| FeederResponse::Done)
+            }
+            FeederCommand::SetServoRaw(position) => {
+                self.set_servo_raw(position).map(|()| FeederResponse::Done)
+            }
+            FeederCommand::SetButtonLockout(lockout) => {
+                self.button_lockout = lockout;
+                Ok(FeederResponse::Done)
+            }
+            FeederCommand::SetButtonCapture(capture) => {
+                self.button_capture = capture;
+                Ok(FeederResponse::Done)
+            }
+            FeederCommand::ClearLatchedError => {
+                self.latched_error = None;
+                Ok(FeederResponse::Done)
+            }
+            FeederCommand::MarkInterrupted => {
+                self.interrupted_offset = Some(self.advance_offset);
+                self.angle = None;
+                Ok(FeederResponse::Done)
+            }
+            FeederCommand::Detach => {
+                self.angle = None;
+                self.output_disabled = true;
+                self.servo
+                    .disable_output()
+                    .and_then(|()| self.secondary.disable_output())
+                    .map(|()| FeederResponse::Done)
+            }
+            FeederCommand::SetCounters(counters) => {
+                self.parts = counters.parts;
+                self.length_fed = counters.length;
+                self.parts_remaining = counters.remaining;
+                self.part_offset = Value::from_num(0);
+                Ok(FeederResponse::Done)
+            }
+            FeederCommand::Home => self.home(abort).await.map(|()| FeederResponse::Done),
+            FeederCommand::Identify => self.identify(abort).await.map(|()| FeederResponse::Done),
             FeederCommand::Advance {
                 length,
                 override_error,
-            } => self.advance(length, override_error).await.map(|()| None),
+            } => self
+                .feed(length, override_error, channel)
+                .await
+                .map(|()| FeederResponse::Done),
             FeederCommand::Enable(state) => {
+                let was_enabled = self.enabled;
                 self.enable(state);
-                Ok(None)
+                if state && !was_enabled && self.config.enable_home_rate > 0 {
+                    self.retract_on_enable(abort).await
+                } else {
+                    Ok(())
+                }
+                .map(|()| FeederResponse::Done)
+            }
+            FeederCommand::WaitForMotion => {
+                match select(Timer::after(self.servo.motion_remaining()), abort.wait()).await {
+                    Either::First(()) => Ok(FeederResponse::Done),
+                    Either::Second(()) => Err(Error::Aborted),
+                }
             }
             #[cfg(test)]
             FeederCommand::Shutdown => return true,
         };
-        channel.response_channel.send(response).await;
+        if let Err(e) = &response {
+            self.last_error = Some(e.clone());
+        }
+        channel.responses.post(tag, response).await;
 
         false
     }
 
     fn set_config(&mut self, config: FeederConfig) -> Result<()> {
-        self.servo.set_pwm_limits(PwmLimits {
-            zero: config.pwm_0,
-            one_eighty: config.pwm_180,
-        })?;
+        let angles = config.lever_angles()?;
+        // The limits are checked against the new period.
+        self.servo.set_frame_rate(config.servo_frame_rate)?;
+        if let Err(e) = self.servo.set_pwm_limits(config.pwm_limits()) {
+            // Failing to restore the old rate leaves the servo no worse off.
+            let _ = self.servo.set_frame_rate(self.config.servo_frame_rate);
+            return Err(e);
+        }
+        self.servo
+            .set_speed(Some(config.slew_rate).filter(|rate| *rate > 0));
+        self.angles = angles;
         self.config = config;
         Ok(())
     }
@@ -271,80 +1413,746 @@ impl<S: Servo, I: Input> Feeder<S, I> {
         self.config.clone()
     }
 
+    async fn get_status(&mut self) -> FeederStatus {
+        FeederStatus {
+            enabled: self.enabled,
+            attention: self.attention,
+            button_lockout: self.button_lockout,
+            feedback: self.feedback.get_state().await,
+            feeds: self.feeds,
+            feed_errors: self.feed_errors,
+            parts: self.parts,
+            length_fed: self.length_fed,
+            parts_remaining: self.parts_remaining,
+            hook_count: self.hook_count,
+            interrupted_offset: self.interrupted_offset,
+            angle: self.angle,
+            advance_offset: self.advance_offset,
+            last_error: self.last_error.clone(),
+            latched_error: self.latched_error.clone(),
+            progress: self.progress,
+        }
+    }
+
     fn set_servo_angle(&mut self, angle: Value) -> Result<()> {
-        if self.enabled {
-            self.servo.set_angle(angle)
+        if !self.enabled {
+            return Err(Error::FeederDisabled(None));
+        }
+        let trim = self.config.trim_angle;
+        let trimmed = angle.saturating_add(trim);
+        if !(self.config.min_angle..=self.config.max_angle).contains(&trimmed) {
+            return Err(Error::AngleOutOfRange);
+        }
+        if self.output_disabled {
+            // The servo starts from where it was left rather than wherever it
+            // was last commanded by the driver.
+            if let Some(last) = self.angle {
+                self.servo.set_angle(last.saturating_add(trim))?;
+            }
+            self.output_disabled = false;
+        }
+        self.servo.set_angle(trimmed)?;
+        self.angle = Some(angle);
+        self.last_motion = Instant::now() + self.servo.motion_remaining();
+        Ok(())
+    }
+
+    // Waits for this feeder's turn to move when moves are being staggered.
+    // The permit is held until the lever has settled.
+    async fn motion_permit(&self) -> Option<MotionPermit<'s>> {
+        match self.scheduler {
+            Some(scheduler) => Some(MotionPermit::acquire(scheduler).await),
+            None => None,
+        }
+    }
+
+    // When the servos are due to stop being driven, if ever.
+    fn servo_idle_at(&self) -> Option<Instant> {
+        let timeout = self.config.servo_idle_timeout;
+        if timeout == 0 || self.output_disabled {
+            return None;
+        }
+        Some(self.last_motion + Duration::from_millis(timeout as u64))
+    }
+
+    // Stops driving the servos once idle.  The last angle is kept to be
+    // re-asserted before the next move.
+    fn disable_output(&mut self) {
+        // Failing to disable the output only leaves the servo buzzing.
+        let _ = self
+            .servo
+            .disable_output()
+            .and_then(|()| self.secondary.disable_output());
+        self.output_disabled = true;
+    }
+
+    fn set_servo_raw(&mut self, position: ServoPosition) -> Result<()> {
+        match position {
+            ServoPosition::Angle(angle) => self.set_servo_angle(angle),
+            ServoPosition::PulseWidth(micros) => {
+                if !self.enabled {
+                    return Err(Error::FeederDisabled(None));
+                }
+                self.servo.set_pulse_width(micros)?;
+                self.angle = None;
+                self.output_disabled = false;
+                self.last_motion = Instant::now();
+                Ok(())
+            }
+        }
+    }
+
+    // Waits `settle_time` ms for the servo to settle.  If aborted, the lever's
+    // position is recorded as unknown and `Error::Aborted` is returned.
+    // Feedback edges are consumed while waiting so they aren't mistaken for
+    // button presses afterwards.
+    async fn settle<R: RawMutex>(
+        &mut self,
+        settle_time: u32,
+        abort: &AbortSignal<R>,
+    ) -> Result<()> {
+        let result = self.wait_settled(settle_time, abort).await;
+        if let Err(Error::Aborted) = result {
+            self.interrupted_offset = Some(self.advance_offset);
+        }
+        result
+    }
+
+    // Waits for the lever to settle without marking the feeder interrupted
+    // if aborted, for moves which don't drive the tape.
+    async fn wait_settled<R: RawMutex>(
+        &mut self,
+        settle_time: u32,
+        abort: &AbortSignal<R>,
+    ) -> Result<()> {
+        // Settling starts once any profiled move has finished.
+        let settle_time = Duration::from_micros(settle_time as u64 * 1000);
+        let settled_at = Instant::now() + self.servo.motion_remaining() + settle_time;
+        loop {
+            match select3(
+                Timer::at(settled_at),
+                abort.wait(),
+                self.feedback.wait_for_state_change(),
+            )
+            .await
+            {
+                Either3::First(()) => return Ok(()),
+                Either3::Second(()) => return Err(Error::Aborted),
+                Either3::Third(()) => {
+                    self.motion_edge = Some((self.feedback.get_state().await, Instant::now()));
+                }
+            }
+        }
+    }
+
+    // Establish a known lever position by retracting, nudging the lever
+    // slightly towards advanced, and retracting again.  Afterwards the feedback
+    // input is expected to report ready.
+    async fn home<R: RawMutex>(&mut self, abort: &AbortSignal<R>) -> Result<()> {
+        let retract = (self.angles.retract, self.config.retract_settle_time);
+        let nudge = (
+            self.nudge_angle(self.angles.retract),
+            self.config.advance_settle_time,
+        );
+
+        for (angle, settle_time) in [retract, nudge, retract] {
+            let _permit = self.motion_permit().await;
+            self.set_servo_angle(angle)?;
+            self.secondary
+                .set_angle(self.config.secondary_retract_angle)?;
+            self.settle(settle_time, abort).await?;
+        }
+
+        self.advance_offset = Value::from_num(0);
+        self.interrupted_offset = None;
+        self.feedback_recognizer.reset();
+        // The nudge may have actuated the switch.
+        self.motion_edge = None;
+
+        if !self.config.ignore_feeback_pin && self.feedback.get_state().await {
+            return Err(Error::HomingFailed);
+        }
+
+        Ok(())
+    }
+
+    // Wiggles the lever so a technician can tell which feeder this is, then
+    // returns it to where it was.  The feed offset is left alone.
+    async fn identify<R: RawMutex>(&mut self, abort: &AbortSignal<R>) -> Result<()> {
+        let angle = self.angle.unwrap_or(self.angles.retract);
+        let nudge_angle = self.nudge_angle(angle);
+
+        let wiggle = [
+            (nudge_angle, self.config.advance_settle_time),
+            (angle, self.config.retract_settle_time),
+        ];
+        for _ in 0..Self::IDENTIFY_WIGGLES {
+            for (target, settle_time) in wiggle {
+                let _permit = self.motion_permit().await;
+                self.set_servo_angle(target)?;
+                // The tape hasn't moved, so an aborted wiggle just puts the
+                // lever back rather than leaving the feeder needing a home.
+                if let Err(e) = self.wait_settled(settle_time, abort).await {
+                    self.set_servo_angle(angle)?;
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn nudge_angle(&self, angle: Value) -> Value {
+        if self.angles.advanced >= self.angles.retract {
+            angle + Self::NUDGE_ANGLE
         } else {
-            Err(Error::FeederDisabled)
+            angle - Self::NUDGE_ANGLE
+        }
+        .clamp(Value::from_num(0), Value::from_num(180))
+    }
+
+    // Advance the feeder and update the feed counters.
+    async fn feed<R: RawMutex>(
+        &mut self,
+        length: FeedLength,
+        override_error: bool,
+        channel: &FeederChannel<R>,
+    ) -> Result<()> {
+        let result = match self.feed_length_mm(length) {
+            Ok(length) => self
+                .advance_and_peel(length, override_error, channel)
+                .await
+                .map(|()| length),
+            Err(e) => Err(e),
+        };
+        match &result {
+            Ok(length) => {
+                self.feeds = self.feeds.wrapping_add(1);
+                let parts = self.parts;
+                self.count_parts(*length);
+                self.count_remaining(self.parts.wrapping_sub(parts) as i32);
+                self.length_fed = self.length_fed.saturating_add(Value64::from(*length));
+                self.consecutive_feed_errors = 0;
+                self.run_hook(HookEvent::FeedComplete);
+            }
+            Err(e) => {
+                self.feed_errors = self.feed_errors.wrapping_add(1);
+                // Errors caused by the request rather than the hardware don't
+                // count towards auto-disabling the feeder.
+                if !matches!(
+                    e,
+                    Error::FeederDisabled(_)
+                        | Error::InvalidFeedLength(_)
+                        | Error::HalfAdvanceUnsupported(_)
+                        | Error::NoPartPitch
+                        | Error::FeederJammed(_)
+                        | Error::Aborted
+                        | Error::FeederInterrupted
+                ) {
+                    self.consecutive_feed_errors += 1;
+                    self.run_hook(HookEvent::Jam);
+                }
+            }
+        }
+
+        if self.consecutive_feed_errors >= Self::MAX_CONSECUTIVE_FEED_ERRORS {
+            if let Err(e) = &result {
+                self.auto_disable(e.clone());
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    fn feed_length_mm(&self, length: FeedLength) -> Result<Value> {
+        match length {
+            FeedLength::Default => Ok(self.config.feed_length),
+            FeedLength::Millimeters(length) => Ok(length),
+            FeedLength::Parts(_) if self.config.mm_per_part == 0 => Err(Error::NoPartPitch),
+            FeedLength::Parts(parts) => Value::checked_from_num(parts)
+                .and_then(|parts| parts.checked_mul(self.config.mm_per_part))
+                .ok_or(Error::InvalidFeedLength(Value::MAX)),
+        }
+    }
+
+    // Backing the tape up un-counts the parts moved back so they can be
+    // picked again.
+    fn count_parts(&mut self, length: Value) {
+        if self.config.mm_per_part == 0 {
+            self.parts = if length < 0 {
+                self.parts.saturating_sub(1)
+            } else {
+                self.parts.wrapping_add(1)
+            };
+            return;
+        }
+        // Feeds shorter than a part only count once they add up to one.
+        self.part_offset += length;
+        let parts = (self.part_offset / self.config.mm_per_part).floor();
+        self.parts = self.parts.saturating_add_signed(parts.to_num());
+        self.part_offset -= parts * self.config.mm_per_part;
+    }
+
+    // Takes the parts just counted off those remaining and warns when the
+    // tape is about to run out.
+    fn count_remaining(&mut self, parts: i32) {
+        let Some(remaining) = self.parts_remaining else {
+            return;
+        };
+        let new_remaining = remaining.saturating_add_signed(parts.saturating_neg());
+        self.parts_remaining = Some(new_remaining);
+
+        let threshold = self.config.runout_warning;
+        if remaining >= threshold && new_remaining < threshold {
+            self.pending_notifications
+                .push(FeederNotification::RunoutWarning(new_remaining))
+                .ok();
+        }
+    }
+
+    // Take the feeder out of service and notify the host.
+    fn auto_disable(&mut self, reason: Error) {
+        self.enabled = false;
+        self.attention = true;
+        self.consecutive_feed_errors = 0;
+        self.pending_notifications
+            .push(FeederNotification::AutoDisabled(reason))
+            .ok();
+        self.run_hook(HookEvent::EnableChange);
+    }
+
+    // Rechecks a feedback switch which reports not ready up to `retry_count`
+    // times before failing.  The lever hasn't moved yet so an abort leaves its
+    // position known.  As when settling, feedback edges are consumed while
+    // waiting so they aren't mistaken for button presses.
+    async fn wait_until_ready<R: RawMutex>(&mut self, abort: &AbortSignal<R>) -> Result<()> {
+        let retry_delay = Duration::from_millis(self.config.retry_delay_ms as u64);
+        let mut retries = self.config.retry_count;
+        while self.feedback.get_state().await {
+            if retries == 0 {
+                return Err(Error::FeederNotReady(None));
+            }
+            retries -= 1;
+            let retry_at = Instant::now() + retry_delay;
+            loop {
+                match select3(
+                    Timer::at(retry_at),
+                    abort.wait(),
+                    self.feedback.wait_for_state_change(),
+                )
+                .await
+                {
+                    Either3::First(()) => break,
+                    Either3::Second(()) => return Err(Error::Aborted),
+                    Either3::Third(()) => {}
+                }
+            }
         }
+        Ok(())
     }
 
-    async fn settle(&self) {
-        Timer::after_micros(self.config.settle_time as u64 * 1000).await;
+    // Advances by `length` and runs the peeler for its configured time.  The
+    // feed has already happened when the peeler is stopped early by an abort
+    // so that doesn't fail it.
+    async fn advance_and_peel<R: RawMutex>(
+        &mut self,
+        length: Value,
+        override_error: bool,
+        channel: &FeederChannel<R>,
+    ) -> Result<()> {
+        let mut result = self.advance(length, override_error, channel).await;
+        if result.is_ok() && length > 0 && self.config.peel_after {
+            result = self.start_peeling();
+        }
+
+        if let Some(peel_until) = self.peel_until.take() {
+            if result.is_ok() {
+                select(Timer::at(peel_until), channel.abort.wait()).await;
+            }
+            result = result.and(self.peeler.set_strength(Value::from_num(0)));
+        }
+        result
+    }
+
+    fn start_peeling(&mut self) -> Result<()> {
+        if self.config.peel_time == 0 {
+            return Ok(());
+        }
+        self.peel_until =
+            Some(Instant::now() + Duration::from_millis(self.config.peel_time as u64));
+        self.peeler.set_strength(self.config.peel_strength / 100)
     }
 
-    async fn advance(&mut self, length: Option<Value>, override_error: bool) -> Result<()> {
+    async fn advance<R: RawMutex>(
+        &mut self,
+        mut length: Value,
+        override_error: bool,
+        channel: &FeederChannel<R>,
+    ) -> Result<()> {
+        let abort = &channel.abort;
+        if !self.enabled {
+            return Err(Error::FeederDisabled(None));
+        }
+        if self.interrupted_offset.is_some() {
+            return Err(Error::FeederInterrupted);
+        }
+        if let Some(e) = &self.latched_error {
+            return Err(e.clone());
+        }
+
         let override_error = override_error || self.config.ignore_feeback_pin;
-        if !override_error && self.feedback.get_state().await {
-            return Err(Error::FeederNotReady);
+        if !override_error {
+            self.wait_until_ready(abort).await?;
         }
 
-        let mut length = length.unwrap_or(self.config.feed_length);
+        self.motion_edge = None;
+
+        let full_advance = self.config.full_advance();
+        let half_advance = self.config.half_advance_length();
+
+        let interpolate = self.config.interpolate_angle;
 
-        // Ensure the the feed length is an even multiple of 2mm.
-        if length % Value::from_num(2) != 0 {
+        // Ensure the the feed length is a multiple of a half advance.  An
+        // interpolated lever can stop anywhere.  The lever's ratchet only
+        // engages the tape going forward so it can only be backed up by
+        // feeders with a release angle.
+        if (length < 0 && self.config.release_angle == 0)
+            || half_advance == 0
+            || (!interpolate && length % half_advance != 0)
+        {
             return Err(Error::InvalidFeedLength(length));
         }
 
+        let max_offset = match self.config.retract_policy {
+            RetractPolicy::AfterDistance if interpolate => {
+                self.config.retract_distance.min(full_advance)
+            }
+            RetractPolicy::AfterDistance if self.config.retract_distance <= half_advance => {
+                half_advance
+            }
+            _ => full_advance,
+        };
+        // Two position feeders can't stop at the half advanced angle.
+        if !interpolate
+            && !self.config.half_advance
+            && (length % full_advance != 0 || max_offset != full_advance)
+        {
+            return Err(Error::HalfAdvanceUnsupported(length));
+        }
+        self.progress = AdvanceProgress {
+            cycles: 0,
+            remaining: length,
+        };
+
+        // Negative lengths back the tape up, for example after a mis-pick.
+        if length < 0 {
+            self.reverse(length, max_offset, channel).await?;
+        }
+
+        if length > 0 && !self.config.peel_after {
+            self.start_peeling()?;
+        }
+
+        let requested = length;
+        let start_count = self.encoder.count();
+        let mut corrections = 0;
+        loop {
+            self.advance_cycles(length, max_offset, override_error, channel)
+                .await?;
+
+            // Closed loop feeders top up a short advance and report one
+            // which can't be topped up.
+            if self.config.encoder_counts_per_mm == 0 || requested <= 0 {
+                break;
+            }
+            let measured = self.measured_travel(start_count);
+            let shortfall = requested - measured;
+            if shortfall < -self.config.feed_tolerance {
+                return Err(Error::Overfeed(measured));
+            }
+            if shortfall <= self.config.feed_tolerance {
+                break;
+            }
+            if corrections == self.config.max_corrections {
+                return Err(Error::Underfeed(measured));
+            }
+            corrections += 1;
+            length = self.correction_length(shortfall);
+        }
+
+        // Reset the feedback as a button recognizer since we just fed.
+        self.feedback_recognizer.reset();
+        if let Some((state, at)) = self.motion_edge.take() {
+            if self.config.motion_feedback == MotionFeedback::Replay {
+                self.feedback_recognizer.update_at(state, at);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Tape moved since the travel sensor read `start_count`, in mm.
+    fn measured_travel(&mut self, start_count: i32) -> Value {
+        let counts = self.encoder.count().wrapping_sub(start_count);
+        let travel = Value64::from_num(counts) / Value64::from(self.config.encoder_counts_per_mm);
+        Value::saturating_from_num(travel)
+    }
+
+    // Length of the advance which best makes up `shortfall` mm.  The lever
+    // only stops at the half and fully advanced positions unless its angle is
+    // interpolated.
+    fn correction_length(&self, shortfall: Value) -> Value {
+        if self.config.interpolate_angle {
+            return shortfall;
+        }
+        let step = if self.config.half_advance {
+            self.config.half_advance_length()
+        } else {
+            self.config.full_advance()
+        };
+        let steps = shortfall
+            .saturating_div(step)
+            .round()
+            .max(Value::from_num(1));
+        steps.saturating_mul(step)
+    }
+
+    // Runs the advance/retract cycles which feed `length` mm.
+    async fn advance_cycles<R: RawMutex>(
+        &mut self,
+        mut length: Value,
+        max_offset: Value,
+        override_error: bool,
+        channel: &FeederChannel<R>,
+    ) -> Result<()> {
+        let abort = &channel.abort;
         while length > Value::from_num(0) {
-            // The feeder can advance in maximum of 4mm increments (the distance between feed
-            // holes.  A feed longer than that needs to be broken up into a series of
+            // The feeder can advance at most a full advance (`holes_per_retract` feed holes)
+            // per cycle.  A feed longer than that needs to be broken up into a series of
             // advance/retract cycles.
             //
-            // Additionally, in order to support tap with 2mm part spacing, we have a
+            // Additionally, in order to support tape with parts every half advance, we have a
             // `half_advanced_angle`.  Some feeders can't be retracted from the half advanced
-            // state so we need to track the a feed offset and only retract the servo when
-            // it reaches 4mm.  This means that sometimes we can only advance 2mm before a retract.
+            // state so we need to track the a feed offset and, depending on the retract policy,
+            // only retract the servo when it reaches a full advance.  This means that sometimes
+            // we can only advance half way before a retract.
+            if self.advance_offset >= max_offset {
+                // Left half advanced by a feed with a different policy.
+                self.retract(abort).await?;
+            }
 
             // Caclulate the maximum amount we can advance this cycle, taking into account the
             // current offset.
-            let advance_length = core::cmp::min(Value::from_num(4) - self.advance_offset, length);
+            let advance_length = core::cmp::min(max_offset - self.advance_offset, length);
 
             // Caclulate the absolute advance position that the advance length equates to, taking
             // into account the current offset.
             let advance_to = self.advance_offset + advance_length;
 
             // Depending on the final advace position, advance to either the full or half angle.
-            if advance_to == Value::from_num(2) {
-                self.set_servo_angle(self.config.half_advanced_angle)?;
-            } else {
-                self.set_servo_angle(self.config.advanced_angle)?;
+            let permit = self.motion_permit().await;
+            let commanded_at = Instant::now();
+            self.move_lever(advance_to)?;
+            self.settle(self.config.advance_settle_time, abort).await?;
+            drop(permit);
+            if !override_error {
+                self.check_for_jam(commanded_at, abort).await?;
             }
 
-            self.settle().await;
-
-            if self.config.always_retract || advance_to == Value::from_num(4) {
-                // If either the feeder should retract on every advance of we have reach a 4mm
-                // offset, retract the servro and reset the offset.
-                self.set_servo_angle(self.config.retract_angle)?;
-                self.settle().await;
-                self.advance_offset = Value::from_num(0);
-            } else {
-                // ... otherwise set the offset to our current advance state.
-                self.advance_offset = advance_to;
+            let retract = match self.config.retract_policy {
+                RetractPolicy::FullAdvance | RetractPolicy::AfterDistance => {
+                    advance_to == max_offset
+                }
+                RetractPolicy::Always => true,
+                RetractPolicy::EndOfFeed => advance_to == max_offset || advance_length == length,
+            };
+            if retract {
+                self.retract(abort).await?;
             }
 
             // Update the length remaining to advance by the amount advanced this cycle.
             length -= advance_length;
+            self.cycle_done(length, channel);
         }
+        Ok(())
+    }
 
-        // Reset the feedback as a button recognizer since we just fed.
-        self.feedback_recognizer.reset();
+    // Waits out the rest of `FeederConfig::jam_timeout` after the lever was
+    // commanded at `commanded_at` for the feedback switch to change, unless it
+    // already changed while settling.  Without a change the jam is latched
+    // and the lever is left where it is.
+    async fn check_for_jam<R: RawMutex>(
+        &mut self,
+        commanded_at: Instant,
+        abort: &AbortSignal<R>,
+    ) -> Result<()> {
+        if self.config.jam_timeout == 0
+            || self.motion_edge.is_some_and(|(_, at)| at >= commanded_at)
+        {
+            return Ok(());
+        }
 
+        let deadline = commanded_at + Duration::from_millis(self.config.jam_timeout as u64);
+        match select3(
+            Timer::at(deadline),
+            abort.wait(),
+            self.feedback.wait_for_state_change(),
+        )
+        .await
+        {
+            Either3::First(()) => {
+                self.latched_error = Some(Error::FeederJammed(None));
+                self.run_hook(HookEvent::Jam);
+                Err(Error::FeederJammed(None))
+            }
+            Either3::Second(()) => {
+                self.interrupted_offset = Some(self.advance_offset);
+                Err(Error::Aborted)
+            }
+            Either3::Third(()) => {
+                self.motion_edge = Some((self.feedback.get_state().await, Instant::now()));
+                Ok(())
+            }
+        }
+    }
+
+    // Backs the tape up by `-length` mm.  From retracted the lever rests on
+    // the ratchet at `FeederConfig::release_angle` and sweeps out to
+    // `max_offset` with the pawl clear so nothing is fed.  There the pawl
+    // re-engages and the lever is brought back towards retracted, pulling the
+    // tape with it.  As with an advance, the lever may be left part way and
+    // the feed offset tracks it.
+    async fn reverse<R: RawMutex>(
+        &mut self,
+        mut length: Value,
+        max_offset: Value,
+        channel: &FeederChannel<R>,
+    ) -> Result<()> {
+        let abort = &channel.abort;
+        while length < 0 {
+            if self.advance_offset == 0 {
+                let _permit = self.motion_permit().await;
+                self.set_servo_angle(self.config.release_angle)?;
+                self.settle(self.config.retract_settle_time, abort).await?;
+                self.move_lever(max_offset)?;
+                self.settle(self.config.advance_settle_time, abort).await?;
+            }
+
+            let reverse_length = core::cmp::min(self.advance_offset, -length);
+            let reverse_to = self.advance_offset - reverse_length;
+            let permit = self.motion_permit().await;
+            self.move_lever(reverse_to)?;
+            self.settle(self.config.retract_settle_time, abort).await?;
+            drop(permit);
+
+            length += reverse_length;
+            self.cycle_done(length, channel);
+        }
         Ok(())
     }
 
+    // Records a completed cycle of an advance or reverse which has
+    // `remaining` mm left to go.
+    fn cycle_done<R: RawMutex>(&mut self, remaining: Value, channel: &FeederChannel<R>) {
+        self.progress = AdvanceProgress {
+            cycles: self.progress.cycles + 1,
+            remaining,
+        };
+        if remaining != 0 {
+            // Progress is best effort so a host which isn't reading
+            // notifications doesn't stall the advance.
+            let _ = channel
+                .notification_channel
+                .try_send(FeederNotification::Progress(self.progress));
+        }
+    }
+
+    // Moves the lever, and the secondary servo with it, to feed offset
+    // `offset`.  The offset is updated as soon as the lever is commanded so an
+    // aborted settle records where it was heading.
+    fn move_lever(&mut self, offset: Value) -> Result<()> {
+        self.set_servo_angle(self.lever_angle(offset))?;
+        self.advance_offset = offset;
+        self.secondary.set_angle(self.secondary_angle(offset))
+    }
+
+    // Angle of the secondary servo at feed offset `offset`.
+    fn secondary_angle(&self, offset: Value) -> Value {
+        if offset == 0 {
+            return self.config.secondary_retract_angle;
+        }
+        let retract = Value64::from(self.config.secondary_retract_angle);
+        let advanced = Value64::from(self.config.secondary_advanced_angle);
+        let angle = retract
+            + (advanced - retract) * Value64::from(offset)
+                / Value64::from(self.config.full_advance());
+        Value::saturating_from_num(angle)
+    }
+
+    // Angle of the lever at feed offset `offset`.
+    fn lever_angle(&self, offset: Value) -> Value {
+        if self.config.interpolate_angle {
+            self.interpolated_angle(offset, self.config.full_advance())
+        } else if offset == 0 {
+            self.angles.retract
+        } else if offset == self.config.half_advance_length() {
+            self.angles.half_advanced
+        } else {
+            self.angles.advanced
+        }
+    }
+
+    // Angle of the lever `offset` mm into a full advance.
+    fn interpolated_angle(&self, offset: Value, full_advance: Value) -> Value {
+        let retract = Value64::from(self.angles.retract);
+        let advanced = Value64::from(self.angles.advanced);
+        let angle =
+            retract + (advanced - retract) * Value64::from(offset) / Value64::from(full_advance);
+        Value::saturating_from_num(angle)
+    }
+
+    async fn retract<R: RawMutex>(&mut self, abort: &AbortSignal<R>) -> Result<()> {
+        let _permit = self.motion_permit().await;
+        self.move_lever(Value::from_num(0))?;
+        self.settle(self.config.retract_settle_time, abort).await
+    }
+
+    // Gently moves the lever to retract at `FeederConfig::enable_home_rate`
+    // so its position is known before the first command.
+    async fn retract_on_enable<R: RawMutex>(&mut self, abort: &AbortSignal<R>) -> Result<()> {
+        self.servo.set_speed(Some(self.config.enable_home_rate));
+        let result = self.retract(abort).await;
+        self.servo
+            .set_speed(Some(self.config.slew_rate).filter(|rate| *rate > 0));
+        result
+    }
+
     fn enable(&mut self, enabled: bool) {
-        self.enabled = enabled
+        if enabled {
+            self.attention = false;
+            self.consecutive_feed_errors = 0;
+        }
+        if enabled != self.enabled {
+            self.enabled = enabled;
+            self.run_hook(HookEvent::EnableChange);
+        }
+    }
+
+    fn run_hook(&mut self, event: HookEvent) {
+        let action = match event {
+            HookEvent::FeedComplete => self.config.feed_complete_hook,
+            HookEvent::Jam => self.config.jam_hook,
+            HookEvent::EnableChange => self.config.enable_hook,
+        };
+        match action {
+            HookAction::None => {}
+            HookAction::Count => self.hook_count = self.hook_count.wrapping_add(1),
+            action => {
+                // Dropped if the notifications queue is full.
+                self.pending_notifications
+                    .push(FeederNotification::Hook(event, action))
+                    .ok();
+            }
+        }
     }
 }
@@ -0,0 +1,70 @@
+use core::fmt::Display;
+
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::Value;
+
+// How long `HookAction::PulseAux` drives the aux output.
+pub const AUX_PULSE: Duration = Duration::from_millis(50);
+
+// Feeder events which can trigger a hook.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookEvent {
+    FeedComplete,
+    // A feed failed because of the feeder rather than the request, e.g. a
+    // jammed tape holding the feedback switch.
+    Jam,
+    EnableChange,
+}
+
+impl Display for HookEvent {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::FeedComplete => write!(f, "feed-complete"),
+            Self::Jam => write!(f, "jam"),
+            Self::EnableChange => write!(f, "enable-change"),
+        }
+    }
+}
+
+// What a feeder does when a hooked event happens.  Limited to a fixed list
+// so a misconfigured hook can't interfere with feeding.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum HookAction {
+    #[default]
+    None,
+    // Pulses the board's aux output for `AUX_PULSE`.
+    PulseAux,
+    LedOn,
+    LedOff,
+    // Writes an `event:` line to the host.
+    Notify,
+    // Increments the feeder's `hook_events_total` metric.
+    Count,
+}
+
+impl HookAction {
+    pub fn from_value(value: Value) -> Option<Self> {
+        match value.checked_to_num::<u8>()? {
+            0 => Some(Self::None),
+            1 => Some(Self::PulseAux),
+            2 => Some(Self::LedOn),
+            3 => Some(Self::LedOff),
+            4 => Some(Self::Notify),
+            5 => Some(Self::Count),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(self) -> Value {
+        Value::from_num(self as u8)
+    }
+}
+
+// Board outputs driven by hooks.  Boards without them simply don't pass any
+// to `GCodeHandler::with_hook_outputs` and those actions are ignored.
+pub trait HookOutputs {
+    fn set_led(&mut self, on: bool);
+    fn set_aux(&mut self, on: bool);
+}
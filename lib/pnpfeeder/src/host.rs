@@ -0,0 +1,68 @@
+use std::io::BufRead;
+
+use crate::{Error, GCodeEvent, LineChecker, Result};
+
+// Host tools share the firmware's command model so events may be built on one
+// thread and handled on another.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<GCodeEvent>();
+    assert_send::<Error>();
+};
+
+// Reads G-code events from a script or log the same way a transport reads them
+// from a connection: comments are dropped, blank lines skipped and any line
+// numbers and checksums validated before parsing.
+pub struct EventReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line_checker: LineChecker,
+}
+
+impl<R: BufRead> EventReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_checker: LineChecker::new(),
+        }
+    }
+
+    // Line number a host should resend from after a framing error.
+    pub fn resend_line(&self) -> u32 {
+        self.line_checker.resend_line()
+    }
+}
+
+impl<R: BufRead> Iterator for EventReader<R> {
+    type Item = Result<GCodeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(_) => return Some(Err(Error::Io)),
+            };
+            let line = strip_comments(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            return Some(self.line_checker.check(line).and_then(GCodeEvent::parse));
+        }
+    }
+}
+
+// Drops `;` comments, which run to the end of the line, and `(...)` comments.
+fn strip_comments(line: &str) -> std::string::String {
+    let mut stripped = std::string::String::with_capacity(line.len());
+    let mut in_paren = false;
+    for c in line.chars() {
+        match (in_paren, c) {
+            (false, ';') => break,
+            (false, '(') => in_paren = true,
+            (false, c) => stripped.push(c),
+            (true, ')') => in_paren = false,
+            (true, _) => {}
+        }
+    }
+    stripped
+}
@@ -11,3 +11,21 @@ pub trait Input {
     #[allow(async_fn_in_trait)]
     async fn get_state(&mut self) -> bool;
 }
+
+// An input which counts pulses, such as a rotary encoder on the sprocket or
+// an optical sensor which sees the sprocket holes pass.  Measures how far the
+// tape actually moved.
+pub trait CountingInput {
+    // Running count of pulses, wrapping on overflow.  Counts down while the
+    // tape backs up if the sensor can tell.
+    fn count(&mut self) -> i32;
+}
+
+// Stands in for the travel sensor of feeders without one.
+pub struct NoCounter;
+
+impl CountingInput for NoCounter {
+    fn count(&mut self) -> i32 {
+        0
+    }
+}
@@ -0,0 +1,261 @@
+// Host jobs and unattended soak tests, which both count feeds and errors per
+// feeder until they end.
+
+use az::CheckedCast;
+use core::fmt::Write as _;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+
+use crate::{
+    soak::Soak, ConfigStore, Error, FeedLength, GCodeHandler, Job, Line, Result, MAX_FEEDERS,
+};
+
+impl<'a, W: Write, C: ConfigStore, R: RawMutex> GCodeHandler<'a, W, C, R> {
+    // Feeds and feed errors of each logical feeder.
+    async fn feeder_counts(&mut self) -> Result<Vec<(u32, u32), MAX_FEEDERS>> {
+        let mut counts = Vec::new();
+        for index in 0..self.feeders.len() {
+            let (_, feeder) = self.resolve_feeder(Some(index))?;
+            let status = feeder.get_status().await?;
+            counts.push((status.feeds, status.feed_errors)).ok();
+        }
+        Ok(counts)
+    }
+
+    async fn set_all_button_lockouts(&mut self, lockout: bool) -> Result<()> {
+        for feeder in self.feeders.iter_mut() {
+            feeder.set_button_lockout(lockout).await?;
+        }
+        Ok(())
+    }
+
+    // Starts a job, optionally identified by `S<id>`.  Button feeds are locked
+    // out until the job ends with M661.  Starting a job while one is in
+    // progress restarts it.
+    pub(crate) async fn handle_m660(&mut self, command: Line) -> Result<()> {
+        let mut id = None;
+        for arg in command.arguments() {
+            match arg.letter {
+                'S' => {
+                    id = Some(
+                        arg.value
+                            .checked_cast()
+                            .ok_or(Error::InvalidArgument(arg.letter))?,
+                    )
+                }
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+
+        let counts = self.feeder_counts().await?;
+        self.set_all_button_lockouts(true).await?;
+        self.job = Some(Job {
+            id,
+            started_at: self.clock.now(),
+            start: Instant::now(),
+            counts,
+        });
+
+        Ok(())
+    }
+
+    // Ends the job started by M660 and reports a summary:
+    // `job: <start timestamp> [id=<id>] duration=<seconds> feeds=<count> errors=<count>`
+    // followed by `job feeder <index>: feeds=<count> errors=<count>` for each
+    // feeder.
+    pub(crate) async fn handle_m661(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        let job = self.job.take().ok_or(Error::NoJob)?;
+        self.set_all_button_lockouts(false).await?;
+        let counts = self.feeder_counts().await?;
+        let counts: Vec<(u32, u32), MAX_FEEDERS> = counts
+            .iter()
+            .zip(&job.counts)
+            .map(|(now, start)| (now.0.wrapping_sub(start.0), now.1.wrapping_sub(start.1)))
+            .collect();
+
+        let duration = job.start.elapsed().as_millis();
+        let mut s: String<128> = String::new();
+        write!(s, "job: {}", job.started_at).ok();
+        if let Some(id) = job.id {
+            write!(s, " id={}", id).ok();
+        }
+        writeln!(
+            s,
+            " duration={}.{:03} feeds={} errors={}",
+            duration / 1000,
+            duration % 1000,
+            counts.iter().map(|count| count.0).sum::<u32>(),
+            counts.iter().map(|count| count.1).sum::<u32>(),
+        )
+        .ok();
+        self.write_output(s.as_bytes()).await;
+
+        for (index, (feeds, errors)) in counts.iter().enumerate() {
+            let mut s: String<64> = String::new();
+            writeln!(s, "job feeder {}: feeds={} errors={}", index, feeds, errors).ok();
+            self.write_output(s.as_bytes()).await;
+        }
+
+        Ok(())
+    }
+
+    // Starts a soak test which feeds the enabled feeders for `S` seconds in a
+    // random order seeded by `R`, waiting `P` ms between feeds.  Errors are
+    // logged as `soak: <timestamp> feeder <index> error:<code> <message>` as
+    // they happen and a summary is output when the test ends, so an
+    // unattended run can be reviewed from a session capture.  Starting a soak
+    // test while one is in progress restarts it.
+    pub(crate) async fn handle_m680(&mut self, command: Line) -> Result<()> {
+        let mut duration = None;
+        let mut seed = 1;
+        let mut interval = 1000;
+        for arg in command.arguments() {
+            let value: u32 = arg
+                .value
+                .checked_to_num()
+                .ok_or(Error::InvalidArgument(arg.letter))?;
+            match arg.letter {
+                'S' if value > 0 => duration = Some(value),
+                'R' => seed = value,
+                'P' => interval = value,
+                letter => return Err(Error::InvalidArgument(letter)),
+            }
+        }
+        let duration = duration.ok_or(Error::InvalidArgument('S'))?;
+
+        let mut any_enabled = false;
+        for feeder in self.feeders.iter_mut() {
+            any_enabled |= feeder.get_status().await?.enabled;
+        }
+        if !any_enabled {
+            return Err(Error::FeederDisabled(None));
+        }
+
+        self.soak = Some(Soak::new(
+            self.clock.now(),
+            Duration::from_secs(duration as u64),
+            Duration::from_millis(interval as u64),
+            seed,
+            self.feeders.len(),
+        ));
+        Ok(())
+    }
+
+    // Ends the soak test started by M680 early and reports its summary.
+    pub(crate) async fn handle_m681(&mut self, command: Line) -> Result<()> {
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+        if self.soak.is_none() {
+            return Err(Error::NoSoak);
+        }
+        self.finish_soak().await;
+        Ok(())
+    }
+
+    pub(crate) async fn wait_for_soak_feed(&self) {
+        match &self.soak {
+            Some(soak) => Timer::at(soak.next_feed.min(soak.end)).await,
+            None => core::future::pending().await,
+        }
+    }
+
+    // Feeds one of the enabled feeders, or ends the soak test once its time is
+    // up or no feeders are left enabled.
+    pub(crate) async fn run_soak_feed(&mut self) {
+        if self
+            .soak
+            .as_ref()
+            .is_some_and(|soak| soak.end <= Instant::now())
+        {
+            self.finish_soak().await;
+            return;
+        }
+
+        // Feeders auto-disabled during the test drop out of it.
+        let mut enabled: Vec<usize, MAX_FEEDERS> = Vec::new();
+        for index in 0..self.feeders.len() {
+            let feeder = &mut self.feeders[self.slot_map[index]];
+            if feeder.get_status().await.is_ok_and(|status| status.enabled) {
+                enabled.push(index).ok();
+            }
+        }
+        let Some(soak) = &mut self.soak else {
+            return;
+        };
+        if enabled.is_empty() {
+            self.finish_soak().await;
+            return;
+        }
+        let index = enabled[soak.choose(enabled.len())];
+
+        let slot = self.slot_map[index];
+        let result = self
+            .run_advance(slot, FeedLength::Default, false)
+            .await
+            .map_err(|e| e.for_feeder(index));
+        if let Err(e) = &result {
+            let mut s: String<96> = String::new();
+            writeln!(
+                s,
+                "soak: {} feeder {} error:{} {}",
+                self.clock.now(),
+                index,
+                e.code(),
+                e
+            )
+            .ok();
+            self.write_output(s.as_bytes()).await;
+        }
+
+        if let Some(soak) = &mut self.soak {
+            soak.record(index, &result);
+            soak.next_feed = Instant::now() + soak.interval;
+        }
+    }
+
+    // Ends the soak test and reports a summary:
+    // `soak: <start timestamp> duration=<seconds> feeds=<count> errors=<count>`
+    // followed by `soak feeder <index>: feeds=<count> errors=<count>
+    // last_error=<code> <message>` for each feeder.
+    async fn finish_soak(&mut self) {
+        let Some(soak) = self.soak.take() else {
+            return;
+        };
+
+        let duration = soak.start.elapsed().as_millis();
+        let mut s: String<128> = String::new();
+        writeln!(
+            s,
+            "soak: {} duration={}.{:03} feeds={} errors={}",
+            soak.started_at,
+            duration / 1000,
+            duration % 1000,
+            soak.stats.iter().map(|stats| stats.feeds).sum::<u32>(),
+            soak.stats.iter().map(|stats| stats.errors).sum::<u32>(),
+        )
+        .ok();
+        self.write_output(s.as_bytes()).await;
+
+        for (index, stats) in soak.stats.iter().enumerate() {
+            let mut s: String<128> = String::new();
+            write!(
+                s,
+                "soak feeder {}: feeds={} errors={}",
+                index, stats.feeds, stats.errors
+            )
+            .ok();
+            match &stats.last_error {
+                Some(e) => writeln!(s, " last_error={} {}", e.code(), e).ok(),
+                None => writeln!(s, " last_error=none").ok(),
+            };
+            self.write_output(s.as_bytes()).await;
+        }
+    }
+}
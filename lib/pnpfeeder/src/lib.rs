@@ -1,25 +1,73 @@
 #![feature(type_alias_impl_trait)]
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use az::Cast;
+use az::CheckedCast;
+use calibration::Calibration;
 use core::fmt::{Display, Write as _};
+use core::future::{pending, poll_fn};
+use embassy_futures::select::{select, select3, select4, select_array, Either, Either3, Either4};
 use embassy_sync::{
-    blocking_mutex::raw::NoopRawMutex,
-    channel::{self, Channel},
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    channel::{Channel, TrySendError},
+    signal::Signal,
 };
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embedded_io_async::Write;
 use fixed::FixedI32;
 use fixed::{types::extra::U16, FixedI64};
 use fixed_gcode::BufferTypes;
 use heapless::{String, Vec};
+use soak::Soak;
 
+mod advance;
+mod bank;
+mod calibration;
+mod capture;
+mod clock;
+mod config;
+mod config_code;
+mod diagnostics;
+mod duty_cycle;
 mod feeder;
+mod hooks;
+#[cfg(feature = "std")]
+mod host;
 mod input;
+mod job;
+mod line_checker;
+mod metrics;
+mod motion;
+mod peel;
+mod playback;
+mod replies;
+mod scheduler;
 mod servo;
+mod soak;
+mod text;
 
-pub use feeder::{Feeder, FeederChannel, FeederClient, FeederConfig};
-pub use input::Input;
-pub use servo::{PwmLimits, Servo};
+pub use bank::{FeederBank, MAX_FEEDERS};
+pub use capture::SessionCapture;
+pub use clock::{Clock, Timestamp};
+pub use duty_cycle::DutyCycleServo;
+pub use feeder::{
+    AdvanceProgress, ConfigGroup, FeedCounters, FeedLength, Feeder, FeederChannel, FeederClient,
+    FeederConfig, FeederNotification, FeederStatus, FieldInfo, MotionFeedback, PendingReply,
+    PositionUnits, RetractPolicy, ServoPosition,
+};
+pub use hooks::{HookAction, HookEvent, HookOutputs, AUX_PULSE};
+#[cfg(feature = "std")]
+pub use host::EventReader;
+pub use input::{CountingInput, Input, NoCounter};
+pub use line_checker::{LineChecker, ResponseChecksum};
+pub use metrics::{Counter, TransportStats};
+pub use motion::{MotionController, MotionServo, MOTION_TICK};
+pub use peel::{NoPeeler, Peeler};
+pub use playback::{Edge, PlaybackInput};
+pub use scheduler::{MotionPermit, MotionScheduler};
+pub use servo::{
+    check_servo_conformance, AngleScaler, NoServo, PwmLimits, Servo, STANDARD_FRAME_RATE,
+};
+pub use text::sanitize;
 
 pub type Value = FixedI32<U16>;
 pub type Value64 = FixedI64<U16>;
@@ -32,7 +80,7 @@ impl BufferTypes<Value> for Types {
 pub type Word = fixed_gcode::Word<Value>;
 pub type Line = fixed_gcode::Line<Value, Types>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Error {
     Disconnected,
     InputBufferOverflow,
@@ -43,17 +91,109 @@ pub enum Error {
     NoIndex,
     InvalidIndex(usize),
     InvalidArgument(char),
-    FeederDisabled,
+    // Feeder errors carry the feeder's index once known to the handler.
+    FeederDisabled(Option<usize>),
     FixedPointError,
     InvalidFeederCommandResponse,
-    FeederNotReady,
+    FeederNotReady(Option<usize>),
     ConfigSetError,
     ConfigGetError,
+    ConfigVerifyError,
     InvalidFeedLength(Value),
+    HomingFailed,
+    MissingLineNumber,
+    MissingChecksum,
+    ChecksumMismatch,
+    LineNumberMismatch(u32),
+    Aborted,
+    FeederInterrupted,
+    NoJob,
+    InvalidConfigCode,
+    ParseError,
+    NoSetupBlock,
+    TooManyColumns,
+    InvalidCartridgeId,
+    HalfAdvanceUnsupported(Value),
+    NoPartPitch,
+    NoSoak,
+    TooManyDeferredAdvances,
+    FeederJammed(Option<usize>),
+    SafeMode,
+    // Length, in mm, the travel sensor measured.
+    Underfeed(Value),
+    Overfeed(Value),
+    // Bit mask of the feeders a group advance failed on.
+    GroupAdvanceFailed(u32),
+    TooManyFeeders,
+    // The servo shares its PWM period with another so can't change its frame
+    // rate on its own.
+    SharedFrameRate,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+impl Error {
+    // Attributes an error from feeder `index` so responses say which feeder
+    // failed.
+    pub fn for_feeder(self, index: usize) -> Self {
+        match self {
+            Self::FeederDisabled(_) => Self::FeederDisabled(Some(index)),
+            Self::FeederNotReady(_) => Self::FeederNotReady(Some(index)),
+            Self::FeederJammed(_) => Self::FeederJammed(Some(index)),
+            e => e,
+        }
+    }
+
+    // Stable numeric code reported with the error so hosts don't need to
+    // match messages.  Codes must never be reused or renumbered; new errors
+    // take the next unused code.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Disconnected => 1,
+            Self::InputBufferOverflow => 2,
+            Self::Io => 3,
+            Self::AngleOutOfRange => 4,
+            Self::PwmValueOutOfRange => 5,
+            Self::UnsupportedCommand(_) => 6,
+            Self::NoIndex => 7,
+            Self::InvalidIndex(_) => 8,
+            Self::InvalidArgument(_) => 9,
+            Self::FeederDisabled(_) => 10,
+            Self::FixedPointError => 11,
+            Self::InvalidFeederCommandResponse => 12,
+            Self::FeederNotReady(_) => 13,
+            Self::ConfigSetError => 14,
+            Self::ConfigGetError => 15,
+            Self::ConfigVerifyError => 16,
+            Self::InvalidFeedLength(_) => 17,
+            Self::HomingFailed => 18,
+            Self::MissingLineNumber => 19,
+            Self::MissingChecksum => 20,
+            Self::ChecksumMismatch => 21,
+            Self::LineNumberMismatch(_) => 22,
+            Self::Aborted => 23,
+            Self::FeederInterrupted => 24,
+            Self::NoJob => 25,
+            Self::InvalidConfigCode => 26,
+            Self::ParseError => 27,
+            Self::NoSetupBlock => 28,
+            Self::TooManyColumns => 29,
+            Self::InvalidCartridgeId => 30,
+            Self::HalfAdvanceUnsupported(_) => 31,
+            Self::NoPartPitch => 32,
+            Self::NoSoak => 33,
+            Self::TooManyDeferredAdvances => 34,
+            Self::FeederJammed(_) => 35,
+            Self::SafeMode => 36,
+            Self::Underfeed(_) => 37,
+            Self::Overfeed(_) => 38,
+            Self::GroupAdvanceFailed(_) => 39,
+            Self::TooManyFeeders => 40,
+            Self::SharedFrameRate => 41,
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
@@ -66,13 +206,51 @@ impl Display for Error {
             Self::NoIndex => write!(f, "no index specified"),
             Self::InvalidIndex(index) => write!(f, "no feeder {}", index),
             Self::InvalidArgument(char) => write!(f, "invalid argument type {}", char),
-            Self::FeederDisabled => write!(f, "feeder disabled"),
+            Self::FeederDisabled(None) => write!(f, "feeder disabled"),
+            Self::FeederDisabled(Some(index)) => write!(f, "feeder {index} disabled"),
             Self::FixedPointError => write!(f, "fixed point error"),
             Self::InvalidFeederCommandResponse => write!(f, "invalid feeder command respons"),
-            Self::FeederNotReady => write!(f, "feeder not ready"),
+            Self::FeederNotReady(None) => write!(f, "feeder not ready"),
+            Self::FeederNotReady(Some(index)) => write!(f, "feeder {index} not ready"),
             Self::ConfigSetError => write!(f, "can't set config"),
             Self::ConfigGetError => write!(f, "can't get config"),
+            Self::ConfigVerifyError => write!(f, "config verification failed"),
             Self::InvalidFeedLength(len) => write!(f, "invald feed length {len}"),
+            Self::HomingFailed => write!(f, "homing failed: feedback not ready"),
+            Self::MissingLineNumber => write!(f, "missing line number"),
+            Self::MissingChecksum => write!(f, "missing checksum"),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Self::LineNumberMismatch(expected) => {
+                write!(f, "line number mismatch, expected {expected}")
+            }
+            Self::Aborted => write!(f, "aborted"),
+            Self::FeederInterrupted => write!(f, "feeder interrupted, home required"),
+            Self::NoJob => write!(f, "no job in progress"),
+            Self::InvalidConfigCode => write!(f, "invalid config code"),
+            Self::ParseError => write!(f, "can't parse line"),
+            Self::NoSetupBlock => write!(f, "no setup block in progress"),
+            Self::TooManyColumns => write!(f, "too many columns"),
+            Self::InvalidCartridgeId => write!(f, "invalid cartridge id"),
+            Self::HalfAdvanceUnsupported(len) => {
+                write!(f, "feed length {len} needs a half advance")
+            }
+            Self::NoPartPitch => write!(f, "no part pitch configured"),
+            Self::NoSoak => write!(f, "no soak test in progress"),
+            Self::TooManyDeferredAdvances => write!(f, "too many deferred advances"),
+            Self::FeederJammed(None) => write!(f, "feeder jammed"),
+            Self::FeederJammed(Some(index)) => write!(f, "feeder {index} jammed"),
+            Self::SafeMode => write!(f, "safe mode, restart with M999"),
+            Self::Underfeed(measured) => write!(f, "underfeed, measured {measured}mm"),
+            Self::Overfeed(measured) => write!(f, "overfeed, measured {measured}mm"),
+            Self::GroupAdvanceFailed(failed) => {
+                write!(f, "group advance failed on feeders")?;
+                for index in (0..u32::BITS).filter(|index| failed & (1 << index) != 0) {
+                    write!(f, " {index}")?;
+                }
+                Ok(())
+            }
+            Self::TooManyFeeders => write!(f, "too many feeders"),
+            Self::SharedFrameRate => write!(f, "frame rate shared with another servo"),
         }
     }
 }
@@ -81,854 +259,5841 @@ pub trait ConfigStore {
     // If no settings exist in the store, the default settings should be returned.
     fn get(&mut self, index: usize) -> Result<FeederConfig>;
     fn set(&mut self, index: usize, config: &FeederConfig) -> Result<()>;
-}
 
-pub enum GCodeEvent {
-    Connect,
-    Disconnect,
-    Line(Line),
-}
+    // Factory default settings restored by M502.
+    fn get_default(&self, index: usize) -> FeederConfig;
 
-pub type GCodeEventChannel<const N: usize> = Channel<NoopRawMutex, GCodeEvent, N>;
-pub type GCodeEventReceiver<'a, const N: usize> =
-    channel::Receiver<'a, NoopRawMutex, GCodeEvent, N>;
-pub type GCodeEventSender<'a, const N: usize> = channel::Sender<'a, NoopRawMutex, GCodeEvent, N>;
+    // Physical slot that logical feeder `index` is mapped to.  If no mapping
+    // exists in the store, `index` should be returned.
+    fn get_slot(&mut self, index: usize) -> Result<usize>;
+    fn set_slot(&mut self, index: usize, slot: usize) -> Result<()>;
 
-pub struct GCodeHandler<'a, W: Write, C: ConfigStore, const N: usize> {
-    feeders: [FeederClient<'a>; N],
-    output: W,
-    config_store: C,
+    // Cartridge associated with logical feeder `index`.  If no cartridge is
+    // stored, an empty id should be returned.
+    fn get_cartridge(&mut self, index: usize) -> Result<CartridgeId>;
+    fn set_cartridge(&mut self, index: usize, cartridge: &str) -> Result<()>;
+
+    // Parts and length fed by the feeder in `slot`.  If none are stored,
+    // zeroed counters should be returned.
+    fn get_counters(&mut self, slot: usize) -> Result<FeedCounters>;
+    fn set_counters(&mut self, slot: usize, counters: &FeedCounters) -> Result<()>;
+
+    // If no board config is stored, `BoardConfig::default()` should be
+    // returned.
+    fn get_board_config(&mut self) -> Result<BoardConfig>;
+    fn set_board_config(&mut self, config: &BoardConfig) -> Result<()>;
+
+    // Set while the feeder in `slot` is part way through an advance so one
+    // cut short by a power loss is caught at the next boot.  If no marker is
+    // stored, false should be returned.
+    fn get_advance_marker(&mut self, slot: usize) -> Result<bool>;
+    fn set_advance_marker(&mut self, slot: usize, in_progress: bool) -> Result<()>;
+
+    // Resets in a row caused by a crash or hang, counted by the board at boot.
+    // If no count is stored, zero should be returned.
+    fn get_crash_count(&mut self) -> Result<u32>;
+    fn set_crash_count(&mut self, count: u32) -> Result<()>;
 }
 
-macro_rules! word {
-    ($letter:literal, $value:literal) => {
-        Word::new($letter, $value)
-    };
+// Serial or part number of the cartridge loaded in a feeder, set by M632.
+pub type CartridgeId = String<32>;
+
+// Settings which apply to the whole board rather than a feeder, set by M612.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BoardConfig {
+    // Enables every feeder at power on, for use without a host, instead of
+    // waiting for M610.
+    pub startup_enable: bool,
+    // Time in ms the LED blinks before feeders are enabled at power on so
+    // hands can be cleared of the levers.
+    pub startup_delay: u32,
+    // Seconds without host commands or feeds after which every feeder is
+    // disabled and its servo detached.  Zero keeps them enabled.
+    pub idle_timeout: u32,
+    // Re-enables the feeders disabled for being idle when the next command
+    // arrives instead of leaving it to the host.
+    pub idle_reenable: bool,
+    // Holds advances for disabled feeders until M610 S1 instead of failing
+    // them, for hosts which send their setup in a fixed order.
+    pub defer_while_disabled: bool,
+    // Most feeders which may move their levers at once, to keep servos
+    // starting together from browning out the supply.  Zero for no limit.
+    pub max_moving_feeders: u32,
 }
 
-impl<'a, W: Write, C: ConfigStore, const N: usize> GCodeHandler<'a, W, C, N> {
-    pub fn new(feeders: [FeederClient<'a>; N], output: W, config_store: C) -> Self {
+impl Default for BoardConfig {
+    fn default() -> Self {
         Self {
-            feeders,
-            output,
-            config_store,
+            startup_enable: false,
+            startup_delay: 3000,
+            idle_timeout: 0,
+            idle_reenable: false,
+            defer_while_disabled: false,
+            max_moving_feeders: 0,
         }
     }
+}
 
-    pub async fn run(&mut self, receiver: GCodeEventReceiver<'_, 2>) {
-        self.initialize_feeder_configs().await;
-        loop {
-            let exit = match receiver.receive().await {
-                GCodeEvent::Connect => self.handle_connect().await,
-                GCodeEvent::Disconnect => self.handle_disconnect().await,
-                GCodeEvent::Line(line) => self.handle_line(line).await,
-            };
-            if exit {
-                break;
-            }
-        }
-    }
+// `FeederConfig` field changes, by tag, parsed from a config line.
+#[derive(Default)]
+struct FeederConfigUpdate {
+    fields: Vec<(char, Value), { FeederConfig::FIELDS.len() }>,
+}
 
-    pub async fn initialize_feeder_configs(&mut self) {
-        for index in 0..N {
-            // It's unclear what the right action is on failure.  Perhaps we
-            // should have a disabled state where and error will be printed
-            // on connection.
-            if let Ok(config) = self.config_store.get(index) {
-                let _ = self.feeders[index].set_config(config).await;
-            }
-        }
+impl FeederConfigUpdate {
+    fn add(&mut self, letter: char, value: Value) -> Result<()> {
+        // Validate the field up front so an invalid line doesn't partially
+        // update a feeder.
+        FeederConfig::default().set_field(letter, value)?;
+        self.fields.retain(|(field, _)| *field != letter);
+        self.fields
+            .push((letter, value))
+            .map_err(|_| Error::InvalidArgument(letter))
     }
 
-    pub async fn handle_connect(&mut self) -> bool {
-        let _ = self.output.write_all(b"saved settings:\n").await;
-        for index in 0..self.feeders.len() {
-            let _ = self.output_feeder_config(Some(index)).await; // Ignore errors on connect.
+    fn apply(&self, config: &mut FeederConfig) -> Result<()> {
+        let old_units = config.position_units;
+        for (letter, value) in &self.fields {
+            config.set_field(*letter, *value)?;
         }
-        let _ = self.output.write_all(b"ready\n").await;
-        false
-    }
 
-    pub async fn handle_disconnect(&mut self) -> bool {
-        // Disable feeders on disconnect
-        for feeder in self.feeders.iter_mut() {
-            feeder.enable(false).await.ok(); // Ignore disable errors on disconnect.
+        // Changing units converts the lever positions which weren't given
+        // along with it so the lever stays where it was.
+        if config.position_units != old_units {
+            let old_config = FeederConfig {
+                position_units: old_units,
+                ..config.clone()
+            };
+            for letter in FeederConfig::POSITION_FIELDS {
+                if self.fields.iter().any(|(field, _)| *field == letter) {
+                    continue;
+                }
+                let angle = old_config.position_angle(old_config.get_field(letter)?)?;
+                let position = config.angle_position(angle)?;
+                config.set_field(letter, round_to_hundredths(position))?;
+            }
         }
-        false
+        Ok(())
     }
+}
 
-    pub async fn handle_line(&mut self, line: Line) -> bool {
-        let Some(command) = line.command() else {
-            return false;
-        };
+// Rounds to the value nearest a hundredth so it prints as one.  Hundredths
+// are finer than any servo can resolve and keep lever positions converted
+// between units from drifting.
+fn round_to_hundredths(value: Value) -> Value {
+    let hundredths: i64 = (Value64::from(value) * 100).round().to_num();
+    let bits = (hundredths * (1 << 16) + 50).div_euclid(100);
+    Value::from_bits(bits.clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+}
 
-        // Use M999 to allow tests to exit the loop.
-        #[cfg(test)]
-        if *command == word!('M', 999) {
-            for feeder in self.feeders.iter_mut() {
-                feeder.shutdown().await;
-            }
-            return true;
-        }
+macro_rules! word {
+    ($letter:literal, $value:literal) => {
+        Word::new($letter, $value)
+    };
+}
 
-        let ret = if *command == word!('M', 600) {
-            self.handle_m600(line).await
-        } else if *command == word!('M', 603) {
-            self.handle_m603(line).await
-        } else if *command == word!('M', 610) {
-            self.handle_m610(line).await
-        } else if *command == word!('M', 620) {
-            self.handle_m620(line).await
-        } else if *command == word!('M', 621) {
-            self.handle_m621(line).await
-        } else {
-            Err(Error::UnsupportedCommand(command.clone()))
-        };
+// Formats a parsed line as its words separated by spaces.
+struct DisplayLine<'a>(&'a Line);
 
-        match ret {
-            Ok(_) => {
-                let _ = self.output.write_all(b"ok\n").await;
-            }
-            Err(e) => {
-                let mut s = String::<64>::new();
-                writeln!(s, "error: {}", e).ok();
-                let _ = self.output.write_all(s.as_bytes()).await;
+impl Display for DisplayLine<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, word) in self
+            .0
+            .command()
+            .into_iter()
+            .chain(self.0.arguments())
+            .enumerate()
+        {
+            if i > 0 {
+                write!(f, " ")?;
             }
+            write!(f, "{word}")?;
         }
-        false
+        Ok(())
     }
+}
 
-    fn resolve_feeder<'b>(
-        &'b mut self,
-        index: Option<usize>,
-    ) -> Result<(usize, &'b mut FeederClient<'a>)>
-    where
-        'a: 'b,
-    {
-        let index = index.ok_or(Error::NoIndex)?;
+// A line the transport couldn't parse as G-code.
+pub type UnparsedLine = String<128>;
 
-        if index >= self.feeders.len() {
-            return Err(Error::InvalidIndex(index));
-        }
+pub enum GCodeEvent {
+    Connect,
+    Disconnect,
+    Line(Line),
+    // Config codes, lines with a string argument, setup block rows, or errors
+    // if none of those are expected.
+    Unparsed(UnparsedLine),
+}
 
-        Ok((index, &mut self.feeders[index]))
+impl GCodeEvent {
+    // Builds the event for a line with any line number and checksum already
+    // removed.  Lines which aren't G-code may be setup block rows so it's left
+    // to the handler to decide if they're errors.
+    pub fn parse(line: &str) -> Result<Self> {
+        match line.parse::<Line>() {
+            Ok(command) => Ok(Self::Line(command)),
+            Err(_) => line
+                .try_into()
+                .map(Self::Unparsed)
+                .map_err(|_| Error::ParseError),
+        }
     }
+}
 
-    async fn handle_m600(&mut self, command: Line) -> Result<()> {
-        let mut index = None;
-        let mut feed_length = None;
-        let mut override_error = false;
+// Number of priority lines which can be queued ahead of other events.
+const PRIORITY_LANE_LEN: usize = 2;
 
-        for arg in command.arguments() {
-            match arg.letter {
-                'N' => index = Some(arg.value.cast()),
-                'F' => feed_length = Some(arg.value.cast()),
-                'X' => override_error = arg.value != 0,
-                letter => return Err(Error::InvalidArgument(letter)),
-            }
-        }
+// Returns true for stop commands.  These are handled ahead of anything
+// already queued and abort the command in progress.
+fn is_stop_command(line: &Line) -> bool {
+    line.command()
+        .is_some_and(|command| *command == word!('M', 112) || *command == word!('M', 410))
+}
 
-        let (_, feeder) = self.resolve_feeder(index)?;
+fn is_advance_command(line: &Line) -> bool {
+    line.command()
+        .is_some_and(|command| *command == word!('M', 600))
+}
 
-        feeder.advance(feed_length, override_error).await?;
+// Queue of events from a transport to a `GCodeHandler`.  Stop commands go
+// through their own priority lane so they aren't stuck behind a backlog of
+// feeds.  `R` guards the queue, which is only shared by tasks on one executor
+// unless another raw mutex is chosen.
+pub struct GCodeEventChannel<const N: usize, R: RawMutex = NoopRawMutex> {
+    events: Channel<R, GCodeEvent, N>,
+    priority: Channel<R, Line, PRIORITY_LANE_LEN>,
+    // Raised as soon as a stop is sent so the command in progress can be
+    // aborted before the stop itself is handled.
+    stop: Signal<R, ()>,
+}
 
-        Ok(())
+impl<const N: usize> GCodeEventChannel<N> {
+    pub const fn new() -> Self {
+        Self::with_raw_mutex()
     }
+}
 
-    async fn handle_m603(&mut self, command: Line) -> Result<()> {
-        let mut index = None;
-        let mut angle = None;
-        for arg in command.arguments() {
-            match arg.letter {
-                'N' => index = Some(arg.value.cast()),
-                'A' => angle = Some(arg.value.cast()),
-                _ => return Err(Error::InvalidArgument(arg.letter)),
-            }
+impl<const N: usize, R: RawMutex> GCodeEventChannel<N, R> {
+    // A queue guarded by `R`, e.g. `CriticalSectionRawMutex` so the transport
+    // and handler can run on different cores or executors.
+    pub const fn with_raw_mutex() -> Self {
+        Self {
+            events: Channel::new(),
+            priority: Channel::new(),
+            stop: Signal::new(),
         }
+    }
 
-        let (_, feeder) = self.resolve_feeder(index)?;
-        if let Some(angle) = angle {
-            feeder.set_servo_angle(angle).await?;
-        }
+    pub fn sender(&self) -> GCodeEventSender<'_, N, R> {
+        GCodeEventSender { channel: self }
+    }
 
-        Ok(())
+    pub fn receiver(&self) -> GCodeEventReceiver<'_, N, R> {
+        GCodeEventReceiver { channel: self }
+    }
+}
+
+impl<const N: usize> Default for GCodeEventChannel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct GCodeEventSender<'a, const N: usize, R: RawMutex = NoopRawMutex> {
+    channel: &'a GCodeEventChannel<N, R>,
+}
+
+// Not derived, which would needlessly require `R: Copy`.
+impl<const N: usize, R: RawMutex> Clone for GCodeEventSender<'_, N, R> {
+    fn clone(&self) -> Self {
+        *self
     }
+}
 
-    async fn handle_m610(&mut self, command: Line) -> Result<()> {
-        let mut status = None;
+impl<const N: usize, R: RawMutex> Copy for GCodeEventSender<'_, N, R> {}
 
-        for arg in command.arguments() {
-            match arg.letter {
-                'S' => status = Some(arg.value != 0.0),
-                letter => return Err(Error::InvalidArgument(letter)),
+impl<'a, const N: usize, R: RawMutex> GCodeEventSender<'a, N, R> {
+    pub async fn send(&self, event: GCodeEvent) {
+        match event {
+            GCodeEvent::Line(line) if is_stop_command(&line) => {
+                self.channel.stop.signal(());
+                self.channel.priority.send(line).await
             }
+            event => self.channel.events.send(event).await,
         }
+    }
 
-        if let Some(status) = status {
-            for feeder in self.feeders.iter_mut() {
-                feeder.enable(status).await?;
+    // Queues an event without waiting, for callers outside an async context.
+    // The event is handed back if the channel is full, as embassy's channels
+    // do.
+    #[allow(clippy::result_large_err)]
+    pub fn try_send(&self, event: GCodeEvent) -> core::result::Result<(), GCodeEvent> {
+        match event {
+            GCodeEvent::Line(line) if is_stop_command(&line) => {
+                self.channel.stop.signal(());
+                self.channel
+                    .priority
+                    .try_send(line)
+                    .map_err(|TrySendError::Full(line)| GCodeEvent::Line(line))
             }
+            event => self
+                .channel
+                .events
+                .try_send(event)
+                .map_err(|TrySendError::Full(event)| event),
         }
-
-        Ok(())
     }
 
-    async fn handle_m620(&mut self, command: Line) -> Result<()> {
-        let mut index = None;
-        let mut advanced_angle = None;
-        let mut half_advanced_angle = None;
-        let mut retract_angle = None;
-        let mut feed_length = None;
-        let mut settle_time = None;
-        let mut pwm_0 = None;
-        let mut pwm_180 = None;
-        let mut ignore_feeback_pin = None;
-        let mut always_retract = None;
-
-        for arg in command.arguments() {
-            match arg.letter {
-                'N' => index = Some(arg.value.cast()),
-                'A' => advanced_angle = Some(arg.value.cast()),
-                'B' => half_advanced_angle = Some(arg.value.cast()),
-                'C' => retract_angle = Some(arg.value.cast()),
-                'F' => feed_length = Some(arg.value.cast()),
-                'U' => settle_time = Some(arg.value.cast()),
-                'V' => pwm_0 = Some(arg.value.cast()),
-                'W' => pwm_180 = Some(arg.value.cast()),
-                'X' => ignore_feeback_pin = Some(arg.value != 0),
-                'Y' => always_retract = Some(arg.value != 0),
-                letter => return Err(Error::InvalidArgument(letter)),
-            }
-        }
-
-        let (index, feeder) = self.resolve_feeder(index)?;
-        let mut config = feeder.get_config().await?;
-
-        macro_rules! handle_parameter {
-            ($parameter:ident) => {
-                if let Some(val) = $parameter {
-                    config.$parameter = val;
-                }
-            };
+    // Waits until `event` can be queued without blocking.  Lets a transport
+    // tell the host to hold off, rather than stalling mid-stream, when the
+    // handler falls behind.
+    pub async fn ready_to_send(&self, event: &GCodeEvent) {
+        match event {
+            GCodeEvent::Line(line) if is_stop_command(line) => {
+                poll_fn(|cx| self.channel.priority.poll_ready_to_send(cx)).await
+            }
+            _ => poll_fn(|cx| self.channel.events.poll_ready_to_send(cx)).await,
         }
+    }
+}
 
-        handle_parameter!(advanced_angle);
-        handle_parameter!(half_advanced_angle);
-        handle_parameter!(retract_angle);
-        handle_parameter!(feed_length);
-        handle_parameter!(settle_time);
-        handle_parameter!(pwm_0);
-        handle_parameter!(pwm_180);
-        handle_parameter!(ignore_feeback_pin);
-        handle_parameter!(always_retract);
-
-        feeder.set_config(config.clone()).await?;
-
-        // Accessing the config store has to happen after updating the feeder
-        // as the feeder reference is mutable borring &self.
-        self.config_store.set(index, &config)?;
+pub struct GCodeEventReceiver<'a, const N: usize, R: RawMutex = NoopRawMutex> {
+    channel: &'a GCodeEventChannel<N, R>,
+}
 
-        Ok(())
+impl<const N: usize, R: RawMutex> Clone for GCodeEventReceiver<'_, N, R> {
+    fn clone(&self) -> Self {
+        *self
     }
+}
 
-    async fn handle_m621(&mut self, command: Line) -> Result<()> {
-        let mut index = None;
-        for arg in command.arguments() {
-            match arg.letter {
-                'N' => index = Some(arg.value.cast()),
-                letter => return Err(Error::InvalidArgument(letter)),
+impl<const N: usize, R: RawMutex> Copy for GCodeEventReceiver<'_, N, R> {}
+
+impl<'a, const N: usize, R: RawMutex> GCodeEventReceiver<'a, N, R> {
+    // Returns the next event, favoring the priority lane.
+    pub async fn receive(&self) -> GCodeEvent {
+        match select(
+            self.channel.priority.receive(),
+            self.channel.events.receive(),
+        )
+        .await
+        {
+            Either::First(line) => {
+                self.channel.stop.reset();
+                GCodeEvent::Line(line)
             }
+            Either::Second(event) => event,
         }
+    }
 
-        self.output_feeder_config(index).await?;
+    // Number of events waiting to be handled.
+    fn queued(&self) -> usize {
+        self.channel.priority.len() + self.channel.events.len()
+    }
 
-        Ok(())
+    // Returns the next event if one is queued.
+    fn try_receive(&self) -> Option<GCodeEvent> {
+        if let Ok(line) = self.channel.priority.try_receive() {
+            self.channel.stop.reset();
+            return Some(GCodeEvent::Line(line));
+        }
+        self.channel.events.try_receive().ok()
     }
 
-    async fn output_feeder_config(&mut self, index: Option<usize>) -> Result<()> {
-        let (index, feeder) = self.resolve_feeder(index)?;
-        let config = feeder.get_config().await?;
-
-        let mut s: String<64> = String::new();
-        writeln!(
-            s,
-            "M620 N{} A{} B{} C{} F{} U{} V{} W{} X{} Y{}",
-            index,
-            config.advanced_angle,
-            config.half_advanced_angle,
-            config.retract_angle,
-            config.feed_length,
-            config.settle_time,
-            config.pwm_0,
-            config.pwm_180,
-            if config.ignore_feeback_pin { 1 } else { 0 },
-            if config.always_retract { 1 } else { 0 },
-        )
-        .ok();
-        let _ = self.output.write_all(s.as_bytes()).await;
+    // Waits for a stop to be sent.
+    async fn wait_for_stop(&self) {
+        self.channel.stop.wait().await
+    }
+}
+
+// An M600 held until its feeder is enabled.
+struct DeferredAdvance {
+    index: usize,
+    feed_length: FeedLength,
+    override_error: bool,
+}
+
+impl Display for DeferredAdvance {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "M600 N{}", self.index)?;
+        match self.feed_length {
+            FeedLength::Default => {}
+            FeedLength::Millimeters(length) => write!(f, " F{length}")?,
+            FeedLength::Parts(parts) => write!(f, " C{parts}")?,
+        }
+        if self.override_error {
+            write!(f, " X1")?;
+        }
         Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    extern crate alloc;
-    use alloc::sync::Arc;
-    use embassy_futures::join::{join, join_array};
-    use embassy_time::Timer;
-    use fixed::traits::ToFixed;
-    use std::{collections::HashMap, string::String, sync::Mutex, vec::Vec};
+// `R` guards the feeders' channels.
+pub struct GCodeHandler<'a, W: Write, C: ConfigStore, R: RawMutex = NoopRawMutex> {
+    feeders: FeederBank<'a, R>,
+    // Logical to physical feeder index mapping.  Like the other per-feeder
+    // arrays, only the first `feeders.len()` entries are used.
+    slot_map: [usize; MAX_FEEDERS],
+    output: W,
+    config_store: C,
+    clock: Clock,
+    status_interval: Option<Duration>,
+    next_status_report: Instant,
+    // Set by M629 to report every config change, whatever made it.
+    watch_config: bool,
+    command_count: u32,
+    error_count: u32,
+    // Set once a write times out and cleared when one succeeds.
+    output_stalled: bool,
+    write_timeouts: u32,
+    // Set by M410 to fail the advances queued behind it.
+    discard_queued_advances: bool,
+    // Advances received while their feeder was disabled, replayed by M610 S1
+    // when `BoardConfig::defer_while_disabled` is set.
+    deferred_advances: Vec<DeferredAdvance, MAX_DEFERRED_ADVANCES>,
+    job: Option<Job>,
+    // Soak test started by M680.
+    soak: Option<Soak>,
+    board: BoardConfig,
+    // Time of the last command or feed, for `BoardConfig::idle_timeout`.
+    last_activity: Instant,
+    // Total feeds when activity was last checked, to notice button feeds.
+    activity_feeds: u32,
+    // Feeders disabled for being idle, set until the next command.
+    idle_disabled: Option<[bool; MAX_FEEDERS]>,
+    // Feed counters of each slot as last written to the config store.
+    saved_counters: [FeedCounters; MAX_FEEDERS],
+    // Slots whose advance marker is set in the config store.
+    advance_marked: [bool; MAX_FEEDERS],
+    next_counter_save: Instant,
+    // Rows of the setup block started by M625.
+    setup_block: Option<Vec<(usize, FeederConfigUpdate), MAX_FEEDERS>>,
+    // Feeder the config code following M628 is applied to.
+    config_code_target: Option<usize>,
+    // The M619 wizard, which takes the console lines until it finishes.
+    calibration: Option<Calibration>,
+    transport_stats: Option<&'a TransportStats>,
+    // Limited to `BoardConfig::max_moving_feeders`.
+    motion_scheduler: Option<&'a MotionScheduler<R>>,
+    // LED and aux output driven by feeder hooks.
+    hook_outputs: Option<&'a mut dyn HookOutputs>,
+    // Session transcript toggled by M670 and dumped by M671.
+    capture: Option<SessionCapture<'a>>,
+    // Reboots into the board's firmware update bootloader for M997.
+    reboot_to_bootloader: Option<fn() -> !>,
+    // Restarts the board for M999.
+    reset: Option<fn() -> !>,
+    // Crash resets in a row before this boot, from the board.
+    crash_count: u32,
+    booted_at: Instant,
+    firmware_name: &'static str,
+    firmware_version: &'static str,
+}
 
-    use super::*;
+// Parses an M625 setup block row: `<feeder>,<A>,<B>,...` with one column per
+// `FeederConfig::FIELDS` tag, in order.  Trailing columns may be left off
+// and empty cells leave the field unchanged.
+fn parse_setup_row(row: &str) -> Result<(usize, FeederConfigUpdate)> {
+    let mut cells = row.split(',').map(str::trim);
+    let index = cells
+        .next()
+        .and_then(|cell| cell.parse().ok())
+        .ok_or(Error::InvalidArgument('N'))?;
 
-    struct FakeServo {
-        limits: servo::PwmLimits,
-        positions: Arc<Mutex<Vec<Value>>>,
+    let mut update = FeederConfigUpdate::default();
+    let mut letters = FeederConfig::FIELDS.iter();
+    for cell in cells {
+        let letter = *letters.next().ok_or(Error::TooManyColumns)?;
+        if cell.is_empty() {
+            continue;
+        }
+        let value = cell.parse().map_err(|_| Error::InvalidArgument(letter))?;
+        update.add(letter, value)?;
     }
 
-    impl FakeServo {
-        const COUNTS_PER_PERIOD: u16 = 9804;
-        fn new() -> (Arc<Mutex<Vec<Value>>>, Self) {
-            let counts_per_ms = Value::from_num(Self::COUNTS_PER_PERIOD) / Value::from_num(20.0);
-            let zero = Value::from_num(1.0) * counts_per_ms;
-            let one_eighty = Value::from_num(2.0) * counts_per_ms;
+    Ok((index, update))
+}
 
-            let positions = Arc::new(Mutex::new(Vec::new()));
-            (
-                positions.clone(),
-                Self {
-                    limits: PwmLimits { zero, one_eighty },
-                    positions,
-                },
-            )
-        }
+// Splits a trailing quoted string argument off a line, as in
+// `M632 N0 "0805-10K"`.  The G-code parser only handles numeric arguments so
+// these lines arrive unparsed.
+fn split_string_argument(line: &str) -> Option<(Line, &str)> {
+    let (command, rest) = line.split_once('"')?;
+    let text = rest.strip_suffix('"')?;
+    if text.contains('"') {
+        return None;
     }
+    Some((command.trim().parse().ok()?, text))
+}
 
-    impl Servo for FakeServo {
-        fn set_angle(&mut self, angle: Value) -> Result<()> {
-            println!("fake servo: set angle {angle}");
+// Work the handler does on its own between commands.
+enum Periodic {
+    StatusReport,
+    SoakFeed,
+    SaveCounters,
+    IdleCheck,
+    ClearCrashCount,
+}
 
-            self.positions.lock().unwrap().push(angle);
-            Ok(())
-        }
+// A host job started by M660.  Feeder counters are snapshotted at the start so
+// M661 can report what happened during the job.
+struct Job {
+    id: Option<u32>,
+    started_at: Timestamp,
+    start: Instant,
+    // Feeds and feed errors of each logical feeder.
+    counts: Vec<(u32, u32), MAX_FEEDERS>,
+}
 
-        fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
-            if limits.zero > Self::COUNTS_PER_PERIOD || limits.one_eighty > Self::COUNTS_PER_PERIOD
-            {
-                return Err(Error::PwmValueOutOfRange);
-            }
-            self.limits = limits;
-            Ok(())
-        }
+// Interval between `busy: processing` reports while a long running command,
+// such as a feed, is in progress.  Keeps hosts from timing out the command.
+const BUSY_INTERVAL: Duration = Duration::from_secs(2);
 
-        fn get_pwm_limits(&self) -> PwmLimits {
-            self.limits.clone()
-        }
-    }
+// How often changed feed counters are written to the config store.  Limits
+// flash wear at the cost of losing up to this long of counts on power loss.
+const COUNTER_SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
-    type FakeInputChannel = Channel<NoopRawMutex, bool, 4>;
+// Number of advances which can be deferred while feeders are disabled.
+const MAX_DEFERRED_ADVANCES: usize = 8;
 
-    struct FakeInput<'a> {
-        channel: &'a FakeInputChannel,
-        state: bool,
-    }
+// Half period of the LED blink while waiting to enable feeders at power on.
+const STARTUP_BLINK: Duration = Duration::from_millis(250);
 
-    impl<'a> FakeInput<'a> {
-        pub fn new(state: bool, channel: &'a FakeInputChannel) -> Self {
-            Self { channel, state }
-        }
+// Crash resets in a row which start the handler in safe mode.
+const SAFE_MODE_CRASHES: u32 = 3;
 
-        async fn poll_state(&mut self) -> bool {
-            loop {
-                // Consume all update events in queue and return state when empty.
-                let Ok(new_state) = self.channel.try_receive() else {
-                    return self.state;
-                };
-                self.state = new_state;
-            }
-        }
+// Uptime after which the board is assumed to have recovered from a crash.
+const STABLE_UPTIME: Duration = Duration::from_secs(60);
 
-        async fn wait_for_state(&mut self, state: bool) {
-            loop {
-                let new_state = self.poll_state().await;
-                if new_state == state {
-                    return;
-                }
-            }
-        }
-    }
+// Time given to the transport to send a response before rebooting.
+const REBOOT_DELAY: Duration = Duration::from_millis(100);
 
-    impl<'a> Input for FakeInput<'a> {
-        async fn wait_for_high(&mut self) {
-            self.wait_for_state(true).await
-        }
+// Longest a write to the host may block before its output is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
 
-        async fn wait_for_low(&mut self) {
-            self.wait_for_state(false).await
-        }
+// Longest `error:` report, which is a group advance failing on all 32
+// feeders it can name.
+const MAX_ERROR_LEN: usize = "error:39 group advance failed on feeders".len() + 32 * 3;
 
-        async fn wait_for_state_change(&mut self) {
-            self.state = self.channel.receive().await;
-        }
+// Commands dispatched by `GCodeHandler::handle_line`.  Reported by M115,
+// less those whose optional hook isn't set, so must be kept in sync.
+const SUPPORTED_COMMANDS: [&str; 49] = [
+    "G28", "G4", "M110", "M112", "M115", "M154", "M280", "M400", "M410", "M500", "M501", "M502",
+    "M600", "M601", "M602", "M603", "M604", "M608", "M610", "M611", "M612", "M619", "M620", "M621",
+    "M622", "M623", "M624", "M625", "M626", "M627", "M628", "M629", "M630", "M631", "M632", "M633",
+    "M634", "M635", "M640", "M641", "M650", "M660", "M661", "M670", "M671", "M680", "M681", "M997",
+    "M999",
+];
 
-        async fn get_state(&mut self) -> bool {
-            self.poll_state().await
+// The line of each `ConfigGroup` which sets feeder `index` to `config`, each
+// starting with `prefix`.
+fn format_feeder_config(prefix: &str, index: usize, config: &FeederConfig) -> Result<String<320>> {
+    let mut s: String<320> = String::new();
+    for group in ConfigGroup::ALL {
+        write!(s, "{}M{} N{}", prefix, group.code(), index).ok();
+        for (tag, letter) in group.fields() {
+            write!(s, " {}{}", letter, config.get_field(*tag)?).ok();
         }
+        s.push('\n').ok();
     }
+    Ok(s)
+}
 
-    struct FakeConfigStore {
-        store: Arc<Mutex<HashMap<usize, FeederConfig>>>,
+// The letter and number of a command word, e.g. `('M', 600)`, or `None` for
+// numbers which aren't whole, which no command has.
+fn command_code(command: &Word) -> Option<(char, u16)> {
+    if command.value.frac() != 0 {
+        return None;
     }
+    Some((command.letter, command.value.checked_cast()?))
+}
 
-    impl FakeConfigStore {
-        fn new() -> Self {
-            Self {
-                store: Arc::new(Mutex::new(HashMap::new())),
-            }
-        }
+// Converts a feeder index or slot argument, rejecting negative and out of
+// range values.
+fn index_arg(arg: &Word) -> Result<usize> {
+    arg.value
+        .checked_cast()
+        .ok_or(Error::InvalidArgument(arg.letter))
+}
 
-        fn get_store(&self) -> Arc<Mutex<HashMap<usize, FeederConfig>>> {
-            self.store.clone()
+// Whether `slot_map` maps its indices onto each of its slots exactly once.
+fn is_permutation(slot_map: &[usize]) -> bool {
+    (0..slot_map.len()).all(|slot| slot_map.contains(&slot))
+}
+
+impl<'a, W: Write, C: ConfigStore, R: RawMutex> GCodeHandler<'a, W, C, R> {
+    pub fn new(feeders: impl Into<FeederBank<'a, R>>, output: W, config_store: C) -> Self {
+        Self {
+            feeders: feeders.into(),
+            slot_map: core::array::from_fn(|index| index),
+            output,
+            config_store,
+            clock: Clock::new(),
+            status_interval: None,
+            watch_config: false,
+            next_status_report: Instant::now(),
+            command_count: 0,
+            error_count: 0,
+            output_stalled: false,
+            write_timeouts: 0,
+            discard_queued_advances: false,
+            deferred_advances: Vec::new(),
+            job: None,
+            soak: None,
+            board: BoardConfig::default(),
+            last_activity: Instant::now(),
+            activity_feeds: 0,
+            idle_disabled: None,
+            saved_counters: [FeedCounters::default(); MAX_FEEDERS],
+            advance_marked: [false; MAX_FEEDERS],
+            next_counter_save: Instant::now() + COUNTER_SAVE_INTERVAL,
+            setup_block: None,
+            config_code_target: None,
+            calibration: None,
+            transport_stats: None,
+            motion_scheduler: None,
+            hook_outputs: None,
+            capture: None,
+            reboot_to_bootloader: None,
+            reset: None,
+            crash_count: 0,
+            booted_at: Instant::now(),
+            firmware_name: env!("CARGO_PKG_NAME"),
+            firmware_version: env!("CARGO_PKG_VERSION"),
         }
+    }
 
-        fn default_config() -> FeederConfig {
-            FeederConfig {
-                advanced_angle: Value::from_num(135.0),
-                half_advanced_angle: Value::from_num(107.5),
-                retract_angle: Value::from_num(80),
-                feed_length: Value::from_num(2.0),
-                settle_time: 3,
-                pwm_0: Value::from_num(490.2),
-                pwm_180: Value::from_num(980.4),
-                ignore_feeback_pin: false,
-                always_retract: false,
+    // Sets the firmware name and version reported by M115.
+    pub fn with_firmware_info(mut self, name: &'static str, version: &'static str) -> Self {
+        self.firmware_name = name;
+        self.firmware_version = version;
+        self
+    }
+
+    // Include the transport's statistics in the M650 metrics output.
+    pub fn with_transport_stats(mut self, transport_stats: &'a TransportStats) -> Self {
+        self.transport_stats = Some(transport_stats);
+        self
+    }
+
+    // Applies `BoardConfig::max_moving_feeders` to the scheduler the feeders
+    // share.
+    pub fn with_motion_scheduler(mut self, scheduler: &'a MotionScheduler<R>) -> Self {
+        self.motion_scheduler = Some(scheduler);
+        self
+    }
+
+    // Lets feeder hooks drive the board's LED and aux output.
+    pub fn with_hook_outputs(mut self, hook_outputs: &'a mut dyn HookOutputs) -> Self {
+        self.hook_outputs = Some(hook_outputs);
+        self
+    }
+
+    // Enables M670 and M671, capturing the session into `buffer`.
+    pub fn with_session_capture(mut self, buffer: &'a mut [u8]) -> Self {
+        self.capture = Some(SessionCapture::new(buffer));
+        self
+    }
+
+    // Enables M997 on boards which can reboot into a bootloader.
+    pub fn with_bootloader(mut self, reboot_to_bootloader: fn() -> !) -> Self {
+        self.reboot_to_bootloader = Some(reboot_to_bootloader);
+        self
+    }
+
+    // Enables M999 on boards which can restart themselves.
+    pub fn with_reset(mut self, reset: fn() -> !) -> Self {
+        self.reset = Some(reset);
+        self
+    }
+
+    // Sets the number of crash resets in a row the board counted before this
+    // boot.  Once it reaches `SAFE_MODE_CRASHES` the handler starts in safe
+    // mode, otherwise the stored count is cleared after `STABLE_UPTIME`.
+    pub fn with_crash_count(mut self, crash_count: u32) -> Self {
+        self.crash_count = crash_count;
+        self
+    }
+
+    // In safe mode stored settings aren't loaded and feeders can't be enabled
+    // so a bad config or a faulty feeder can't crash the board again before
+    // the host can connect.  M999 or a power cycle leaves safe mode.
+    pub fn in_safe_mode(&self) -> bool {
+        self.crash_count >= SAFE_MODE_CRASHES
+    }
+
+    // `Q` is the depth of the transport's event queue and `E` guards it.
+    pub async fn run<const Q: usize, E: RawMutex>(
+        &mut self,
+        receiver: GCodeEventReceiver<'_, Q, E>,
+    ) {
+        self.initialize_feeder_configs().await;
+        loop {
+            // Notifications are polled first so they are output before any
+            // further queued commands are handled.
+            let event = match select3(
+                select_array(core::array::from_fn::<_, MAX_FEEDERS, _>(|index| {
+                    let feeder = self.feeders.get(index);
+                    async move {
+                        match feeder {
+                            Some(feeder) => feeder.wait_for_notification().await,
+                            None => pending().await,
+                        }
+                    }
+                })),
+                self.wait_for_periodic(),
+                receiver.receive(),
+            )
+            .await
+            {
+                Either3::First((notification, slot)) => {
+                    self.output_notification(slot, notification).await;
+                    continue;
+                }
+                Either3::Second(periodic) => {
+                    match periodic {
+                        Periodic::StatusReport => self.output_status(receiver.queued()).await,
+                        Periodic::SoakFeed => self.run_soak_feed().await,
+                        Periodic::SaveCounters => self.save_counters().await,
+                        Periodic::IdleCheck => self.check_idle().await,
+                        Periodic::ClearCrashCount => self.clear_crash_count(),
+                    }
+                    continue;
+                }
+                Either3::Third(event) => event,
+            };
+            if self.handle_event(event, receiver).await {
+                break;
+            }
+            if core::mem::take(&mut self.discard_queued_advances)
+                && self.discard_queued_advances(receiver).await
+            {
+                break;
             }
         }
     }
 
-    impl ConfigStore for FakeConfigStore {
-        fn get(&mut self, index: usize) -> Result<FeederConfig> {
-            Ok(self
-                .store
-                .lock()
-                .unwrap()
-                .get(&index)
-                .cloned()
-                .unwrap_or(Self::default_config()))
+    async fn handle_event<const Q: usize, E: RawMutex>(
+        &mut self,
+        event: GCodeEvent,
+        receiver: GCodeEventReceiver<'_, Q, E>,
+    ) -> bool {
+        if let Some(capture) = &mut self.capture {
+            match &event {
+                GCodeEvent::Connect => capture.record_event("connect"),
+                GCodeEvent::Disconnect => capture.record_event("disconnect"),
+                GCodeEvent::Line(line) => capture.record_command(DisplayLine(line)),
+                GCodeEvent::Unparsed(line) => capture.record_command(line),
+            }
+        }
+        if matches!(event, GCodeEvent::Line(_) | GCodeEvent::Unparsed(_)) {
+            self.note_activity().await;
         }
+        match event {
+            GCodeEvent::Connect => self.handle_connect().await,
+            GCodeEvent::Disconnect => {
+                self.cancel_calibration().await;
+                self.handle_disconnect().await
+            }
+            GCodeEvent::Line(line) => {
+                // A config code must immediately follow its M628, and G-code
+                // ends the calibration wizard.
+                self.config_code_target = None;
+                self.cancel_calibration().await;
 
-        fn set(&mut self, index: usize, config: &FeederConfig) -> Result<()> {
-            self.store.lock().unwrap().insert(index, config.clone());
-            Ok(())
+                // Watch for stops while the line is handled so they can
+                // interrupt long running feeds.
+                let feeders = self.feeders.clone();
+                let stop = async {
+                    loop {
+                        receiver.wait_for_stop().await;
+                        for feeder in feeders.iter() {
+                            feeder.abort();
+                        }
+                    }
+                };
+                match select(self.handle_line(line), stop).await {
+                    Either::First(exit) => exit,
+                    Either::Second(never) => never,
+                }
+            }
+            GCodeEvent::Unparsed(line) => {
+                let result = match self.config_code_target.take() {
+                    Some(index) => self.apply_config_code(index, &line).await,
+                    None if self.calibration.is_some() => {
+                        self.handle_calibration_input(&line).await
+                    }
+                    None => match split_string_argument(&line) {
+                        Some((command, text)) => self.handle_string_line(command, text).await,
+                        None => self.handle_setup_row(&line),
+                    },
+                };
+                self.output_result(result).await;
+                false
+            }
+        }
+    }
+
+    // Fails every queued advance.  Other queued events are handled as usual.
+    async fn discard_queued_advances<const Q: usize, E: RawMutex>(
+        &mut self,
+        receiver: GCodeEventReceiver<'_, Q, E>,
+    ) -> bool {
+        while let Some(event) = receiver.try_receive() {
+            let exit = match event {
+                GCodeEvent::Line(line) if is_advance_command(&line) => {
+                    self.command_count = self.command_count.wrapping_add(1);
+                    self.output_result(Err(Error::Aborted)).await;
+                    false
+                }
+                event => self.handle_event(event, receiver).await,
+            };
+            if exit {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub async fn initialize_feeder_configs(&mut self) {
+        if self.in_safe_mode() {
+            // Feeders run with factory defaults.  Saving with M500 replaces
+            // the stored settings, which is how a bad config is recovered.
+            for slot in 0..self.feeders.len() {
+                let config = self.config_store.get_default(slot);
+                self.feeders[slot].set_config(config).await.ok();
+            }
+            return;
+        }
+
+        let mut slot_map = self.slot_map;
+        for (index, mapped) in slot_map.iter_mut().take(self.feeders.len()).enumerate() {
+            if let Ok(slot) = self.config_store.get_slot(index) {
+                *mapped = slot;
+            }
+        }
+        // A stored map which isn't a permutation, e.g. one left by a write
+        // which failed part way through an M630 swap, would leave some
+        // feeder unreachable.  The identity map is kept instead.
+        if is_permutation(&slot_map[..self.feeders.len()]) {
+            self.slot_map = slot_map;
+        }
+
+        // It's unclear what the right action is on failure.  Perhaps we
+        // should have a disabled state where and error will be printed
+        // on connection.
+        let _ = self.load_feeder_configs().await;
+
+        for slot in 0..self.feeders.len() {
+            if let Ok(counters) = self.config_store.get_counters(slot) {
+                if self.feeders[slot].set_counters(counters).await.is_ok() {
+                    self.saved_counters[slot] = counters;
+                }
+            }
+            // The lever stopped somewhere along the stroke, not at retract.
+            if let Ok(true) = self.config_store.get_advance_marker(slot) {
+                self.advance_marked[slot] = true;
+                self.feeders[slot].mark_interrupted().await.ok();
+            }
+        }
+
+        if let Ok(board) = self.config_store.get_board_config() {
+            self.apply_motion_limit(&board);
+            if board.startup_enable {
+                self.startup_enable(board.startup_delay).await;
+            }
+            self.board = board;
+        }
+        self.last_activity = Instant::now();
+    }
+
+    fn apply_motion_limit(&self, board: &BoardConfig) {
+        if let Some(scheduler) = self.motion_scheduler {
+            scheduler.set_limit(board.max_moving_feeders);
+        }
+    }
+
+    // Blinks the LED for `delay` ms as a warning and then enables every
+    // feeder.
+    async fn startup_enable(&mut self, delay: u32) {
+        let enable_at = Instant::now() + Duration::from_millis(delay as u64);
+        let mut led = false;
+        while Instant::now() < enable_at {
+            led = !led;
+            if let Some(outputs) = &mut self.hook_outputs {
+                outputs.set_led(led);
+            }
+            Timer::at((Instant::now() + STARTUP_BLINK).min(enable_at)).await;
+        }
+        if let Some(outputs) = &mut self.hook_outputs {
+            outputs.set_led(false);
+        }
+        for feeder in self.feeders.iter_mut() {
+            feeder.enable(true).await.ok();
+        }
+    }
+
+    // Loads every feeder's config from the store.  Keeps going if one fails
+    // and returns the first error.
+    async fn load_feeder_configs(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for slot in 0..self.feeders.len() {
+            let loaded = match self.config_store.get(slot) {
+                Ok(config) => self.feeders[slot].set_config(config).await,
+                Err(e) => Err(e),
+            };
+            if result.is_ok() {
+                result = loaded;
+            }
+        }
+        result
+    }
+
+    pub async fn handle_connect(&mut self) -> bool {
+        self.output_stalled = false;
+        if self.in_safe_mode() {
+            let mut s: String<160> = String::new();
+            writeln!(
+                s,
+                "notice: {} safe mode after {} crash resets, stored settings not loaded \
+                 and feeders disabled, M999 to restart",
+                self.clock.now(),
+                self.crash_count
+            )
+            .ok();
+            self.write_output(s.as_bytes()).await;
+        }
+        self.write_output(b"saved settings:\n").await;
+        for index in 0..self.feeders.len() {
+            let _ = self.output_feeder_config(Some(index)).await; // Ignore errors on connect.
+        }
+        for slot in 0..self.feeders.len() {
+            if self.advance_marked[slot] {
+                let mut s: String<96> = String::new();
+                writeln!(
+                    s,
+                    "notice: {} feeder {} was interrupted mid-advance, home required",
+                    self.clock.now(),
+                    self.logical_index(slot)
+                )
+                .ok();
+                self.write_output(s.as_bytes()).await;
+            }
+        }
+        self.write_output(b"ready\n").await;
+        false
+    }
+
+    pub async fn handle_disconnect(&mut self) -> bool {
+        // Disable feeders on disconnect.  Button lockout is tied to the host's
+        // job so it is cleared as well.  A soak test is meant to run without a
+        // host so its feeders are left enabled.
+        self.job = None;
+        self.deferred_advances.clear();
+        let soaking = self.soak.is_some();
+        for feeder in self.feeders.iter_mut() {
+            if !soaking {
+                feeder.enable(false).await.ok(); // Ignore disable errors on disconnect.
+            }
+            feeder.set_button_lockout(false).await.ok();
+        }
+        false
+    }
+
+    pub async fn handle_line(&mut self, line: Line) -> bool {
+        let Some(command) = line.command() else {
+            return false;
+        };
+
+        // Without a reset, M999 allows tests to exit the loop.
+        #[cfg(test)]
+        if *command == word!('M', 999) && self.reset.is_none() {
+            for feeder in self.feeders.iter_mut() {
+                feeder.shutdown().await;
+            }
+            return true;
+        }
+
+        self.command_count = self.command_count.wrapping_add(1);
+
+        let ret = match command_code(command) {
+            Some(('G', 4)) => self.handle_g4(line).await,
+            Some(('G', 28)) => self.handle_g28(line).await,
+            Some(('M', 110)) => self.handle_m110(line).await,
+            Some(('M', 112)) => self.handle_m112(line).await,
+            Some(('M', 115)) => self.handle_m115(line).await,
+            Some(('M', 154)) => self.handle_m154(line).await,
+            Some(('M', 280)) => self.handle_m280(line).await,
+            Some(('M', 400)) => self.handle_m400(line).await,
+            Some(('M', 410)) => self.handle_m410(line).await,
+            Some(('M', 500)) => self.handle_m500(line).await,
+            Some(('M', 501)) => self.handle_m501(line).await,
+            Some(('M', 502)) => self.handle_m502(line).await,
+            Some(('M', 600)) => self.handle_m600(line).await,
+            Some(('M', 601)) => self.handle_m601(line).await,
+            Some(('M', 602)) => self.handle_m602(line).await,
+            Some(('M', 603)) => self.handle_m603(line).await,
+            Some(('M', 604)) => self.handle_m604(line).await,
+            Some(('M', 608)) => self.handle_m608(line).await,
+            Some(('M', 610)) => self.handle_m610(line).await,
+            Some(('M', 611)) => self.handle_m611(line).await,
+            Some(('M', 612)) => self.handle_m612(line).await,
+            Some(('M', 619)) => self.handle_m619(line).await,
+            Some(('M', 620)) => self.handle_m620(line).await,
+            Some(('M', 621)) => self.handle_m621(line).await,
+            Some(('M', 622)) => self.handle_m622(line).await,
+            Some(('M', 623)) => self.handle_m623(line).await,
+            Some(('M', 624)) => self.handle_m624(line).await,
+            Some(('M', 625)) => self.handle_m625(line).await,
+            Some(('M', 626)) => self.handle_m626(line).await,
+            Some(('M', 627)) => self.handle_m627(line).await,
+            Some(('M', 628)) => self.handle_m628(line).await,
+            Some(('M', 629)) => self.handle_m629(line).await,
+            Some(('M', 630)) => self.handle_m630(line).await,
+            Some(('M', 631)) => self.handle_m631(line).await,
+            Some(('M', 632)) => self.handle_m632(line, "").await,
+            Some(('M', 633)) => self.handle_m633(line).await,
+            Some(('M', 634)) => self.handle_m634(line).await,
+            Some(('M', 635)) => self.handle_m635(line).await,
+            Some(('M', 640)) => self.handle_m640(line).await,
+            Some(('M', 641)) => self.handle_m641(line).await,
+            Some(('M', 650)) => self.handle_m650(line).await,
+            Some(('M', 660)) => self.handle_m660(line).await,
+            Some(('M', 661)) => self.handle_m661(line).await,
+            Some(('M', 670)) => self.handle_m670(line).await,
+            Some(('M', 671)) => self.handle_m671(line).await,
+            Some(('M', 680)) => self.handle_m680(line).await,
+            Some(('M', 681)) => self.handle_m681(line).await,
+            Some(('M', 997)) => self.handle_m997(line).await,
+            Some(('M', 999)) => self.handle_m999(line).await,
+            _ => Err(Error::UnsupportedCommand(command.clone())),
+        };
+
+        self.output_result(ret).await;
+        false
+    }
+
+    async fn output_result(&mut self, result: Result<()>) {
+        match result {
+            Ok(_) => {
+                self.write_output(b"ok\n").await;
+            }
+            Err(e) => {
+                self.error_count = self.error_count.wrapping_add(1);
+                let mut s = String::<MAX_ERROR_LEN>::new();
+                write!(s, "error:{} {}", e.code(), e).ok();
+                self.write_output(s.as_bytes()).await;
+                // Written separately so a message which doesn't fit still
+                // ends the line.
+                self.write_output(b"\n").await;
+            }
+        }
+    }
+
+    // Resolves a logical feeder index from gcode to its physical slot and client.
+    fn resolve_feeder<'b>(
+        &'b mut self,
+        index: Option<usize>,
+    ) -> Result<(usize, &'b mut FeederClient<'a, R>)>
+    where
+        'a: 'b,
+    {
+        let index = index.ok_or(Error::NoIndex)?;
+
+        if index >= self.feeders.len() {
+            return Err(Error::InvalidIndex(index));
+        }
+
+        let slot = self.slot_map[index];
+        Ok((slot, &mut self.feeders[slot]))
+    }
+
+    // Line numbers are tracked by the transport's `LineChecker` before lines
+    // are parsed so there is nothing left to do here.
+    async fn handle_m110(&mut self, _command: Line) -> Result<()> {
+        Ok(())
+    }
+
+    async fn wait_for_periodic(&self) -> Periodic {
+        let periodic = select4(
+            self.wait_for_status_report(),
+            self.wait_for_soak_feed(),
+            Timer::at(self.next_counter_save),
+            self.wait_for_idle(),
+        );
+        match select(periodic, self.wait_for_stable_uptime()).await {
+            Either::First(Either4::First(())) => Periodic::StatusReport,
+            Either::First(Either4::Second(())) => Periodic::SoakFeed,
+            Either::First(Either4::Third(())) => Periodic::SaveCounters,
+            Either::First(Either4::Fourth(())) => Periodic::IdleCheck,
+            Either::Second(()) => Periodic::ClearCrashCount,
+        }
+    }
+
+    async fn wait_for_stable_uptime(&self) {
+        // Safe mode is only left by a restart.
+        if self.crash_count == 0 || self.in_safe_mode() {
+            core::future::pending().await
+        }
+        Timer::at(self.booted_at + STABLE_UPTIME).await
+    }
+
+    fn clear_crash_count(&mut self) {
+        self.config_store.set_crash_count(0).ok();
+        self.crash_count = 0;
+    }
+
+    async fn wait_for_idle(&self) {
+        let timeout = Duration::from_secs(self.board.idle_timeout as u64);
+        // A soak test is left to run unattended.
+        if timeout.as_ticks() == 0 || self.idle_disabled.is_some() || self.soak.is_some() {
+            core::future::pending().await
+        }
+        Timer::at(self.last_activity + timeout).await
+    }
+
+    // Disables every feeder and detaches its servo once there have been no
+    // commands or feeds for `BoardConfig::idle_timeout`.  Button feeds are
+    // noticed by the feed count changing since the last check.
+    async fn check_idle(&mut self) {
+        let mut feeds = 0u32;
+        let mut enabled = [false; MAX_FEEDERS];
+        for (slot, feeder) in self.feeders.iter_mut().enumerate() {
+            if let Ok(status) = feeder.get_status().await {
+                feeds = feeds.wrapping_add(status.feeds);
+                enabled[slot] = status.enabled;
+            }
+        }
+        if feeds != self.activity_feeds {
+            self.activity_feeds = feeds;
+            self.last_activity = Instant::now();
+            return;
+        }
+
+        for (slot, feeder) in self.feeders.iter_mut().enumerate() {
+            if enabled[slot] {
+                feeder.enable(false).await.ok();
+                feeder.detach().await.ok();
+            }
+        }
+        self.idle_disabled = Some(enabled);
+        if enabled.contains(&true) {
+            let mut s: String<64> = String::new();
+            writeln!(
+                s,
+                "notice: {} feeders disabled while idle",
+                self.clock.now()
+            )
+            .ok();
+            self.write_output(s.as_bytes()).await;
+        }
+    }
+
+    // Called for each command from the host.  Feeders disabled for being idle
+    // are re-enabled if the board is configured to.
+    async fn note_activity(&mut self) {
+        self.last_activity = Instant::now();
+        let Some(disabled) = self.idle_disabled.take() else {
+            return;
+        };
+        if self.board.idle_reenable {
+            for (slot, feeder) in self.feeders.iter_mut().enumerate() {
+                if disabled[slot] {
+                    feeder.enable(true).await.ok();
+                }
+            }
+        }
+    }
+
+    // Writes the feed counters which changed since they were last saved.
+    async fn save_counters(&mut self) {
+        self.next_counter_save = Instant::now() + COUNTER_SAVE_INTERVAL;
+        for slot in 0..self.feeders.len() {
+            let Ok(status) = self.feeders[slot].get_status().await else {
+                continue;
+            };
+            let counters = FeedCounters {
+                parts: status.parts,
+                length: status.length_fed,
+                remaining: status.parts_remaining,
+            };
+            if counters != self.saved_counters[slot]
+                && self.config_store.set_counters(slot, &counters).is_ok()
+            {
+                self.saved_counters[slot] = counters;
+            }
         }
     }
 
-    async fn run_handler<W: Write, C: ConfigStore>(
-        feeders: [FeederClient<'_>; 2],
-        output: W,
-        config_store: C,
-        line_reciever: GCodeEventReceiver<'_, 2>,
-    ) {
-        let mut gcode_handler = GCodeHandler::new(feeders, output, config_store);
-        gcode_handler.run(line_reciever).await;
+    async fn wait_for_status_report(&self) {
+        match self.status_interval {
+            Some(_) => Timer::at(self.next_status_report).await,
+            None => core::future::pending().await,
+        }
+    }
+
+    // Logical index used to report on the feeder in physical `slot`.
+    fn logical_index(&self, slot: usize) -> usize {
+        self.slot_map
+            .iter()
+            .position(|mapped| *mapped == slot)
+            .unwrap_or(slot)
+    }
+
+    async fn output_notification(&mut self, slot: usize, notification: FeederNotification) {
+        let index = self.logical_index(slot);
+        let mut s: String<96> = String::new();
+        match notification {
+            FeederNotification::AutoDisabled(reason) => writeln!(
+                s,
+                "notice: {} feeder {} auto-disabled: {}",
+                self.clock.now(),
+                index,
+                reason
+            ),
+            FeederNotification::RunoutWarning(remaining) => writeln!(
+                s,
+                "warning: {} feeder {} low on parts: {} remaining",
+                self.clock.now(),
+                index,
+                remaining
+            ),
+            FeederNotification::Progress(progress) => writeln!(
+                s,
+                "progress: feeder {} cycles={} remaining={}",
+                index, progress.cycles, progress.remaining
+            ),
+            FeederNotification::Hook(event, action) => {
+                self.run_hook(index, event, action).await;
+                return;
+            }
+            FeederNotification::ButtonPressed => {
+                if self.calibration.as_ref().map(|c| c.slot) == Some(slot) {
+                    // Nothing is waiting for a response so failures are
+                    // reported as part of the wizard's output.
+                    if let Err(e) = self.accept_calibration_step().await {
+                        let mut s: String<96> = String::new();
+                        writeln!(s, "calibrate: feeder {} failed: {}", index, e).ok();
+                        self.write_output(s.as_bytes()).await;
+                    }
+                }
+                return;
+            }
+            FeederNotification::ConfigChanged(config) => {
+                if self.watch_config {
+                    if let Ok(s) = format_feeder_config("config: ", index, &config) {
+                        self.write_output(s.as_bytes()).await;
+                    }
+                }
+                return;
+            }
+        }
+        .ok();
+        self.write_output(s.as_bytes()).await;
+    }
+
+    async fn run_hook(&mut self, index: usize, event: HookEvent, action: HookAction) {
+        match action {
+            HookAction::Notify => {
+                let mut s: String<64> = String::new();
+                writeln!(s, "event: feeder {} {}", index, event).ok();
+                self.write_output(s.as_bytes()).await;
+            }
+            HookAction::PulseAux => {
+                if let Some(outputs) = &mut self.hook_outputs {
+                    outputs.set_aux(true);
+                    Timer::after(AUX_PULSE).await;
+                    outputs.set_aux(false);
+                }
+            }
+            HookAction::LedOn | HookAction::LedOff => {
+                if let Some(outputs) = &mut self.hook_outputs {
+                    outputs.set_led(action == HookAction::LedOn);
+                }
+            }
+            // Counted by the feeder.
+            HookAction::None | HookAction::Count => {}
+        }
+    }
+
+    // Reboots into the firmware update bootloader.  The response is sent
+    // first since the reboot drops the host connection.
+    async fn handle_m997(&mut self, command: Line) -> Result<()> {
+        let Some(reboot_to_bootloader) = self.reboot_to_bootloader else {
+            return Err(Error::UnsupportedCommand(word!('M', 997)));
+        };
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        self.write_output(b"ok\n").await;
+        Timer::after(REBOOT_DELAY).await;
+        reboot_to_bootloader()
+    }
+
+    // Restarts the controller to recover it without a power cycle.  Feeders
+    // are disabled first so the reset doesn't catch a feed partway through.
+    async fn handle_m999(&mut self, command: Line) -> Result<()> {
+        let Some(reset) = self.reset else {
+            return Err(Error::UnsupportedCommand(word!('M', 999)));
+        };
+        if let Some(arg) = command.arguments().next() {
+            return Err(Error::InvalidArgument(arg.letter));
+        }
+
+        for feeder in self.feeders.iter_mut() {
+            // The reset goes ahead even if a feeder doesn't respond.
+            feeder.enable(false).await.ok();
+        }
+        self.write_output(b"ok\n").await;
+        Timer::after(REBOOT_DELAY).await;
+        reset()
+    }
+
+    // Writes `buf` to the host, dropping it if the transport is stalled so
+    // that feeders and command handling aren't blocked.  After a timeout,
+    // writes are no longer waited on until one succeeds or the host
+    // reconnects.
+    async fn write_output(&mut self, buf: &[u8]) {
+        if let Some(capture) = &mut self.capture {
+            capture.record_output(buf);
+        }
+        let timeout = if self.output_stalled {
+            Duration::from_ticks(0)
+        } else {
+            WRITE_TIMEOUT
+        };
+        match with_timeout(timeout, self.output.write_all(buf)).await {
+            // I/O errors are ignored the same as timeouts.
+            Ok(_) => self.output_stalled = false,
+            Err(_) => {
+                self.output_stalled = true;
+                self.write_timeouts = self.write_timeouts.wrapping_add(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    use alloc::sync::Arc;
+    use core::{cell::Cell, convert::Infallible, future::Future};
+    use embassy_futures::{
+        join::{join, join3, join_array},
+        select::{select, Either},
+        yield_now,
+    };
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_time::{MockDriver, Timer};
+    use fixed::traits::ToFixed;
+    use std::{collections::HashMap, string::String, sync::Mutex, vec::Vec};
+
+    use super::*;
+
+    struct FakeServo {
+        limits: servo::PwmLimits,
+        positions: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl FakeServo {
+        const PERIOD_MICROS: u32 = 20_000;
+        fn new() -> (Arc<Mutex<Vec<Value>>>, Self) {
+            let positions = Arc::new(Mutex::new(Vec::new()));
+            (
+                positions.clone(),
+                Self {
+                    limits: PwmLimits::standard(),
+                    positions,
+                },
+            )
+        }
+    }
+
+    impl Servo for FakeServo {
+        fn set_angle(&mut self, angle: Value) -> Result<()> {
+            println!("fake servo: set angle {angle}");
+            self.limits.scale_angle(angle)?;
+
+            self.positions.lock().unwrap().push(angle);
+            Ok(())
+        }
+
+        fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+            limits.check_range(Self::PERIOD_MICROS)?;
+            self.limits = limits;
+            Ok(())
+        }
+
+        fn get_pwm_limits(&self) -> PwmLimits {
+            self.limits.clone()
+        }
+
+        // Pulse widths are recorded along with angles, as is.
+        fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+            println!("fake servo: set pulse width {micros}");
+            self.positions.lock().unwrap().push(micros);
+            Ok(())
+        }
+    }
+
+    // Records each strength along with when it was set.
+    struct FakePeeler {
+        strengths: Arc<Mutex<Vec<(Instant, Value)>>>,
+    }
+
+    impl Peeler for FakePeeler {
+        fn set_strength(&mut self, strength: Value) -> Result<()> {
+            self.strengths
+                .lock()
+                .unwrap()
+                .push((Instant::now(), strength));
+            Ok(())
+        }
+    }
+
+    // Reads out the given counts in turn, then keeps returning the last.
+    struct FakeEncoder {
+        counts: Vec<i32>,
+    }
+
+    impl CountingInput for FakeEncoder {
+        fn count(&mut self) -> i32 {
+            match self.counts.len() {
+                0 => 0,
+                1 => self.counts[0],
+                _ => self.counts.remove(0),
+            }
+        }
+    }
+
+    type FakeInputChannel = Channel<NoopRawMutex, bool, 4>;
+
+    struct FakeInput<'a> {
+        channel: &'a FakeInputChannel,
+        state: bool,
+    }
+
+    impl<'a> FakeInput<'a> {
+        pub fn new(state: bool, channel: &'a FakeInputChannel) -> Self {
+            Self { channel, state }
+        }
+
+        async fn poll_state(&mut self) -> bool {
+            loop {
+                // Consume all update events in queue and return state when empty.
+                let Ok(new_state) = self.channel.try_receive() else {
+                    return self.state;
+                };
+                self.state = new_state;
+            }
+        }
+
+        async fn wait_for_state(&mut self, state: bool) {
+            loop {
+                let new_state = self.poll_state().await;
+                if new_state == state {
+                    return;
+                }
+            }
+        }
+    }
+
+    impl<'a> Input for FakeInput<'a> {
+        async fn wait_for_high(&mut self) {
+            self.wait_for_state(true).await
+        }
+
+        async fn wait_for_low(&mut self) {
+            self.wait_for_state(false).await
+        }
+
+        async fn wait_for_state_change(&mut self) {
+            self.state = self.channel.receive().await;
+        }
+
+        async fn get_state(&mut self) -> bool {
+            self.poll_state().await
+        }
+    }
+
+    // An input which changes state every millisecond until `remaining`
+    // changes have been reported, then goes quiet.  Reading it takes longer
+    // than that so a change is always waiting once the last was handled.
+    struct ChatteringInput<'a> {
+        remaining: &'a Cell<u32>,
+        next_change: Instant,
+    }
+
+    impl<'a> Input for ChatteringInput<'a> {
+        async fn wait_for_high(&mut self) {}
+
+        async fn wait_for_low(&mut self) {}
+
+        async fn wait_for_state_change(&mut self) {
+            if self.remaining.get() == 0 {
+                core::future::pending().await
+            }
+            // Like a latched edge, a change which is already due is reported
+            // without yielding.
+            if Instant::now() < self.next_change {
+                Timer::at(self.next_change).await;
+            }
+            self.next_change += Duration::from_millis(1);
+            self.remaining.set(self.remaining.get() - 1);
+        }
+
+        async fn get_state(&mut self) -> bool {
+            Timer::after_millis(2).await;
+            self.remaining.get() & 1 == 0
+        }
+    }
+
+    struct FakeConfigStore {
+        store: Arc<Mutex<HashMap<usize, FeederConfig>>>,
+        slots: HashMap<usize, usize>,
+        cartridges: HashMap<usize, CartridgeId>,
+        counters: Arc<Mutex<HashMap<usize, FeedCounters>>>,
+        board: BoardConfig,
+        advance_markers: Arc<Mutex<HashMap<usize, bool>>>,
+        crash_count: Arc<Mutex<u32>>,
+        drop_writes: bool,
+    }
+
+    impl FakeConfigStore {
+        fn new() -> Self {
+            Self {
+                store: Arc::new(Mutex::new(HashMap::new())),
+                slots: HashMap::new(),
+                cartridges: HashMap::new(),
+                counters: Arc::new(Mutex::new(HashMap::new())),
+                board: BoardConfig::default(),
+                advance_markers: Arc::new(Mutex::new(HashMap::new())),
+                crash_count: Arc::new(Mutex::new(0)),
+                drop_writes: false,
+            }
+        }
+
+        // Simulates a flash which silently fails to write.
+        fn new_dropping_writes() -> Self {
+            Self {
+                drop_writes: true,
+                ..Self::new()
+            }
+        }
+
+        fn get_store(&self) -> Arc<Mutex<HashMap<usize, FeederConfig>>> {
+            self.store.clone()
+        }
+
+        fn default_config() -> FeederConfig {
+            FeederConfig {
+                advanced_angle: Value::from_num(135.0),
+                half_advanced_angle: Value::from_num(107.5),
+                retract_angle: Value::from_num(80),
+                feed_length: Value::from_num(2.0),
+                advance_settle_time: 3,
+                retract_settle_time: 3,
+                pwm_0: Value::from_num(1000),
+                pwm_180: Value::from_num(2000),
+                ignore_feeback_pin: false,
+                retract_policy: RetractPolicy::FullAdvance,
+                button_feed_interval: 0,
+                motion_feedback: MotionFeedback::Ignore,
+                retract_distance: Value::from_num(4),
+                half_advance: true,
+                mm_per_part: Value::from_num(0),
+                feed_complete_hook: HookAction::None,
+                jam_hook: HookAction::None,
+                enable_hook: HookAction::None,
+                sprocket_pitch: Value::from_num(4),
+                holes_per_retract: 1,
+                interpolate_angle: false,
+                slew_rate: Value::from_num(0),
+                retry_count: 0,
+                retry_delay_ms: 500,
+                runout_warning: 0,
+                jam_timeout: 0,
+                position_units: PositionUnits::Degrees,
+                peel_time: 0,
+                peel_strength: Value::from_num(100),
+                peel_after: false,
+                secondary_advanced_angle: Value::from_num(90),
+                secondary_retract_angle: Value::from_num(90),
+                encoder_counts_per_mm: Value::from_num(0),
+                feed_tolerance: Value::from_num(0.5),
+                max_corrections: 2,
+                min_angle: Value::from_num(0),
+                max_angle: Value::from_num(180),
+                servo_idle_timeout: 0,
+                enable_home_rate: Value::from_num(0),
+                servo_frame_rate: STANDARD_FRAME_RATE,
+                trim_angle: Value::from_num(0),
+                release_angle: Value::from_num(0),
+            }
+        }
+    }
+
+    impl ConfigStore for FakeConfigStore {
+        fn get_default(&self, _index: usize) -> FeederConfig {
+            Self::default_config()
+        }
+
+        fn get(&mut self, index: usize) -> Result<FeederConfig> {
+            Ok(self
+                .store
+                .lock()
+                .unwrap()
+                .get(&index)
+                .cloned()
+                .unwrap_or(Self::default_config()))
+        }
+
+        fn set(&mut self, index: usize, config: &FeederConfig) -> Result<()> {
+            if !self.drop_writes {
+                self.store.lock().unwrap().insert(index, config.clone());
+            }
+            Ok(())
+        }
+
+        fn get_slot(&mut self, index: usize) -> Result<usize> {
+            Ok(self.slots.get(&index).copied().unwrap_or(index))
+        }
+
+        fn set_slot(&mut self, index: usize, slot: usize) -> Result<()> {
+            if !self.drop_writes {
+                self.slots.insert(index, slot);
+            }
+            Ok(())
+        }
+
+        fn get_cartridge(&mut self, index: usize) -> Result<CartridgeId> {
+            Ok(self.cartridges.get(&index).cloned().unwrap_or_default())
+        }
+
+        fn set_cartridge(&mut self, index: usize, cartridge: &str) -> Result<()> {
+            if !self.drop_writes {
+                self.cartridges.insert(index, cartridge.try_into().unwrap());
+            }
+            Ok(())
+        }
+
+        fn get_counters(&mut self, slot: usize) -> Result<FeedCounters> {
+            Ok(self
+                .counters
+                .lock()
+                .unwrap()
+                .get(&slot)
+                .copied()
+                .unwrap_or_default())
+        }
+
+        fn set_counters(&mut self, slot: usize, counters: &FeedCounters) -> Result<()> {
+            if !self.drop_writes {
+                self.counters.lock().unwrap().insert(slot, *counters);
+            }
+            Ok(())
+        }
+
+        fn get_board_config(&mut self) -> Result<BoardConfig> {
+            Ok(self.board.clone())
+        }
+
+        fn set_board_config(&mut self, config: &BoardConfig) -> Result<()> {
+            if !self.drop_writes {
+                self.board = config.clone();
+            }
+            Ok(())
+        }
+
+        fn get_advance_marker(&mut self, slot: usize) -> Result<bool> {
+            Ok(self
+                .advance_markers
+                .lock()
+                .unwrap()
+                .get(&slot)
+                .copied()
+                .unwrap_or(false))
+        }
+
+        fn set_advance_marker(&mut self, slot: usize, in_progress: bool) -> Result<()> {
+            if !self.drop_writes {
+                self.advance_markers
+                    .lock()
+                    .unwrap()
+                    .insert(slot, in_progress);
+            }
+            Ok(())
+        }
+
+        fn get_crash_count(&mut self) -> Result<u32> {
+            Ok(*self.crash_count.lock().unwrap())
+        }
+
+        fn set_crash_count(&mut self, count: u32) -> Result<()> {
+            if !self.drop_writes {
+                *self.crash_count.lock().unwrap() = count;
+            }
+            Ok(())
+        }
+    }
+
+    // Every test runs against embassy-time's global mock driver so scenarios
+    // run instantly and deterministically.  Tests are serialized because the
+    // driver is shared.
+    static MOCK_TIME_LOCK: Mutex<()> = Mutex::new(());
+
+    // Number of executor polls between each 1ms tick of mock time.  Gives
+    // every runnable task a chance to settle before time advances.
+    const MOCK_TIME_POLLS_PER_TICK: usize = 16;
+
+    // Each test runs on its own thread so holding the lock is intentional.
+    #[allow(clippy::await_holding_lock)]
+    async fn with_mock_time<F: Future>(future: F) -> F::Output {
+        let _guard = MOCK_TIME_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let clock = async {
+            loop {
+                for _ in 0..MOCK_TIME_POLLS_PER_TICK {
+                    yield_now().await;
+                }
+                MockDriver::get().advance(Duration::from_millis(1));
+            }
+        };
+        match select(future, clock).await {
+            Either::First(output) => output,
+            Either::Second(never) => never,
+        }
+    }
+
+    // Plays `trace` into the feedback input of an enabled feeder and returns
+    // the number of feeds it performed.
+    async fn play_feedback_trace(trace: &[Edge], config: FeederConfig) -> u32 {
+        with_mock_time(async {
+            let (_positions, servo) = FakeServo::new();
+            let trace_end = trace.last().map(|edge| edge.at).unwrap_or_default();
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new(servo, PlaybackInput::new(true, trace));
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                client.set_config(config).await.unwrap();
+                client.enable(true).await.unwrap();
+                Timer::after(trace_end + Duration::from_millis(50)).await;
+                let status = client.get_status().await.unwrap();
+                client.shutdown().await;
+                status.feeds
+            };
+            let (_, feeds) = join(feeder.run(&channel), test_future).await;
+            feeds
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn chattering_input_doesnt_starve_commands() {
+        with_mock_time(async {
+            let (_positions, servo) = FakeServo::new();
+            let remaining = Cell::new(1_000);
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new(
+                servo,
+                ChatteringInput {
+                    remaining: &remaining,
+                    next_change: Instant::now(),
+                },
+            );
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                client.enable(true).await.unwrap();
+                client.get_status().await.unwrap();
+                // Both commands were handled while the input was chattering.
+                assert!(remaining.get() > 0);
+                client.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+        })
+        .await
+    }
+
+    #[test]
+    fn channels_can_be_shared_between_cores() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<FeederChannel<CriticalSectionRawMutex>>();
+        assert_sync::<GCodeEventChannel<8, CriticalSectionRawMutex>>();
+        assert_sync::<MotionScheduler<CriticalSectionRawMutex>>();
+    }
+
+    #[futures_test::test]
+    async fn clients_sharing_a_channel_get_their_own_responses() {
+        with_mock_time(async {
+            let (positions, servo) = FakeServo::new();
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new(servo, PlaybackInput::new(false, &[]));
+
+            let test_future = async {
+                let mut first = FeederClient::new(&channel);
+                let mut second = FeederClient::new(&channel);
+                first.enable(true).await.unwrap();
+                // The advance's response arrives ahead of the status but is
+                // only collected by the client which started it.
+                let advance = first.start_advance(FeedLength::Default, false).await;
+                let status = second.get_status().await.unwrap();
+                assert_eq!(status.feeds, 1);
+                first.finish(advance).await.unwrap();
+                first.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+            assert!(!positions.lock().unwrap().is_empty());
+        })
+        .await
+    }
+
+    async fn run_handler<W: Write, C: ConfigStore>(
+        feeders: FeederBank<'_>,
+        output: W,
+        mut config_store: C,
+        line_reciever: GCodeEventReceiver<'_, 2>,
+    ) {
+        let mut capture_buffer = [0; 512];
+        // The count is left for the board to update at boot.
+        let crash_count = config_store.get_crash_count().unwrap();
+        let mut gcode_handler = GCodeHandler::new(feeders, output, config_store)
+            .with_session_capture(&mut capture_buffer)
+            .with_crash_count(crash_count);
+        gcode_handler.run(line_reciever).await;
+    }
+
+    async fn run_test_harness(
+        line_reciever: GCodeEventReceiver<'_, 2>,
+        fake_inputs: &[FakeInputChannel; 2],
+    ) -> ([Vec<Value>; 2], Vec<u8>, HashMap<usize, FeederConfig>) {
+        run_test_harness_with_store(line_reciever, fake_inputs, FakeConfigStore::new()).await
+    }
+
+    async fn run_test_harness_with_store(
+        line_reciever: GCodeEventReceiver<'_, 2>,
+        fake_inputs: &[FakeInputChannel; 2],
+        config_store: FakeConfigStore,
+    ) -> ([Vec<Value>; 2], Vec<u8>, HashMap<usize, FeederConfig>) {
+        let mut output = Vec::<u8>::new();
+        let (positions, configs) =
+            run_test_harness_with_output(line_reciever, fake_inputs, config_store, &mut output)
+                .await;
+        (positions, output, configs)
+    }
+
+    async fn run_test_harness_with_output<W: Write>(
+        line_reciever: GCodeEventReceiver<'_, 2>,
+        fake_inputs: &[FakeInputChannel; 2],
+        config_store: FakeConfigStore,
+        output: W,
+    ) -> ([Vec<Value>; 2], HashMap<usize, FeederConfig>) {
+        let (positions_0, servo_0) = FakeServo::new();
+        let (positions_1, servo_1) = FakeServo::new();
+        let mut feeder_0 = Feeder::new(servo_0, FakeInput::new(false, &fake_inputs[0]));
+        let mut feeder_1 = Feeder::new(servo_1, FakeInput::new(false, &fake_inputs[1]));
+        let channels = [&FeederChannel::new(), &FeederChannel::new()];
+        let feeder_future = join_array([feeder_0.run(channels[0]), feeder_1.run(channels[1])]);
+        let backing_store = config_store.get_store();
+        let mut feeders = FeederBank::new();
+        for channel in channels {
+            feeders.register(FeederClient::new(channel)).unwrap();
+        }
+        with_mock_time(join(
+            feeder_future,
+            run_handler(feeders, output, config_store, line_reciever),
+        ))
+        .await;
+        let positions_0 = positions_0.lock().unwrap().clone();
+        let positions_1 = positions_1.lock().unwrap().clone();
+        let configs = backing_store.lock().unwrap().clone();
+        ([positions_0, positions_1], configs)
+    }
+
+    // Like `run_test_harness` but with the servos driven by a
+    // `MotionController` so slew rates take effect.  Returns every position
+    // each servo was stepped through.
+    async fn run_test_harness_with_motion(
+        line_reciever: GCodeEventReceiver<'_, 2>,
+        fake_inputs: &[FakeInputChannel; 2],
+    ) -> ([Vec<Value>; 2], Vec<u8>) {
+        let (positions_0, mut servo_0) = FakeServo::new();
+        let (positions_1, mut servo_1) = FakeServo::new();
+        let controller = MotionController::new([&mut servo_0, &mut servo_1]);
+        let mut feeder_0 = Feeder::new(controller.servo(0), FakeInput::new(false, &fake_inputs[0]));
+        let mut feeder_1 = Feeder::new(controller.servo(1), FakeInput::new(false, &fake_inputs[1]));
+        let channels = [&FeederChannel::new(), &FeederChannel::new()];
+        let feeder_future = join_array([feeder_0.run(channels[0]), feeder_1.run(channels[1])]);
+        let mut feeders = FeederBank::new();
+        for channel in channels {
+            feeders.register(FeederClient::new(channel)).unwrap();
+        }
+        let mut output = Vec::<u8>::new();
+        with_mock_time(select(
+            controller.run(),
+            join(
+                feeder_future,
+                run_handler(feeders, &mut output, FakeConfigStore::new(), line_reciever),
+            ),
+        ))
+        .await;
+        let positions_0 = positions_0.lock().unwrap().clone();
+        let positions_1 = positions_1.lock().unwrap().clone();
+        ([positions_0, positions_1], output)
+    }
+
+    // Lines which aren't G-code are sent unparsed, as the transport does.
+    fn line_event(s: &str) -> GCodeEvent {
+        GCodeEvent::parse(s).unwrap()
+    }
+
+    #[test]
+    fn error_codes_are_stable() {
+        // Hosts depend on these so existing codes must never change.
+        assert_eq!(Error::Disconnected.code(), 1);
+        assert_eq!(Error::InvalidIndex(3).code(), 8);
+        assert_eq!(Error::FeederNotReady(Some(3)).code(), 13);
+        assert_eq!(Error::Aborted.code(), 23);
+        assert_eq!(Error::InvalidCartridgeId.code(), 30);
+        assert_eq!(Error::HalfAdvanceUnsupported(Value::from_num(2)).code(), 31);
+        assert_eq!(Error::NoPartPitch.code(), 32);
+        assert_eq!(Error::NoSoak.code(), 33);
+        assert_eq!(Error::TooManyDeferredAdvances.code(), 34);
+        assert_eq!(Error::FeederJammed(Some(1)).code(), 35);
+        assert_eq!(Error::SafeMode.code(), 36);
+        assert_eq!(Error::Underfeed(Value::from_num(3)).code(), 37);
+        assert_eq!(Error::Overfeed(Value::from_num(5)).code(), 38);
+        assert_eq!(Error::GroupAdvanceFailed(0b1010).code(), 39);
+        assert_eq!(Error::TooManyFeeders.code(), 40);
+        assert_eq!(Error::SharedFrameRate.code(), 41);
+    }
+
+    #[test]
+    fn error_reports_fit_every_failed_group_feeder() {
+        let e = Error::GroupAdvanceFailed(u32::MAX);
+        let report = format!("error:{} {}", e.code(), e);
+        assert!(report.ends_with(" 30 31"));
+        assert!(report.len() <= MAX_ERROR_LEN);
+    }
+
+    #[test]
+    fn feeder_bank_registers_feeders_in_order() {
+        let channel = FeederChannel::new();
+        let mut bank = FeederBank::new();
+        for slot in 0..MAX_FEEDERS {
+            assert_eq!(bank.register(FeederClient::new(&channel)), Ok(slot));
+        }
+        assert_eq!(
+            bank.register(FeederClient::new(&channel)),
+            Err(Error::TooManyFeeders)
+        );
+        assert_eq!(bank.len(), MAX_FEEDERS);
+    }
+
+    #[test]
+    fn session_capture_keeps_recent_whole_lines() {
+        fn captured(capture: &SessionCapture) -> String {
+            let (first, second) = capture.as_slices();
+            String::from_utf8([first, second].concat()).unwrap()
+        }
+
+        let mut buffer = [0; 24];
+        let mut capture = SessionCapture::new(&mut buffer);
+        capture.record_command("M400");
+        assert_eq!(captured(&capture), "");
+
+        capture.set_enabled(true);
+        capture.record_command("M400");
+        capture.record_output(b"o");
+        capture.record_output(b"k\n");
+        assert_eq!(captured(&capture), "> M400\n< ok\n");
+
+        // Older lines are dropped to make room, even if the buffer wraps.
+        capture.record_command("M600 N0");
+        capture.record_output(b"error:10");
+        capture.record_event("disconnect");
+        assert_eq!(captured(&capture), "< error:10\n@disconnect\n");
+
+        capture.set_enabled(false);
+        capture.set_enabled(true);
+        assert_eq!(captured(&capture), "");
+    }
+
+    #[test]
+    fn sanitize_replaces_non_ascii() {
+        assert_eq!(sanitize::<32>("0805 10K"), "0805 10K");
+        // Each multi-byte character becomes a single `?`.
+        assert_eq!(sanitize::<32>("10µF ±5% 電容"), "10?F ?5% ??");
+        assert_eq!(sanitize::<32>("\"R1\"\t\u{7f}"), "'R1'??");
+    }
+
+    #[test]
+    fn sanitize_truncates_at_character_boundaries() {
+        // Truncation lands right after multi-byte characters of each width.
+        assert_eq!(sanitize::<4>("abcé"), "abc?");
+        assert_eq!(sanitize::<4>("abc€x"), "abc?");
+        assert_eq!(sanitize::<4>("ab😀cd"), "ab?c");
+        assert_eq!(sanitize::<3>("😀😀😀😀"), "???");
+        assert_eq!(sanitize::<0>("é"), "");
+    }
+
+    #[futures_test::test]
+    async fn test_harnes_exits_on_m999() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move { line_sender.send(line_event("M999")).await };
+        let (_, _) = join(test_harness_future, test_future).await;
+    }
+
+    #[futures_test::test]
+    async fn feeder_doesnt_move_before_enabled() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M603 N1 A120.0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            "error:10 feeder 1 disabled\n",
+            String::from_utf8_lossy(&output)
+        );
+        assert!(servos[0].is_empty());
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn angle_limits_stop_every_move() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M634 N0 F85 H130")).await;
+            line_sender.send(line_event("M603 N0 A150")).await;
+            line_sender.send(line_event("M603 N0 A120")).await;
+            // The configured advanced angle of 135 is past the limit.
+            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M634 N0 H190")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\n\
+             error:4 angle out of range\n\
+             ok\n\
+             error:4 angle out of range\n\
+             invalid: H190\n\
+             error:9 invalid argument type H\n"
+        );
+        assert_eq!(servos[0], vec![Value::from_num(120)]);
+    }
+
+    #[futures_test::test]
+    async fn m600_advances_group_together() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 N1 F4")).await;
+            line_sender.send(line_event("M600 N1 N1 F4")).await;
+            line_sender.send(line_event("M600 N0 N1 N2 F4")).await;
+            // Feeder 1's failure doesn't stop feeder 0 advancing.
+            Timer::after_millis(1000).await;
+            fake_inputs[1].send(true).await;
+            line_sender.send(line_event("M600 N0 N1 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             error:9 invalid argument type N\n\
+             error:8 no feeder 2\n\
+             group: N1 error:13 feeder 1 not ready\n\
+             error:39 group advance failed on feeders 1\n"
+        );
+        let advance = vec![Value::from_num(135), Value::from_num(80)];
+        assert_eq!(servos[0], [advance.clone(), advance.clone()].concat());
+        assert_eq!(servos[1], advance);
+    }
+
+    #[futures_test::test]
+    async fn trim_offsets_every_move() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M634 N0 B-2.5 H133")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M603 N0 A100")).await;
+            // Limits apply to the trimmed angle.
+            line_sender.send(line_event("M603 N0 A136")).await;
+            line_sender.send(line_event("M634 N0 B21")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nok\nok\n\
+             error:4 angle out of range\n\
+             invalid: B21\n\
+             error:9 invalid argument type B\n"
+        );
+        assert_eq!(
+            servos[0],
+            vec![
+                Value::from_num(132.5),
+                Value::from_num(77.5),
+                Value::from_num(97.5)
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn frame_rate_is_checked_by_the_servo() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            // The fake servo, like most, only runs at the standard rate.
+            line_sender.send(line_event("M634 N0 Q200")).await;
+            line_sender.send(line_event("M634 N0 Q30")).await;
+            line_sender.send(line_event("M634 N0 Q50")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "error:5 pwm value out of range\n\
+             invalid: Q30\n\
+             error:9 invalid argument type Q\n\
+             ok\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn m603_moves_correct_servo() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M603 N1 A120.0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        println!("{}", String::from_utf8_lossy(&output));
+        assert!(servos[0].is_empty());
+        assert_eq!(servos[1], vec![Value::from_num(120.0)]);
+    }
+
+    #[futures_test::test]
+    async fn m600_advances_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert!(servos[0].is_empty());
+
+        // Servo 1 should go to the advanced angle then the retract angle.
+        let feeder_config_defaults: FeederConfig = Default::default();
+        assert_eq!(
+            servos[1],
+            vec![
+                feeder_config_defaults.advanced_angle,
+                feeder_config_defaults.retract_angle
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn m620_changes_feeder_angles() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N1 A122 C22")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert!(servos[0].is_empty());
+        assert_eq!(servos[1], vec![Value::from_num(122), Value::from_num(22)]);
+    }
+
+    #[futures_test::test]
+    async fn m500_saves_m620_changes_to_store() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N1 A122 C22")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M500")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert_eq!(
+            *config.get(&1).unwrap(),
+            FeederConfig {
+                advanced_angle: 122.0f32.to_fixed(),
+                retract_angle: 22.0f32.to_fixed(),
+                ..FakeConfigStore::default_config()
+            }
+        );
+    }
+
+    #[futures_test::test]
+    async fn m629_reports_config_changes() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M620 N0 A120")).await;
+            line_sender.send(line_event("M629 S1")).await;
+            line_sender.send(line_event("M622 N1 S0")).await;
+            // Unchanged configs aren't reported.
+            line_sender.send(line_event("M620 N1 A120")).await;
+            line_sender.send(line_event("M629 S0")).await;
+            line_sender.send(line_event("M620 N0 A110")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             ok\n\
+             config: M620 N1 A120 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\n\
+             config: M623 N1 R0 D500 W0 J0 E0 T0.5 C2\nconfig: M634 N1 U0 F0 H180 I0 O0 Q50 B0 K0\nconfig: M635 N1 P0 S100 A0 X90 Y90\n\
+             ok\n\
+             ok\n\
+             ok\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn m624_describes_every_field() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M634 N1 U1")).await;
+            line_sender.send(line_event("M624 N1")).await;
+            line_sender.send(line_event("M624 N2")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), FeederConfig::FIELDS.len() + 3, "{output}");
+        assert_eq!(
+            lines[1],
+            "field: M620 N1 A name=advanced_angle unit=us min=1000 max=2000 value=1750"
+        );
+        assert_eq!(
+            lines[16],
+            "field: M620 N1 J name=jam_hook unit=choice min=0 max=5 value=0"
+        );
+        assert_eq!(
+            lines[39],
+            "field: M635 N1 S name=peel_strength unit=% min=0 max=100 value=100"
+        );
+        assert_eq!(
+            lines[FeederConfig::FIELDS.len() + 1..],
+            ["ok", "error:8 no feeder 2"]
+        );
+    }
+
+    #[test]
+    fn config_groups_set_every_field_once() {
+        let mut tags: Vec<char, { FeederConfig::FIELDS.len() }> = Vec::new();
+        for group in ConfigGroup::ALL {
+            for (i, (tag, letter)) in group.fields().iter().enumerate() {
+                assert!(!['N', 'L'].contains(letter), "M{} {letter}", group.code());
+                assert!(letter.is_ascii_uppercase(), "M{} {letter}", group.code());
+                assert!(
+                    group.fields()[..i].iter().all(|(_, l)| l != letter),
+                    "M{} {letter}",
+                    group.code()
+                );
+                tags.push(*tag).unwrap();
+            }
+        }
+        tags.sort_unstable();
+        let mut fields = FeederConfig::FIELDS;
+        fields.sort_unstable();
+        assert_eq!(tags[..], fields);
+    }
+
+    #[futures_test::test]
+    async fn config_lines_only_take_their_groups_letters() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M635 N0 P200 S40 A1")).await;
+            line_sender.send(line_event("M634 N0 I5000 B-2")).await;
+            // I is a servo field, not a peel one.
+            line_sender.send(line_event("M635 N0 I100")).await;
+            line_sender.send(line_event("M621 N0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..4],
+            [
+                "ok",
+                "ok",
+                "invalid: I100",
+                "error:9 invalid argument type I"
+            ]
+        );
+        assert_eq!(
+            lines[5..],
+            [
+                "M623 N0 R0 D500 W0 J0 E0 T0.5 C2",
+                "M634 N0 U0 F0 H180 I5000 O0 Q50 B-2 K0",
+                "M635 N0 P200 S40 A1 X90 Y90",
+                "ok"
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn m621_reflects_m620_changes() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender
+                .send(line_event("M620 N1 A1 B2 C3 F4 U5 V6 W7 X1 Y0"))
+                .await;
+            line_sender.send(line_event("M621 N1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "ok\nM620 N1 A1 B2 C3 F4 U5 V6 W7 X1 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N1 R0 D500 W0 J0 E0 T0.5 C2\nM634 N1 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N1 P0 S100 A0 X90 Y90\nok\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn feeders_disable_on_disconnect() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M603 N1 A120.0")).await;
+            line_sender.send(GCodeEvent::Disconnect).await;
+            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M603 N1 A90.0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        println!("{}", String::from_utf8_lossy(&output));
+        // Ensure that servo 1 didn't move to 90 after a disconnect.
+        assert!(servos[0].is_empty());
+        assert_eq!(servos[1], vec![Value::from_num(120.0)]);
+    }
+
+    #[futures_test::test]
+    async fn settings_output_on_connect() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(String::from_utf8_lossy(&output), "saved settings:\nM620 N0 A135 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\nM620 N1 A135 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N1 R0 D500 W0 J0 E0 T0.5 C2\nM634 N1 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N1 P0 S100 A0 X90 Y90\nready\n");
+    }
+
+    #[futures_test::test]
+    async fn advance_returns_error_on_high_feedback() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        // drive feedback hgih.
+        fake_inputs[0].send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nerror:13 feeder 0 not ready\n");
+    }
+
+    #[futures_test::test]
+    async fn feeder_errors_name_the_logical_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        // The feeder in slot 1 isn't ready.
+        fake_inputs[1].send(true).await;
+
+        let test_future = async move {
+            // Logical feeder 0 is moved to slot 1 and feeder 1 to slot 0.
+            line_sender.send(line_event("M630 N0 S1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             error:10 feeder 1 disabled\n\
+             ok\n\
+             error:13 feeder 0 not ready\n\
+             ok\n"
+        );
+        assert_eq!(servos[0].len(), 2);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn invalid_fields_leave_every_feeder_unchanged() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            // Valid fields on a line with invalid ones aren't applied to any
+            // of the feeders it selects.
+            line_sender.send(line_event("M620 A90 U-1 C70 H2")).await;
+            line_sender.send(line_event("M623 N1 R2 D-1")).await;
+            line_sender.send(line_event("M621")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 14, "{output}");
+        assert_eq!(
+            lines[..5],
+            [
+                "invalid: U-1",
+                "invalid: H2",
+                "error:9 invalid argument type U",
+                "invalid: D-1",
+                "error:9 invalid argument type D",
+            ]
+        );
+        assert!(lines[5].starts_with("M620 N0 A135 B107.5 C80 "), "{output}");
+        assert!(lines[6].starts_with("M623 N0 R0 "), "{output}");
+        assert!(lines[9].starts_with("M620 N1 A135 B107.5 C80 "), "{output}");
+        assert!(lines[10].starts_with("M623 N1 R0 "), "{output}");
+        assert_eq!(lines[13], "ok");
+    }
+
+    #[futures_test::test]
+    async fn advance_respects_override_error_arg() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        // drive feedback hgih.
+        fake_inputs[0].send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nok\n");
+    }
+
+    #[futures_test::test]
+    async fn advance_retries_while_feedback_not_ready() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        fake_inputs[0].send(true).await;
+
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            // Fails once the single retry runs out.
+            line_sender.send(line_event("M623 N0 R1 D50")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            // Succeeds when the tape is ready before the second retry.
+            line_sender.send(line_event("M623 N0 R2 D200")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            Timer::after_millis(250).await;
+            fake_inputs[0].send(false).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nok\nerror:13 feeder 0 not ready\nok\nok\n");
+        assert!(!servos[0].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn advance_respects_ignore_feedback_pin_config() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        // drive feedback high.
+        fake_inputs[0].send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 X1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nok\nok\n");
+    }
+
+    #[futures_test::test]
+    async fn feedback_pulse_half_advances_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            // Set to known angles
+            line_sender.send(line_event("M620 N0 A122 C22")).await;
+            // Press switch
+
+            feedback0.send(false).await;
+            Timer::after_micros(250_000).await;
+            // Release switch.
+            feedback0.send(true).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn m619_calibrates_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M619 N0")).await;
+            line_sender.send(line_event("+")).await;
+            line_sender.send(line_event("-20")).await;
+            line_sender.send(line_event("1200")).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("+2.5")).await;
+            // The feedback button accepts like `y`.
+            feedback0.send(false).await;
+            Timer::after_micros(250_000).await;
+            feedback0.send(true).await;
+            Timer::after_micros(250_000).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("-1")).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("M500")).await;
+            // G-code puts the config back.
+            line_sender.send(line_event("M619 N0")).await;
+            line_sender.send(line_event("+")).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("y")).await;
+            line_sender.send(line_event("M500")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, configs), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             calibrate: +/-[amount] adjusts, y or the feedback button accepts, q quits\n\
+             calibrate: feeder 0 pwm_0 1000us\n\
+             ok\n\
+             calibrate: feeder 0 pwm_0 1010us\n\
+             ok\n\
+             calibrate: feeder 0 pwm_0 990us\n\
+             ok\n\
+             error:27 can't parse line\n\
+             calibrate: feeder 0 pwm_180 2000us\n\
+             ok\n\
+             calibrate: feeder 0 retract_angle 80deg\n\
+             ok\n\
+             calibrate: feeder 0 retract_angle 82.5deg\n\
+             ok\n\
+             calibrate: feeder 0 half_advanced_angle 107.5deg\n\
+             calibrate: feeder 0 advanced_angle 135deg\n\
+             ok\n\
+             calibrate: feeder 0 advanced_angle 134deg\n\
+             ok\n\
+             calibrate: feeder 0 done, M500 saves\n\
+             ok\n\
+             ok\n\
+             calibrate: +/-[amount] adjusts, y or the feedback button accepts, q quits\n\
+             calibrate: feeder 0 pwm_0 990us\n\
+             ok\n\
+             calibrate: feeder 0 pwm_0 1000us\n\
+             ok\n\
+             calibrate: feeder 0 pwm_180 2000us\n\
+             ok\n\
+             calibrate: feeder 0 retract_angle 82.5deg\n\
+             ok\n\
+             ok\n"
+        );
+        let config = &configs[&0];
+        assert_eq!(config.pwm_0, Value::from_num(990));
+        assert_eq!(config.retract_angle, Value::from_num(82.5));
+        assert_eq!(config.advanced_angle, Value::from_num(134));
+        assert_eq!(
+            servos[0],
+            vec![
+                Value::from_num(1000),
+                Value::from_num(1010),
+                Value::from_num(990),
+                Value::from_num(2000),
+                Value::from_num(80),
+                Value::from_num(82.5),
+                Value::from_num(107.5),
+                Value::from_num(135),
+                Value::from_num(134),
+                Value::from_num(990),
+                Value::from_num(1000),
+                Value::from_num(2000),
+                Value::from_num(82.5),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn feeder_only_retracts_on_4mm_bondaries() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+
+            // Set to known angles, ignore feedback pin, and only retract at the full advance.
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 Y0"))
+                .await;
+
+            // Feeding by 2mm advance to the half angle and not retract.
+            line_sender.send(line_event("M600 N0 F2")).await;
+            // Feeding by another 4mm should advance to the full angle, retract and advance
+            // to the half angle.
+            line_sender.send(line_event("M600 N0 F4")).await;
+            // Feeding by a final 2mm should advance to the full angle and retract.
+            line_sender.send(line_event("M600 N0 F2")).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert_eq!(
+            servos[0],
+            vec![
+                // F2 half advances.
+                Value::from_num(25),
+                // F4 full advances, retracts, and half advances.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(25),
+                // F2 full advances and retracts.
+                Value::from_num(50),
+                Value::from_num(0)
+            ]
+        );
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn two_position_feeders_reject_half_advances() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 H0"))
+                .await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("M600 N0 F6")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             error:31 feed length 2 needs a half advance\n\
+             error:31 feed length 6 needs a half advance\n\
+             ok\n"
+        );
+        assert_eq!(servos[0], vec![Value::from_num(50), Value::from_num(0)]);
+    }
+
+    #[futures_test::test]
+    async fn part_pitch_counts_parts() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 P8")).await;
+            // Half a part only counts once the next half is fed.
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 C1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M620 N0 P-4")).await;
+            line_sender.send(line_event("M600 N0 C-1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             ok\n\
+             progress: feeder 0 cycles=1 remaining=4\n\
+             ok\n\
+             ok\n\
+             feeder 0: enabled=1 attention=0 feedback=0 offset=0 angle=80 interrupted=none \
+             parts=2 cycles=1 remaining=0 latched=none last_error=none\n\
+             ok\n\
+             invalid: P-4\n\
+             error:9 invalid argument type P\n\
+             error:9 invalid argument type C\n"
+        );
+        // 16mm is four full advances.
+        assert_eq!(servos[0].len(), 8);
+    }
+
+    #[futures_test::test]
+    async fn m280_sets_raw_servo_positions() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M280 P0 S90")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M280 P0 S90")).await;
+            line_sender.send(line_event("M280 P0 S1500")).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M280 P0")).await;
+            line_sender.send(line_event("M280 S90")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "error:10 feeder 0 disabled\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             feeder 0: enabled=1 attention=0 feedback=0 offset=0 angle=none interrupted=none \
+             parts=0 cycles=0 remaining=0 latched=none last_error=10 feeder disabled\n\
+             ok\n\
+             error:9 invalid argument type S\n\
+             error:7 no index specified\n"
+        );
+        // Values of 200 and up are pulse widths.
+        assert_eq!(servos[0], vec![Value::from_num(90), Value::from_num(1500)]);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn m602_reports_feeder_runtime_state() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F2")).await;
+            line_sender.send(line_event("M602 N1")).await;
+            line_sender.send(line_event("M602 N7")).await;
+            line_sender.send(line_event("M602 N1 X1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             feeder 1: enabled=1 attention=0 feedback=0 offset=2 angle=107.5 interrupted=none \
+             parts=1 cycles=1 remaining=0 latched=none last_error=none\n\
+             ok\n\
+             error:8 no feeder 7\n\
+             error:9 invalid argument type X\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn m601_wiggles_lever_and_returns() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 A50 B25 C10")).await;
+            // The lever's position is unknown so it wiggles from retracted.
+            line_sender.send(line_event("M601 N0")).await;
+            line_sender.send(line_event("M603 N0 A25")).await;
+            line_sender.send(line_event("M601 N0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(String::from_utf8_lossy(&output), "ok\nok\nok\nok\nok\n");
+        let wiggle = |angle: i32| [angle + 5, angle, angle + 5, angle, angle + 5, angle];
+        let expected: Vec<Value> = wiggle(10)
+            .into_iter()
+            .chain([25])
+            .chain(wiggle(25))
+            .map(Value::from_num)
+            .collect();
+        assert_eq!(servos[0], expected);
+    }
+
+    #[futures_test::test]
+    async fn aborted_m601_leaves_feeder_ready_to_advance() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C10 U500"))
+                .await;
+            line_sender.send(line_event("M601 N0")).await;
+            Timer::after_millis(100).await;
+            line_sender.send(line_event("M410")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nerror:23 aborted\nok\nok\n"
+        );
+        // The lever is put back before the advance runs from retracted.
+        assert_eq!(servos[0][..3], [15, 10, 50].map(Value::from_num));
+    }
+
+    #[futures_test::test]
+    async fn lever_positions_can_be_pulse_widths() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M634 N0 U1")).await;
+            // Between the 1000us and 2000us limits.
+            line_sender.send(line_event("M620 N0 A1500 C1000")).await;
+            line_sender.send(line_event("M620 N0 A2500")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nok\nerror:4 angle out of range\nok\n"
+        );
+        assert_eq!(servos[0], [90, 0].map(Value::from_num));
+    }
+
+    #[futures_test::test]
+    async fn always_retract_feeder_retracts_on_every_advance() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+
+            // Set to known angles, ignore feedback pin, and retract after every advance.
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 Y1"))
+                .await;
+
+            // Feeding by 2mm advance to the half angle and retract.
+            line_sender.send(line_event("M600 N0 F2")).await;
+            // Feeding by 4mm advance to the full angle and retract.
+            line_sender.send(line_event("M600 N0 F4")).await;
+            // Feeding by 6mm advance to the full angle, retract, advance to the half anfle, and retract.
+            line_sender.send(line_event("M600 N0 F6")).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        assert_eq!(
+            servos[0],
+            vec![
+                // F2 half advances and retracts.
+                Value::from_num(25),
+                Value::from_num(0),
+                // F4 full advances and retracts.
+                Value::from_num(50),
+                Value::from_num(0),
+                // F3 full advances, retracts, half advances, and retracts.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(25),
+                Value::from_num(0),
+            ]
+        );
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn sprocket_geometry_sets_advance_lengths() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 L1 A50 B25 C0 X1"))
+                .await;
+            // Feeder 0 moves two 4mm holes per advance.
+            line_sender.send(line_event("M620 N0 T2")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 F12")).await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            // Feeder 1 feeds tape punched every 2mm.
+            line_sender.send(line_event("M620 N1 S2")).await;
+            line_sender.send(line_event("M600 N1 F3")).await;
+            line_sender.send(line_event("M620 N1 S0")).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             updated 2 of 2 feeders\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             progress: feeder 0 cycles=1 remaining=8\n\
+             ok\n\
+             error:17 invald feed length 2\n\
+             ok\n\
+             progress: feeder 1 cycles=1 remaining=1\n\
+             ok\n\
+             invalid: S0\n\
+             error:9 invalid argument type S\n"
+        );
+        assert_eq!(
+            servos[0],
+            vec![
+                // F4 half advances.
+                Value::from_num(25),
+                // F12 completes the advance, retracts and advances 8mm.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(50),
+                Value::from_num(0),
+            ]
+        );
+        assert_eq!(
+            servos[1],
+            vec![
+                // F3 is a full 2mm advance and a 1mm half advance.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(25),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn interpolated_angles_allow_any_feed_length() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 I1"))
+                .await;
+            line_sender.send(line_event("M600 N0 F1")).await;
+            line_sender.send(line_event("M600 N0 F1.5")).await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, _output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            servos[0],
+            vec![
+                Value::from_num(12.5),
+                Value::from_num(31.25),
+                // F2 completes the advance, retracts and advances another 0.5mm.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(6.25),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn negative_feeds_reverse_the_tape() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 P4"))
+                .await;
+            // Without a release angle the tape can't be backed up.
+            line_sender.send(line_event("M600 N0 F-2")).await;
+            line_sender.send(line_event("M634 N0 K60")).await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("M600 N0 F6")).await;
+            line_sender.send(line_event("M600 N0 F-6")).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M600 N0 F-3")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        // The lever rests at the release angle before each sweep out.
+        assert_eq!(
+            servos[0],
+            [25, 50, 0, 50, 0, 60, 50, 0, 60, 50, 25].map(Value::from_num)
+        );
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("error:17 invald feed length -2\n"));
+        // Backing up 6mm of the 8mm fed leaves no whole parts fed.
+        assert!(output.ends_with(
+            "progress: feeder 0 cycles=1 remaining=-2\nok\n\
+             feeder 0: enabled=1 attention=0 feedback=0 offset=2 angle=25 interrupted=none \
+             parts=0 cycles=2 remaining=0 latched=none \
+             last_error=17 invald feed length -2\nok\n\
+             error:17 invald feed length -3\n"
+        ));
+    }
+
+    #[futures_test::test]
+    async fn negative_feeds_need_a_release_angle() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 A50 B25 C0")).await;
+            line_sender.send(line_event("M600 N0 F-2")).await;
+            line_sender.send(line_event("M634 N0 K0")).await;
+            line_sender.send(line_event("M600 N0 F-4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nerror:17 invald feed length -2\nok\nerror:17 invald feed length -4\n"
+        );
+        // The lever is never moved.
+        assert!(servos[0].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn retract_policies_choose_when_to_retract() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 L1 A50 B25 C0 X1"))
+                .await;
+            // Feeder 0 retracts at the end of every feed.
+            line_sender.send(line_event("M620 N0 Y2")).await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("M600 N0 F6")).await;
+            // Feeder 1 retracts every 2mm.
+            line_sender.send(line_event("M620 N1 Y3 Z2")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M620 N1 Z0")).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert!(String::from_utf8_lossy(&output)
+            .ends_with("ok\ninvalid: Z0\nerror:9 invalid argument type Z\n"));
+        assert_eq!(
+            servos[0],
+            vec![
+                // F2 half advances and retracts.
+                Value::from_num(25),
+                Value::from_num(0),
+                // F6 full advances, retracts, half advances, and retracts.
+                Value::from_num(50),
+                Value::from_num(0),
+                Value::from_num(25),
+                Value::from_num(0),
+            ]
+        );
+        assert_eq!(
+            servos[1],
+            vec![
+                // F4 half advances and retracts twice.
+                Value::from_num(25),
+                Value::from_num(0),
+                Value::from_num(25),
+                Value::from_num(0),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn shared_feeders_use_their_own_configs() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_input = FakeInputChannel::new();
+        let (positions, servo) = FakeServo::new();
+        let mut feeder = Feeder::new(servo, FakeInput::new(false, &fake_input));
+        let channels = [&FeederChannel::new(), &FeederChannel::new()];
+        let mut output = Vec::<u8>::new();
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 A50 B25 C0 X1 Y1"))
+                .await;
+            line_sender
+                .send(line_event("M620 N1 A60 B30 C10 X1 Y1"))
+                .await;
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("M600 N1 F2")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        with_mock_time(join3(
+            feeder.run_shared(channels),
+            run_handler(
+                [
+                    FeederClient::new(channels[0]),
+                    FeederClient::new(channels[1]),
+                ]
+                .into(),
+                &mut output,
+                FakeConfigStore::new(),
+                gcode_channel.receiver(),
+            ),
+            test_future,
+        ))
+        .await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nok\nok\nok\nok\nok\n");
+        assert_eq!(
+            *positions.lock().unwrap(),
+            vec![
+                // N0 F2 half advances and retracts with feeder 0's angles.
+                Value::from_num(25),
+                Value::from_num(0),
+                // N1 F2 half advances and retracts with feeder 1's angles.
+                Value::from_num(30),
+                Value::from_num(10),
+                // N0 F4 full advances and retracts with feeder 0's angles.
+                Value::from_num(50),
+                Value::from_num(0),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn config_codes_round_trip_and_reject_bad_checksums() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let config = FeederConfig {
+            advanced_angle: Value::from_num(120),
+            slew_rate: Value::from_num(45.5),
+            jam_timeout: 250,
+            ..Default::default()
+        };
+        let code = config_code::encode(&config).unwrap();
+        // Any mistyped character changes the checksum.
+        let (body, last) = code.split_at(code.len() - 1);
+        let bad_code = format!("{body}{}", if last == "0" { 1 } else { 0 });
+        let test_future = async {
+            line_sender.send(line_event("M628 N1")).await;
+            line_sender.send(line_event(&code)).await;
+            line_sender.send(line_event("M627 N1")).await;
+            line_sender.send(line_event("M628 N1")).await;
+            line_sender.send(line_event(&bad_code)).await;
+            line_sender.send(line_event("M627 N1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            format!("ok\nok\n{code}\nok\nok\nerror:26 invalid config code\n{code}\nok\n")
+        );
+    }
+
+    #[futures_test::test]
+    async fn m630_remaps_feeder_slots() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M630 N0 S1")).await;
+            line_sender.send(line_event("M630 N1 S0")).await;
+            line_sender.send(line_event("M603 N0 A120.0")).await;
+            line_sender.send(line_event("M620 N0 A1")).await;
+            line_sender.send(line_event("M500")).await;
+            line_sender.send(line_event("M631")).await;
+            line_sender.send(line_event("M630 N0 S2")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "ok\nok\nok\nok\nok\nok\nM630 N0 S1\nM630 N1 S0\nok\nerror:8 no feeder 2\n"
+        );
+        assert!(servos[0].is_empty());
+        assert_eq!(servos[1], vec![Value::from_num(120.0)]);
+        // Configs are stored by physical slot.
+        assert_eq!(config.get(&1).unwrap().advanced_angle, Value::from_num(1));
+        assert_eq!(config[&0], FakeConfigStore::default_config());
+    }
+
+    #[futures_test::test]
+    async fn m620_updates_range_of_feeders() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M620 N0 L1 U400")).await;
+            line_sender.send(line_event("M620 N1 N0 A100")).await;
+            line_sender.send(line_event("M620 N1 L0 A100")).await;
+            line_sender.send(line_event("M500")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "updated 2 of 2 feeders\nok\nupdated 2 of 2 feeders\nok\nerror:9 invalid argument type L\nok\n"
+        );
+        for index in 0..2 {
+            assert_eq!(
+                *config.get(&index).unwrap(),
+                FeederConfig {
+                    advanced_angle: Value::from_num(100),
+                    advance_settle_time: 400,
+                    ..FakeConfigStore::default_config()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn config_codes_fit_every_field_at_its_widest() {
+        let mut config = FeederConfig::default();
+        for letter in FeederConfig::FIELDS {
+            let info = config.field_info(letter).unwrap();
+            let widest = match info.min.unsigned_abs() >= info.max.unsigned_abs() {
+                true => info.min,
+                false => info.max,
+            };
+            config.set_field(letter, widest).unwrap();
+        }
+        let code = config_code::encode(&config).unwrap();
+        assert_eq!(config_code::decode(&code).unwrap(), config);
+    }
+
+    #[test]
+    fn stored_fields_load_into_newer_configs() {
+        // A record written before fields were added only has some of them,
+        // possibly alongside letters this firmware doesn't know.
+        let stored = [('A', Value::from_num(120)), ('?', Value::from_num(1))];
+        let mut config = FeederConfig::default();
+        for (letter, value) in stored {
+            let _ = config.set_field(letter, value);
+        }
+        assert_eq!(
+            config,
+            FeederConfig {
+                advanced_angle: Value::from_num(120),
+                ..Default::default()
+            }
+        );
+        assert!(config.set_field('?', Value::from_num(1)).is_err());
+
+        // Every field reads back as it was stored.
+        for letter in FeederConfig::FIELDS {
+            let value = config.get_field(letter).unwrap();
+            let mut loaded = FeederConfig::default();
+            loaded.set_field(letter, value).unwrap();
+            assert_eq!(loaded.get_field(letter).unwrap(), value);
+        }
+    }
+
+    #[futures_test::test]
+    async fn m630_swaps_the_displaced_feeder_slot() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M630 N1 S0")).await;
+            line_sender.send(line_event("M631")).await;
+            line_sender.send(line_event("M630 N1 S1")).await;
+            line_sender.send(line_event("M631")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "ok\nM630 N0 S1\nM630 N1 S0\nok\nok\nM630 N0 S0\nM630 N1 S1\nok\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn stored_slot_maps_with_duplicates_are_ignored() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let mut store = FakeConfigStore::new();
+        store.slots.insert(0, 1);
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M631")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "M630 N0 S0\nM630 N1 S1\nok\n");
+    }
+
+    #[futures_test::test]
+    async fn config_write_failures_are_reported() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness_with_store(
+            gcode_channel.receiver(),
+            &fake_inputs,
+            FakeConfigStore::new_dropping_writes(),
+        );
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M620 N1 A122")).await;
+            line_sender.send(line_event("M500")).await;
+            line_sender.send(line_event("M630 N0 S1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "ok\nerror:16 config verification failed\nerror:16 config verification failed\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn m632_limits_and_sanitizes_cartridge_ids() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let longest = "0123456789ABCDEFGHIJKLMNOPQRSTUV";
+        let test_future = async move {
+            line_sender
+                .send(line_event(&format!("M632 N0 \"{longest}\"")))
+                .await;
+            line_sender
+                .send(line_event(&format!("M632 N0 \"{longest}W\"")))
+                .await;
+            // Length is counted in characters, each stored as one `?`.
+            line_sender
+                .send(line_event(&format!("M632 N1 \"{}\"", "µ".repeat(32))))
+                .await;
+            line_sender.send(line_event("M632 N2 \"R1\"")).await;
+            line_sender.send(line_event("M633")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            format!(
+                "ok\n\
+                 error:30 invalid cartridge id\n\
+                 ok\n\
+                 error:8 no feeder 2\n\
+                 M632 N0 \"{longest}\"\n\
+                 M632 N1 \"{}\"\n\
+                 ok\n",
+                "?".repeat(32)
+            )
+        );
+    }
+
+    #[futures_test::test]
+    async fn m641_reports_wall_clock_set_by_m640() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M640 H12 I30 S15")).await;
+            line_sender.send(line_event("M641")).await;
+            line_sender.send(line_event("M640 H24")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "ok");
+        assert!(lines[1].starts_with("time: "));
+        assert!(lines[1].ends_with(" 12:30:15"));
+        assert_eq!(lines[2], "ok");
+        assert_eq!(lines[3], "error:9 invalid argument type H");
+    }
+
+    #[futures_test::test]
+    async fn m154_enables_periodic_status_reports() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback1 = &fake_inputs[1];
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M603 N5 A1")).await;
+            feedback1.send(true).await;
+            line_sender.send(line_event("M154 S1")).await;
+            Timer::after_millis(1_200).await;
+            line_sender.send(line_event("M154 S0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0..3], ["ok", "error:8 no feeder 5", "ok"]);
+        assert!(lines[3].starts_with("status: "));
+        assert!(
+            lines[3].ends_with(" errors=1 enabled=11 attention=00 feedback=01 queued=0 deferred=0")
+        );
+        assert_eq!(lines[4], "ok");
+    }
+
+    #[futures_test::test]
+    async fn status_reports_follow_feedback_pin_levels() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+        let test_future = async move {
+            // Levels are reported whether or not the feeders are enabled.
+            feedback0.send(true).await;
+            line_sender.send(line_event("M154 S1")).await;
+            Timer::after_millis(1_200).await;
+            line_sender.send(line_event("M154 S0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("status: "));
+        assert!(
+            lines[1].contains(" enabled=00 attention=00 feedback=10 "),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[futures_test::test]
+    async fn status_reports_count_deferred_advances() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M612 Q1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M154 S1")).await;
+            Timer::after_millis(1_200).await;
+            line_sender.send(line_event("M154 S0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let status = output
+            .lines()
+            .find(|line| line.starts_with("status: "))
+            .unwrap();
+        assert!(status.ends_with(" queued=0 deferred=2"), "{}", status);
+    }
+
+    #[futures_test::test]
+    async fn m650_dumps_metrics() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M650")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "error:10 feeder 1 disabled\n\
+             ok\n\
+             ok\n\
+             commands_total 4\n\
+             command_errors_total 1\n\
+             write_timeouts_total 0\n\
+             feeds_total{feeder=\"0\"} 0\n\
+             feed_errors_total{feeder=\"0\"} 0\n\
+             hook_events_total{feeder=\"0\"} 0\n\
+             feeds_total{feeder=\"1\"} 1\n\
+             feed_errors_total{feeder=\"1\"} 1\n\
+             hook_events_total{feeder=\"1\"} 0\n\
+             ok\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn repeated_feed_errors_auto_disable_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+
+        // drive feedback high.
+        fake_inputs[0].send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            lines[0..4],
+            [
+                "ok",
+                "error:13 feeder 0 not ready",
+                "error:13 feeder 0 not ready",
+                "error:13 feeder 0 not ready"
+            ]
+        );
+        assert!(lines[4].starts_with("notice: "));
+        assert!(lines[4].ends_with(" feeder 0 auto-disabled: feeder not ready"));
+        assert_eq!(lines[5..8], ["error:10 feeder 0 disabled", "ok", "ok"]);
+    }
+
+    #[futures_test::test]
+    async fn button_feeds_are_rate_limited() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            // Set to known angles and a 2s minimum interval between button feeds.
+            line_sender.send(line_event("M620 N0 A122 C22 R2000")).await;
+
+            // Press the switch twice in quick succession.
+            for _ in 0..2 {
+                feedback0.send(false).await;
+                Timer::after_millis(100).await;
+                feedback0.send(true).await;
+                Timer::after_millis(100).await;
+            }
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        println!("{}", String::from_utf8_lossy(&output));
+        // Only the first press feeds.
+        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn g4_dwells() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            let start = Instant::now();
+            line_sender.send(line_event("G4 P250")).await;
+            line_sender.send(line_event("G4 S1.5")).await;
+            line_sender.send(line_event("G4 P-1")).await;
+            line_sender.send(line_event("M999")).await;
+            start
+        };
+        let ((_servos, output, _config), start) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nerror:9 invalid argument type P\n"
+        );
+        assert!(Instant::now() - start >= Duration::from_millis(1750));
+    }
+
+    #[futures_test::test]
+    async fn m400_waits_for_button_feeds() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            feedback0.send(false).await;
+            Timer::after_millis(100).await;
+            feedback0.send(true).await;
+
+            // The button feed is still settling.
+            line_sender.send(line_event("M400")).await;
+            line_sender.send(line_event("M400 N0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nerror:9 invalid argument type N\n"
+        );
+        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
+    }
+
+    #[futures_test::test]
+    async fn m611_locks_out_button_feeds() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+
+        // Start with switch unpressed.
+        feedback0.send(true).await;
+
+        let press_button = || async {
+            feedback0.send(false).await;
+            Timer::after_millis(100).await;
+            feedback0.send(true).await;
+            Timer::after_millis(100).await;
+        };
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 A122 C22")).await;
+
+            line_sender.send(line_event("M611 S1 N0")).await;
+            press_button().await;
+            line_sender.send(line_event("M611 S0")).await;
+            press_button().await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(output, "ok\nok\nok\nok\n");
+        // Only the press after the lockout is cleared feeds.
+        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn g28_homes_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback1 = &fake_inputs[1];
+
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender
+                .send(line_event("M620 N0 L1 A50 B25 C10 Y0"))
+                .await;
+            // Leave feeder 0 half advanced.
+            line_sender.send(line_event("M600 N0 F2")).await;
+            line_sender.send(line_event("G28 N0")).await;
+            // After homing a 2mm feed half advances again rather than fully
+            // advancing.
+            line_sender.send(line_event("M600 N0 F2")).await;
+
+            // Drive feeder 1's feedback high so homing fails.
+            feedback1.send(true).await;
+            line_sender.send(line_event("G28")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        assert_eq!(
+            output,
+            "ok\nupdated 2 of 2 feeders\nok\nok\nok\nok\nerror:18 homing failed: feedback not ready\n"
+        );
+        assert_eq!(
+            servos[0],
+            vec![
+                Value::from_num(25),
+                // G28 N0 retracts, nudges, and retracts.
+                Value::from_num(10),
+                Value::from_num(15),
+                Value::from_num(10),
+                Value::from_num(25),
+                // G28 homes every feeder.
+                Value::from_num(10),
+                Value::from_num(15),
+                Value::from_num(10),
+            ]
+        );
+        assert_eq!(
+            servos[1],
+            vec![
+                Value::from_num(10),
+                Value::from_num(15),
+                Value::from_num(10),
+            ]
+        );
+    }
+
+    #[futures_test::test]
+    async fn recorded_button_presses_are_recognized() {
+        // A press captured from a feeder switch: contact bounce on both the
+        // falling and rising edges around a ~150ms press.
+        const BOUNCY_PRESS: [Edge; 8] = [
+            Edge::new(100, false),
+            Edge::new(102, true),
+            Edge::new(103, false),
+            Edge::new(105, true),
+            Edge::new(106, false),
+            Edge::new(256, true),
+            Edge::new(257, false),
+            Edge::new(258, true),
+        ];
+        assert_eq!(
+            play_feedback_trace(&BOUNCY_PRESS, FakeConfigStore::default_config()).await,
+            1
+        );
+
+        // A switch held down for over a second is not a feed request.
+        const LONG_PRESS: [Edge; 4] = [
+            Edge::new(100, false),
+            Edge::new(101, true),
+            Edge::new(102, false),
+            Edge::new(1200, true),
+        ];
+        assert_eq!(
+            play_feedback_trace(&LONG_PRESS, FakeConfigStore::default_config()).await,
+            0
+        );
+
+        // Two deliberate presses feed twice.
+        const DOUBLE_PRESS: [Edge; 4] = [
+            Edge::new(100, false),
+            Edge::new(200, true),
+            Edge::new(400, false),
+            Edge::new(500, true),
+        ];
+        assert_eq!(
+            play_feedback_trace(&DOUBLE_PRESS, FakeConfigStore::default_config()).await,
+            2
+        );
+    }
+
+    #[futures_test::test]
+    async fn feedback_during_advance_is_reconciled() {
+        // A press ending at 200ms feeds and the feed settles at 500ms.  The
+        // lever brushes the switch while moving and the next press starts
+        // before the feed has settled.
+        const TRACE: [Edge; 6] = [
+            Edge::new(100, false),
+            Edge::new(200, true),
+            Edge::new(250, false),
+            Edge::new(260, true),
+            Edge::new(400, false),
+            Edge::new(550, true),
+        ];
+        let mut config = FakeConfigStore::default_config();
+        config.advance_settle_time = 300;
+        config.retract_settle_time = 300;
+        assert_eq!(play_feedback_trace(&TRACE, config.clone()).await, 1);
+
+        config.motion_feedback = MotionFeedback::Replay;
+        assert_eq!(play_feedback_trace(&TRACE, config).await, 2);
+    }
+
+    #[futures_test::test]
+    async fn advance_without_feedback_change_latches_jam() {
+        // The lever actuates the switch during the first advance only.
+        const TRACE: [Edge; 2] = [Edge::new(50, true), Edge::new(60, false)];
+        with_mock_time(async {
+            let (_positions, servo) = FakeServo::new();
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new(servo, PlaybackInput::new(false, &TRACE));
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                let mut config = FakeConfigStore::default_config();
+                config.advance_settle_time = 20;
+                config.retract_settle_time = 20;
+                config.jam_timeout = 100;
+                client.set_config(config).await.unwrap();
+                client.enable(true).await.unwrap();
+                let feed_4mm = FeedLength::Millimeters(Value::from_num(4));
+                assert_eq!(client.advance(feed_4mm, false).await, Ok(()));
+                assert_eq!(
+                    client.advance(feed_4mm, false).await,
+                    Err(Error::FeederJammed(None))
+                );
+                // Latched until cleared, without moving the lever.
+                let status = client.get_status().await.unwrap();
+                assert_eq!(
+                    client.advance(feed_4mm, false).await,
+                    Err(Error::FeederJammed(None))
+                );
+                assert_eq!(client.get_status().await.unwrap().angle, status.angle);
+                client.clear_latched_error().await.unwrap();
+                assert_eq!(client.advance(feed_4mm, true).await, Ok(()));
+                client.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn motion_scheduler_staggers_feeders() {
+        with_mock_time(async {
+            let scheduler = MotionScheduler::new(1);
+            let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+            let channels = [FeederChannel::new(), FeederChannel::new()];
+            let (positions_0, servo_0) = FakeServo::new();
+            let (positions_1, servo_1) = FakeServo::new();
+            let mut feeder_0 = Feeder::new(servo_0, FakeInput::new(false, &fake_inputs[0]))
+                .with_motion_scheduler(&scheduler);
+            let mut feeder_1 = Feeder::new(servo_1, FakeInput::new(false, &fake_inputs[1]))
+                .with_motion_scheduler(&scheduler);
+
+            let test_future = async {
+                let mut clients = [
+                    FeederClient::new(&channels[0]),
+                    FeederClient::new(&channels[1]),
+                ];
+                let mut config = FakeConfigStore::default_config();
+                config.advance_settle_time = 100;
+                config.retract_settle_time = 100;
+                for client in clients.iter_mut() {
+                    client.set_config(config.clone()).await.unwrap();
+                    client.enable(true).await.unwrap();
+                }
+                let feed_4mm = FeedLength::Millimeters(Value::from_num(4));
+
+                // Only one lever moves at a time so the advances run in turn.
+                let [client_0, client_1] = &mut clients;
+                let start = Instant::now();
+                let results = join(
+                    client_0.advance(feed_4mm, false),
+                    client_1.advance(feed_4mm, false),
+                )
+                .await;
+                assert_eq!(results, (Ok(()), Ok(())));
+                assert!(start.elapsed() >= Duration::from_millis(400));
+
+                // Without a limit they overlap.
+                scheduler.set_limit(0);
+                let start = Instant::now();
+                let results = join(
+                    client_0.advance(feed_4mm, false),
+                    client_1.advance(feed_4mm, false),
+                )
+                .await;
+                assert_eq!(results, (Ok(()), Ok(())));
+                assert!(start.elapsed() < Duration::from_millis(300));
+
+                for client in clients.iter_mut() {
+                    client.shutdown().await;
+                }
+            };
+            join3(
+                feeder_0.run(&channels[0]),
+                feeder_1.run(&channels[1]),
+                test_future,
+            )
+            .await;
+
+            let feed = [135, 80, 135, 80].map(Value::from_num);
+            assert_eq!(*positions_0.lock().unwrap(), feed);
+            assert_eq!(*positions_1.lock().unwrap(), feed);
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn peeler_runs_while_or_after_advancing() {
+        with_mock_time(async {
+            let strengths = Arc::new(Mutex::new(Vec::new()));
+            let peeler = FakePeeler {
+                strengths: strengths.clone(),
+            };
+            let channel = FeederChannel::new();
+            let mut feeder =
+                Feeder::new_with_peeler(FakeServo::new().1, PlaybackInput::new(false, &[]), peeler);
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                let mut config = FakeConfigStore::default_config();
+                config.advance_settle_time = 20;
+                config.retract_settle_time = 20;
+                config.peel_time = 100;
+                config.peel_strength = Value::from_num(50);
+                config.release_angle = Value::from_num(60);
+                client.set_config(config.clone()).await.unwrap();
+                client.enable(true).await.unwrap();
+                let feed_4mm = FeedLength::Millimeters(Value::from_num(4));
+
+                let start = Instant::now();
+                assert_eq!(client.advance(feed_4mm, false).await, Ok(()));
+                // The feed waits for the peeler to finish.
+                assert_eq!(start.elapsed().as_millis(), 100);
+                // Backing the tape up doesn't peel.
+                let back_4mm = FeedLength::Millimeters(Value::from_num(-4));
+                assert_eq!(client.advance(back_4mm, false).await, Ok(()));
+
+                config.peel_after = true;
+                client.set_config(config).await.unwrap();
+                let start_after = Instant::now();
+                assert_eq!(client.advance(feed_4mm, false).await, Ok(()));
+                client.shutdown().await;
+
+                let strengths: Vec<_> = strengths
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(at, strength)| (at.duration_since(start).as_millis(), *strength))
+                    .collect();
+                let after = start_after.duration_since(start).as_millis();
+                assert_eq!(
+                    strengths,
+                    [
+                        (0, Value::from_num(0.5)),
+                        (100, Value::from_num(0)),
+                        (after + 40, Value::from_num(0.5)),
+                        (after + 140, Value::from_num(0)),
+                    ]
+                );
+            };
+            join(feeder.run(&channel), test_future).await;
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn secondary_servo_moves_with_lever() {
+        with_mock_time(async {
+            let (positions, servo) = FakeServo::new();
+            let (secondary_positions, secondary) = FakeServo::new();
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new_dual(servo, secondary, PlaybackInput::new(false, &[]));
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                let mut config = FakeConfigStore::default_config();
+                config.advance_settle_time = 20;
+                config.retract_settle_time = 20;
+                config.secondary_advanced_angle = Value::from_num(150);
+                config.secondary_retract_angle = Value::from_num(30);
+                client.set_config(config).await.unwrap();
+                client.enable(true).await.unwrap();
+                let feed_2mm = FeedLength::Millimeters(Value::from_num(2));
+                assert_eq!(client.advance(feed_2mm, false).await, Ok(()));
+                assert_eq!(client.advance(feed_2mm, false).await, Ok(()));
+                client.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+
+            assert_eq!(*positions.lock().unwrap(), [107.5, 135.0, 80.0]);
+            assert_eq!(*secondary_positions.lock().unwrap(), [90, 150, 30]);
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn encoder_tops_up_short_feeds() {
+        with_mock_time(async {
+            let (positions, servo) = FakeServo::new();
+            let channel = FeederChannel::new();
+            // Counts at the start of each feed and after each pass.  The
+            // first feed slips 2mm, the second not at all and the third
+            // overshoots.
+            let encoder = FakeEncoder {
+                counts: std::vec![0, 20, 41, 100, 100, 100, 100, 200, 260],
+            };
+            let mut feeder =
+                Feeder::new_with_encoder(servo, PlaybackInput::new(false, &[]), encoder);
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                let mut config = FakeConfigStore::default_config();
+                config.encoder_counts_per_mm = Value::from_num(10);
+                client.set_config(config).await.unwrap();
+                client.enable(true).await.unwrap();
+                let feed_4mm = FeedLength::Millimeters(Value::from_num(4));
+                assert_eq!(client.advance(feed_4mm, false).await, Ok(()));
+                assert_eq!(
+                    client.advance(feed_4mm, false).await,
+                    Err(Error::Underfeed(Value::from_num(0)))
+                );
+                assert_eq!(
+                    client.advance(feed_4mm, false).await,
+                    Err(Error::Overfeed(Value::from_num(6)))
+                );
+                client.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+
+            // The 2mm shortfall is made up with a half advance, left half
+            // advanced for the next feed.
+            assert_eq!(
+                positions.lock().unwrap()[..5],
+                [135.0, 80.0, 107.5, 135.0, 80.0]
+            );
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn idle_servo_is_released_until_next_move() {
+        with_mock_time(async {
+            let (positions, servo) = FakeServo::new();
+            let channel = FeederChannel::new();
+            let mut feeder = Feeder::new(servo, PlaybackInput::new(false, &[]));
+
+            let test_future = async {
+                let mut client = FeederClient::new(&channel);
+                let mut config = FakeConfigStore::default_config();
+                config.advance_settle_time = 20;
+                config.retract_settle_time = 20;
+                config.servo_idle_timeout = 100;
+                client.set_config(config).await.unwrap();
+                client.enable(true).await.unwrap();
+                let feed_2mm = FeedLength::Millimeters(Value::from_num(2));
+                assert_eq!(client.advance(feed_2mm, false).await, Ok(()));
+                // Idle time counts from when the lever was commanded.
+                Timer::after_millis(70).await;
+                assert_eq!(*positions.lock().unwrap(), [107.5]);
+                Timer::after_millis(20).await;
+                // Released with a zero width pulse.
+                assert_eq!(*positions.lock().unwrap(), [107.5, 0.0]);
+                assert_eq!(client.advance(feed_2mm, false).await, Ok(()));
+                client.shutdown().await;
+            };
+            join(feeder.run(&channel), test_future).await;
+
+            // The last angle is re-asserted before moving on.
+            assert_eq!(*positions.lock().unwrap(), [107.5, 0.0, 107.5, 135.0, 80.0]);
+        })
+        .await
+    }
+
+    #[futures_test::test]
+    async fn m608_clears_latched_jams() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M623 N0 J50")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M608 N0")).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..5],
+            [
+                "ok",
+                "ok",
+                "error:35 feeder 0 jammed",
+                "error:35 feeder 0 jammed",
+                "ok"
+            ]
+        );
+        assert!(lines[5].contains(" latched=35 "), "{output}");
+        assert_eq!(lines[6..8], ["ok", "ok"]);
+        assert!(lines[8].contains(" latched=none "), "{output}");
+        assert_eq!(lines[9..], ["ok", "ok"]);
+    }
+
+    #[futures_test::test]
+    async fn m608_without_index_clears_every_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M623 N0 J50")).await;
+            line_sender.send(line_event("M623 N1 J50")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M608")).await;
+            line_sender.send(line_event("M602")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 9, "{output}");
+        assert_eq!(
+            lines[3..6],
+            ["error:35 feeder 0 jammed", "error:35 feeder 1 jammed", "ok"]
+        );
+        assert!(lines[6].contains(" latched=none "), "{output}");
+        assert!(lines[7].contains(" latched=none "), "{output}");
+        assert_eq!(lines[8], "ok");
+    }
+
+    #[futures_test::test]
+    async fn disconnect_during_advance_disables_feeder() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 U1000")).await;
+            // The host goes away while the slow advance is still settling.
+            line_sender.send(line_event("M600 N0 F4")).await;
+            Timer::after_millis(100).await;
+            line_sender.send(GCodeEvent::Disconnect).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nok\nerror:10 feeder 0 disabled\n"
+        );
+        // The in flight advance completes but nothing moves after the
+        // disconnect.
+        assert_eq!(servos[0], vec![Value::from_num(135), Value::from_num(80)]);
+        assert!(servos[1].is_empty());
+    }
+
+    #[futures_test::test]
+    async fn button_feeds_run_concurrently_with_commands() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback0 = &fake_inputs[0];
+        let start = Instant::now();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N0 L1 U500")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+
+            // Press feeder 0's button while feeder 1 is advancing.
+            feedback0.send(true).await;
+            feedback0.send(false).await;
+            Timer::after_millis(100).await;
+            feedback0.send(true).await;
+
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let elapsed = start.elapsed();
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nupdated 2 of 2 feeders\nok\nok\n"
+        );
+        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
+        assert_eq!(servos[1], vec![Value::from_num(135), Value::from_num(80)]);
+        // Feeder 0's 500ms feed overlaps feeder 1's 1000ms advance.
+        assert!(elapsed < Duration::from_millis(1500), "{elapsed:?}");
+    }
+
+    #[futures_test::test]
+    async fn negative_feeder_index_is_rejected() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M600 N-1")).await;
+            line_sender.send(line_event("M620 N0 L-2 A90")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "error:9 invalid argument type N\nerror:9 invalid argument type L\n"
+        );
+    }
+
+    #[futures_test::test]
+    async fn priority_commands_skip_queued_commands() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            // Queued behind the advance.
+            line_sender.send(line_event("M621 N0")).await;
+            line_sender.send(line_event("M621 N1")).await;
+            line_sender.send(line_event("M410")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 13);
+        assert_eq!(lines[0..3], ["ok", "error:23 aborted", "ok"]);
+        assert!(lines[3].starts_with("M620 N0 "));
+    }
+
+    #[futures_test::test]
+    async fn m410_aborts_queued_advances_and_keeps_feeders_enabled() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N1 U500")).await;
+            line_sender.send(line_event("M600 N1 F8")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            Timer::after_millis(100).await;
+            line_sender.send(line_event("M410")).await;
+
+            // The interrupted feeder needs homing before it feeds again.
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("G28 N1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             error:23 aborted\n\
+             ok\n\
+             error:23 aborted\n\
+             error:23 aborted\n\
+             error:24 feeder interrupted, home required\n\
+             ok\n\
+             ok\n\
+             ok\n"
+        );
+        assert_eq!(servos[0], vec![Value::from_num(135), Value::from_num(80)]);
+        assert_eq!(
+            servos[1],
+            [135, 80, 85, 80, 135, 80].map(Value::from_num).to_vec()
+        );
+    }
+
+    #[futures_test::test]
+    async fn feed_counters_are_restored_saved_and_reset() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let config_store = FakeConfigStore::new();
+        let counters = config_store.counters.clone();
+        counters.lock().unwrap().insert(
+            0,
+            FeedCounters {
+                parts: 5,
+                length: Value64::from_num(20),
+                remaining: None,
+            },
+        );
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, config_store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F2")).await;
+            line_sender.send(line_event("M604")).await;
+            // Counters are also saved periodically.
+            line_sender.send(line_event("M500")).await;
+            Timer::after_millis(100).await;
+            assert_eq!(
+                counters.lock().unwrap().get(&0),
+                Some(&FeedCounters {
+                    parts: 6,
+                    length: Value64::from_num(24),
+                    remaining: None,
+                })
+            );
+            line_sender.send(line_event("M604 N1 R1")).await;
+            line_sender.send(line_event("M604 N1")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nok\n\
+             feeder 0: parts=6 length=24\n\
+             feeder 1: parts=1 length=2\n\
+             ok\nok\nok\n\
+             feeder 1: parts=0 length=0\n\
+             ok\n"
+        );
+        assert_eq!(
+            counters.lock().unwrap().get(&1),
+            Some(&FeedCounters::default())
+        );
+    }
+
+    #[futures_test::test]
+    async fn advance_marker_flags_interrupted_feeder_until_homed() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let config_store = FakeConfigStore::new();
+        let markers = config_store.advance_markers.clone();
+        markers.lock().unwrap().insert(1, true);
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, config_store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("G28 N1")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines.iter().any(|line| line.starts_with("notice: ")
+            && line.ends_with(" feeder 1 was interrupted mid-advance, home required")));
+        assert!(output.ends_with(
+            "ready\nok\n\
+             error:24 feeder interrupted, home required\n\
+             ok\nok\nok\n"
+        ));
+        assert!(markers.lock().unwrap().values().all(|marked| !marked));
+    }
+
+    #[futures_test::test]
+    async fn repeated_crashes_start_in_safe_mode() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let config_store = FakeConfigStore::new();
+        *config_store.crash_count.lock().unwrap() = 3;
+        let mut config = FakeConfigStore::default_config();
+        config.advanced_angle = Value::from_num(100);
+        config_store.get_store().lock().unwrap().insert(0, config);
+        let crash_count = config_store.crash_count.clone();
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, config_store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            // Safe mode lasts until a restart.
+            Timer::after_secs(120).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("notice: "));
+        assert!(lines[0].ends_with(
+            " safe mode after 3 crash resets, stored settings not loaded \
+             and feeders disabled, M999 to restart"
+        ));
+        // Feeders run with the default config rather than the stored one.
+        assert!(lines[2].starts_with("M620 N0 A135 "));
+        assert_eq!(
+            lines[10..],
+            [
+                "ready",
+                "error:36 safe mode, restart with M999",
+                "error:10 feeder 0 disabled",
+            ]
+        );
+        assert_eq!(*crash_count.lock().unwrap(), 3);
+    }
+
+    #[futures_test::test]
+    async fn crash_count_is_cleared_after_stable_uptime() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let config_store = FakeConfigStore::new();
+        *config_store.crash_count.lock().unwrap() = 2;
+        let crash_count = config_store.crash_count.clone();
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, config_store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            Timer::after_secs(30).await;
+            assert_eq!(*crash_count.lock().unwrap(), 2);
+            Timer::after_secs(31).await;
+            assert_eq!(*crash_count.lock().unwrap(), 0);
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(String::from_utf8_lossy(&output), "ok\n");
+    }
+
+    #[futures_test::test]
+    async fn feeders_are_disabled_while_idle() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M612 I1 E1")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            Timer::after_millis(1500).await;
+            // The next command re-enables the feeders before it runs.
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M612 E0")).await;
+            Timer::after_millis(1500).await;
+            line_sender.send(line_event("M602 N0")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0..2], ["ok", "ok"]);
+        assert!(
+            lines[2].starts_with("notice: ") && lines[2].ends_with(" feeders disabled while idle"),
+            "{output}"
+        );
+        assert!(lines[3].starts_with("feeder 0: enabled=1 "), "{output}");
+        assert!(lines[7].starts_with("feeder 0: enabled=0 "), "{output}");
+        assert_eq!(lines.len(), 9, "{output}");
+        // Servos are detached by dropping their pulses.
+        assert!(servos[1].contains(&Value::ZERO));
+    }
+
+    #[futures_test::test]
+    async fn advances_are_deferred_while_disabled() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M612 Q1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 C2")).await;
+            line_sender.send(line_event("M600 N1")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N1 F2")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "error:10 feeder 0 disabled\n\
+             ok\n\
+             deferred: M600 N0 F4\n\
+             ok\n\
+             deferred: M600 N1 C2\n\
+             ok\n\
+             deferred: M600 N1\n\
+             ok\n\
+             replayed: M600 N0 F4 ok\n\
+             replayed: M600 N1 C2 error:32 no part pitch configured\n\
+             replayed: M600 N1 ok\n\
+             ok\n\
+             ok\n"
+        );
+        assert!(!servos[0].is_empty());
     }
 
-    async fn run_test_harness(
-        line_reciever: GCodeEventReceiver<'_, 2>,
-        fake_inputs: &[FakeInputChannel; 2],
-    ) -> ([Vec<Value>; 2], Vec<u8>, HashMap<usize, FeederConfig>) {
-        let (positions_0, servo_0) = FakeServo::new();
-        let (positions_1, servo_1) = FakeServo::new();
-        let mut feeder_0 = Feeder::new(servo_0, FakeInput::new(false, &fake_inputs[0]));
-        let mut feeder_1 = Feeder::new(servo_1, FakeInput::new(false, &fake_inputs[1]));
-        let channels = [&FeederChannel::new(), &FeederChannel::new()];
-        let feeder_future = join_array([feeder_0.run(channels[0]), feeder_1.run(channels[1])]);
-        let mut output = Vec::<u8>::new();
+    #[futures_test::test]
+    async fn runout_warning_counts_down_parts_loaded() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let config_store = FakeConfigStore::new();
-        let backing_store = config_store.get_store();
-        join(
-            feeder_future,
-            run_handler(
-                [
-                    FeederClient::new(channels[0]),
-                    FeederClient::new(channels[1]),
-                ],
-                &mut output,
-                config_store,
-                line_reciever,
-            ),
-        )
-        .await;
-        let positions_0 = positions_0.lock().unwrap().clone();
-        let positions_1 = positions_1.lock().unwrap().clone();
-        let configs = backing_store.lock().unwrap().clone();
-        ([positions_0, positions_1], output, configs)
+        let counters = config_store.counters.clone();
+        let test_harness_future =
+            run_test_harness_with_store(gcode_channel.receiver(), &fake_inputs, config_store);
+        let line_sender = gcode_channel.sender();
+        let test_future = async {
+            line_sender.send(line_event("M623 N0 W2")).await;
+            line_sender.send(line_event("M604 N0 P3")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0")).await;
+            line_sender.send(line_event("M600 N0")).await;
+            line_sender.send(line_event("M600 N0")).await;
+            line_sender.send(line_event("M600 N0")).await;
+            line_sender.send(line_event("M604")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0..5], ["ok", "ok", "ok", "ok", "ok"], "{output}");
+        // Only crossing the threshold warns.
+        assert!(
+            lines[5].starts_with("warning: ")
+                && lines[5].ends_with(" feeder 0 low on parts: 1 remaining"),
+            "{output}"
+        );
+        assert_eq!(
+            lines[6..],
+            [
+                "ok",
+                "ok",
+                "feeder 0: parts=4 length=8 remaining=0",
+                "feeder 1: parts=0 length=0",
+                "ok",
+            ],
+            "{output}"
+        );
+        assert_eq!(counters.lock().unwrap().get(&0).unwrap().remaining, Some(3));
     }
 
-    fn line_event(s: &str) -> GCodeEvent {
-        GCodeEvent::Line(s.parse().unwrap())
+    #[futures_test::test]
+    async fn m661_reports_job_summary() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let feedback1 = &fake_inputs[1];
+        let test_future = async move {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M661")).await;
+            line_sender.send(line_event("M660 S42")).await;
+            line_sender.send(line_event("M600 N0 F8")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M600 N1 F3")).await;
+            // Button feeds are locked out during the job.
+            Timer::after_millis(100).await;
+            feedback1.send(true).await;
+            feedback1.send(false).await;
+            Timer::after_millis(100).await;
+            feedback1.send(true).await;
+            Timer::after_millis(100).await;
+            line_sender.send(line_event("M661")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0..7],
+            [
+                "ok",
+                "ok",
+                "error:25 no job in progress",
+                "ok",
+                "progress: feeder 0 cycles=1 remaining=4",
+                "ok",
+                "ok",
+            ]
+        );
+        assert_eq!(lines[7], "error:17 invald feed length 3");
+        assert!(lines[8].starts_with("job: "), "{}", lines[8]);
+        assert!(lines[8].contains(" id=42 duration="), "{}", lines[8]);
+        assert!(lines[8].ends_with(" feeds=2 errors=1"), "{}", lines[8]);
+        assert_eq!(
+            lines[9..],
+            [
+                "job feeder 0: feeds=1 errors=0",
+                "job feeder 1: feeds=1 errors=1",
+                "ok"
+            ]
+        );
+        assert_eq!(servos[1], vec![Value::from_num(135), Value::from_num(80)]);
     }
 
     #[futures_test::test]
-    async fn test_harnes_exits_on_m999() {
+    async fn soak_test_logs_errors_and_reports_summary() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
-        let test_future = async move { line_sender.send(line_event("M999")).await };
-        let (_, _) = join(test_harness_future, test_future).await;
+
+        // Feeder 0 is never ready.
+        fake_inputs[0].send(true).await;
+
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M681")).await;
+            line_sender.send(line_event("M680 S60 R7 P20")).await;
+            Timer::after_millis(600).await;
+            line_sender.send(line_event("M681")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+
+        let output = String::from_utf8_lossy(&output);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0..3],
+            ["ok", "error:33 no soak test in progress", "ok"]
+        );
+        // Feeder 0 drops out of the test once it is auto-disabled.
+        let errors: Vec<&str> = lines
+            .iter()
+            .filter(|line| line.starts_with("soak: ") && line.contains(" feeder "))
+            .copied()
+            .collect();
+        assert_eq!(errors.len(), 3);
+        for error in errors {
+            assert!(
+                error.ends_with(" feeder 0 error:13 feeder 0 not ready"),
+                "{error}"
+            );
+        }
+        assert!(output.contains(" feeder 0 auto-disabled: "), "{output}");
+
+        let summary = &lines[lines.len() - 4..];
+        assert!(summary[0].starts_with("soak: "), "{}", summary[0]);
+        assert!(summary[0].contains(" errors=3"), "{}", summary[0]);
+        assert_eq!(
+            summary[1],
+            "soak feeder 0: feeds=3 errors=3 last_error=13 feeder 0 not ready"
+        );
+        let feeds: u32 = summary[2]
+            .strip_prefix("soak feeder 1: feeds=")
+            .and_then(|rest| rest.strip_suffix(" errors=0 last_error=none"))
+            .and_then(|feeds| feeds.parse().ok())
+            .expect(summary[2]);
+        assert!(feeds > 3, "{feeds}");
+        assert!(!servos[1].is_empty());
+        assert_eq!(summary[3], "ok");
     }
 
     #[futures_test::test]
-    async fn feeder_doesnt_move_before_enabled() {
+    async fn long_feeds_report_busy_and_progress() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
         let test_future = async move {
-            line_sender.send(line_event("M603 N1 A120.0")).await;
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M620 N1 U1500 Q1500")).await;
+            // Two advance/retract cycles take 6s.
+            line_sender.send(line_event("M600 N1 F8")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M602 N1")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
-        assert_eq!("error: feeder disabled\n", String::from_utf8_lossy(&output));
-        assert!(servos[0].is_empty());
-        assert!(servos[1].is_empty());
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             busy: processing\n\
+             progress: feeder 1 cycles=1 remaining=4\n\
+             busy: processing\n\
+             ok\n\
+             ok\n\
+             feeder 1: enabled=1 attention=0 feedback=0 offset=0 angle=80 interrupted=none \
+             parts=1 cycles=2 remaining=0 latched=none last_error=none\n\
+             ok\n"
+        );
     }
 
     #[futures_test::test]
-    async fn m603_moves_correct_servo() {
+    async fn progress_is_reported_between_cycles() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
         let test_future = async move {
             line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M603 N1 A120.0")).await;
+            // Six advance/retract cycles.
+            line_sender.send(line_event("M600 N0 F24")).await;
+            // A single cycle reports no progress.
+            line_sender.send(line_event("M600 N1 F4")).await;
             line_sender.send(line_event("M999")).await;
         };
         let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
-        println!("{}", String::from_utf8_lossy(&output));
-        assert!(servos[0].is_empty());
-        assert_eq!(servos[1], vec![Value::from_num(120.0)]);
+
+        let output = String::from_utf8_lossy(&output);
+        let progress: Vec<&str> = output
+            .lines()
+            .filter(|line| line.starts_with("progress: "))
+            .collect();
+        assert_eq!(
+            progress,
+            [
+                "progress: feeder 0 cycles=1 remaining=20",
+                "progress: feeder 0 cycles=2 remaining=16",
+                "progress: feeder 0 cycles=3 remaining=12",
+                "progress: feeder 0 cycles=4 remaining=8",
+                "progress: feeder 0 cycles=5 remaining=4",
+            ]
+        );
+        assert!(output.ends_with("ok\nok\n"), "{output}");
+        assert_eq!(servos[0].len(), 12);
     }
 
     #[futures_test::test]
-    async fn m600_advances_feeder() {
+    async fn m112_aborts_feed_in_progress() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
+        let start = Instant::now();
         let test_future = async move {
             line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M620 N1 U500")).await;
+            line_sender.send(line_event("M600 N1 F8")).await;
+            Timer::after_millis(100).await;
+            line_sender.send(line_event("M112")).await;
+            line_sender.send(line_event("M600 N1 F8")).await;
             line_sender.send(line_event("M999")).await;
         };
         let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
 
-        println!("{}", String::from_utf8_lossy(&output));
-        assert!(servos[0].is_empty());
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\nerror:23 aborted\nok\nerror:10 feeder 1 disabled\n"
+        );
+        // The feed stopped at its first move.
+        assert_eq!(servos[1], vec![Value::from_num(135)]);
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
 
-        // Servo 1 should go to the advanced angle then the retract angle.
-        let feeder_config_defaults: FeederConfig = Default::default();
+    // Output which blocks while `stalled` is set, like a host which has
+    // stopped reading.
+    struct StallableOutput<'a> {
+        output: &'a Mutex<Vec<u8>>,
+        stalled: &'a Cell<bool>,
+    }
+
+    impl<'a> embedded_io_async::ErrorType for StallableOutput<'a> {
+        type Error = Infallible;
+    }
+
+    impl<'a> Write for StallableOutput<'a> {
+        async fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Infallible> {
+            while self.stalled.get() {
+                Timer::after_millis(1).await;
+            }
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[futures_test::test]
+    async fn stalled_output_doesnt_block_commands() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let output = Mutex::new(Vec::new());
+        let stalled = Cell::new(true);
+        let test_harness_future = run_test_harness_with_output(
+            gcode_channel.receiver(),
+            &fake_inputs,
+            FakeConfigStore::new(),
+            StallableOutput {
+                output: &output,
+                stalled: &stalled,
+            },
+        );
+        let line_sender = gcode_channel.sender();
+        let start = Instant::now();
+        let test_future = async {
+            line_sender.send(line_event("M610 S1")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M621 N0")).await;
+            // Wait for the queue to drain then let the host catch up.
+            Timer::after_millis(3000).await;
+            stalled.set(false);
+            line_sender.send(line_event("M650")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((servos, _config), _) = join(test_harness_future, test_future).await;
+
+        // Only the first write waits for the timeout.  The rest are dropped
+        // immediately.
+        let output = output.into_inner().unwrap();
+        let lines: Vec<_> = core::str::from_utf8(&output).unwrap().lines().collect();
         assert_eq!(
-            servos[1],
-            vec![
-                feeder_config_defaults.advanced_angle,
-                feeder_config_defaults.retract_angle
+            lines[0..3],
+            [
+                "commands_total 4",
+                "command_errors_total 0",
+                "write_timeouts_total 4"
             ]
         );
+        assert_eq!(servos[0], vec![Value::from_num(135), Value::from_num(80)]);
+        assert!(start.elapsed() < Duration::from_millis(3500));
+    }
+
+    const TRANSCRIPTS: [(&str, &str); 3] = [
+        ("config", include_str!("../transcripts/config.txt")),
+        ("feed", include_str!("../transcripts/feed.txt")),
+        ("metrics", include_str!("../transcripts/metrics.txt")),
+    ];
+
+    // Runs a transcript in the format described in `transcripts/README.md`
+    // and returns the expected and actual output.
+    async fn run_transcript(transcript: &str) -> (String, String) {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let mut expected = String::new();
+        let mut script = Vec::new();
+        for line in transcript.lines() {
+            if let Some(response) = line.strip_prefix("< ") {
+                expected += response;
+                expected += "\n";
+            } else if !line.is_empty() && !line.starts_with('#') {
+                script.push(line);
+            }
+        }
+
+        let test_future = async move {
+            for line in script {
+                if let Some(command) = line.strip_prefix("> ") {
+                    line_sender.send(line_event(command)).await;
+                } else if line == "@connect" {
+                    line_sender.send(GCodeEvent::Connect).await;
+                } else if line == "@disconnect" {
+                    line_sender.send(GCodeEvent::Disconnect).await;
+                } else if let Some(ms) = line.strip_prefix("@wait ") {
+                    Timer::after_millis(ms.parse().unwrap()).await;
+                } else {
+                    panic!("unrecognized transcript line: {line}");
+                }
+            }
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        (expected, String::from_utf8_lossy(&output).into_owned())
+    }
+
+    #[futures_test::test]
+    async fn golden_transcripts() {
+        for (name, transcript) in TRANSCRIPTS {
+            let (expected, actual) = run_transcript(transcript).await;
+            assert_eq!(actual, expected, "transcript {name} diverged");
+        }
+    }
+
+    #[test]
+    fn command_codes_are_whole_numbers() {
+        let code = |line: &str| command_code(line.parse::<Line>().unwrap().command().unwrap());
+        assert_eq!(code("M600 N1"), Some(('M', 600)));
+        assert_eq!(code("G4 P10"), Some(('G', 4)));
+        assert_eq!(code("M600.5 N1"), None);
+    }
+
+    #[futures_test::test]
+    async fn event_reader_matches_transport() {
+        let script = "; feeds\nN1 M600 N0*122 (first)\n\nN2 M600 N1*0\n1,2,3\nM600 N0\nM112\n";
+        let mut reader = EventReader::new(script.as_bytes());
+        let channel = GCodeEventChannel::<2>::new();
+        let sender = channel.sender();
+
+        let event = reader.next().unwrap().unwrap();
+        assert!(matches!(&event, GCodeEvent::Line(line) if is_advance_command(line)));
+        assert!(sender.try_send(event).is_ok());
+        assert_eq!(reader.next().unwrap().err(), Some(Error::ChecksumMismatch));
+        assert_eq!(reader.resend_line(), 2);
+        let event = reader.next().unwrap().unwrap();
+        assert!(matches!(&event, GCodeEvent::Unparsed(row) if row == "1,2,3"));
+        assert!(sender.try_send(event).is_ok());
+
+        // A full channel hands the event back but stops still get through.
+        let event = reader.next().unwrap().unwrap();
+        assert!(matches!(sender.try_send(event), Err(GCodeEvent::Line(_))));
+        assert!(sender.try_send(reader.next().unwrap().unwrap()).is_ok());
+        assert!(reader.next().is_none());
+
+        let receiver = channel.receiver();
+        assert!(
+            matches!(receiver.receive().await, GCodeEvent::Line(line) if is_stop_command(&line))
+        );
+        assert!(matches!(receiver.receive().await, GCodeEvent::Line(_)));
+        assert!(matches!(receiver.receive().await, GCodeEvent::Unparsed(_)));
+    }
+
+    #[futures_test::test]
+    async fn sender_waits_for_room_in_deeper_queue() {
+        let channel = GCodeEventChannel::<4>::new();
+        let sender = channel.sender();
+        let receiver = channel.receiver();
+        for _ in 0..4 {
+            assert!(sender.try_send(line_event("M600 N0")).is_ok());
+        }
+        let event = line_event("M600 N1");
+        let Err(event) = sender.try_send(event) else {
+            panic!("queue should be full");
+        };
+        // A stop has its own lane so it doesn't wait behind the backlog.
+        let stop = line_event("M112");
+        sender.ready_to_send(&stop).await;
+
+        join(receiver.receive(), sender.ready_to_send(&event)).await;
+        assert!(sender.try_send(event).is_ok());
+    }
+
+    #[test]
+    fn fake_servo_conforms() {
+        let (_positions, mut servo) = FakeServo::new();
+        check_servo_conformance(&mut servo, Value::from_num(FakeServo::PERIOD_MICROS));
+    }
+
+    // Records every duty cycle written, out of 10000.
+    struct FakeDutyCycle {
+        duties: Vec<u16>,
+    }
+
+    impl embedded_hal::pwm::ErrorType for FakeDutyCycle {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_hal::pwm::SetDutyCycle for FakeDutyCycle {
+        fn max_duty_cycle(&self) -> u16 {
+            10000
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> core::result::Result<(), Self::Error> {
+            self.duties.push(duty);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn duty_cycle_servo_converts_pulse_widths() {
+        let mut servo = DutyCycleServo::new(FakeDutyCycle { duties: Vec::new() }, 50);
+        check_servo_conformance(&mut servo, Value::from_num(20_000));
+
+        let mut servo = DutyCycleServo::new(FakeDutyCycle { duties: Vec::new() }, 200);
+        assert_eq!(servo.set_angle(Value::from_num(90)), Ok(()));
+        assert_eq!(servo.set_angle(Value::from_num(90)), Ok(()));
+        assert_eq!(servo.set_pulse_width(Value::from_num(2500)), Ok(()));
+        assert_eq!(
+            servo.set_pulse_width(Value::from_num(5001)),
+            Err(Error::PwmValueOutOfRange)
+        );
+        assert_eq!(servo.disable_output(), Ok(()));
+        assert_eq!(servo.set_frame_rate(200), Ok(()));
+        assert_eq!(servo.set_frame_rate(50), Err(Error::PwmValueOutOfRange));
+        assert_eq!(servo.into_inner().duties, vec![3000, 5000, 0]);
+    }
+
+    // Fixed point rounding means scaled values may differ from the exact
+    // result in the last few bits.
+    fn assert_counts_near(counts: Result<Value>, expected: f64) {
+        let counts = counts.unwrap();
+        let error = (counts.to_num::<f64>() - expected).abs();
+        assert!(error < 0.001, "{counts} != {expected}");
+    }
+
+    #[test]
+    fn scale_angle_maps_boundaries_to_limits() {
+        let limits = PwmLimits {
+            zero: Value::from_num(1000),
+            one_eighty: Value::from_num(2000),
+        };
+        assert_eq!(limits.scale_angle(Value::from_num(0)), Ok(limits.zero));
+        assert_eq!(
+            limits.scale_angle(Value::from_num(180)),
+            Ok(limits.one_eighty)
+        );
+        assert_counts_near(limits.scale_angle(Value::from_num(90)), 1500.0);
+    }
+
+    #[test]
+    fn scale_angle_handles_fractional_angles() {
+        let limits = PwmLimits {
+            zero: Value::from_num(0),
+            one_eighty: Value::from_num(1800),
+        };
+        assert_eq!(
+            limits.scale_angle(Value::from_num(0.5)),
+            Ok(Value::from_num(5))
+        );
+        assert_eq!(
+            limits.scale_angle(Value::from_num(107.5)),
+            Ok(Value::from_num(1075))
+        );
+    }
+
+    #[test]
+    fn scale_angle_handles_inverted_limits() {
+        let limits = PwmLimits {
+            zero: Value::from_num(2000),
+            one_eighty: Value::from_num(1000),
+        };
+        assert_eq!(
+            limits.scale_angle(Value::from_num(45)),
+            Ok(Value::from_num(1750))
+        );
+    }
+
+    #[test]
+    fn scale_angle_does_not_overflow_wide_ranges() {
+        // range * angle exceeds `Value`'s range so the intermediate math
+        // must be done in 64 bits.
+        let limits = PwmLimits {
+            zero: Value::from_num(0),
+            one_eighty: Value::from_num(30000),
+        };
+        assert_eq!(
+            limits.scale_angle(Value::from_num(180)),
+            Ok(Value::from_num(30000))
+        );
+        assert_counts_near(limits.scale_angle(Value::from_num(179.5)), 29916.666667);
+    }
+
+    #[test]
+    fn scale_angle_rejects_out_of_range_angles() {
+        let limits = PwmLimits {
+            zero: Value::from_num(1000),
+            one_eighty: Value::from_num(2000),
+        };
+        assert_eq!(
+            limits.scale_angle(Value::from_num(-0.5)),
+            Err(Error::AngleOutOfRange)
+        );
+        assert_eq!(
+            limits.scale_angle(Value::from_num(180.5)),
+            Err(Error::AngleOutOfRange)
+        );
+    }
+
+    #[futures_test::test]
+    async fn m621_without_index_dumps_all_feeders() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M620 N1 A120")).await;
+            line_sender.send(line_event("M621")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             M620 N0 A135 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\n\
+             M620 N1 A120 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N1 R0 D500 W0 J0 E0 T0.5 C2\nM634 N1 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N1 P0 S100 A0 X90 Y90\n\
+             ok\n"
+        );
+    }
+
+    #[test]
+    fn pwm_limits_check_range() {
+        assert_eq!(PwmLimits::standard().check_range(20_000), Ok(()));
+        let negative = PwmLimits {
+            zero: Value::from_num(-1),
+            one_eighty: Value::from_num(2000),
+        };
+        assert_eq!(negative.check_range(20_000), Err(Error::PwmValueOutOfRange));
+        let too_large = PwmLimits {
+            zero: Value::from_num(1000),
+            one_eighty: Value::from_num(2600),
+        };
+        assert_eq!(too_large.check_range(2500), Err(Error::PwmValueOutOfRange));
+    }
+
+    #[test]
+    fn legacy_pwm_counts_convert_to_micros() {
+        let mut config = FeederConfig {
+            advanced_angle: Value::from_num(857.85),
+            pwm_0: Value::from_num(490.2),
+            pwm_180: Value::from_num(980.4),
+            position_units: PositionUnits::PulseWidth,
+            ..Default::default()
+        };
+        config.convert_legacy_pwm_counts().unwrap();
+        assert_eq!(config.pwm_0, Value::from_num(1000));
+        assert_eq!(config.pwm_180, Value::from_num(2000));
+        assert_eq!(config.advanced_angle, Value::from_num(1750));
+
+        // Positions in degrees are left alone.
+        let mut config = FeederConfig {
+            advanced_angle: Value::from_num(135),
+            pwm_0: Value::from_num(490.2),
+            ..Default::default()
+        };
+        config.convert_legacy_pwm_counts().unwrap();
+        assert_eq!(config.advanced_angle, Value::from_num(135));
+        assert_eq!(config.pwm_0, Value::from_num(1000));
+    }
+
+    #[futures_test::test]
+    async fn m115_reports_firmware_info() {
+        let gcode_channel = GCodeEventChannel::<2>::new();
+        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
+        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            line_sender.send(line_event("M115")).await;
+            line_sender.send(line_event("M999")).await;
+        };
+        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            format!(
+                "FIRMWARE_NAME:pnpfeeder FIRMWARE_VERSION:{} FEEDER_COUNT:2 \
+                 COMMANDS:G28,G4,M110,M112,M115,M154,M280,M400,M410,M500,M501,M502,M600,M601,M602,M603,M604,M608,M610,M611,M612,M619,M620,M621,M622,M623,M624,M625,M626,M627,M628,M629,M630,M631,M632,M633,M634,M635,M640,M641,M650,M660,M661,M670,M671,M680,M681\n\
+                 ok\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
     }
 
     #[futures_test::test]
-    async fn m620_changes_feeder_angles() {
+    #[should_panic(expected = "rebooting to bootloader")]
+    async fn m997_reboots_to_bootloader() {
+        fn reboot_to_bootloader() -> ! {
+            panic!("rebooting to bootloader");
+        }
+        let channels = [FeederChannel::new(), FeederChannel::new()];
+        let mut output = Vec::<u8>::new();
+        let mut handler = GCodeHandler::new(
+            channels.each_ref().map(FeederClient::new),
+            &mut output,
+            FakeConfigStore::new(),
+        )
+        .with_bootloader(reboot_to_bootloader);
+        with_mock_time(handler.handle_line("M997".parse().unwrap())).await;
+    }
+
+    #[futures_test::test]
+    #[should_panic(expected = "resetting")]
+    async fn m999_resets() {
+        fn reset() -> ! {
+            panic!("resetting");
+        }
         let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let channels = [FeederChannel::new(), FeederChannel::new()];
+        let mut output = Vec::<u8>::new();
+        let mut handler = GCodeHandler::new(
+            channels.each_ref().map(FeederClient::new),
+            &mut output,
+            FakeConfigStore::new(),
+        )
+        .with_reset(reset);
+        let (mut feeder_0, mut feeder_1) = (
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+        );
         let line_sender = gcode_channel.sender();
-        let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M620 N1 A122 C22")).await;
-            line_sender.send(line_event("M600 N1 F4")).await;
-            line_sender.send(line_event("M999")).await;
-        };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        with_mock_time(join3(
+            handler.run(gcode_channel.receiver()),
+            join(feeder_0.run(&channels[0]), feeder_1.run(&channels[1])),
+            line_sender.send(line_event("M999")),
+        ))
+        .await;
+    }
 
-        println!("{}", String::from_utf8_lossy(&output));
-        assert!(servos[0].is_empty());
-        assert_eq!(servos[1], vec![Value::from_num(122), Value::from_num(22)]);
+    #[derive(Default)]
+    struct FakeHookOutputs(Vec<(&'static str, bool)>);
+
+    impl HookOutputs for FakeHookOutputs {
+        fn set_led(&mut self, on: bool) {
+            self.0.push(("led", on));
+        }
+
+        fn set_aux(&mut self, on: bool) {
+            self.0.push(("aux", on));
+        }
     }
 
     #[futures_test::test]
-    async fn m620_updates_feeder_config_in_store() {
+    async fn hooks_run_on_feeder_events() {
         let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let channels = [FeederChannel::new(), FeederChannel::new()];
+        let mut output = Vec::<u8>::new();
+        let mut hook_outputs = FakeHookOutputs::default();
+        let mut handler = GCodeHandler::new(
+            channels.each_ref().map(FeederClient::new),
+            &mut output,
+            FakeConfigStore::new(),
+        )
+        .with_hook_outputs(&mut hook_outputs);
+        let (mut feeder_0, mut feeder_1) = (
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+        );
         let line_sender = gcode_channel.sender();
         let test_future = async move {
+            line_sender.send(line_event("M620 N0 D1 K2")).await;
+            line_sender.send(line_event("M620 N1 D4 K5")).await;
             line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M620 N1 A122 C22")).await;
+            line_sender.send(line_event("M600 N0 F4")).await;
             line_sender.send(line_event("M600 N1 F4")).await;
+            line_sender.send(line_event("M650")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((_servos, output, config), _) = join(test_harness_future, test_future).await;
+        with_mock_time(join3(
+            handler.run(gcode_channel.receiver()),
+            join(feeder_0.run(&channels[0]), feeder_1.run(&channels[1])),
+            test_future,
+        ))
+        .await;
+        drop(handler);
 
-        println!("{}", String::from_utf8_lossy(&output));
+        let output = String::from_utf8_lossy(&output);
+        assert!(
+            output.contains("ok\nevent: feeder 1 feed-complete\n"),
+            "{output}"
+        );
+        assert!(output.contains("hook_events_total{feeder=\"1\"} 1\n"));
         assert_eq!(
-            *config.get(&1).unwrap(),
-            FeederConfig {
-                advanced_angle: 122.0f32.to_fixed(),
-                retract_angle: 22.0f32.to_fixed(),
-                ..FakeConfigStore::default_config()
-            }
+            hook_outputs.0,
+            vec![("led", true), ("aux", true), ("aux", false)]
         );
     }
 
     #[futures_test::test]
-    async fn m621_reflects_m620_changes() {
+    async fn feeders_enable_at_startup_after_blinking() {
         let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let channels = [FeederChannel::new(), FeederChannel::new()];
+        let mut output = Vec::<u8>::new();
+        let mut hook_outputs = FakeHookOutputs::default();
+        let mut config_store = FakeConfigStore::new();
+        config_store.board = BoardConfig {
+            startup_enable: true,
+            startup_delay: 1000,
+            ..Default::default()
+        };
+        let mut handler = GCodeHandler::new(
+            channels.each_ref().map(FeederClient::new),
+            &mut output,
+            config_store,
+        )
+        .with_hook_outputs(&mut hook_outputs);
+        let (mut feeder_0, mut feeder_1) = (
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+            Feeder::new(FakeServo::new().1, PlaybackInput::new(false, &[])),
+        );
         let line_sender = gcode_channel.sender();
         let test_future = async move {
-            line_sender
-                .send(line_event("M620 N1 A1 B2 C3 F4 U5 V6 W7 X1 Y0"))
-                .await;
-            line_sender.send(line_event("M621 N1")).await;
+            line_sender.send(line_event("M612")).await;
+            line_sender.send(line_event("M602 N1")).await;
+            line_sender.send(line_event("M612 S0")).await;
+            line_sender.send(line_event("M612")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let (_, _, enabled_at) = with_mock_time(join3(
+            handler.run(gcode_channel.receiver()),
+            join(feeder_0.run(&channels[0]), feeder_1.run(&channels[1])),
+            async {
+                test_future.await;
+                Instant::now()
+            },
+        ))
+        .await;
+        drop(handler);
 
         let output = String::from_utf8_lossy(&output);
-        assert_eq!(output, "ok\nM620 N1 A1 B2 C3 F4 U5 V6 W7 X1 Y0\nok\n");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0..2], ["M612 S1 P1000 I0 E0 Q0 C0", "ok"]);
+        assert!(lines[2].starts_with("feeder 1: enabled=1 "), "{output}");
+        assert_eq!(lines[3..], ["ok", "ok", "M612 S0 P1000 I0 E0 Q0 C0", "ok"]);
+        assert!(enabled_at.as_millis() >= 1000);
+        assert_eq!(
+            hook_outputs.0,
+            [true, false, true, false, false].map(|on| ("led", on))
+        );
     }
 
-    #[futures_test::test]
-    async fn feeders_disable_on_disconnect() {
-        let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
-        let line_sender = gcode_channel.sender();
-        let test_future = async move {
-            line_sender.send(GCodeEvent::Connect).await;
-            line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M603 N1 A120.0")).await;
-            line_sender.send(GCodeEvent::Disconnect).await;
-            line_sender.send(GCodeEvent::Connect).await;
-            line_sender.send(line_event("M603 N1 A90.0")).await;
-            line_sender.send(line_event("M999")).await;
+    #[test]
+    fn line_checker_validates_numbered_lines() {
+        let mut checker = LineChecker::new();
+        assert_eq!(checker.check("M621 N0"), Ok("M621 N0"));
+        assert_eq!(checker.check("N1 M115*39"), Ok("M115"));
+        assert_eq!(checker.check("N2 M600 N0 F4*43"), Ok("M600 N0 F4"));
+        assert_eq!(checker.resend_line(), 3);
+
+        // Corrupted and out of order lines are rejected without advancing.
+        assert_eq!(
+            checker.check("N2 M600 N0 F4*42"),
+            Err(Error::ChecksumMismatch)
+        );
+        assert_eq!(
+            checker.check("N2 M600 N0 F4*43"),
+            Err(Error::LineNumberMismatch(3))
+        );
+        assert_eq!(checker.check("N3 M115"), Err(Error::MissingChecksum));
+        assert_eq!(checker.check("M115*39"), Err(Error::MissingLineNumber));
+        assert_eq!(checker.resend_line(), 3);
+
+        // M110 resets the line number.
+        assert_eq!(checker.check("N3 M110 N10*79"), Ok("M110 N10"));
+        assert_eq!(checker.check("N11 M621 N0*72"), Ok("M621 N0"));
+        assert_eq!(checker.check("N1 M110*34"), Ok("M110"));
+        assert_eq!(checker.resend_line(), 2);
+        assert_eq!(checker.check("M110 N0"), Ok("M110 N0"));
+        assert_eq!(checker.resend_line(), 1);
+    }
+
+    #[test]
+    fn response_checksums_are_appended_to_lines() {
+        let mut checksum = ResponseChecksum::new();
+        let mut output = String::new();
+        // Lines can arrive split across writes.
+        for chunk in ["o", "k\nbusy: pro", "cessing\n\n"] {
+            for b in chunk.bytes() {
+                if let Some(suffix) = checksum.update(b) {
+                    output.push_str(&suffix);
+                }
+                output.push(b as char);
+            }
+        }
+        assert_eq!(output, "ok*4\nbusy: processing*12\n*0\n");
+
+        // Checksums match those of inbound lines.
+        let mut checker = LineChecker::new();
+        assert_eq!(checker.check("N1 M115*39"), Ok("M115"));
+        let mut checksum = ResponseChecksum::new();
+        let suffix = "N1 M115\n".bytes().find_map(|b| checksum.update(b));
+        assert_eq!(suffix.unwrap(), "*39");
+    }
+
+    #[test]
+    fn angle_scaler_matches_scale_angle() {
+        let limits = PwmLimits {
+            zero: Value::from_num(1000),
+            one_eighty: Value::from_num(2000),
         };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
-        println!("{}", String::from_utf8_lossy(&output));
-        // Ensure that servo 1 didn't move to 90 after a disconnect.
-        assert!(servos[0].is_empty());
-        assert_eq!(servos[1], vec![Value::from_num(120.0)]);
+        let scaler = AngleScaler::new(limits.clone());
+        for tenths in 0..=1800 {
+            let angle = Value::from_num(tenths) / 10;
+            let expected = limits.scale_angle(angle).unwrap().to_num::<f64>();
+            assert_counts_near(scaler.scale(angle), expected);
+        }
+        assert_eq!(
+            scaler.scale(Value::from_num(180.5)),
+            Err(Error::AngleOutOfRange)
+        );
+    }
+
+    #[test]
+    fn angle_scaler_skips_unchanged_counts() {
+        let mut scaler = AngleScaler::new(PwmLimits::standard());
+        let first = scaler.update(Value::from_num(80)).unwrap();
+        assert!(first.is_some());
+        assert_eq!(scaler.update(Value::from_num(80)), Ok(None));
+        assert!(scaler.update(Value::from_num(135)).unwrap().is_some());
+
+        // Setting the same limits keeps the cache, new limits clear it.
+        scaler.set_limits(PwmLimits::standard());
+        assert_eq!(scaler.update(Value::from_num(135)), Ok(None));
+        scaler.set_limits(PwmLimits {
+            zero: Value::from_num(500),
+            one_eighty: Value::from_num(2500),
+        });
+        assert!(scaler.update(Value::from_num(135)).unwrap().is_some());
     }
 
     #[futures_test::test]
-    async fn settings_output_on_connect() {
+    async fn m626_applies_setup_block() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
         let test_future = async move {
-            line_sender.send(GCodeEvent::Connect).await;
+            line_sender.send(line_event("M625")).await;
+            line_sender.send(line_event("0,120,100")).await;
+            line_sender.send(line_event("1, , ,60,4")).await;
+            line_sender.send(line_event("M626")).await;
+            line_sender.send(line_event("M621")).await;
+
+            // Duplicate feeders and extra columns discard the block.
+            line_sender.send(line_event("M625")).await;
+            line_sender.send(line_event("0,90")).await;
+            line_sender.send(line_event("0,80")).await;
+            line_sender.send(line_event("M626")).await;
+            line_sender.send(line_event("M625")).await;
+            line_sender
+                .send(line_event(
+                    "1,1,2,3,4,5,6,7,8,1,10,1,4,1,2,1,2,3,4,1,0,3,0,0,500,0,0,0,0,100,0,90,90,0,0.5,2,0,180,0,0,50,0,0,14",
+                ))
+                .await;
+            line_sender.send(line_event("M626")).await;
             line_sender.send(line_event("M999")).await;
         };
         let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
-        assert_eq!(String::from_utf8_lossy(&output), "saved settings:\nM620 N0 A135 B107.5 C80 F2 U3 V490.2 W980.4 X0 Y0\nM620 N1 A135 B107.5 C80 F2 U3 V490.2 W980.4 X0 Y0\nready\n");
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             ok\n\
+             updated 2 feeders\n\
+             ok\n\
+             M620 N0 A120 B100 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\n\
+             M620 N1 A135 B107.5 C60 F4 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N1 R0 D500 W0 J0 E0 T0.5 C2\nM634 N1 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N1 P0 S100 A0 X90 Y90\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             error:9 invalid argument type N\n\
+             error:28 no setup block in progress\n\
+             ok\n\
+             error:29 too many columns\n\
+             error:28 no setup block in progress\n"
+        );
     }
 
     #[futures_test::test]
-    async fn advance_returns_error_on_high_feedback() {
+    async fn m620_changes_are_only_saved_by_m500() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
+        let test_future = async move {
+            // M501 discards unsaved changes.
+            line_sender.send(line_event("M620 N0 A100")).await;
+            line_sender.send(line_event("M501")).await;
+            line_sender.send(line_event("M621 N0")).await;
 
-        // drive feedback hgih.
-        fake_inputs[0].send(true).await;
+            line_sender.send(line_event("M620 N0 A110")).await;
+            line_sender.send(line_event("M500")).await;
 
-        let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M600 N0 F4")).await;
+            // M502 restores defaults in RAM only.
+            line_sender.send(line_event("M620 N1 A120")).await;
+            line_sender.send(line_event("M502")).await;
+            line_sender.send(line_event("M621 N0")).await;
+            line_sender.send(line_event("M501")).await;
+            line_sender.send(line_event("M621 N0")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let ((_servos, output, config), _) = join(test_harness_future, test_future).await;
 
-        let output = String::from_utf8_lossy(&output);
-        assert_eq!(output, "ok\nerror: feeder not ready\n");
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\n\
+             ok\n\
+             M620 N0 A135 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             ok\n\
+             M620 N0 A135 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\n\
+             ok\n\
+             ok\n\
+             M620 N0 A110 B107.5 C80 F2 U3 V1000 W2000 X0 Y0 R0 E0 Z4 H1 P0 D0 J0 K0 S4 T1 I0 Q3 O0\nM623 N0 R0 D500 W0 J0 E0 T0.5 C2\nM634 N0 U0 F0 H180 I0 O0 Q50 B0 K0\nM635 N0 P0 S100 A0 X90 Y90\n\
+             ok\n"
+        );
+        assert_eq!(config[&0].advanced_angle, Value::from_num(110));
+        assert_eq!(config[&1], FakeConfigStore::default_config());
     }
 
     #[futures_test::test]
-    async fn advance_respects_override_error_arg() {
+    async fn m620_without_index_is_only_saved_by_m500() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
         let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
-
-        // drive feedback hgih.
-        fake_inputs[0].send(true).await;
-
         let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M600 N0 F4 X1")).await;
+            line_sender.send(line_event("M620 A100")).await;
+            line_sender.send(line_event("M621 N1")).await;
+            line_sender.send(line_event("M501")).await;
+            line_sender.send(line_event("M621 N1")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let ((_servos, output, config), _) = join(test_harness_future, test_future).await;
 
         let output = String::from_utf8_lossy(&output);
-        assert_eq!(output, "ok\nok\n");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert!(lines[1].starts_with("M620 N1 A100 "), "{}", lines[1]);
+        assert!(lines[7].starts_with("M620 N1 A135 "), "{}", lines[7]);
+        assert!(config.is_empty());
     }
 
     #[futures_test::test]
-    async fn advance_respects_ignore_feedback_pin_config() {
+    async fn slew_rate_steps_the_lever() {
         let gcode_channel = GCodeEventChannel::<2>::new();
         let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
+        let test_harness_future =
+            run_test_harness_with_motion(gcode_channel.receiver(), &fake_inputs);
         let line_sender = gcode_channel.sender();
-
-        // drive feedback high.
-        fake_inputs[0].send(true).await;
-
         let test_future = async move {
             line_sender.send(line_event("M610 S1")).await;
-            line_sender.send(line_event("M620 N0 X1")).await;
+            line_sender.send(line_event("M620 N0 O275")).await;
+            line_sender.send(line_event("M620 N1 O-1")).await;
             line_sender.send(line_event("M600 N0 F4")).await;
+            line_sender.send(line_event("M600 N1 F4")).await;
             line_sender.send(line_event("M999")).await;
         };
-        let ((_servos, output, _config), _) = join(test_harness_future, test_future).await;
+        let ((servos, output), _) = join(test_harness_future, test_future).await;
 
-        let output = String::from_utf8_lossy(&output);
-        assert_eq!(output, "ok\nok\nok\n");
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "ok\nok\ninvalid: O-1\nerror:9 invalid argument type O\nok\nok\n"
+        );
+        // The first move jumps as the lever's position is unknown.  Retracting
+        // 55° at 275°/s then takes 200ms, one step per tick.
+        assert_eq!(servos[0].first(), Some(&Value::from_num(135)));
+        assert_eq!(servos[0].last(), Some(&Value::from_num(80)));
+        assert_eq!(servos[0].len(), 1 + 200 / MOTION_TICK.as_millis() as usize);
+        assert!(servos[0].windows(2).all(|pair| pair[0] >= pair[1]));
+        // Without a slew rate every move jumps.
+        assert_eq!(servos[1], [135, 80].map(Value::from_num));
     }
 
     #[futures_test::test]
-    async fn feedback_pulse_half_advances_feeder() {
-        let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
-        let line_sender = gcode_channel.sender();
-        let feedback0 = &fake_inputs[0];
-
-        // Start with switch unpressed.
-        feedback0.send(true).await;
-
-        let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
-            // Set to known angles
-            line_sender.send(line_event("M620 N0 A122 C22")).await;
-            // Press switch
+    async fn motion_controller_eases_moves() {
+        let (positions, mut servo) = FakeServo::new();
+        let controller = MotionController::new([&mut servo]);
+        controller.set_speed(0, Some(Value::from_num(90)));
+        let mut motion_servo = controller.servo(0);
 
-            feedback0.send(false).await;
-            Timer::after_micros(250_000).await;
-            // Release switch.
-            feedback0.send(true).await;
+        let test_future = async {
+            // The first move jumps as the servo's position is unknown.
+            motion_servo.set_angle(Value::from_num(0)).unwrap();
+            assert_eq!(motion_servo.motion_remaining(), Duration::from_ticks(0));
 
-            line_sender.send(line_event("M999")).await;
+            motion_servo.set_angle(Value::from_num(90)).unwrap();
+            assert_eq!(motion_servo.motion_remaining(), Duration::from_secs(1));
+            assert!(motion_servo.set_angle(Value::from_num(181)).is_err());
+            Timer::after_millis(1100).await;
+            assert_eq!(motion_servo.motion_remaining(), Duration::from_ticks(0));
         };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        with_mock_time(select(controller.run(), test_future)).await;
 
-        println!("{}", String::from_utf8_lossy(&output));
-        assert_eq!(servos[0], vec![Value::from_num(107.5)]);
-        assert!(servos[1].is_empty());
+        let positions = positions.lock().unwrap();
+        assert_eq!(positions.first(), Some(&Value::from_num(0)));
+        assert_eq!(positions.last(), Some(&Value::from_num(90)));
+        // One update per tick, easing in and out.
+        assert_eq!(positions.len(), 1 + 1000 / MOTION_TICK.as_millis() as usize);
+        assert!(positions.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(positions[2] - positions[1] < positions[26] - positions[25]);
     }
 
     #[futures_test::test]
-    async fn feeder_only_retracts_on_4mm_bondaries() {
-        let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
-        let line_sender = gcode_channel.sender();
-        let feedback0 = &fake_inputs[0];
+    async fn advance_and_retract_settle_separately() {
+        let (_positions, servo) = FakeServo::new();
+        let fake_input = FakeInputChannel::new();
+        let mut feeder = Feeder::new(servo, FakeInput::new(false, &fake_input));
+        let channel = FeederChannel::new();
 
-        // Start with switch unpressed.
-        feedback0.send(true).await;
+        let test_future = async {
+            let mut client = FeederClient::new(&channel);
+            client
+                .set_config(FeederConfig {
+                    advance_settle_time: 200,
+                    retract_settle_time: 50,
+                    ..FakeConfigStore::default_config()
+                })
+                .await
+                .unwrap();
+            client.enable(true).await.unwrap();
 
-        let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
+            let start = Instant::now();
+            client
+                .advance(FeedLength::Millimeters(Value::from_num(4)), false)
+                .await
+                .unwrap();
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(250), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(300), "{elapsed:?}");
+            client.shutdown().await;
+        };
+        with_mock_time(join(feeder.run(&channel), test_future)).await;
+    }
 
-            // Set to known angles, ignore feedback pin, and disable `always_retract`.
-            line_sender
-                .send(line_event("M620 N0 A50 B25 C0 X1 Y0"))
-                .await;
+    #[futures_test::test]
+    async fn feeders_settle_after_profiled_moves() {
+        let (positions, mut servo) = FakeServo::new();
+        let controller = MotionController::new([&mut servo]);
+        let fake_input = FakeInputChannel::new();
+        let mut feeder = Feeder::new(controller.servo(0), FakeInput::new(false, &fake_input));
+        let channel = FeederChannel::new();
 
-            // Feeding by 2mm advance to the half angle and not retract.
-            line_sender.send(line_event("M600 N0 F2")).await;
-            // Feeding by another 4mm should advance to the full angle, retract and advance
-            // to the half angle.
-            line_sender.send(line_event("M600 N0 F4")).await;
-            // Feeding by a final 2mm should advance to the full angle and retract.
-            line_sender.send(line_event("M600 N0 F2")).await;
+        let test_future = async {
+            let mut client = FeederClient::new(&channel);
+            client
+                .set_config(FeederConfig {
+                    slew_rate: Value::from_num(110),
+                    ..FakeConfigStore::default_config()
+                })
+                .await
+                .unwrap();
+            client.enable(true).await.unwrap();
+            client.set_servo_angle(Value::from_num(80)).await.unwrap();
 
-            line_sender.send(line_event("M999")).await;
+            // 80 -> 135 -> 80 takes 500ms each way at 110°/s.
+            let start = Instant::now();
+            client
+                .advance(FeedLength::Millimeters(Value::from_num(4)), false)
+                .await
+                .unwrap();
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(1000), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(1100), "{elapsed:?}");
+            client.shutdown().await;
         };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        with_mock_time(select(
+            controller.run(),
+            join(feeder.run(&channel), test_future),
+        ))
+        .await;
 
-        println!("{}", String::from_utf8_lossy(&output));
-        assert_eq!(
-            servos[0],
-            vec![
-                // F2 half advances.
-                Value::from_num(25),
-                // F4 full advances, retracts, and half advances.
-                Value::from_num(50),
-                Value::from_num(0),
-                Value::from_num(25),
-                // F2 full advances and retracts.
-                Value::from_num(50),
-                Value::from_num(0)
-            ]
-        );
-        assert!(servos[1].is_empty());
+        let positions = positions.lock().unwrap();
+        assert!(positions.contains(&Value::from_num(135)));
+        assert_eq!(positions.last(), Some(&Value::from_num(80)));
     }
 
     #[futures_test::test]
-    async fn always_retract_feeder_retracts_on_every_advance() {
-        let gcode_channel = GCodeEventChannel::<2>::new();
-        let fake_inputs = [FakeInputChannel::new(), FakeInputChannel::new()];
-        let test_harness_future = run_test_harness(gcode_channel.receiver(), &fake_inputs);
-        let line_sender = gcode_channel.sender();
-        let feedback0 = &fake_inputs[0];
-
-        // Start with switch unpressed.
-        feedback0.send(true).await;
+    async fn enabling_eases_lever_to_retract() {
+        let (positions, mut servo) = FakeServo::new();
+        let controller = MotionController::new([&mut servo]);
+        let fake_input = FakeInputChannel::new();
+        let mut feeder = Feeder::new(controller.servo(0), FakeInput::new(false, &fake_input));
+        let channel = FeederChannel::new();
 
-        let test_future = async move {
-            line_sender.send(line_event("M610 S1")).await;
+        let test_future = async {
+            let mut client = FeederClient::new(&channel);
+            client
+                .set_config(FeederConfig {
+                    enable_home_rate: Value::from_num(55),
+                    ..FakeConfigStore::default_config()
+                })
+                .await
+                .unwrap();
+            // The servo's position is unknown so it jumps to retract.
+            client.enable(true).await.unwrap();
+            assert_eq!(
+                client.get_status().await.unwrap().angle,
+                Some(Value::from_num(80))
+            );
 
-            // Set to known angles, ignore feedback pin, and enable `always_retract`.
-            line_sender
-                .send(line_event("M620 N0 A50 B25 C0 X1 Y1"))
-                .await;
+            client.set_servo_angle(Value::from_num(135)).await.unwrap();
+            client.enable(false).await.unwrap();
 
-            // Feeding by 2mm advance to the half angle and retract.
-            line_sender.send(line_event("M600 N0 F2")).await;
-            // Feeding by 4mm advance to the full angle and retract.
-            line_sender.send(line_event("M600 N0 F4")).await;
-            // Feeding by 6mm advance to the full angle, retract, advance to the half anfle, and retract.
-            line_sender.send(line_event("M600 N0 F6")).await;
+            // 135 -> 80 takes a second at 55°/s.
+            let start = Instant::now();
+            client.enable(true).await.unwrap();
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(1000), "{elapsed:?}");
+            assert!(elapsed < Duration::from_millis(1100), "{elapsed:?}");
 
-            line_sender.send(line_event("M999")).await;
+            // Already enabled, so the lever stays put.
+            client.set_servo_angle(Value::from_num(135)).await.unwrap();
+            client.enable(true).await.unwrap();
+            assert_eq!(
+                client.get_status().await.unwrap().angle,
+                Some(Value::from_num(135))
+            );
+            client.shutdown().await;
         };
-        let ((servos, output, _config), _) = join(test_harness_future, test_future).await;
+        with_mock_time(select(
+            controller.run(),
+            join(feeder.run(&channel), test_future),
+        ))
+        .await;
 
-        println!("{}", String::from_utf8_lossy(&output));
-        assert_eq!(
-            servos[0],
-            vec![
-                // F2 half advances and retracts.
-                Value::from_num(25),
-                Value::from_num(0),
-                // F4 full advances and retracts.
-                Value::from_num(50),
-                Value::from_num(0),
-                // F3 full advances, retracts, half advances, and retracts.
-                Value::from_num(50),
-                Value::from_num(0),
-                Value::from_num(25),
-                Value::from_num(0),
-            ]
-        );
-        assert!(servos[1].is_empty());
+        let positions = positions.lock().unwrap();
+        assert_eq!(positions[..2], [80, 135].map(Value::from_num));
+        assert!(positions.len() > 10);
+        assert_eq!(positions.last(), Some(&Value::from_num(135)));
     }
 }
@@ -0,0 +1,122 @@
+use core::fmt::Write as _;
+
+use heapless::String;
+
+use crate::{Error, Result};
+
+// Validates Marlin style `N<line> <command>*<checksum>` framing ahead of
+// parsing so hosts can stream reliably over noisy links.  Lines without a
+// line number or checksum are passed through unchanged.
+pub struct LineChecker {
+    next_line: u32,
+}
+
+impl LineChecker {
+    pub const fn new() -> Self {
+        Self { next_line: 1 }
+    }
+
+    // Line number the host should resend from after an error.
+    pub fn resend_line(&self) -> u32 {
+        self.next_line
+    }
+
+    // Returns the command with any line number and checksum removed.
+    pub fn check<'a>(&mut self, line: &'a str) -> Result<&'a str> {
+        let (body, checksum) = match line.rsplit_once('*') {
+            Some((body, checksum)) => (body, Some(checksum)),
+            None => (line, None),
+        };
+
+        let Some(numbered) = body.strip_prefix('N') else {
+            if checksum.is_some() {
+                return Err(Error::MissingLineNumber);
+            }
+            if let Some(Some(number)) = m110_line(line) {
+                self.next_line = number.wrapping_add(1);
+            }
+            return Ok(line);
+        };
+
+        let checksum: u8 = checksum
+            .ok_or(Error::MissingChecksum)?
+            .trim()
+            .parse()
+            .map_err(|_| Error::ChecksumMismatch)?;
+        if body.bytes().fold(0, |acc, b| acc ^ b) != checksum {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        let digits = numbered
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(numbered.len());
+        let number: u32 = numbered[..digits]
+            .parse()
+            .map_err(|_| Error::MissingLineNumber)?;
+        let command = numbered[digits..].trim();
+
+        // M110 sets the current line number, either to its own N argument or
+        // the line's.
+        if let Some(argument) = m110_line(command) {
+            self.next_line = argument.unwrap_or(number).wrapping_add(1);
+            return Ok(command);
+        }
+
+        if number != self.next_line {
+            return Err(Error::LineNumberMismatch(self.next_line));
+        }
+        self.next_line = number.wrapping_add(1);
+        Ok(command)
+    }
+}
+
+impl Default for LineChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Appends `*<checksum>` to each response line, using the same checksum as
+// inbound lines, so hosts on noisy links can detect corrupted output.  Fed the
+// response stream a byte at a time.
+pub struct ResponseChecksum {
+    checksum: u8,
+}
+
+impl ResponseChecksum {
+    pub const fn new() -> Self {
+        Self { checksum: 0 }
+    }
+
+    // Returns the `*<checksum>` to write ahead of `b` if it ends a line.
+    pub fn update(&mut self, b: u8) -> Option<String<4>> {
+        if b != b'\n' {
+            self.checksum ^= b;
+            return None;
+        }
+
+        let mut suffix = String::new();
+        write!(suffix, "*{}", self.checksum).ok();
+        self.checksum = 0;
+        Some(suffix)
+    }
+}
+
+impl Default for ResponseChecksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// If `command` is an M110, returns its N argument if it has one.
+fn m110_line(command: &str) -> Option<Option<u32>> {
+    let rest = command.strip_prefix("M110")?;
+    if rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(
+        rest.trim_start()
+            .strip_prefix('N')
+            .and_then(|number| number.trim().parse().ok()),
+    )
+}
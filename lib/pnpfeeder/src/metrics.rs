@@ -0,0 +1,53 @@
+use core::cell::Cell;
+
+// A counter which can be shared between tasks on the same executor.  The
+// RP2040's Cortex-M0+ has no atomic read-modify-write so a `Cell` is used.
+#[derive(Default)]
+pub struct Counter(Cell<u32>);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(Cell::new(0))
+    }
+
+    pub fn add(&self, count: u32) {
+        self.0.set(self.0.get().wrapping_add(count));
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.get()
+    }
+}
+
+// Statistics updated by the transport (i.e. USB CDC) which feeds a `GCodeHandler`.
+#[derive(Default)]
+pub struct TransportStats {
+    pub rx_bytes: Counter,
+    pub tx_bytes: Counter,
+    pub lines: Counter,
+    pub parse_errors: Counter,
+    pub overflows: Counter,
+    pub write_timeouts: Counter,
+    pub resends: Counter,
+    // Times the host was told to wait because the event queue was full.
+    pub queue_waits: Counter,
+}
+
+impl TransportStats {
+    pub const fn new() -> Self {
+        Self {
+            rx_bytes: Counter::new(),
+            tx_bytes: Counter::new(),
+            lines: Counter::new(),
+            parse_errors: Counter::new(),
+            overflows: Counter::new(),
+            write_timeouts: Counter::new(),
+            resends: Counter::new(),
+            queue_waits: Counter::new(),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::NoopRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{PwmLimits, Result, Servo, Value, Value64};
+
+// Interval at which the motion task updates moving servos.  Matches the 20ms
+// period of a hobby servo's PWM; updating faster has no effect.
+pub const MOTION_TICK: Duration = Duration::from_millis(20);
+
+// A single eased move of one servo.
+struct Move {
+    from: Value,
+    to: Value,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Move {
+    fn end(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    fn angle_at(&self, now: Instant) -> Value {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+
+        // Smoothstep easing so the lever accelerates and decelerates gently.
+        let t =
+            Value64::from_num(elapsed.as_millis()) / Value64::from_num(self.duration.as_millis());
+        let eased = t * t * (Value64::from_num(3) - Value64::from_num(2) * t);
+        let delta = Value64::from(self.to) - Value64::from(self.from);
+        Value::from_num(Value64::from(self.from) + delta * eased)
+    }
+}
+
+struct Axis<'a> {
+    servo: &'a mut dyn Servo,
+    // Average speed in degrees per second.  `None` moves instantly.
+    speed: Option<Value>,
+    // Last angle written to the servo, if known.
+    position: Option<Value>,
+    active: Option<Move>,
+}
+
+impl<'a> Axis<'a> {
+    fn position_at(&self, now: Instant) -> Option<Value> {
+        match &self.active {
+            Some(active) => Some(active.angle_at(now)),
+            None => self.position,
+        }
+    }
+
+    fn start_move(&mut self, angle: Value, now: Instant) -> Result<()> {
+        // Validate the target up front as errors can't be reported once the
+        // motion task is driving the servo.
+        self.servo.get_pwm_limits().scale_angle(angle)?;
+
+        let from = self.position_at(now);
+        let speed = self.speed.filter(|speed| *speed > 0);
+        let (Some(from), Some(speed)) = (from, speed) else {
+            // Without a known start position or speed, jump straight there.
+            self.active = None;
+            self.servo.set_angle(angle)?;
+            self.position = Some(angle);
+            return Ok(());
+        };
+
+        let delta = Value64::from((angle - from).abs());
+        let millis = (delta * 1000 / Value64::from(speed)).to_num::<u64>();
+        self.active = Some(Move {
+            from,
+            to: angle,
+            start: now,
+            duration: Duration::from_millis(millis),
+        });
+        Ok(())
+    }
+
+    fn tick(&mut self, now: Instant) {
+        let Some(active) = &self.active else {
+            return;
+        };
+        let angle = active.angle_at(now);
+        if now >= active.end() {
+            self.active = None;
+        }
+        // The target was validated in `start_move`.
+        let _ = self.servo.set_angle(angle);
+        self.position = Some(angle);
+    }
+}
+
+// Drives eased moves for a set of servos from a single task ticking at a
+// fixed rate, rather than each feeder running its own motion loop.  Feeders
+// are given a `MotionServo` handle in place of their servo.
+pub struct MotionController<'a, const M: usize> {
+    axes: Mutex<NoopRawMutex, RefCell<[Axis<'a>; M]>>,
+    wake: Signal<NoopRawMutex, ()>,
+}
+
+impl<'a, const M: usize> MotionController<'a, M> {
+    pub fn new(servos: [&'a mut dyn Servo; M]) -> Self {
+        let axes = servos.map(|servo| Axis {
+            servo,
+            speed: None,
+            position: None,
+            active: None,
+        });
+        Self {
+            axes: Mutex::new(RefCell::new(axes)),
+            wake: Signal::new(),
+        }
+    }
+
+    // Returns a `Servo` for the controller's `index`th servo.
+    pub fn servo(&self, index: usize) -> MotionServo<'_, 'a, M> {
+        assert!(index < M);
+        MotionServo {
+            controller: self,
+            index,
+        }
+    }
+
+    // Sets the average speed, in degrees per second, of subsequent moves.
+    // `None` moves instantly.
+    pub fn set_speed(&self, index: usize, speed: Option<Value>) {
+        self.with_axis(index, |axis| axis.speed = speed);
+    }
+
+    fn with_axis<R>(&self, index: usize, f: impl FnOnce(&mut Axis<'a>) -> R) -> R {
+        self.axes.lock(|axes| f(&mut axes.borrow_mut()[index]))
+    }
+
+    fn is_moving(&self) -> bool {
+        self.axes
+            .lock(|axes| axes.borrow().iter().any(|axis| axis.active.is_some()))
+    }
+
+    pub async fn run(&self) -> ! {
+        loop {
+            // Sleep until a move starts so idle servos cost nothing.
+            if !self.is_moving() {
+                self.wake.wait().await;
+            }
+
+            let mut next_tick = Instant::now();
+            while self.is_moving() {
+                next_tick += MOTION_TICK;
+                Timer::at(next_tick).await;
+                let now = Instant::now();
+                self.axes.lock(|axes| {
+                    for axis in axes.borrow_mut().iter_mut() {
+                        axis.tick(now);
+                    }
+                });
+            }
+        }
+    }
+}
+
+pub struct MotionServo<'m, 'a, const M: usize> {
+    controller: &'m MotionController<'a, M>,
+    index: usize,
+}
+
+impl<'m, 'a, const M: usize> Servo for MotionServo<'m, 'a, M> {
+    fn set_angle(&mut self, angle: Value) -> Result<()> {
+        let now = Instant::now();
+        self.controller
+            .with_axis(self.index, |axis| axis.start_move(angle, now))?;
+        self.controller.wake.signal(());
+        Ok(())
+    }
+
+    fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()> {
+        self.controller
+            .with_axis(self.index, |axis| axis.servo.set_pwm_limits(limits))
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        self.controller
+            .with_axis(self.index, |axis| axis.servo.get_pwm_limits())
+    }
+
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()> {
+        self.controller.with_axis(self.index, |axis| {
+            // The pulse overrides any move in progress and leaves the angle
+            // unknown, so the next move jumps rather than easing.
+            axis.servo.set_pulse_width(micros)?;
+            axis.active = None;
+            axis.position = None;
+            Ok(())
+        })
+    }
+
+    fn disable_output(&mut self) -> Result<()> {
+        self.controller.with_axis(self.index, |axis| {
+            // The lever stays where it was so the next move eases from there.
+            axis.servo.disable_output()?;
+            axis.active = None;
+            Ok(())
+        })
+    }
+
+    fn set_speed(&mut self, speed: Option<Value>) {
+        self.controller.set_speed(self.index, speed);
+    }
+
+    fn set_frame_rate(&mut self, hz: u32) -> Result<()> {
+        self.controller
+            .with_axis(self.index, |axis| axis.servo.set_frame_rate(hz))
+    }
+
+    fn motion_remaining(&self) -> Duration {
+        let now = Instant::now();
+        self.controller
+            .with_axis(self.index, |axis| match &axis.active {
+                Some(active) => active.end().saturating_duration_since(now),
+                None => Duration::from_ticks(0),
+            })
+    }
+}
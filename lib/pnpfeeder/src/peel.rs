@@ -0,0 +1,19 @@
+use crate::{Result, Value};
+
+// Takes up the cover tape peeled off the parts as the tape advances, for
+// example a small DC motor or a second servo.
+pub trait Peeler {
+    // Runs the peeler at `strength`, from 0 for stopped to 1 for full power.
+    // Peelers which can only be switched on and off run at any strength above
+    // zero.
+    fn set_strength(&mut self, strength: Value) -> Result<()>;
+}
+
+// Stands in for the peeler of feeders without one.
+pub struct NoPeeler;
+
+impl Peeler for NoPeeler {
+    fn set_strength(&mut self, _strength: Value) -> Result<()> {
+        Ok(())
+    }
+}
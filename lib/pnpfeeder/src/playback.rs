@@ -0,0 +1,92 @@
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::Input;
+
+// A single transition of a recorded input trace.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Edge {
+    // Time of the edge relative to the start of the trace.
+    pub at: Duration,
+    pub state: bool,
+}
+
+impl Edge {
+    pub const fn new(at_ms: u64, state: bool) -> Self {
+        Self {
+            at: Duration::from_millis(at_ms),
+            state,
+        }
+    }
+}
+
+// An `Input` which replays a recorded, timestamped edge sequence in real
+// time.  Used to validate feedback recognition against traces captured from
+// real switches.  Edges must be sorted by time.
+pub struct PlaybackInput<'a> {
+    edges: &'a [Edge],
+    start: Instant,
+    next_edge: usize,
+    state: bool,
+}
+
+impl<'a> PlaybackInput<'a> {
+    // Playback starts immediately with `initial_state`.
+    pub fn new(initial_state: bool, edges: &'a [Edge]) -> Self {
+        Self {
+            edges,
+            start: Instant::now(),
+            next_edge: 0,
+            state: initial_state,
+        }
+    }
+
+    // Returns true once every edge in the trace has been played back.
+    pub fn is_finished(&self) -> bool {
+        self.next_edge >= self.edges.len()
+    }
+
+    // Apply all edges whose time has passed.
+    fn catch_up(&mut self) {
+        let elapsed = self.start.elapsed();
+        while let Some(edge) = self.edges.get(self.next_edge) {
+            if edge.at > elapsed {
+                break;
+            }
+            self.state = edge.state;
+            self.next_edge += 1;
+        }
+    }
+
+    async fn wait_for_state(&mut self, state: bool) {
+        self.catch_up();
+        while self.state != state {
+            self.wait_for_state_change().await;
+        }
+    }
+}
+
+impl<'a> Input for PlaybackInput<'a> {
+    async fn wait_for_high(&mut self) {
+        self.wait_for_state(true).await
+    }
+
+    async fn wait_for_low(&mut self) {
+        self.wait_for_state(false).await
+    }
+
+    async fn wait_for_state_change(&mut self) {
+        self.catch_up();
+        let Some(edge) = self.edges.get(self.next_edge) else {
+            // The trace is over so the input never changes again.
+            return core::future::pending().await;
+        };
+        Timer::at(self.start + edge.at).await;
+        self.state = edge.state;
+        self.next_edge += 1;
+    }
+
+    async fn get_state(&mut self) -> bool {
+        self.catch_up();
+        self.state
+    }
+}
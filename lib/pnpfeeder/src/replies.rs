@@ -0,0 +1,99 @@
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::{
+    blocking_mutex::{raw::RawMutex, Mutex},
+    waitqueue::MultiWakerRegistration,
+};
+use heapless::Vec;
+
+// Identifies a request so its reply can be matched to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Tag(u32);
+
+// Replies to tagged requests, each held until the client which sent the
+// request collects it.  Several clients can have requests outstanding with
+// one server without a reply reaching the wrong client.  The server waits
+// before replying when `N` replies haven't been collected.
+pub struct Replies<R: RawMutex, T, const N: usize> {
+    state: Mutex<R, RefCell<RepliesState<T, N>>>,
+}
+
+struct RepliesState<T, const N: usize> {
+    next_tag: u32,
+    ready: Vec<(Tag, T), N>,
+    waiters: MultiWakerRegistration<8>,
+}
+
+impl<R: RawMutex, T, const N: usize> Replies<R, T, N> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(RepliesState {
+                next_tag: 0,
+                ready: Vec::new(),
+                waiters: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    // Tags a new request.  Tags are reused only after 2^32 requests, long
+    // after any reply to the earlier request was collected.
+    pub fn next_tag(&self) -> Tag {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let tag = Tag(state.next_tag);
+            state.next_tag = state.next_tag.wrapping_add(1);
+            tag
+        })
+    }
+
+    pub async fn post(&self, tag: Tag, reply: T) {
+        let mut reply = Some(reply);
+        poll_fn(|cx| {
+            self.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                if state.ready.is_full() {
+                    state.waiters.register(cx.waker());
+                    return Poll::Pending;
+                }
+                if let Some(reply) = reply.take() {
+                    // There is room since the list isn't full.
+                    let _ = state.ready.push((tag, reply));
+                }
+                state.waiters.wake();
+                Poll::Ready(())
+            })
+        })
+        .await
+    }
+
+    // Waits for the reply to the request tagged `tag`.  Safe to cancel and
+    // call again.
+    pub async fn take(&self, tag: Tag) -> T {
+        poll_fn(|cx| {
+            self.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                match state.ready.iter().position(|(ready, _)| *ready == tag) {
+                    Some(index) => {
+                        let (_, reply) = state.ready.swap_remove(index);
+                        // A full list may have kept the server waiting.
+                        state.waiters.wake();
+                        Poll::Ready(reply)
+                    }
+                    None => {
+                        state.waiters.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl<R: RawMutex, T, const N: usize> Default for Replies<R, T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,115 @@
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use embassy_sync::{
+    blocking_mutex::{
+        raw::{NoopRawMutex, RawMutex},
+        Mutex,
+    },
+    waitqueue::MultiWakerRegistration,
+};
+
+// Limits how many feeders move their levers at once.  A servo draws a burst
+// of current as it starts moving and several starting together can brown out
+// the supply.  A feeder holds a permit from commanding its lever until the
+// lever has settled so moves are staggered rather than simultaneous.
+//
+// `R` guards the scheduler, so feeders running on one core and a handler on
+// the other need a mutex which is safe across cores.
+pub struct MotionScheduler<R: RawMutex = NoopRawMutex> {
+    state: Mutex<R, RefCell<SchedulerState>>,
+}
+
+struct SchedulerState {
+    // Zero for no limit.
+    limit: u32,
+    moving: u32,
+    waiters: MultiWakerRegistration<8>,
+}
+
+impl MotionScheduler {
+    pub fn new(limit: u32) -> Self {
+        Self::with_raw_mutex(limit)
+    }
+}
+
+impl<R: RawMutex> MotionScheduler<R> {
+    pub fn with_raw_mutex(limit: u32) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(SchedulerState {
+                limit,
+                moving: 0,
+                waiters: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    // Feeders which are already moving finish their moves; the new limit
+    // applies to the permits handed out afterwards.
+    pub fn set_limit(&self, limit: u32) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.limit = limit;
+            state.waiters.wake();
+        });
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.state.lock(|state| state.borrow().limit)
+    }
+
+    // Waits until fewer than the limit of feeders are moving.  The returned
+    // permit is given back when dropped.
+    pub async fn acquire(&self) -> MotionPermit<'_> {
+        poll_fn(|cx| self.poll_acquire(cx)).await;
+        MotionPermit { scheduler: self }
+    }
+}
+
+// Lets a feeder hold a scheduler whichever mutex guards it.
+pub(crate) trait Permits {
+    fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<()>;
+    fn release(&self);
+}
+
+impl<R: RawMutex> Permits for MotionScheduler<R> {
+    fn poll_acquire(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if state.limit == 0 || state.moving < state.limit {
+                state.moving += 1;
+                Poll::Ready(())
+            } else {
+                state.waiters.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    fn release(&self) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.moving -= 1;
+            state.waiters.wake();
+        });
+    }
+}
+
+pub struct MotionPermit<'a> {
+    scheduler: &'a dyn Permits,
+}
+
+impl<'a> MotionPermit<'a> {
+    // Waits for a permit from a scheduler held without its mutex type.
+    pub(crate) async fn acquire(scheduler: &'a dyn Permits) -> MotionPermit<'a> {
+        poll_fn(|cx| scheduler.poll_acquire(cx)).await;
+        MotionPermit { scheduler }
+    }
+}
+
+impl Drop for MotionPermit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
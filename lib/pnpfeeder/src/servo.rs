@@ -1,21 +1,119 @@
 use crate::{Error, Result, Value, Value64};
-use fixed::traits::LosslessTryFrom;
+use embassy_time::Duration;
+use fixed::{traits::LosslessTryFrom, types::extra::U32, FixedI64};
 
-#[derive(Clone)]
+// Pulses a second hobby servos expect.  Digital servos often hold position
+// better at up to 333Hz.
+pub const STANDARD_FRAME_RATE: u32 = 50;
+
+// Pulse widths, in microseconds, at 0° and 180°.  Drivers convert them to
+// whatever counts their hardware uses.
+#[derive(Clone, Debug, PartialEq)]
 pub struct PwmLimits {
     pub zero: Value,
     pub one_eighty: Value,
 }
 
 impl PwmLimits {
+    // Typical hobby servo limits: a 1ms pulse at 0° and a 2ms pulse at 180°.
+    pub fn standard() -> Self {
+        Self {
+            zero: Value::from_num(1000),
+            one_eighty: Value::from_num(2000),
+        }
+    }
+
+    // Checks that hardware with a period of `period_micros` can output every
+    // pulse width between the limits.
+    pub fn check_range(&self, period_micros: u32) -> Result<()> {
+        let valid = Value64::from_num(0)..=Value64::from_num(period_micros);
+        if !valid.contains(&Value64::from(self.zero))
+            || !valid.contains(&Value64::from(self.one_eighty))
+        {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        Ok(())
+    }
+
     pub fn scale_angle(&self, angle: Value) -> Result<Value> {
         if !(0.0..=180.0).contains(&angle) {
             return Err(Error::AngleOutOfRange);
         }
         let angle = Value64::from(angle);
         let range = Value64::from(self.one_eighty - self.zero);
-        let counts = Value64::from(self.zero) + (range * angle / Value64::from_num(180.0));
-        Value::lossless_try_from(counts).ok_or(Error::FixedPointError)
+        let micros = Value64::from(self.zero) + (range * angle / Value64::from_num(180.0));
+        Value::lossless_try_from(micros).ok_or(Error::FixedPointError)
+    }
+
+    // The inverse of `scale_angle`: the angle which outputs a pulse of
+    // `micros`.
+    pub fn pulse_width_to_angle(&self, micros: Value) -> Result<Value> {
+        let range = Value64::from(self.one_eighty - self.zero);
+        if range == 0 {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        let angle = Value64::from(micros - self.zero) * Value64::from_num(180.0) / range;
+        if !(0.0..=180.0).contains(&angle) {
+            return Err(Error::AngleOutOfRange);
+        }
+        Value::lossless_try_from(angle).ok_or(Error::FixedPointError)
+    }
+}
+
+// Converts angles to pulse widths for a set of `PwmLimits`.  The scale factor is
+// computed once when the limits change instead of dividing on every angle, and
+// the last output is remembered so drivers can skip redundant hardware
+// updates.
+pub struct AngleScaler {
+    limits: PwmLimits,
+    micros_per_degree: FixedI64<U32>,
+    last_micros: Option<Value>,
+}
+
+impl AngleScaler {
+    pub fn new(limits: PwmLimits) -> Self {
+        let range = FixedI64::<U32>::from_num(limits.one_eighty - limits.zero);
+        Self {
+            micros_per_degree: range / 180,
+            limits,
+            last_micros: None,
+        }
+    }
+
+    pub fn limits(&self) -> &PwmLimits {
+        &self.limits
+    }
+
+    // Forgets the last output so the next update is always written.  Used
+    // after the output is set without going through the scaler.
+    pub fn invalidate(&mut self) {
+        self.last_micros = None;
+    }
+
+    pub fn set_limits(&mut self, limits: PwmLimits) {
+        if limits != self.limits {
+            *self = Self::new(limits);
+        }
+    }
+
+    pub fn scale(&self, angle: Value) -> Result<Value> {
+        if !(0.0..=180.0).contains(&angle) {
+            return Err(Error::AngleOutOfRange);
+        }
+        let micros = FixedI64::<U32>::from_num(self.limits.zero)
+            + self.micros_per_degree * FixedI64::<U32>::from_num(angle);
+        Value::checked_from_num(micros).ok_or(Error::FixedPointError)
+    }
+
+    // Returns the pulse width for `angle`, or `None` if it is unchanged since
+    // the last update.
+    pub fn update(&mut self, angle: Value) -> Result<Option<Value>> {
+        let micros = self.scale(angle)?;
+        if self.last_micros == Some(micros) {
+            return Ok(None);
+        }
+        self.last_micros = Some(micros);
+        Ok(Some(micros))
     }
 }
 
@@ -23,4 +121,115 @@ pub trait Servo {
     fn set_angle(&mut self, angle: Value) -> Result<()>;
     fn set_pwm_limits(&mut self, limits: PwmLimits) -> Result<()>;
     fn get_pwm_limits(&self) -> PwmLimits;
+    // Outputs a pulse of `micros` microseconds regardless of the limits.  Used
+    // for calibration.
+    fn set_pulse_width(&mut self, micros: Value) -> Result<()>;
+
+    // Stops driving the servo, so it goes limp and stops buzzing, until the
+    // next angle or pulse width is set.  By default a zero width pulse is
+    // output, which hobby servos treat the same as no signal.
+    fn disable_output(&mut self) -> Result<()> {
+        self.set_pulse_width(Value::from_num(0))
+    }
+
+    // Time until the servo reaches the last commanded angle.  Servos which
+    // move instantly, as far as the driver can tell, don't need to override
+    // this.
+    fn motion_remaining(&self) -> Duration {
+        Duration::from_ticks(0)
+    }
+
+    // Sets the average speed, in degrees per second, of subsequent moves.
+    // `None` moves instantly.  Servos which can't slew ignore it.
+    fn set_speed(&mut self, _speed: Option<Value>) {}
+
+    // Sets how many pulses a second are output.  Limits keep the same pulse
+    // widths, but are rejected with `Error::PwmValueOutOfRange` if they no
+    // longer fit in the shorter period.  Servos with a fixed rate only accept
+    // `STANDARD_FRAME_RATE`.
+    fn set_frame_rate(&mut self, hz: u32) -> Result<()> {
+        if hz != STANDARD_FRAME_RATE {
+            return Err(Error::PwmValueOutOfRange);
+        }
+        Ok(())
+    }
+}
+
+// Stands in for the secondary servo of feeders with only a lever servo.
+pub struct NoServo;
+
+impl Servo for NoServo {
+    fn set_angle(&mut self, _angle: Value) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_pwm_limits(&mut self, _limits: PwmLimits) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_pwm_limits(&self) -> PwmLimits {
+        PwmLimits {
+            zero: Value::from_num(0),
+            one_eighty: Value::from_num(0),
+        }
+    }
+
+    fn set_pulse_width(&mut self, _micros: Value) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_frame_rate(&mut self, _hz: u32) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Conformance checks for `Servo` implementations.  Panics if `servo` does not
+// behave as `Feeder` expects.  `max_micros` is the longest pulse the
+// implementation can output.  The servo is left with its original limits.
+pub fn check_servo_conformance<S: Servo>(servo: &mut S, max_micros: Value) {
+    let original = servo.get_pwm_limits();
+
+    // Limits within range are accepted and read back unchanged.
+    let limits = PwmLimits {
+        zero: max_micros / 4,
+        one_eighty: max_micros / 2,
+    };
+    assert!(servo.set_pwm_limits(limits.clone()).is_ok());
+    let read_back = servo.get_pwm_limits();
+    assert!(read_back.zero == limits.zero && read_back.one_eighty == limits.one_eighty);
+
+    // Limits beyond what the hardware can output are rejected and leave the
+    // current limits in place.
+    let too_large = max_micros + Value::DELTA;
+    for invalid in [
+        PwmLimits {
+            zero: too_large,
+            one_eighty: limits.one_eighty,
+        },
+        PwmLimits {
+            zero: limits.zero,
+            one_eighty: too_large,
+        },
+    ] {
+        assert!(matches!(
+            servo.set_pwm_limits(invalid),
+            Err(Error::PwmValueOutOfRange)
+        ));
+        assert!(servo.get_pwm_limits().zero == limits.zero);
+        assert!(servo.get_pwm_limits().one_eighty == limits.one_eighty);
+    }
+
+    // Angles are accepted over the full range, including the boundaries and
+    // fractional angles, and rejected outside of it.
+    for angle in [0.0, 0.5, 45.25, 90.0, 179.5, 180.0] {
+        assert!(servo.set_angle(Value::from_num(angle)).is_ok());
+    }
+    for angle in [-0.5, -180.0, 180.5, 360.0] {
+        assert!(matches!(
+            servo.set_angle(Value::from_num(angle)),
+            Err(Error::AngleOutOfRange)
+        ));
+    }
+
+    assert!(servo.set_pwm_limits(original).is_ok());
 }
@@ -0,0 +1,66 @@
+use embassy_time::{Duration, Instant};
+
+use heapless::Vec;
+
+use crate::{Error, Timestamp, MAX_FEEDERS};
+
+// Per-feeder results of a soak test.
+#[derive(Clone, Debug, Default)]
+pub struct SoakStats {
+    pub feeds: u32,
+    pub errors: u32,
+    pub last_error: Option<Error>,
+}
+
+// An unattended soak test started by M680.  Feeds are spread over the enabled
+// feeders in an order picked by a seeded generator so a failing run can be
+// repeated.
+pub struct Soak {
+    pub started_at: Timestamp,
+    pub start: Instant,
+    pub end: Instant,
+    pub interval: Duration,
+    pub next_feed: Instant,
+    pub stats: Vec<SoakStats, MAX_FEEDERS>,
+    rng: u32,
+}
+
+impl Soak {
+    // `feeders` is how many feeders the board has.
+    pub fn new(
+        started_at: Timestamp,
+        duration: Duration,
+        interval: Duration,
+        seed: u32,
+        feeders: usize,
+    ) -> Self {
+        let start = Instant::now();
+        Self {
+            started_at,
+            start,
+            end: start + duration,
+            interval,
+            next_feed: start,
+            stats: (0..feeders).map(|_| SoakStats::default()).collect(),
+            // Xorshift never leaves zero.
+            rng: seed.max(1),
+        }
+    }
+
+    // Picks one of `count` choices.
+    pub fn choose(&mut self, count: usize) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as usize % count
+    }
+
+    pub fn record(&mut self, index: usize, result: &Result<(), Error>) {
+        let stats = &mut self.stats[index];
+        stats.feeds = stats.feeds.wrapping_add(1);
+        if let Err(e) = result {
+            stats.errors = stats.errors.wrapping_add(1);
+            stats.last_error = Some(e.clone());
+        }
+    }
+}
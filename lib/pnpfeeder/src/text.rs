@@ -0,0 +1,21 @@
+use heapless::String;
+
+// Copies `text` into a fixed size buffer as printable ASCII so it can be
+// stored and echoed back without producing invalid output lines.  Other
+// characters, including each multi-byte character, become a single `?` and
+// `"` becomes `'` so the result can always be quoted.  Text which doesn't fit
+// is truncated, which can't split a character since the result is ASCII.
+pub fn sanitize<const N: usize>(text: &str) -> String<N> {
+    let mut sanitized = String::new();
+    for c in text.chars() {
+        let c = match c {
+            '"' => '\'',
+            ' '..='~' => c,
+            _ => '?',
+        };
+        if sanitized.push(c).is_err() {
+            break;
+        }
+    }
+    sanitized
+}
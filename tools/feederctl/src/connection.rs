@@ -0,0 +1,292 @@
+use std::{
+    fmt::Display,
+    io::{self, BufRead, BufReader, Read, Write},
+    time::{Duration, Instant},
+};
+
+use pnpfeeder::GCodeEvent;
+use serialport::SerialPort;
+
+// The board reports `busy: processing` every 2s while a command runs so a
+// reply which takes much longer than that isn't coming.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long to poll the port before giving up on a line.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serial(serialport::Error),
+    // An `error:` reply from the board.
+    Board(String),
+    // A line which isn't a G-code command.
+    Invalid(String),
+    Timeout,
+    Usage(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "i/o error: {e}"),
+            Self::Serial(e) => write!(f, "serial error: {e}"),
+            Self::Board(reply) => write!(f, "board replied {reply}"),
+            Self::Invalid(line) => write!(f, "not a G-code command: {line}"),
+            Self::Timeout => write!(f, "timed out waiting for a reply"),
+            Self::Usage(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(e: serialport::Error) -> Self {
+        Self::Serial(e)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+// What a connection talks over; a serial port outside of tests.
+pub trait Port: Read + Write {}
+
+impl<T: Read + Write> Port for T {}
+
+// A G-code session with a board over its USB serial port.
+pub struct Connection {
+    port: BufReader<Box<dyn Port>>,
+    // Bytes of a line which was still arriving when a read timed out.
+    partial: Vec<u8>,
+}
+
+impl Connection {
+    // Opens `path` and waits for the settings the board reports when a host
+    // connects.
+    pub fn open(path: &str) -> Result<(Self, Vec<String>)> {
+        let mut port = serialport::new(path, 115_200)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        // The board only treats the port as connected once DTR is raised.
+        port.write_data_terminal_ready(true)?;
+        let mut connection = Self::new(Box::new(port));
+        let settings = connection.read_settings()?;
+        Ok((connection, settings))
+    }
+
+    fn new(port: Box<dyn Port>) -> Self {
+        Self {
+            port: BufReader::new(port),
+            partial: Vec::new(),
+        }
+    }
+
+    // Returns the settings reported ahead of `ready`.
+    fn read_settings(&mut self) -> Result<Vec<String>> {
+        let mut settings = Vec::new();
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        while Instant::now() < deadline {
+            match self.read_line()? {
+                Some(line) if line == "ready" => break,
+                Some(line) if !line.is_empty() && line != "saved settings:" => settings.push(line),
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    // Returns the next line from the board, or `None` if no whole line
+    // arrived.  A line which straddles the read timeout is kept until the
+    // rest of it arrives.
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        match self.port.read_until(b'\n', &mut self.partial) {
+            Ok(_) if self.partial.ends_with(b"\n") => {
+                let line = String::from_utf8_lossy(&self.partial).trim().to_string();
+                self.partial.clear();
+                Ok(Some(line))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Sends `line` and returns its output once the board replies `ok`.  Lines
+    // are parsed with the firmware's own grammar first so typos are caught
+    // before anything moves.
+    pub fn command(&mut self, line: &str) -> Result<Vec<String>> {
+        self.command_with(line, |_| {})
+    }
+
+    // Like `command` but passes each line of output to `on_line` as it
+    // arrives, e.g. progress reports from a long feed.
+    pub fn command_with(
+        &mut self,
+        line: &str,
+        mut on_line: impl FnMut(&str),
+    ) -> Result<Vec<String>> {
+        let line = line.trim();
+        if !matches!(GCodeEvent::parse(line), Ok(GCodeEvent::Line(command)) if command.command().is_some())
+        {
+            return Err(Error::Invalid(line.to_string()));
+        }
+        self.send_raw(line)?;
+
+        let mut output = Vec::new();
+        let mut last_reply = Instant::now();
+        loop {
+            let Some(reply) = self.read_line()? else {
+                if last_reply.elapsed() > REPLY_TIMEOUT {
+                    return Err(Error::Timeout);
+                }
+                continue;
+            };
+            last_reply = Instant::now();
+            // The board echoes what it is sent.
+            if reply.is_empty() || reply == line || reply.starts_with("busy:") {
+                continue;
+            }
+            if reply == "ok" {
+                return Ok(output);
+            }
+            if reply.starts_with("error:") {
+                return Err(Error::Board(reply));
+            }
+            on_line(&reply);
+            output.push(reply);
+        }
+    }
+
+    // Sends a line without waiting for a reply.
+    pub fn send_raw(&mut self, line: &str) -> Result<()> {
+        let port = self.port.get_mut();
+        port.write_all(line.as_bytes())?;
+        port.write_all(b"\n")?;
+        port.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    // Replays `reads` one per read, timing out on `None`, and records what is
+    // written.
+    struct FakePort {
+        reads: VecDeque<Option<&'static str>>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front().flatten() {
+                Some(data) => {
+                    buf[..data.len()].copy_from_slice(data.as_bytes());
+                    Ok(data.len())
+                }
+                None => Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+
+    impl Write for FakePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake_connection(reads: &[Option<&'static str>]) -> (Connection, Arc<Mutex<Vec<u8>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let port = FakePort {
+            reads: reads.iter().copied().collect(),
+            written: written.clone(),
+        };
+        (Connection::new(Box::new(port)), written)
+    }
+
+    #[test]
+    fn lines_straddling_a_timeout_are_joined() {
+        let (mut connection, _) = fake_connection(&[Some("M620 N0 "), None, Some("A135\r\nok\n")]);
+
+        assert_eq!(connection.read_line().unwrap(), None);
+        assert_eq!(
+            connection.read_line().unwrap().as_deref(),
+            Some("M620 N0 A135")
+        );
+        assert_eq!(connection.read_line().unwrap().as_deref(), Some("ok"));
+        assert_eq!(connection.read_line().unwrap(), None);
+    }
+
+    #[test]
+    fn settings_are_read_up_to_ready() {
+        let (mut connection, _) = fake_connection(&[
+            Some("saved settings:\nM620 N0 A135\n"),
+            None,
+            Some("M623 N0 R0\n\nready\nok\n"),
+        ]);
+
+        assert_eq!(
+            connection.read_settings().unwrap(),
+            ["M620 N0 A135", "M623 N0 R0"]
+        );
+        assert_eq!(connection.read_line().unwrap().as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn command_skips_echoes_and_busy_reports() {
+        let (mut connection, written) = fake_connection(&[
+            Some("M621 N0\n"),
+            None,
+            Some("busy: processing\nM620 N0 A135\n"),
+            Some("M623 N0 R0\nok\n"),
+        ]);
+
+        let mut seen = Vec::new();
+        let output = connection
+            .command_with(" M621 N0 ", |line| seen.push(line.to_string()))
+            .unwrap();
+
+        assert_eq!(output, ["M620 N0 A135", "M623 N0 R0"]);
+        assert_eq!(seen, output);
+        assert_eq!(*written.lock().unwrap(), b"M621 N0\n");
+    }
+
+    #[test]
+    fn error_replies_fail_the_command() {
+        let (mut connection, _) =
+            fake_connection(&[Some("invalid: D-1\nerror:9 invalid argument type D\n")]);
+
+        match connection.command("M623 N1 D-1") {
+            Err(Error::Board(reply)) => assert_eq!(reply, "error:9 invalid argument type D"),
+            result => panic!("unexpected {result:?}"),
+        }
+    }
+
+    #[test]
+    fn lines_which_arent_commands_are_not_sent() {
+        let (mut connection, written) = fake_connection(&[]);
+
+        assert!(matches!(
+            connection.command("; M620"),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(connection.command(""), Err(Error::Invalid(_))));
+        assert!(written.lock().unwrap().is_empty());
+    }
+}
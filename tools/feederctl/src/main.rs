@@ -0,0 +1,349 @@
+// Host side companion for the feeder controller.  Talks to the board over its
+// USB serial port using the same G-code the firmware implements.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    process::{Command, ExitCode},
+    thread,
+    time::Duration,
+};
+
+use connection::{Connection, Error, Result};
+use pnpfeeder::ConfigGroup;
+
+mod connection;
+
+const USAGE: &str = "\
+usage: feederctl [--port <path>] <command> [args]
+
+commands:
+  info                      firmware name, version and supported commands
+  send <gcode>...           sends each line and prints the replies
+  dump                      prints every feeder's config as the lines which set it
+  restore <file>            applies the lines from `dump` and saves them
+  calibrate <feeder>        interactively finds a feeder's lever angles
+  burnin <feeder> [feeds] [length]
+                            feeds repeatedly, reporting errors as they happen
+  tail [interval]           prints events, with status reports every interval
+                            seconds if given
+  flash <file.uf2>          loads new firmware with picotool
+
+The port defaults to $FEEDERCTL_PORT, or /dev/ttyACM0.";
+
+const DEFAULT_PORT: &str = "/dev/ttyACM0";
+
+// Feeds between `burnin` status reports.
+const BURNIN_REPORT_INTERVAL: u32 = 10;
+
+// Time the board takes to reappear as a USB mass storage device after M997.
+const BOOTLOADER_DELAY: Duration = Duration::from_secs(2);
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut port = env::var("FEEDERCTL_PORT").unwrap_or_else(|_| DEFAULT_PORT.to_string());
+    if args.first().is_some_and(|arg| arg == "--port") && args.len() > 1 {
+        port = args.remove(1);
+        args.remove(0);
+    }
+
+    let Some((command, args)) = args.split_first() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+    let result = match (command.as_str(), args) {
+        ("info", []) => with_connection(&port, info),
+        ("send", lines) if !lines.is_empty() => {
+            with_connection(&port, |connection| send(connection, lines))
+        }
+        ("dump", []) => with_connection(&port, dump),
+        ("restore", [path]) => with_connection(&port, |connection| restore(connection, path)),
+        ("calibrate", [feeder]) => {
+            with_connection(&port, |connection| calibrate(connection, feeder))
+        }
+        ("burnin", [feeder, rest @ ..]) if rest.len() <= 2 => {
+            with_connection(&port, |connection| {
+                burnin(connection, feeder, rest.first(), rest.get(1))
+            })
+        }
+        ("tail", interval) if interval.len() <= 1 => {
+            with_connection(&port, |connection| tail(connection, interval.first()))
+        }
+        ("flash", [path]) => flash(&port, path),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("feederctl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn with_connection(port: &str, f: impl FnOnce(&mut Connection) -> Result<()>) -> Result<()> {
+    let (mut connection, _settings) = Connection::open(port)?;
+    f(&mut connection)
+}
+
+fn print_lines(lines: &[String]) {
+    for line in lines {
+        println!("{line}");
+    }
+}
+
+fn info(connection: &mut Connection) -> Result<()> {
+    print_lines(&connection.command("M115")?);
+    Ok(())
+}
+
+fn send(connection: &mut Connection, lines: &[String]) -> Result<()> {
+    for line in lines {
+        connection.command_with(line, |reply| println!("{reply}"))?;
+    }
+    Ok(())
+}
+
+// M621 reports configs as the M620, M623, M634 and M635 commands which
+// recreate them.
+fn dump(connection: &mut Connection) -> Result<()> {
+    print_lines(&connection.command("M621")?);
+    Ok(())
+}
+
+// Returns the config lines of a file written by `dump`, skipping blank lines
+// and `#` comments.  The whole file is checked before anything is sent.
+fn restore_lines(path: &str, file: impl BufRead) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for line in file.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let is_config = ConfigGroup::ALL
+            .iter()
+            .any(|group| line.starts_with(&format!("M{} ", group.code())));
+        if !is_config {
+            return Err(Error::Usage(format!(
+                "{path} has a line which doesn't set a config: {line}"
+            )));
+        }
+        lines.push(line.to_string());
+    }
+    Ok(lines)
+}
+
+fn restore(connection: &mut Connection, path: &str) -> Result<()> {
+    let lines = restore_lines(path, BufReader::new(File::open(path)?))?;
+    for line in &lines {
+        connection.command(line)?;
+    }
+    connection.command("M500")?;
+    println!("restored and saved {path}");
+    Ok(())
+}
+
+// Returns the value of `letter` in an M620 line reported by M621.
+fn config_field(line: &str, letter: char) -> Option<f64> {
+    line.split_whitespace()
+        .find_map(|word| word.strip_prefix(letter))
+        .and_then(|value| value.parse().ok())
+}
+
+fn calibrate(connection: &mut Connection, feeder: &str) -> Result<()> {
+    let config = connection.command(&format!("M621 N{feeder}"))?;
+    let mut angle = config
+        .first()
+        .and_then(|line| config_field(line, 'C'))
+        .unwrap_or(90.0);
+    connection.command("M610 S1")?;
+    connection.command(&format!("M280 P{feeder} S{angle}"))?;
+
+    println!(
+        "feeder {feeder} at the retract angle {angle}\n\
+         <angle> moves the lever, +[step] and -[step] nudge it\n\
+         a, b and c use the current angle as the advanced, half advanced and\n\
+         retract angle, f test feeds, save saves the config, q quits"
+    );
+    let stdin = io::stdin();
+    for input in stdin.lock().lines() {
+        let input = input?;
+        let command = match input.trim() {
+            "q" => break,
+            "save" => "M500".to_string(),
+            "f" => format!("M600 N{feeder}"),
+            letter @ ("a" | "b" | "c") => {
+                let letter = letter.to_ascii_uppercase();
+                format!("M620 N{feeder} {letter}{angle}")
+            }
+            nudge if nudge.starts_with(['+', '-']) => {
+                let step: f64 = match nudge[1..].parse() {
+                    Ok(step) => step,
+                    Err(_) if nudge.len() == 1 => 1.0,
+                    Err(_) => {
+                        println!("can't parse {nudge}");
+                        continue;
+                    }
+                };
+                let step = if nudge.starts_with('-') { -step } else { step };
+                angle = (angle + step).clamp(0.0, 180.0);
+                format!("M280 P{feeder} S{angle}")
+            }
+            target => match target.parse::<f64>() {
+                Ok(target) => {
+                    angle = target.clamp(0.0, 180.0);
+                    format!("M280 P{feeder} S{angle}")
+                }
+                Err(_) => {
+                    println!("unknown input {target}");
+                    continue;
+                }
+            },
+        };
+        // Keep going after the board rejects something so a bad angle doesn't
+        // lose the session.
+        match connection.command(&command) {
+            Ok(output) => {
+                print_lines(&output);
+                println!("ok, angle {angle}");
+            }
+            Err(Error::Board(reply)) => println!("{reply}"),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn burnin(
+    connection: &mut Connection,
+    feeder: &str,
+    feeds: Option<&String>,
+    length: Option<&String>,
+) -> Result<()> {
+    let feeds: u32 = match feeds {
+        Some(feeds) => feeds
+            .parse()
+            .map_err(|_| Error::Usage(format!("invalid feed count {feeds}")))?,
+        None => 100,
+    };
+    let mut command = format!("M600 N{feeder}");
+    if let Some(length) = length {
+        command += &format!(" F{length}");
+    }
+
+    connection.command("M610 S1")?;
+    let mut errors = 0;
+    for feed in 1..=feeds {
+        match connection.command_with(&command, |line| println!("{line}")) {
+            Ok(_) => {}
+            Err(Error::Board(reply)) => {
+                errors += 1;
+                println!("feed {feed}: {reply}");
+            }
+            Err(e) => return Err(e),
+        }
+        if feed % BURNIN_REPORT_INTERVAL == 0 || feed == feeds {
+            println!("{feed} of {feeds} feeds, {errors} errors");
+        }
+    }
+    print_lines(&connection.command(&format!("M602 N{feeder}"))?);
+    Ok(())
+}
+
+fn tail(connection: &mut Connection, interval: Option<&String>) -> Result<()> {
+    if let Some(interval) = interval {
+        connection.command(&format!("M154 S{interval}"))?;
+    }
+    loop {
+        if let Some(line) = connection.read_line()? {
+            if !line.is_empty() {
+                println!("{line}");
+            }
+        }
+    }
+}
+
+fn picotool_load(path: &str, force: bool) -> Result<bool> {
+    let mut picotool = Command::new("picotool");
+    picotool.args(["load", "-x", path]);
+    // Reboots the board into its bootloader through the firmware's picotool
+    // interface.
+    if force {
+        picotool.arg("-f");
+    }
+    Ok(picotool.status()?.success())
+}
+
+// Boards built without the picotool interface are rebooted into the
+// bootloader with M997 instead.
+fn flash(port: &str, path: &str) -> Result<()> {
+    if picotool_load(path, true)? {
+        return Ok(());
+    }
+    println!("picotool couldn't reboot the board, trying M997");
+    let (mut connection, _settings) = Connection::open(port)?;
+    // The board replies before it reboots.
+    connection.command("M997")?;
+    drop(connection);
+    thread::sleep(BOOTLOADER_DELAY);
+    if picotool_load(path, false)? {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::other("picotool load failed")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_reads_dumped_config_lines() {
+        let dump = "\
+# feeder 0
+M620 N0 A135 B107.5 C80\r
+  M623 N0 R0 D500
+M634 N0 U0 F0 H180
+
+M620 N1 A120
+";
+        assert_eq!(
+            restore_lines("dump.txt", dump.as_bytes()).unwrap(),
+            [
+                "M620 N0 A135 B107.5 C80",
+                "M623 N0 R0 D500",
+                "M634 N0 U0 F0 H180",
+                "M620 N1 A120"
+            ]
+        );
+    }
+
+    #[test]
+    fn restore_rejects_other_commands() {
+        let dump = "M620 N0 A135\nM500\n";
+        match restore_lines("dump.txt", dump.as_bytes()) {
+            Err(Error::Usage(message)) => {
+                assert_eq!(
+                    message,
+                    "dump.txt has a line which doesn't set a config: M500"
+                )
+            }
+            result => panic!("unexpected {result:?}"),
+        }
+    }
+
+    #[test]
+    fn config_field_reads_a_letter() {
+        let line = "M620 N0 A135 B107.5 C80 F2";
+        assert_eq!(config_field(line, 'B'), Some(107.5));
+        assert_eq!(config_field(line, 'C'), Some(80.0));
+        assert_eq!(config_field(line, 'Z'), None);
+        assert_eq!(config_field("M620 N0 CX", 'C'), None);
+    }
+}